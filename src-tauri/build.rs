@@ -16,8 +16,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         &[
             "proto/client/client.proto",
             "proto/enrollment/enrollment.proto",
+            "service-proto/service_status.proto",
         ],
-        &["proto/client", "proto/enrollment"],
+        &["proto/client", "proto/enrollment", "service-proto"],
     )?;
 
     tauri_build::build();
@@ -0,0 +1,2 @@
+ALTER TABLE location ADD COLUMN doh_url TEXT NULL;
+ALTER TABLE location ADD COLUMN dot_host TEXT NULL;
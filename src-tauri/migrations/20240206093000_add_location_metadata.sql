@@ -0,0 +1 @@
+ALTER TABLE location ADD COLUMN metadata TEXT NULL;
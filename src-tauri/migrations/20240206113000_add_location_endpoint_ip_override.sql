@@ -0,0 +1 @@
+ALTER TABLE location ADD COLUMN endpoint_ip_override TEXT NULL;
@@ -0,0 +1 @@
+ALTER TABLE location ADD COLUMN kill_switch BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,2 @@
+ALTER TABLE connection ADD COLUMN disconnect_reason TEXT;
+ALTER TABLE tunnel_connection ADD COLUMN disconnect_reason TEXT;
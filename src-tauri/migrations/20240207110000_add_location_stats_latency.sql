@@ -0,0 +1 @@
+ALTER TABLE location_stats ADD COLUMN latency_ms INTEGER NULL;
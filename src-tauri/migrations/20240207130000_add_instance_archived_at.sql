@@ -0,0 +1 @@
+ALTER TABLE instance ADD COLUMN archived_at TIMESTAMP NULL;
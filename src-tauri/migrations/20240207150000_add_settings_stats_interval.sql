@@ -0,0 +1 @@
+ALTER TABLE settings ADD COLUMN stats_interval_seconds INTEGER DEFAULT 1 NOT NULL;
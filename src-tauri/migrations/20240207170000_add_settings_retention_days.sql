@@ -0,0 +1 @@
+ALTER TABLE settings ADD COLUMN retention_days INTEGER DEFAULT 0 NOT NULL;
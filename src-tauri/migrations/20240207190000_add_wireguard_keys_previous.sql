@@ -0,0 +1,2 @@
+ALTER TABLE wireguard_keys ADD COLUMN previous_pubkey TEXT;
+ALTER TABLE wireguard_keys ADD COLUMN previous_prvkey TEXT;
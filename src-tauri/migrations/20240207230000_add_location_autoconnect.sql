@@ -0,0 +1 @@
+ALTER TABLE location ADD COLUMN autoconnect BOOLEAN DEFAULT FALSE NOT NULL;
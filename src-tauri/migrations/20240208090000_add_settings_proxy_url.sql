@@ -0,0 +1 @@
+ALTER TABLE settings ADD COLUMN proxy_url TEXT;
@@ -0,0 +1 @@
+ALTER TABLE location ADD COLUMN dns_search TEXT;
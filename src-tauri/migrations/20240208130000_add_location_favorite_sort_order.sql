@@ -0,0 +1,2 @@
+ALTER TABLE location ADD COLUMN favorite BOOLEAN DEFAULT FALSE NOT NULL;
+ALTER TABLE location ADD COLUMN sort_order INTEGER DEFAULT 0 NOT NULL;
@@ -0,0 +1 @@
+ALTER TABLE settings ADD COLUMN connection_timeout_seconds INTEGER DEFAULT 30 NOT NULL;
@@ -0,0 +1 @@
+ALTER TABLE connection ADD COLUMN resolved_endpoint TEXT;
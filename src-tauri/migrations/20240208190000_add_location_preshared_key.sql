@@ -0,0 +1 @@
+ALTER TABLE location ADD COLUMN preshared_key TEXT;
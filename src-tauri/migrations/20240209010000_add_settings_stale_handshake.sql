@@ -0,0 +1,2 @@
+ALTER TABLE settings ADD COLUMN stale_handshake_seconds INTEGER DEFAULT 180 NOT NULL;
+ALTER TABLE settings ADD COLUMN stale_handshake_notification BOOLEAN DEFAULT false NOT NULL;
@@ -0,0 +1 @@
+ALTER TABLE settings ADD COLUMN fallback_dns TEXT;
@@ -0,0 +1,2 @@
+ALTER TABLE wireguard_keys ADD COLUMN profile_name TEXT;
+ALTER TABLE wireguard_keys ADD COLUMN address TEXT;
@@ -0,0 +1 @@
+ALTER TABLE settings ADD COLUMN allow_insecure BOOLEAN DEFAULT false NOT NULL;
@@ -0,0 +1 @@
+ALTER TABLE settings ADD COLUMN disconnect_on_exit BOOLEAN DEFAULT true NOT NULL;
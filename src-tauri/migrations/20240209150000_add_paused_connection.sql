@@ -0,0 +1,6 @@
+CREATE TABLE paused_connection (
+    id INTEGER PRIMARY KEY NOT NULL,
+    location_id INTEGER NOT NULL,
+    connection_type TEXT NOT NULL,
+    profile_id INTEGER
+);
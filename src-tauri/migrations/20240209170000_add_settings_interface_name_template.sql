@@ -0,0 +1 @@
+ALTER TABLE settings ADD COLUMN interface_name_template TEXT;
@@ -0,0 +1,7 @@
+CREATE TABLE location_peak_throughput (
+  location_id INTEGER PRIMARY KEY NOT NULL,
+  upload_rate INTEGER NOT NULL,
+  download_rate INTEGER NOT NULL,
+  updated_at TIMESTAMP NOT NULL,
+  FOREIGN KEY (location_id) REFERENCES location(id) ON DELETE CASCADE
+);
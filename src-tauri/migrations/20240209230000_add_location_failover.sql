@@ -0,0 +1,2 @@
+ALTER TABLE location ADD COLUMN failover_group TEXT;
+ALTER TABLE location ADD COLUMN failover_priority INTEGER NOT NULL DEFAULT 0;
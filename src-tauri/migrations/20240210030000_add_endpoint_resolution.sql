@@ -0,0 +1,2 @@
+ALTER TABLE settings ADD COLUMN endpoint_resolution TEXT DEFAULT 'auto' NOT NULL;
+ALTER TABLE location ADD COLUMN sticky_endpoint TEXT;
@@ -0,0 +1,7 @@
+CREATE TABLE location_lifetime_transfer (
+  location_id INTEGER PRIMARY KEY NOT NULL,
+  upload INTEGER NOT NULL,
+  download INTEGER NOT NULL,
+  updated_at TIMESTAMP NOT NULL,
+  FOREIGN KEY (location_id) REFERENCES location(id) ON DELETE CASCADE
+);
@@ -0,0 +1 @@
+ALTER TABLE location ADD COLUMN force_tunnel_dns BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1 @@
+ALTER TABLE settings ADD COLUMN public_ip_echo_url TEXT;
@@ -0,0 +1 @@
+ALTER TABLE settings ADD COLUMN launch_on_startup BOOLEAN NOT NULL DEFAULT false;
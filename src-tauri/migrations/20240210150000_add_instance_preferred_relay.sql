@@ -0,0 +1 @@
+ALTER TABLE instance ADD COLUMN preferred_relay TEXT;
@@ -0,0 +1,2 @@
+ALTER TABLE location ADD COLUMN imported BOOLEAN NOT NULL DEFAULT FALSE;
+UPDATE location SET imported = TRUE WHERE network_id = 0;
@@ -1,25 +1,52 @@
 use std::{
     collections::HashMap,
     sync::{Arc, Mutex},
+    time::Duration,
 };
 
+use serde::Serialize;
+use tokio::{sync::watch, time::timeout};
 use tokio_util::sync::CancellationToken;
 use tonic::transport::Channel;
+use tracing::Level;
 
 use crate::{
-    database::{ActiveConnection, DbPool},
+    connection_state::{ConnectionState, ConnectionStateInfo, LastError},
+    database::{ActiveConnection, DbPool, DisconnectReason, Settings, StatsBuffer},
+    error::Error,
+    issue_capture::{CaptureEntryKind, IssueCapture},
     service::{
-        proto::desktop_daemon_service_client::DesktopDaemonServiceClient, utils::setup_client,
+        proto::desktop_daemon_service_client::DesktopDaemonServiceClient,
+        utils::{set_service_url, setup_client, ServiceAddress},
     },
     utils::disconnect_interface,
     ConnectionType,
 };
 
+// upper bound of tunnel teardown on exit, so unresponsive service doesn't block quitting
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+// verbosity of interface logs until log level is changed in settings
+pub const DEFAULT_LOG_WATCHER_LEVEL: Level = Level::DEBUG;
+
 pub struct AppState {
     pub db: Arc<Mutex<Option<DbPool>>>,
     pub active_connections: Arc<Mutex<Vec<ActiveConnection>>>,
-    pub client: DesktopDaemonServiceClient<Channel>,
+    // replaced when `service_url` setting changes
+    pub client: Arc<Mutex<DesktopDaemonServiceClient<Channel>>>,
+    // notifies long-lived streams that they should reconnect with the new client
+    pub service_url_changes: watch::Sender<()>,
     pub log_watchers: Arc<Mutex<HashMap<String, CancellationToken>>>,
+    // connection attempts in progress by location ID and key profile ID (`None` for the default
+    // key pair), cancelled by `cancel_connect` command
+    pub pending_connects: Arc<Mutex<HashMap<(i64, Option<i64>), CancellationToken>>>,
+    // verbosity of logs passed by running log watchers, follows changes of `log_level` setting
+    pub log_watcher_level: watch::Sender<Level>,
+    pub issue_capture: Arc<Mutex<Option<IssueCapture>>>,
+    pub stats_buffer: Arc<StatsBuffer>,
+    pub connection_states: Arc<Mutex<HashMap<(i64, ConnectionType), ConnectionStateInfo>>>,
+    pub last_errors: Arc<Mutex<HashMap<(i64, ConnectionType), LastError>>>,
+    // bumped by `reset_peak` command, so stats threads forget peaks of current connection
+    pub peak_generations: Arc<Mutex<HashMap<i64, u64>>>,
 }
 
 impl Default for AppState {
@@ -35,8 +62,16 @@ impl AppState {
         AppState {
             db: Arc::new(Mutex::new(None)),
             active_connections: Arc::new(Mutex::new(Vec::new())),
-            client,
+            client: Arc::new(Mutex::new(client)),
+            service_url_changes: watch::channel(()).0,
             log_watchers: Arc::new(Mutex::new(HashMap::new())),
+            pending_connects: Arc::new(Mutex::new(HashMap::new())),
+            log_watcher_level: watch::channel(DEFAULT_LOG_WATCHER_LEVEL).0,
+            issue_capture: Arc::new(Mutex::new(None)),
+            stats_buffer: Arc::new(StatsBuffer::default()),
+            connection_states: Arc::new(Mutex::new(HashMap::new())),
+            last_errors: Arc::new(Mutex::new(HashMap::new())),
+            peak_generations: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
@@ -49,12 +84,193 @@ impl AppState {
             .unwrap()
     }
 
+    /// gRPC client of `defguard-service`.
+    pub fn client(&self) -> DesktopDaemonServiceClient<Channel> {
+        self.client
+            .lock()
+            .expect("Failed to lock gRPC client mutex")
+            .clone()
+    }
+
+    /// Points gRPC client at `defguard-service` listening on given URL.
+    /// Requests already in flight finish on the previous channel.
+    pub fn set_service_url(&self, service_url: &str) -> Result<(), Error> {
+        ServiceAddress::parse(service_url)?;
+        set_service_url(service_url);
+        let client = setup_client()?;
+        *self
+            .client
+            .lock()
+            .expect("Failed to lock gRPC client mutex") = client;
+        self.service_url_changes.send_replace(());
+        info!("Connecting to defguard-service at {service_url}");
+        Ok(())
+    }
+
+    /// Record an entry in issue capture timeline if a capture is in progress.
+    pub fn record_capture<T: Serialize>(&self, kind: CaptureEntryKind, name: &str, payload: T) {
+        if let Some(capture) = self
+            .issue_capture
+            .lock()
+            .expect("Failed to lock issue capture mutex")
+            .as_mut()
+        {
+            capture.record(kind, name, payload);
+        }
+    }
+
+    /// Number of times peak throughput of location was reset since the app started.
+    pub fn peak_generation(&self, location_id: i64) -> u64 {
+        self.peak_generations
+            .lock()
+            .expect("Failed to lock peak generations mutex")
+            .get(&location_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Makes stats threads forget peak throughput of location they've seen so far.
+    pub fn reset_peak_generation(&self, location_id: i64) {
+        *self
+            .peak_generations
+            .lock()
+            .expect("Failed to lock peak generations mutex")
+            .entry(location_id)
+            .or_default() += 1;
+    }
+
+    /// Registers connection attempt of location with key profile, or with the default key pair
+    /// if `profile_id` is `None`, and returns token cancelling it.
+    pub fn begin_connect(&self, location_id: i64, profile_id: Option<i64>) -> CancellationToken {
+        let token = CancellationToken::new();
+        self.pending_connects
+            .lock()
+            .expect("Failed to lock pending connects mutex")
+            .insert((location_id, profile_id), token.clone());
+        token
+    }
+
+    /// Unregisters connection attempt of location once it's finished.
+    pub fn finish_connect(&self, location_id: i64, profile_id: Option<i64>) {
+        self.pending_connects
+            .lock()
+            .expect("Failed to lock pending connects mutex")
+            .remove(&(location_id, profile_id));
+    }
+
+    /// Cancels connection attempt of location. Returns `false` if there was none in progress.
+    pub fn cancel_connect(&self, location_id: i64, profile_id: Option<i64>) -> bool {
+        match self
+            .pending_connects
+            .lock()
+            .expect("Failed to lock pending connects mutex")
+            .remove(&(location_id, profile_id))
+        {
+            Some(token) => {
+                token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn set_connection_state(
+        &self,
+        location_id: i64,
+        connection_type: &ConnectionType,
+        state: ConnectionState,
+    ) {
+        debug!("Location {location_id} {connection_type:?} connection state: {state:?}");
+        let key = (location_id, connection_type.clone());
+        let info = ConnectionStateInfo::new(state);
+        {
+            let mut last_errors = self
+                .last_errors
+                .lock()
+                .expect("Failed to lock last errors mutex");
+            match &info.state {
+                ConnectionState::Error(message) => {
+                    last_errors.insert(
+                        key.clone(),
+                        LastError {
+                            message: message.clone(),
+                            at: info.since,
+                        },
+                    );
+                }
+                ConnectionState::Connected => {
+                    last_errors.remove(&key);
+                }
+                _ => {}
+            }
+        }
+        self.connection_states
+            .lock()
+            .expect("Failed to lock connection states mutex")
+            .insert(key, info);
+    }
+
+    /// Returns the last connection failure of location, unless it was connected since.
+    pub fn last_error(
+        &self,
+        location_id: i64,
+        connection_type: &ConnectionType,
+    ) -> Option<LastError> {
+        self.last_errors
+            .lock()
+            .expect("Failed to lock last errors mutex")
+            .get(&(location_id, connection_type.clone()))
+            .cloned()
+    }
+
+    /// Returns tracked connection state, reconciled with active connections
+    /// since connections can also be closed outside of `disconnect` command.
+    pub fn connection_state(
+        &self,
+        location_id: i64,
+        connection_type: &ConnectionType,
+    ) -> ConnectionStateInfo {
+        let is_active = self
+            .active_connections
+            .lock()
+            .expect("Failed to lock active connections mutex")
+            .iter()
+            .any(|con| con.location_id == location_id && con.connection_type.eq(connection_type));
+        let state = self
+            .connection_states
+            .lock()
+            .expect("Failed to lock connection states mutex")
+            .get(&(location_id, connection_type.clone()))
+            .cloned();
+        let mut info = match state {
+            Some(info) if info.state == ConnectionState::Connected && !is_active => {
+                ConnectionStateInfo::new(ConnectionState::Idle)
+            }
+            Some(info) => info,
+            None if is_active => ConnectionStateInfo::new(ConnectionState::Connected),
+            None => ConnectionStateInfo::new(ConnectionState::Idle),
+        };
+        info.elapsed_seconds = info.elapsed().num_seconds();
+        info
+    }
+
     pub fn get_connections(&self) -> Vec<ActiveConnection> {
         self.active_connections
             .lock()
             .expect("Failed to lock active connections mutex")
             .clone()
     }
+
+    /// Returns number of active connections without cloning them.
+    pub fn active_connection_count(&self) -> usize {
+        self.active_connections
+            .lock()
+            .expect("Failed to lock active connections mutex")
+            .len()
+    }
+
+    /// Removes connection of location or tunnel made with the default key pair,
+    /// key profile connections are removed with [`Self::find_and_remove_profile_connection`].
     pub fn find_and_remove_connection(
         &self,
         location_id: i64,
@@ -64,7 +280,9 @@ impl AppState {
         let mut connections = self.active_connections.lock().unwrap();
 
         if let Some(index) = connections.iter().position(|conn| {
-            conn.location_id == location_id && conn.connection_type.eq(connection_type)
+            conn.location_id == location_id
+                && conn.connection_type.eq(connection_type)
+                && conn.profile_id.is_none()
         }) {
             // Found a connection with the specified location_id
             let removed_connection = connections.remove(index);
@@ -75,36 +293,188 @@ impl AppState {
         }
     }
 
-    pub fn get_connection_id_by_type(&self, connection_type: &ConnectionType) -> Vec<i64> {
-        let active_connections = self.active_connections.lock().unwrap();
+    /// Removes active connections using given interface, regardless of their location.
+    pub fn remove_connections_by_interface(&self, interface_name: &str) -> Vec<ActiveConnection> {
+        let mut connections = self
+            .active_connections
+            .lock()
+            .expect("Failed to lock active connections mutex");
+        let (removed, kept): (Vec<ActiveConnection>, Vec<ActiveConnection>) =
+            std::mem::take(&mut *connections)
+                .into_iter()
+                .partition(|connection| connection.interface_name == interface_name);
+        *connections = kept;
+        removed
+    }
 
-        let connection_ids: Vec<i64> = active_connections
-            .iter()
-            .filter_map(|con| {
-                if con.connection_type.eq(connection_type) {
-                    Some(con.location_id)
-                } else {
-                    None
-                }
-            })
-            .collect();
+    /// Removes all connections of location, made with the default key pair and key profiles.
+    pub fn remove_location_connections(&self, location_id: i64) -> Vec<ActiveConnection> {
+        let mut connections = self
+            .active_connections
+            .lock()
+            .expect("Failed to lock active connections mutex");
+        let (removed, kept): (Vec<ActiveConnection>, Vec<ActiveConnection>) =
+            std::mem::take(&mut *connections)
+                .into_iter()
+                .partition(|connection| {
+                    connection.location_id == location_id
+                        && connection.connection_type == ConnectionType::Location
+                });
+        *connections = kept;
+        removed
+    }
+
+    /// Removes connection of location made with given key profile, or with the default key pair
+    /// if `profile_id` is `None`.
+    pub fn find_and_remove_profile_connection(
+        &self,
+        location_id: i64,
+        profile_id: Option<i64>,
+    ) -> Option<ActiveConnection> {
+        debug!("Removing active connection for location with id: {location_id}, profile: {profile_id:?}");
+        let mut connections = self.active_connections.lock().unwrap();
+        let index = connections.iter().position(|conn| {
+            conn.location_id == location_id
+                && conn.connection_type == ConnectionType::Location
+                && conn.profile_id == profile_id
+        })?;
+        let removed_connection = connections.remove(index);
+        info!("Removed connection from active connections: {removed_connection:#?}");
+        Some(removed_connection)
+    }
 
+    /// Returns IDs of connected locations or tunnels. Location connected with the default key pair
+    /// and with key profiles at the same time is listed once.
+    pub fn get_connection_id_by_type(&self, connection_type: &ConnectionType) -> Vec<i64> {
+        let mut connection_ids: Vec<i64> = Vec::new();
+        for (location_id, _) in self.get_connection_keys_by_type(connection_type) {
+            if !connection_ids.contains(&location_id) {
+                connection_ids.push(location_id);
+            }
+        }
         connection_ids
     }
 
-    pub async fn close_all_connections(&self) -> Result<(), crate::error::Error> {
-        info!("Closing all active connections...");
-        let active_connections = self.get_connections();
-        info!("Found {} active connections", active_connections.len());
-        for connection in active_connections {
-            debug!("Found active connection");
+    /// Returns location or tunnel IDs of active connections with key profiles connections
+    /// were made with, `None` for the default key pair.
+    pub fn get_connection_keys_by_type(
+        &self,
+        connection_type: &ConnectionType,
+    ) -> Vec<(i64, Option<i64>)> {
+        self.active_connections
+            .lock()
+            .expect("Failed to lock active connections mutex")
+            .iter()
+            .filter(|con| con.connection_type.eq(connection_type))
+            .map(|con| (con.location_id, con.profile_id))
+            .collect()
+    }
+
+    /// Disconnects all active connections, failure of one connection doesn't stop closing
+    /// the remaining ones. Connections which failed to disconnect stay tracked and interface
+    /// names of them are returned.
+    pub async fn close_all_connections(&self) -> Vec<String> {
+        let connections = std::mem::take(
+            &mut *self
+                .active_connections
+                .lock()
+                .expect("Failed to lock active connections mutex"),
+        );
+        info!("Closing {} active connections", connections.len());
+        let mut failed = Vec::new();
+        for connection in connections {
             trace!("Connection: {connection:#?}");
-            debug!("Removing interface");
-            disconnect_interface(connection, self).await?;
+            let interface_name = connection.interface_name.clone();
+            if let Err(err) =
+                disconnect_interface(connection.clone(), self, DisconnectReason::AppShutdown).await
+            {
+                error!("Failed to close interface {interface_name}: {err}");
+                self.active_connections
+                    .lock()
+                    .expect("Failed to lock active connections mutex")
+                    .push(connection);
+                failed.push(interface_name);
+            }
         }
-        Ok(())
+        failed
+    }
+
+    /// Cancels pending connection attempts and forgets connection states, errors and peak
+    /// throughput of all locations, so none of them is carried over to another data profile.
+    pub fn forget_location_state(&self) {
+        for (_, token) in self
+            .pending_connects
+            .lock()
+            .expect("Failed to lock pending connects mutex")
+            .drain()
+        {
+            token.cancel();
+        }
+        self.connection_states
+            .lock()
+            .expect("Failed to lock connection states mutex")
+            .clear();
+        self.last_errors
+            .lock()
+            .expect("Failed to lock last errors mutex")
+            .clear();
+        self.peak_generations
+            .lock()
+            .expect("Failed to lock peak generations mutex")
+            .clear();
     }
 
+    /// Disconnects all tunnels when the app quits, unless `disconnect_on_exit` setting is disabled.
+    /// Connections are taken out of active connections, so teardown runs only once.
+    pub async fn shutdown_connections(&self) {
+        if !Settings::disconnect_on_exit(&self.get_pool()).await {
+            info!("Leaving active connections up on exit");
+            return;
+        }
+        let connections = std::mem::take(
+            &mut *self
+                .active_connections
+                .lock()
+                .expect("Failed to lock active connections mutex"),
+        );
+        info!(
+            "Disconnecting {} active connections before exit",
+            connections.len()
+        );
+        let teardown = async {
+            for connection in connections {
+                let interface_name = connection.interface_name.clone();
+                if let Err(err) =
+                    disconnect_interface(connection, self, DisconnectReason::AppShutdown).await
+                {
+                    error!("Failed to disconnect interface {interface_name} on exit: {err}");
+                }
+            }
+        };
+        if timeout(SHUTDOWN_TIMEOUT, teardown).await.is_err() {
+            warn!("Disconnecting tunnels took longer than {SHUTDOWN_TIMEOUT:?}, exiting anyway");
+        }
+    }
+
+    /// Marks connection of location made with the default key pair as connected regardless
+    /// of location schedule.
+    pub fn mark_forced(&self, location_id: i64) {
+        if let Some(connection) = self
+            .active_connections
+            .lock()
+            .expect("Failed to lock active connections mutex")
+            .iter_mut()
+            .find(|conn| {
+                conn.location_id == location_id
+                    && conn.connection_type == ConnectionType::Location
+                    && conn.profile_id.is_none()
+            })
+        {
+            connection.forced = true;
+        }
+    }
+
+    /// Finds connection of location or tunnel made with the default key pair.
     pub fn find_connection(
         &self,
         id: i64,
@@ -115,10 +485,11 @@ impl AppState {
         "Checking for active connection with id: {id}, connection_type: {connection_type:?} in active connections: {connections:#?}"
     );
 
-        if let Some(connection) = connections
-            .iter()
-            .find(|conn| conn.location_id == id && conn.connection_type == connection_type)
-        {
+        if let Some(connection) = connections.iter().find(|conn| {
+            conn.location_id == id
+                && conn.connection_type == connection_type
+                && conn.profile_id.is_none()
+        }) {
             // 'connection' now contains the first element with the specified id and connection_type
             debug!("Found connection: {connection:#?}");
             Some(connection.to_owned())
@@ -0,0 +1,197 @@
+//! Launching the app at system login
+//!
+//! The app registers itself the way each platform expects: a LaunchAgent on macOS, a registry
+//! `Run` value on Windows and an XDG autostart entry on Linux. Registration lives outside of
+//! the app, so it's also read back on startup in case user changed it in system settings.
+
+use std::{env, io, path::PathBuf};
+
+use crate::error::Error;
+
+#[cfg(target_os = "macos")]
+const LAUNCH_AGENT_LABEL: &str = "net.defguard.client";
+#[cfg(target_os = "windows")]
+const RUN_KEY: &str = r"HKCU\Software\Microsoft\Windows\CurrentVersion\Run";
+#[cfg(target_os = "windows")]
+const RUN_VALUE: &str = "defguard-client";
+
+fn autostart_error(err: io::Error) -> Error {
+    if err.kind() == io::ErrorKind::PermissionDenied {
+        Error::Autostart(format!("permission denied: {err}"))
+    } else {
+        Error::Autostart(err.to_string())
+    }
+}
+
+/// Path launching the app. AppImage runs from a temporary mount, so its own file is used.
+fn executable() -> Result<PathBuf, Error> {
+    #[cfg(target_os = "linux")]
+    if let Some(appimage) = env::var_os("APPIMAGE").filter(|appimage| !appimage.is_empty()) {
+        return Ok(PathBuf::from(appimage));
+    }
+    env::current_exe().map_err(autostart_error)
+}
+
+/// Escapes text placed into plist XML.
+#[cfg(target_os = "macos")]
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Quotes argument of desktop entry `Exec` key. Characters reserved inside quotes are escaped
+/// with backslash, which is escaped again as the key is a string value, and `%` would start
+/// a field code.
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+fn quote_exec_arg(arg: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in arg.chars() {
+        match c {
+            '"' | '`' | '$' => {
+                quoted.push_str("\\\\");
+                quoted.push(c);
+            }
+            '\\' => quoted.push_str("\\\\\\\\"),
+            '%' => quoted.push_str("%%"),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Location of autostart entry on Linux and macOS.
+#[cfg(not(target_os = "windows"))]
+fn entry_path() -> Result<PathBuf, Error> {
+    #[cfg(target_os = "macos")]
+    let path = dirs::home_dir().map(|home| {
+        home.join("Library/LaunchAgents")
+            .join(format!("{LAUNCH_AGENT_LABEL}.plist"))
+    });
+    #[cfg(not(target_os = "macos"))]
+    let path = dirs::config_dir().map(|config| config.join("autostart/defguard-client.desktop"));
+    path.ok_or_else(|| Error::Autostart("home directory not found".into()))
+}
+
+/// Contents of autostart entry launching `executable`.
+#[cfg(not(target_os = "windows"))]
+fn entry(executable: &str) -> String {
+    #[cfg(target_os = "macos")]
+    {
+        let executable = escape_xml(executable);
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+            <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \
+            \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+            <plist version=\"1.0\">\n<dict>\n\
+            <key>Label</key>\n<string>{LAUNCH_AGENT_LABEL}</string>\n\
+            <key>ProgramArguments</key>\n<array>\n<string>{executable}</string>\n</array>\n\
+            <key>RunAtLoad</key>\n<true/>\n\
+            </dict>\n</plist>\n"
+        )
+    }
+    #[cfg(not(target_os = "macos"))]
+    {
+        let executable = quote_exec_arg(executable);
+        format!(
+            "[Desktop Entry]\nType=Application\nName=defguard\nExec={executable}\n\
+            X-GNOME-Autostart-enabled=true\n"
+        )
+    }
+}
+
+/// Checks if the app is registered to launch at login.
+pub fn is_enabled() -> Result<bool, Error> {
+    #[cfg(not(target_os = "windows"))]
+    {
+        Ok(entry_path()?.exists())
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        let output = std::process::Command::new("reg")
+            .args(["query", RUN_KEY, "/v", RUN_VALUE])
+            .output()
+            .map_err(autostart_error)?;
+        Ok(output.status.success())
+    }
+}
+
+/// Registers the app to launch at login, or removes the registration.
+pub fn set_enabled(enabled: bool) -> Result<(), Error> {
+    let executable = executable()?;
+    let executable = executable.to_string_lossy();
+
+    #[cfg(not(target_os = "windows"))]
+    {
+        let path = entry_path()?;
+        if enabled {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent).map_err(autostart_error)?;
+            }
+            std::fs::write(&path, entry(&executable)).map_err(autostart_error)?;
+        } else if path.exists() {
+            std::fs::remove_file(&path).map_err(autostart_error)?;
+        }
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        if !enabled && !is_enabled()? {
+            return Ok(());
+        }
+        let quoted = format!("\"{executable}\"");
+        let args = if enabled {
+            vec![
+                "add", RUN_KEY, "/v", RUN_VALUE, "/t", "REG_SZ", "/d", &quoted, "/f",
+            ]
+        } else {
+            vec!["delete", RUN_KEY, "/v", RUN_VALUE, "/f"]
+        };
+        let output = std::process::Command::new("reg")
+            .args(args)
+            .output()
+            .map_err(autostart_error)?;
+        if !output.status.success() {
+            return Err(Error::Autostart(
+                String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            ));
+        }
+    }
+
+    info!(
+        "{} launching {executable} at login",
+        if enabled { "Enabled" } else { "Disabled" }
+    );
+    Ok(())
+}
+
+#[cfg(all(test, not(target_os = "windows")))]
+mod tests {
+    use super::*;
+
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    #[test]
+    fn test_quote_exec_arg() {
+        assert_eq!(
+            quote_exec_arg("/opt/defguard client/defguard"),
+            r#""/opt/defguard client/defguard""#
+        );
+        assert_eq!(
+            quote_exec_arg(r#"/tmp/a"b$c`d\e%f"#),
+            r#""/tmp/a\\"b\\$c\\`d\\\\e%%f""#
+        );
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn test_escape_xml() {
+        assert_eq!(
+            escape_xml("/Applications/a&b <c>.app"),
+            "/Applications/a&amp;b &lt;c&gt;.app"
+        );
+    }
+}
@@ -11,25 +11,84 @@ use tauri::{Manager, State};
 use tauri_plugin_log::LogTarget;
 
 use defguard_client::{
-    __cmd__active_connection, __cmd__all_connections, __cmd__all_instances, __cmd__all_locations,
-    __cmd__all_tunnels, __cmd__connect, __cmd__delete_instance, __cmd__delete_tunnel,
-    __cmd__disconnect, __cmd__get_latest_app_version, __cmd__get_settings, __cmd__last_connection,
-    __cmd__location_interface_details, __cmd__location_stats, __cmd__open_link,
-    __cmd__parse_tunnel_config, __cmd__save_device_config, __cmd__save_tunnel,
-    __cmd__tunnel_details, __cmd__update_instance, __cmd__update_location_routing,
-    __cmd__update_settings,
+    __cmd__active_connection, __cmd__active_connection_count, __cmd__add_key_profile,
+    __cmd__all_connections, __cmd__all_instances, __cmd__all_instances_including_archived,
+    __cmd__all_locations, __cmd__all_tunnels, __cmd__archive_instance, __cmd__cancel_connect,
+    __cmd__check_address_conflict, __cmd__check_instance_updates, __cmd__check_ipv6_leak,
+    __cmd__check_routing_conflicts, __cmd__check_wireguard_driver, __cmd__collect_diagnostics,
+    __cmd__connect, __cmd__connect_instance, __cmd__connect_with_profile, __cmd__connection_health,
+    __cmd__connection_state, __cmd__create_data_profile, __cmd__delete_data_profile,
+    __cmd__delete_instance, __cmd__delete_key_profile, __cmd__delete_tunnel, __cmd__disconnect,
+    __cmd__disconnect_by_interface, __cmd__export_configuration, __cmd__export_location_config,
+    __cmd__export_stats_csv, __cmd__get_effective_dns, __cmd__get_interface_logs,
+    __cmd__get_latest_app_version, __cmd__get_settings, __cmd__import_configuration,
+    __cmd__import_location_config, __cmd__instance_stats, __cmd__last_connection,
+    __cmd__list_data_profiles, __cmd__list_key_profiles, __cmd__list_service_interfaces,
+    __cmd__location_config_qr, __cmd__location_interface_details,
+    __cmd__location_lifetime_transfer, __cmd__location_peak_throughput, __cmd__location_routes,
+    __cmd__location_stats, __cmd__location_stats_with_gaps, __cmd__monthly_usage, __cmd__open_link,
+    __cmd__parse_tunnel_config, __cmd__pause_all_connections, __cmd__power_impact,
+    __cmd__prune_stats, __cmd__recent_connections, __cmd__reconnect,
+    __cmd__regenerate_interface_name, __cmd__rename_location, __cmd__reorder_locations,
+    __cmd__reset_location_stats, __cmd__reset_peak, __cmd__reset_settings,
+    __cmd__resume_connections, __cmd__save_device_config, __cmd__save_tunnel,
+    __cmd__service_status, __cmd__set_endpoint_ip_override, __cmd__set_location_favorite,
+    __cmd__set_preferred_relay, __cmd__settings_change_impact, __cmd__start_issue_capture,
+    __cmd__stop_issue_capture, __cmd__switch_data_profile, __cmd__test_location,
+    __cmd__total_transfer, __cmd__tunnel_details, __cmd__tunnel_public_ip,
+    __cmd__unarchive_instance, __cmd__update_instance, __cmd__update_location_allowed_ips,
+    __cmd__update_location_autoconnect, __cmd__update_location_dns,
+    __cmd__update_location_dns_search, __cmd__update_location_encrypted_dns,
+    __cmd__update_location_endpoints, __cmd__update_location_failover,
+    __cmd__update_location_force_tunnel_dns, __cmd__update_location_kill_switch,
+    __cmd__update_location_metadata, __cmd__update_location_mtu,
+    __cmd__update_location_preshared_key, __cmd__update_location_routes,
+    __cmd__update_location_routing, __cmd__update_location_schedule, __cmd__update_settings,
+    __cmd__validate_keys,
     appstate::AppState,
+    autostart,
     commands::{
-        active_connection, all_connections, all_instances, all_locations, all_tunnels, connect,
-        delete_instance, delete_tunnel, disconnect, get_latest_app_version, get_settings,
-        last_connection, location_interface_details, location_stats, open_link,
-        parse_tunnel_config, save_device_config, save_tunnel, tunnel_details, update_instance,
-        update_location_routing, update_settings,
+        active_connection, active_connection_count, add_key_profile, all_connections,
+        all_instances, all_instances_including_archived, all_locations, all_tunnels,
+        archive_instance, cancel_connect, check_address_conflict, check_instance_updates,
+        check_ipv6_leak, check_routing_conflicts, check_wireguard_driver, collect_diagnostics,
+        connect, connect_instance, connect_with_profile, connection_health, connection_state,
+        create_data_profile, delete_data_profile, delete_instance, delete_key_profile,
+        delete_tunnel, disconnect, disconnect_by_interface, export_configuration,
+        export_location_config, export_stats_csv, get_effective_dns, get_interface_logs,
+        get_latest_app_version, get_settings, import_configuration, import_location_config,
+        instance_stats, last_connection, list_data_profiles, list_key_profiles,
+        list_service_interfaces, location_config_qr, location_interface_details,
+        location_lifetime_transfer, location_peak_throughput, location_routes, location_stats,
+        location_stats_with_gaps, monthly_usage, open_link, parse_tunnel_config,
+        pause_all_connections, power_impact, prune_stats, recent_connections, reconnect,
+        regenerate_interface_name, rename_location, reorder_locations, reset_location_stats,
+        reset_peak, reset_settings, resume_connections, save_device_config, save_tunnel,
+        service_status, set_endpoint_ip_override, set_location_favorite, set_preferred_relay,
+        settings_change_impact, start_issue_capture, stop_issue_capture, switch_data_profile,
+        test_location, total_transfer, tunnel_details, tunnel_public_ip, unarchive_instance,
+        update_instance, update_location_allowed_ips, update_location_autoconnect,
+        update_location_dns, update_location_dns_search, update_location_encrypted_dns,
+        update_location_endpoints, update_location_failover, update_location_force_tunnel_dns,
+        update_location_kill_switch, update_location_metadata, update_location_mtu,
+        update_location_preshared_key, update_location_routes, update_location_routing,
+        update_location_schedule, update_settings, validate_keys,
     },
-    database::{self, models::settings::Settings},
+    connection_state::connection_watchdog_loop,
+    database::{
+        self, models::settings::Settings, stats_buffer::flush_stats_loop,
+        stats_retention::prune_stats_loop,
+    },
+    failover::failover_loop,
+    issue_capture::{emit_event, CaptureEntryKind},
     latest_app_version::fetch_latest_app_version_loop,
-    tray::{configure_tray_icon, create_tray_menu, handle_tray_event},
-    utils::load_log_targets,
+    schedule::schedule_loop,
+    tray::{
+        create_tray_menu, handle_tray_event, reload_tray_menu, tray_icon_loop, update_tray_icon,
+    },
+    utils::{
+        autoconnect_locations, load_log_targets, push_fallback_dns, restore_active_connections,
+    },
 };
 use std::{env, str::FromStr};
 
@@ -77,31 +136,117 @@ async fn main() {
         LevelFilter::from_str(&env::var("DEFGUARD_CLIENT_LOG_LEVEL").unwrap_or("info".into()))
             .unwrap_or(LevelFilter::Info);
 
+    let command_handler = tauri::generate_handler![
+        all_locations,
+        save_device_config,
+        all_instances,
+        connect,
+        disconnect,
+        update_instance,
+        location_stats,
+        location_interface_details,
+        all_connections,
+        last_connection,
+        active_connection,
+        update_location_routing,
+        get_settings,
+        update_settings,
+        settings_change_impact,
+        delete_instance,
+        parse_tunnel_config,
+        save_tunnel,
+        all_tunnels,
+        open_link,
+        tunnel_details,
+        delete_tunnel,
+        get_latest_app_version,
+        start_issue_capture,
+        stop_issue_capture,
+        check_ipv6_leak,
+        update_location_encrypted_dns,
+        active_connection_count,
+        update_location_metadata,
+        check_address_conflict,
+        set_endpoint_ip_override,
+        power_impact,
+        location_stats_with_gaps,
+        list_data_profiles,
+        create_data_profile,
+        delete_data_profile,
+        switch_data_profile,
+        check_wireguard_driver,
+        monthly_usage,
+        connection_state,
+        reconnect,
+        connect_instance,
+        update_location_kill_switch,
+        total_transfer,
+        update_location_dns,
+        export_location_config,
+        test_location,
+        all_instances_including_archived,
+        archive_instance,
+        unarchive_instance,
+        prune_stats,
+        update_location_mtu,
+        update_location_autoconnect,
+        service_status,
+        update_location_dns_search,
+        update_location_allowed_ips,
+        set_location_favorite,
+        reorder_locations,
+        list_service_interfaces,
+        update_location_preshared_key,
+        import_location_config,
+        rename_location,
+        regenerate_interface_name,
+        export_configuration,
+        import_configuration,
+        instance_stats,
+        get_interface_logs,
+        collect_diagnostics,
+        get_effective_dns,
+        add_key_profile,
+        list_key_profiles,
+        delete_key_profile,
+        connect_with_profile,
+        check_instance_updates,
+        check_routing_conflicts,
+        connection_health,
+        update_location_routes,
+        pause_all_connections,
+        resume_connections,
+        location_peak_throughput,
+        reset_peak,
+        location_config_qr,
+        recent_connections,
+        update_location_failover,
+        validate_keys,
+        update_location_schedule,
+        disconnect_by_interface,
+        location_lifetime_transfer,
+        update_location_force_tunnel_dns,
+        export_stats_csv,
+        reset_location_stats,
+        update_location_endpoints,
+        tunnel_public_ip,
+        set_preferred_relay,
+        cancel_connect,
+        location_routes,
+        reset_settings,
+    ];
+
     let app = tauri::Builder::default()
-        .invoke_handler(tauri::generate_handler![
-            all_locations,
-            save_device_config,
-            all_instances,
-            connect,
-            disconnect,
-            update_instance,
-            location_stats,
-            location_interface_details,
-            all_connections,
-            last_connection,
-            active_connection,
-            update_location_routing,
-            get_settings,
-            update_settings,
-            delete_instance,
-            parse_tunnel_config,
-            save_tunnel,
-            all_tunnels,
-            open_link,
-            tunnel_details,
-            delete_tunnel,
-            get_latest_app_version,
-        ])
+        .invoke_handler(move |invoke| {
+            // every command is recorded while issue capture is in progress
+            let app_handle = invoke.message.window().app_handle();
+            app_handle.state::<AppState>().record_capture(
+                CaptureEntryKind::Command,
+                invoke.message.command(),
+                invoke.message.payload(),
+            );
+            command_handler(invoke);
+        })
         .on_window_event(|event| match event.event() {
             tauri::WindowEvent::CloseRequested { api, .. } => {
                 event.window().hide().unwrap();
@@ -112,8 +257,7 @@ async fn main() {
         .system_tray(system_tray)
         .on_system_tray_event(handle_tray_event)
         .plugin(tauri_plugin_single_instance::init(|app, argv, cwd| {
-            app.emit_all("single-instance", Payload { args: argv, cwd })
-                .unwrap();
+            emit_event(app, "single-instance", Payload { args: argv, cwd }).unwrap();
         }))
         .plugin(
             tauri_plugin_log::Builder::default()
@@ -152,10 +296,53 @@ async fn main() {
     let result = database::info(&app_state.get_pool()).await;
     info!("Database info result: {:#?}", result);
     // configure tray
+    if let Err(err) = update_tray_icon(&app_handle).await {
+        error!("Failed to configure tray icon: {err}");
+    }
     if let Ok(settings) = Settings::get(&app_state.get_pool()).await {
-        configure_tray_icon(&app_handle, &settings.tray_icon_theme).unwrap();
+        if let Some(service_url) = &settings.service_url {
+            if let Err(err) = app_state.set_service_url(service_url) {
+                error!("Invalid defguard-service URL {service_url}, using default: {err}");
+            }
+        }
+        // service doesn't keep fallback DNS across restarts
+        push_fallback_dns(settings.fallback_dns.as_deref()).await;
+        // launch at login may have been changed in system settings
+        match autostart::is_enabled() {
+            Ok(enabled) if enabled != settings.launch_on_startup => {
+                info!("Launch at login was changed outside of the app, updating setting");
+                let mut settings = settings;
+                settings.launch_on_startup = enabled;
+                if let Err(err) = settings.save(&app_state.get_pool()).await {
+                    error!("Failed to save launch at login setting: {err}");
+                }
+            }
+            Ok(_) => {}
+            Err(err) => error!("Failed to read launch at login registration: {err}"),
+        }
+    }
+
+    // reconcile connections with interfaces left by previous run
+    if let Err(err) = restore_active_connections(&app_handle).await {
+        error!("Failed to restore active connections: {err}");
     }
+    // list instance locations in tray menu
+    if let Err(err) = reload_tray_menu(&app_handle).await {
+        error!("Failed to load tray menu: {err}");
+    }
+
+    // verify WireGuard backend is installed
+    let _ = check_wireguard_driver(app_handle.clone()).await;
+
+    // connect locations marked for autoconnect without blocking startup
+    tauri::async_runtime::spawn(autoconnect_locations(app_handle.clone()));
 
+    tauri::async_runtime::spawn(flush_stats_loop(app_handle.clone()));
+    tauri::async_runtime::spawn(prune_stats_loop(app_handle.clone()));
+    tauri::async_runtime::spawn(connection_watchdog_loop(app_handle.clone()));
+    tauri::async_runtime::spawn(failover_loop(app_handle.clone()));
+    tauri::async_runtime::spawn(schedule_loop(app_handle.clone()));
+    tauri::async_runtime::spawn(tray_icon_loop(app_handle.clone()));
     tauri::async_runtime::spawn(
         async move { fetch_latest_app_version_loop(app_handle.clone()).await },
     );
@@ -173,7 +360,8 @@ async fn main() {
             let app_state: State<AppState> = app_handle.state();
             tokio::task::block_in_place(|| {
                 tokio::runtime::Handle::current().block_on(async {
-                    let _ = app_state.close_all_connections().await;
+                    app_state.shutdown_connections().await;
+                    let _ = app_state.stats_buffer.flush(&app_state.get_pool()).await;
                     app_handle.exit(0);
                 });
             });
@@ -1,24 +1,80 @@
+#[cfg(not(target_os = "macos"))]
+use crate::utils::location_interface_name;
 use crate::{
-    appstate::AppState,
+    appstate::{AppState, DEFAULT_LOG_WATCHER_LEVEL},
+    autostart,
+    config_drift::{
+        fetch_device_config, instance_changes, location_drift, InstanceUpdatePreview, LocationDrift,
+    },
+    connection_health::{location_health, LocationHealth},
+    connection_state::{ConnectionState, ConnectionStateInfo},
     database::{
-        models::{instance::InstanceInfo, settings::SettingsPatch},
-        ActiveConnection, Connection, ConnectionInfo, Instance, Location, LocationStats, Settings,
-        Tunnel, TunnelConnection, TunnelConnectionInfo, TunnelStats, WireguardKeys,
+        backup::{self, ImportSummary},
+        models::{
+            instance::InstanceInfo,
+            location::MonthlyUsage,
+            settings::{
+                normalize_text_setting, SettingsPatch, DEFAULT_PUBLIC_IP_ECHO_URL,
+                MIN_STATS_INTERVAL_SECONDS,
+            },
+        },
+        open_db,
+        profiles::{
+            delete_profile, list_profiles, profile_dir, profile_exists, set_active_profile,
+            validate_profile_name, DataProfile,
+        },
+        stats_retention::prune_location_stats,
+        ActiveConnection, Connection, ConnectionInfo, DbPool, DisconnectReason, Instance,
+        LifetimeTransfer, Location, LocationStats, PausedConnection, PeakThroughput, PresharedKey,
+        RecentConnectionInfo, Settings, Tunnel, TunnelConnection, TunnelConnectionInfo,
+        TunnelStats, WireguardKeys,
     },
+    diagnostics,
     error::Error,
+    handshake_probe::{probe_handshake, HANDSHAKE_PROBE_TIMEOUT},
+    issue_capture::{emit_event, IssueCapture},
+    power_impact::{estimate_power_impact, PowerImpact},
     proto::{DeviceConfig, DeviceConfigResponse},
-    service::{log_watcher::stop_log_watcher_task, proto::RemoveInterfaceRequest},
-    tray::configure_tray_icon,
+    proxy::{http_client, validate_proxy_url},
+    schedule::{parse_schedule, verify_schedule},
+    service::{
+        config::DEFAULT_STATS_PERIOD,
+        log_watcher::{spawn_log_watcher_task, stop_log_watcher_task, LogFormat},
+        proto::RemoveInterfaceRequest,
+        status::{
+            list_interface_routes, list_managed_interfaces,
+            proto::{InterfaceRoute, ManagedInterface},
+            query_service_status, ServiceStatus,
+        },
+        utils::ServiceAddress,
+        DAEMON_BASE_URL,
+    },
+    tray::{refresh_tray, update_tray_icon},
     utils::{
-        disconnect_interface, get_location_interface_details, get_tunnel_interface_details,
-        handle_connection_for_location, handle_connection_for_tunnel,
+        active_full_tunnel_locations, check_location_ipv6_leak, check_wireguard_backend,
+        disconnect_interface, encrypted_dns_commands, fetch_public_ip, find_address_conflicts,
+        get_location_interface_details, get_tunnel_interface_details,
+        handle_connection_for_location, handle_connection_for_tunnel, kill_switch_commands,
+        location_endpoint, location_endpoints, normalize_instance_url,
+        normalize_stored_instance_url, parse_allowed_ips, parse_dns_search, parse_dns_servers,
+        parse_endpoints, parse_extra_routes, push_fallback_dns, resolve_interface_name,
+        served_by_relay, setup_location_connection, split_dns_entries, system_dns_servers,
+        tunnel_dns_commands, validate_echo_url, validate_interface_name_template, validate_mtu,
+        AddressConflict, ResolvedInterfaceName, RoutingConflictPayload,
+    },
+    wg_config::{
+        derive_pubkey, location_to_wireguard_config, parse_location_config, parse_wireguard_config,
+        LocationConfig,
     },
-    wg_config::parse_wireguard_config,
     CommonConnection, CommonConnectionInfo, CommonLocationStats, ConnectionType,
 };
 use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use defguard_wireguard_rs::{key::Key, net::IpAddrMask};
+use local_ip_address::local_ip;
+use qrcode::{render::svg, QrCode};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, env, str::FromStr};
+use sqlx::SqliteConnection;
+use std::{collections::HashMap, env, fmt::Write, net::IpAddr, str::FromStr};
 use struct_patch::Patch;
 use tauri::{AppHandle, Manager, State};
 
@@ -33,12 +89,113 @@ pub async fn connect(
     location_id: i64,
     connection_type: ConnectionType,
     preshared_key: Option<String>,
+    ignore_address_conflict: Option<bool>,
+    force: Option<bool>,
+    handle: AppHandle,
+) -> Result<(), Error> {
+    let state = handle.state::<AppState>();
+    state.set_connection_state(location_id, &connection_type, ConnectionState::Connecting);
+    let result = establish_connection(
+        location_id,
+        &connection_type,
+        preshared_key,
+        ignore_address_conflict,
+        force,
+        handle.clone(),
+    )
+    .await;
+    let connection_state = match &result {
+        Ok(()) => ConnectionState::Connected,
+        Err(Error::Cancelled) => ConnectionState::Idle,
+        Err(err) => ConnectionState::Error(err.to_string()),
+    };
+    state.set_connection_state(location_id, &connection_type, connection_state);
+    result
+}
+
+/// Cancels connection attempt of location which is still in progress. Interface created
+/// by the attempt is removed and `connect` fails with `CANCELLED` error. Attempt made
+/// with key profile is cancelled when `profile_id` is given.
+/// Returns `false` if location wasn't being connected.
+#[tauri::command(async)]
+pub async fn cancel_connect(
+    location_id: i64,
+    profile_id: Option<i64>,
+    handle: AppHandle,
+) -> Result<bool, Error> {
+    let state = handle.state::<AppState>();
+    let cancelled = state.cancel_connect(location_id, profile_id);
+    if cancelled {
+        info!("Cancelling connection attempt of location {location_id}, profile: {profile_id:?}");
+    } else {
+        debug!("Location {location_id} isn't being connected with profile {profile_id:?}, nothing to cancel");
+    }
+    Ok(cancelled)
+}
+
+/// Fails if location address conflicts with local networks, unless conflict is ignored.
+fn verify_address_conflicts(
+    location: &Location,
+    ignore_address_conflict: Option<bool>,
+    handle: &AppHandle,
+) -> Result<(), Error> {
+    let conflicts = find_address_conflicts(location)?;
+    if !conflicts.is_empty() {
+        let details = conflicts
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        if !ignore_address_conflict.unwrap_or_default() {
+            error!("Location {location} address conflicts with local network: {details}");
+            return Err(Error::AddressConflict(details));
+        }
+        warn!("Connecting location {location} despite address conflict: {details}");
+        emit_event(&handle, "address-conflict", &conflicts)?;
+    }
+    Ok(())
+}
+
+/// Fails if location belongs to archived instance.
+async fn verify_instance_active(location: &Location, pool: &DbPool) -> Result<(), Error> {
+    match Instance::find_by_id(pool, location.instance_id).await? {
+        Some(instance) if instance.archived_at.is_some() => {
+            warn!(
+                "Location {location} belongs to archived instance {}",
+                instance.name
+            );
+            Err(Error::CommandError(format!(
+                "Instance {} is archived",
+                instance.name
+            )))
+        }
+        Some(_) => Ok(()),
+        None => Err(Error::NotFound),
+    }
+}
+
+async fn establish_connection(
+    location_id: i64,
+    connection_type: &ConnectionType,
+    preshared_key: Option<String>,
+    ignore_address_conflict: Option<bool>,
+    force: Option<bool>,
     handle: AppHandle,
 ) -> Result<(), Error> {
     let state = handle.state::<AppState>();
     if connection_type.eq(&ConnectionType::Location) {
         if let Some(location) = Location::find_by_id(&state.get_pool(), location_id).await? {
-            handle_connection_for_location(&location, preshared_key, handle).await?
+            verify_instance_active(&location, &state.get_pool()).await?;
+            if force.unwrap_or_default() {
+                info!("Connecting location {location} regardless of its schedule");
+            } else {
+                verify_schedule(&location)?;
+            }
+            verify_address_conflicts(&location, ignore_address_conflict, &handle)?;
+            handle_connection_for_location(&location, preshared_key, None, handle.clone()).await?;
+            if force.unwrap_or_default() {
+                state.mark_forced(location_id);
+            }
         } else {
             error!("Location {location_id} not found");
             return Err(Error::NotFound);
@@ -52,34 +209,461 @@ pub async fn connect(
     Ok(())
 }
 
+/// Connect all locations of given instance. Failure of a single location doesn't stop
+/// connecting the remaining ones, result of each location is returned instead.
+#[tauri::command(async)]
+pub async fn connect_instance(
+    instance_id: i64,
+    handle: AppHandle,
+) -> Result<Vec<(i64, Result<(), String>)>, Error> {
+    debug!("Connecting all locations of instance {instance_id}");
+    let state = handle.state::<AppState>();
+    match Instance::find_by_id(&state.get_pool(), instance_id).await? {
+        Some(instance) if instance.archived_at.is_some() => {
+            warn!(
+                "Instance {} is archived, not connecting its locations",
+                instance.name
+            );
+            return Err(Error::CommandError(format!(
+                "Instance {} is archived",
+                instance.name
+            )));
+        }
+        Some(_) => {}
+        None => return Err(Error::NotFound),
+    }
+    let locations = Location::find_by_instance_id(&state.get_pool(), instance_id).await?;
+    let active_locations = state.get_connection_keys_by_type(&ConnectionType::Location);
+    let mut results = Vec::new();
+    for location in locations {
+        let location_id = location.id.expect("Missing Location ID");
+        // connections with key profiles don't replace connection with the default key pair
+        if active_locations.contains(&(location_id, None)) {
+            debug!("Location {location} already connected, skipping");
+            continue;
+        }
+        state.set_connection_state(
+            location_id,
+            &ConnectionType::Location,
+            ConnectionState::Connecting,
+        );
+        let result = if location.mfa_enabled {
+            // preshared key is obtained through MFA separately for each location
+            Err(Error::CommandError("Location requires MFA".into()))
+        } else {
+            match verify_schedule(&location)
+                .and_then(|()| verify_address_conflicts(&location, None, &handle))
+            {
+                Ok(()) => setup_location_connection(&location, None, None, handle.clone()).await,
+                Err(err) => Err(err),
+            }
+        };
+        let connection_state = match &result {
+            Ok(()) => {
+                info!("Location {location} connected");
+                ConnectionState::Connected
+            }
+            Err(err) => {
+                error!("Failed to connect location {location}: {err}");
+                ConnectionState::Error(err.to_string())
+            }
+        };
+        state.set_connection_state(location_id, &ConnectionType::Location, connection_state);
+        results.push((location_id, result.map_err(|err| err.to_string())));
+    }
+    let payload = Payload {
+        message: "Created new connections".into(),
+    };
+    emit_event(&handle, "connection-changed", payload)?;
+    refresh_tray(&handle);
+    Ok(results)
+}
+
+/// Disconnects all active connections and remembers them, so exactly the same connections
+/// can be restored with `resume_connections`, also after restart. Returns number of paused
+/// connections.
+#[tauri::command(async)]
+pub async fn pause_all_connections(handle: AppHandle) -> Result<usize, Error> {
+    let state = handle.state::<AppState>();
+    let pool = state.get_pool();
+    let connections = state.get_connections();
+    info!("Pausing {} active connections", connections.len());
+    // connections are remembered first, so failure leaves all of them connected and tracked
+    let mut transaction = pool.begin().await?;
+    for connection in &connections {
+        PausedConnection::new(
+            connection.location_id,
+            connection.connection_type.clone(),
+            connection.profile_id,
+        )
+        .save(&mut *transaction)
+        .await?;
+    }
+    transaction.commit().await?;
+    let mut paused = 0;
+    for connection in connections {
+        // connection may have been disconnected in the meantime
+        for connection in state.remove_connections_by_interface(&connection.interface_name) {
+            let (location_id, connection_type, profile_id) = (
+                connection.location_id,
+                connection.connection_type.clone(),
+                connection.profile_id,
+            );
+            let interface_name = connection.interface_name.clone();
+            if let Err(err) =
+                disconnect_interface(connection, &state, DisconnectReason::Paused).await
+            {
+                error!("Failed to pause connection of interface {interface_name}: {err}");
+            }
+            if profile_id.is_none() {
+                state.set_connection_state(location_id, &connection_type, ConnectionState::Idle);
+            }
+            stop_log_watcher_task(handle.clone(), interface_name)?;
+            paused += 1;
+        }
+    }
+    let payload = Payload {
+        message: "Connections paused".into(),
+    };
+    emit_event(&handle, "connection-changed", payload)?;
+    refresh_tray(&handle);
+    Ok(paused)
+}
+
+/// Reconnects connections closed by `pause_all_connections`. Failure of a single connection
+/// doesn't stop resuming the remaining ones, result of each location is returned instead.
+#[tauri::command(async)]
+pub async fn resume_connections(
+    handle: AppHandle,
+) -> Result<Vec<(i64, Result<(), String>)>, Error> {
+    let state = handle.state::<AppState>();
+    let pool = state.get_pool();
+    let paused = PausedConnection::all(&pool).await?;
+    info!("Resuming {} paused connections", paused.len());
+    let mut results = Vec::new();
+    for connection in paused {
+        let location_id = connection.location_id;
+        let requires_mfa = match connection.connection_type {
+            ConnectionType::Location => Location::find_by_id(&pool, location_id)
+                .await?
+                .is_some_and(|location| location.mfa_enabled),
+            ConnectionType::Tunnel => false,
+        };
+        let result = if requires_mfa {
+            // preshared key is obtained through MFA separately for each connection
+            Err(Error::CommandError("Location requires MFA".into()))
+        } else {
+            match connection.profile_id {
+                Some(profile_id) => {
+                    connect_with_profile(location_id, profile_id, None, None, handle.clone()).await
+                }
+                None => {
+                    connect(
+                        location_id,
+                        connection.connection_type,
+                        None,
+                        None,
+                        None,
+                        handle.clone(),
+                    )
+                    .await
+                }
+            }
+        };
+        if let Err(err) = &result {
+            error!("Failed to resume connection of location {location_id}: {err}");
+        }
+        results.push((location_id, result.map_err(|err| err.to_string())));
+    }
+    PausedConnection::delete_all(&pool).await?;
+    let payload = Payload {
+        message: "Connections resumed".into(),
+    };
+    emit_event(&handle, "connection-changed", payload)?;
+    refresh_tray(&handle);
+    Ok(results)
+}
+
+/// Disconnects location or tunnel. Location connected with key profile is disconnected
+/// when `profile_id` is given.
 #[tauri::command]
 pub async fn disconnect(
     location_id: i64,
     connection_type: ConnectionType,
+    profile_id: Option<i64>,
     handle: AppHandle,
 ) -> Result<(), Error> {
     debug!("Disconnecting location {}", location_id);
     let state = handle.state::<AppState>();
-    if let Some(connection) = state.find_and_remove_connection(location_id, &connection_type) {
+    let connection = match profile_id {
+        Some(_) => state.find_and_remove_profile_connection(location_id, profile_id),
+        None => state.find_and_remove_connection(location_id, &connection_type),
+    };
+    if let Some(connection) = connection {
         let interface_name = connection.interface_name.clone();
         debug!("Found active connection");
         trace!("Connection: {:#?}", connection);
-        disconnect_interface(connection, &state).await?;
+        // connection is already removed from active connections and saved with service error
+        // reason if interface removal fails, so app state is updated regardless of the result
+        let result =
+            disconnect_interface(connection, &state, DisconnectReason::UserInitiated).await;
+        // connection state is tracked for connections with the default key pair only
+        if profile_id.is_none() {
+            state.set_connection_state(location_id, &connection_type, ConnectionState::Idle);
+        }
         debug!("Connection saved");
-        handle.emit_all(
-            "connection-changed",
-            Payload {
-                message: "Created new connection".into(),
-            },
-        )?;
+        let payload = Payload {
+            message: "Created new connection".into(),
+        };
+        emit_event(&handle, "connection-changed", payload)?;
+        refresh_tray(&handle);
         stop_log_watcher_task(handle, interface_name)?;
-        Ok(())
+        result
     } else {
         error!("Connection for location with id: {location_id} not found");
         Err(Error::NotFound)
     }
 }
 
+// handshakes newer than this (in seconds) mean the tunnel is healthy
+const HANDSHAKE_FRESHNESS_SECONDS: i64 = 180;
+
+/// Re-create interface of connected location, e.g. after the service dropped it.
+/// Does nothing if the tunnel had a recent handshake, unless `force` is set.
+#[tauri::command(async)]
+pub async fn reconnect(
+    location_id: i64,
+    force: Option<bool>,
+    handle: AppHandle,
+) -> Result<(), Error> {
+    debug!("Reconnecting location {location_id}");
+    let state = handle.state::<AppState>();
+    let Some(location) = Location::find_by_id(&state.get_pool(), location_id).await? else {
+        error!("Location {location_id} not found");
+        return Err(Error::NotFound);
+    };
+    if state
+        .find_connection(location_id, ConnectionType::Location)
+        .is_none()
+    {
+        return Err(Error::NotFound);
+    }
+    if location.mfa_enabled {
+        error!(
+            "Location {location} requires MFA, it can't be reconnected without new preshared key"
+        );
+        return Err(Error::CommandError(
+            "Location with MFA enabled must be connected again".into(),
+        ));
+    }
+    // unforced reconnect only happens when tunnel stopped receiving handshakes
+    let reason = if force.unwrap_or_default() {
+        DisconnectReason::UserInitiated
+    } else {
+        DisconnectReason::NetworkFailure
+    };
+    if !force.unwrap_or_default() {
+        let last_handshake = LocationStats::latest_handshake(&state.get_pool(), location_id)
+            .await?
+            .unwrap_or_default();
+        if last_handshake > 0
+            && Utc::now().timestamp() - last_handshake < HANDSHAKE_FRESHNESS_SECONDS
+        {
+            info!("Location {location} tunnel is healthy, skipping reconnect");
+            return Ok(());
+        }
+    }
+
+    // clean up interface which might be half-open
+    if let Some(connection) =
+        state.find_and_remove_connection(location_id, &ConnectionType::Location)
+    {
+        let interface_name = connection.interface_name.clone();
+        if let Err(err) = disconnect_interface(connection, &state, reason).await {
+            warn!("Failed to remove interface {interface_name} before reconnecting: {err}");
+        }
+        let _ = stop_log_watcher_task(handle.clone(), interface_name);
+    }
+    state.set_connection_state(
+        location_id,
+        &ConnectionType::Location,
+        ConnectionState::Connecting,
+    );
+    let result = handle_connection_for_location(&location, None, None, handle.clone()).await;
+    let connection_state = match &result {
+        Ok(()) => ConnectionState::Connected,
+        Err(err) => ConnectionState::Error(err.to_string()),
+    };
+    state.set_connection_state(location_id, &ConnectionType::Location, connection_state);
+    if result.is_ok() {
+        info!("Location {location} reconnected");
+    }
+    result
+}
+
+/// Key profile of instance, private key is never sent to frontend.
+#[derive(Debug, Serialize)]
+pub struct KeyProfileInfo {
+    pub id: i64,
+    pub instance_id: i64,
+    pub name: String,
+    pub pubkey: String,
+    pub address: Option<String>,
+}
+
+impl From<WireguardKeys> for KeyProfileInfo {
+    fn from(keys: WireguardKeys) -> Self {
+        Self {
+            id: keys.id.expect("Missing key profile ID"),
+            instance_id: keys.instance_id,
+            name: keys.profile_name.unwrap_or_default(),
+            pubkey: keys.pubkey,
+            address: keys.address,
+        }
+    }
+}
+
+/// Adds named key pair to instance, so its locations can be connected with another
+/// device identity alongside the default one. `address` is interface address assigned
+/// to the device registered with this key, location address is used if not given.
+#[tauri::command(async)]
+pub async fn add_key_profile(
+    instance_id: i64,
+    name: String,
+    prvkey: String,
+    address: Option<String>,
+    app_state: State<'_, AppState>,
+) -> Result<KeyProfileInfo, Error> {
+    let pool = app_state.get_pool();
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Err(Error::CommandError(
+            "Key profile name must not be empty".into(),
+        ));
+    }
+    let Some(instance) = Instance::find_by_id(&pool, instance_id).await? else {
+        error!("Instance with id: {instance_id} not found.");
+        return Err(Error::NotFound);
+    };
+    if WireguardKeys::find_profiles_by_instance_id(&pool, instance_id)
+        .await?
+        .iter()
+        .any(|profile| profile.profile_name.as_deref() == Some(name.as_str()))
+    {
+        error!(
+            "Key profile {name} already exists in instance {}",
+            instance.name
+        );
+        return Err(Error::CommandError(format!(
+            "Key profile {name} already exists"
+        )));
+    }
+    let prvkey = prvkey.trim().to_string();
+    let pubkey = derive_pubkey(&prvkey).map_err(|err| {
+        error!("Invalid private key of key profile {name}: {err}");
+        Error::CommandError(format!("Invalid private key: {err}"))
+    })?;
+    let address = address
+        .map(|address| address.trim().to_string())
+        .filter(|address| !address.is_empty());
+    if let Some(address) = &address {
+        IpAddrMask::from_str(address)?;
+    }
+    let mut profile = WireguardKeys::new_profile(instance_id, name, pubkey, prvkey, address);
+    profile.save(&pool).await?;
+    info!(
+        "Added key profile {} to instance {}",
+        profile.profile_name.as_deref().unwrap_or_default(),
+        instance.name
+    );
+    Ok(profile.into())
+}
+
+#[tauri::command(async)]
+pub async fn list_key_profiles(
+    instance_id: i64,
+    app_state: State<'_, AppState>,
+) -> Result<Vec<KeyProfileInfo>, Error> {
+    Ok(
+        WireguardKeys::find_profiles_by_instance_id(&app_state.get_pool(), instance_id)
+            .await?
+            .into_iter()
+            .map(Into::into)
+            .collect(),
+    )
+}
+
+/// Removes key profile which isn't used by any active connection.
+#[tauri::command(async)]
+pub async fn delete_key_profile(
+    profile_id: i64,
+    app_state: State<'_, AppState>,
+) -> Result<(), Error> {
+    let pool = app_state.get_pool();
+    let Some(profile) = WireguardKeys::find_by_id(&pool, profile_id)
+        .await?
+        .filter(|keys| keys.profile_name.is_some())
+    else {
+        error!("Key profile with id: {profile_id} not found.");
+        return Err(Error::NotFound);
+    };
+    if app_state
+        .get_connections()
+        .iter()
+        .any(|connection| connection.profile_id == Some(profile_id))
+    {
+        return Err(Error::CommandError(
+            "Key profile is used by active connection, disconnect it first".into(),
+        ));
+    }
+    profile.delete(&pool).await?;
+    info!(
+        "Deleted key profile {}",
+        profile.profile_name.unwrap_or_default()
+    );
+    Ok(())
+}
+
+/// Connects location with key pair of given profile. Interface name includes the profile,
+/// so location can be connected with the default key pair and other profiles at the same time.
+#[tauri::command(async)]
+pub async fn connect_with_profile(
+    location_id: i64,
+    profile_id: i64,
+    preshared_key: Option<String>,
+    ignore_address_conflict: Option<bool>,
+    handle: AppHandle,
+) -> Result<(), Error> {
+    let state = handle.state::<AppState>();
+    let pool = state.get_pool();
+    let Some(location) = Location::find_by_id(&pool, location_id).await? else {
+        error!("Location {location_id} not found");
+        return Err(Error::NotFound);
+    };
+    let Some(profile) = WireguardKeys::find_by_id(&pool, profile_id)
+        .await?
+        .filter(|keys| keys.profile_name.is_some() && keys.instance_id == location.instance_id)
+    else {
+        error!("Key profile {profile_id} of location {location} instance not found");
+        return Err(Error::NotFound);
+    };
+    if state.get_connections().iter().any(|connection| {
+        connection.location_id == location_id && connection.profile_id == Some(profile_id)
+    }) {
+        return Err(Error::CommandError(
+            "Location is already connected with this key profile".into(),
+        ));
+    }
+    verify_instance_active(&location, &pool).await?;
+    verify_schedule(&location)?;
+    verify_address_conflicts(&location, ignore_address_conflict, &handle)?;
+    info!(
+        "Connecting location {location} with key profile {}",
+        profile.profile_name.unwrap_or_default()
+    );
+    handle_connection_for_location(&location, preshared_key, Some(profile_id), handle.clone()).await
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Device {
     pub id: i64,
@@ -91,6 +675,7 @@ pub struct Device {
 
 #[must_use]
 pub fn device_config_to_location(device_config: DeviceConfig, instance_id: i64) -> Location {
+    let (dns, dns_search) = split_dns_entries(device_config.dns.as_deref());
     Location {
         id: None,
         instance_id,
@@ -100,10 +685,31 @@ pub fn device_config_to_location(device_config: DeviceConfig, instance_id: i64)
         pubkey: device_config.pubkey,
         endpoint: device_config.endpoint,
         allowed_ips: device_config.allowed_ips,
-        dns: device_config.dns,
+        dns,
         route_all_traffic: false,
         mfa_enabled: device_config.mfa_enabled,
         keepalive_interval: device_config.keepalive_interval.into(),
+        doh_url: None,
+        dot_host: None,
+        metadata: None,
+        endpoint_ip_override: None,
+        kill_switch: false,
+        mtu: None,
+        autoconnect: false,
+        dns_search,
+        favorite: false,
+        sort_order: 0,
+        preshared_key: None,
+        display_name: None,
+        interface_name: None,
+        extra_routes: None,
+        failover_group: None,
+        failover_priority: 0,
+        schedule: None,
+        sticky_endpoint: None,
+        force_tunnel_dns: false,
+        endpoints: None,
+        imported: false,
     }
 }
 #[derive(Serialize, Deserialize, Debug)]
@@ -118,6 +724,15 @@ pub struct InstanceResponse {
 pub struct SaveDeviceConfigResponse {
     locations: Vec<Location>,
     instance: Instance,
+    // `false` if already enrolled instance was updated
+    created: bool,
+}
+
+#[derive(Clone, Serialize)]
+pub struct EnrollmentProgress {
+    pub step: String,
+    pub current: usize,
+    pub total: usize,
 }
 
 #[tauri::command(async)]
@@ -129,52 +744,119 @@ pub async fn save_device_config(
 ) -> Result<SaveDeviceConfigResponse, Error> {
     debug!("Received device configuration: {response:#?}");
 
-    let mut transaction = app_state.get_pool().begin().await?;
+    let pool = app_state.get_pool();
+    let allow_insecure = Settings::get(&pool).await?.allow_insecure;
     let instance_info = response
         .instance
         .expect("Missing instance info in device config response");
-    let mut instance: Instance = instance_info.into();
+    // enrolling already known instance again updates it instead of adding a duplicate
+    let existing = Instance::find_by_uuid(&pool, &instance_info.id).await?;
+    let created = existing.is_none();
+    let mut instance = match existing {
+        Some(mut instance) => {
+            info!(
+                "Instance {} is already enrolled, updating it",
+                instance.name
+            );
+            // new URLs are checked against stored ones, like when instance is updated
+            instance.url =
+                normalize_stored_instance_url(&instance_info.url, &instance.url, allow_insecure)?;
+            instance.proxy_url = normalize_stored_instance_url(
+                &instance_info.proxy_url,
+                &instance.proxy_url,
+                allow_insecure,
+            )?;
+            instance.name = instance_info.name;
+            instance.username = instance_info.username;
+            instance.archived_at = None;
+            instance
+        }
+        None => {
+            let mut instance: Instance = instance_info.into();
+            instance.url = normalize_instance_url(&instance.url, allow_insecure)?;
+            instance.proxy_url = normalize_instance_url(&instance.proxy_url, allow_insecure)?;
+            instance
+        }
+    };
+    let mut current_locations = match instance.id {
+        Some(instance_id) => Location::find_by_instance_id(&pool, instance_id).await?,
+        None => Vec::new(),
+    };
+    current_locations.retain(|location| !location.imported);
+
+    let existing_keys = match instance.id {
+        Some(instance_id) => WireguardKeys::find_by_instance_id(&pool, instance_id).await?,
+        None => None,
+    };
 
+    let mut transaction = pool.begin().await?;
     instance.save(&mut *transaction).await?;
+    let instance_id = instance.id.expect("Missing instance ID");
 
     let device = response
         .device
         .expect("Missing device info in device config response");
-    let mut keys = WireguardKeys::new(
-        instance.id.expect("Missing instance ID"),
-        device.pubkey,
-        private_key,
-    );
+    let mut keys = match existing_keys {
+        Some(mut keys) if keys.pubkey != device.pubkey => {
+            // gateways may still know only the old key, keep it until location accepts the new one
+            keys.rotate(device.pubkey, private_key);
+            keys
+        }
+        Some(keys) => keys,
+        None => WireguardKeys::new(instance_id, device.pubkey, private_key),
+    };
     keys.save(&mut *transaction).await?;
-    for location in response.configs {
-        let mut new_location =
-            device_config_to_location(location, instance.id.expect("Missing instance ID"));
-        new_location.save(&mut *transaction).await?;
+    // progress is reported as each location is saved, failure rolls back all of them
+    let total = response.configs.len();
+    for (index, config) in response.configs.into_iter().enumerate() {
+        let location = save_synced_location(
+            &mut transaction,
+            &mut current_locations,
+            config,
+            instance_id,
+        )
+        .await?;
+        emit_event(
+            &handle,
+            "enrollment-progress",
+            EnrollmentProgress {
+                step: format!("Saved location {}", location.name),
+                current: index + 1,
+                total,
+            },
+        )?;
+    }
+    for removed_location in current_locations {
+        removed_location.delete(&mut *transaction).await?;
     }
     transaction.commit().await?;
-    info!("Instance created.");
-    trace!("Created following instance: {instance:#?}");
+    if created {
+        info!("Instance created.");
+    } else {
+        info!("Instance {} updated.", instance.name);
+    }
+    trace!("Saved following instance: {instance:#?}");
     let locations = Location::find_by_instance_id(
         &app_state.get_pool(),
         instance.id.expect("Missing instance ID"),
     )
     .await?;
-    trace!("Created following locations: {locations:#?}");
-    handle.emit_all("instance-update", ())?;
+    trace!("Saved following locations: {locations:#?}");
+    emit_event(&handle, "instance-update", ())?;
+    refresh_tray(&handle);
     let res: SaveDeviceConfigResponse = SaveDeviceConfigResponse {
         locations,
         instance,
+        created,
     };
     Ok(res)
 }
 
-#[tauri::command(async)]
-pub async fn all_instances(app_state: State<'_, AppState>) -> Result<Vec<InstanceInfo>, Error> {
-    debug!("Retrieving all instances.");
-
-    let instances = Instance::all(&app_state.get_pool()).await?;
-    debug!("Found ({}) instances", instances.len());
-    trace!("Instances found: {instances:#?}");
+/// Adds info about keys and active connections to instances.
+async fn instances_info(
+    app_state: &AppState,
+    instances: Vec<Instance>,
+) -> Result<Vec<InstanceInfo>, Error> {
     let mut instance_info: Vec<InstanceInfo> = vec![];
     let connection_ids: Vec<i64> = app_state.get_connection_id_by_type(&ConnectionType::Location);
     for instance in instances {
@@ -200,8 +882,21 @@ pub async fn all_instances(app_state: State<'_, AppState>) -> Result<Vec<Instanc
             proxy_url: instance.proxy_url,
             active: connected,
             pubkey: keys.pubkey,
+            archived_at: instance.archived_at,
+            preferred_relay: instance.preferred_relay,
         });
     }
+    Ok(instance_info)
+}
+
+#[tauri::command(async)]
+pub async fn all_instances(app_state: State<'_, AppState>) -> Result<Vec<InstanceInfo>, Error> {
+    debug!("Retrieving all instances.");
+
+    let instances = Instance::all(&app_state.get_pool()).await?;
+    debug!("Found ({}) instances", instances.len());
+    trace!("Instances found: {instances:#?}");
+    let instance_info = instances_info(&app_state, instances).await?;
     info!("Instances retrieved({})", instance_info.len());
     trace!("Returning following instances: {instance_info:#?}");
     Ok(instance_info)
@@ -220,6 +915,20 @@ pub struct LocationInfo {
     pub pubkey: String,
     pub mfa_enabled: bool,
     pub network_id: i64,
+    pub metadata: Option<String>,
+    pub endpoint_ip_override: Option<String>,
+    pub kill_switch: bool,
+    pub force_tunnel_dns: bool,
+    pub endpoints: Option<String>,
+    pub autoconnect: bool,
+    pub favorite: bool,
+    pub sort_order: i64,
+    pub failover_group: Option<String>,
+    pub failover_priority: i64,
+    pub schedule: Option<String>,
+    // the last connection failure, until location is connected successfully
+    pub last_error: Option<String>,
+    pub last_error_at: Option<NaiveDateTime>,
 }
 
 #[tauri::command(async)]
@@ -233,10 +942,14 @@ pub async fn all_locations(
         app_state.get_connection_id_by_type(&ConnectionType::Location);
     let mut location_info = vec![];
     for location in locations {
+        let last_error = app_state.last_error(
+            location.id.expect("Missing location ID"),
+            &ConnectionType::Location,
+        );
         let info = LocationInfo {
             id: location.id.expect("Missing location ID"),
             instance_id: location.instance_id,
-            name: location.name,
+            name: location.display_name.unwrap_or(location.name),
             address: location.address,
             endpoint: location.endpoint,
             active: active_locations_ids.contains(&location.id.expect("Missing location ID")),
@@ -245,6 +958,19 @@ pub async fn all_locations(
             pubkey: location.pubkey,
             mfa_enabled: location.mfa_enabled,
             network_id: location.network_id,
+            metadata: location.metadata,
+            endpoint_ip_override: location.endpoint_ip_override,
+            kill_switch: location.kill_switch,
+            force_tunnel_dns: location.force_tunnel_dns,
+            endpoints: location.endpoints,
+            autoconnect: location.autoconnect,
+            favorite: location.favorite,
+            sort_order: location.sort_order,
+            failover_group: location.failover_group,
+            failover_priority: location.failover_priority,
+            schedule: location.schedule,
+            last_error_at: last_error.as_ref().map(|error| error.at),
+            last_error: last_error.map(|error| error.message),
         };
         location_info.push(info);
     }
@@ -272,6 +998,10 @@ pub struct LocationInterfaceDetails {
     pub allowed_ips: String,
     pub persistent_keepalive_interval: Option<u16>,
     pub last_handshake: Option<i64>,
+    pub mtu: Option<u32>, // `None` if MTU is auto-detected
+    pub dns_search: Option<String>,
+    pub preshared_key: bool, // only tells if preshared key is configured
+    pub extra_routes: Option<String>,
 }
 
 #[tauri::command(async)]
@@ -282,63 +1012,214 @@ pub async fn location_interface_details(
 ) -> Result<LocationInterfaceDetails, Error> {
     let pool = app_state.get_pool();
     match connection_type {
-        ConnectionType::Location => get_location_interface_details(location_id, &pool).await,
+        ConnectionType::Location => {
+            // key pair used by active connection may differ from the current one
+            let active_pubkey = app_state
+                .find_connection(location_id, ConnectionType::Location)
+                .and_then(|connection| connection.pubkey);
+            get_location_interface_details(location_id, active_pubkey, &pool).await
+        }
         ConnectionType::Tunnel => get_tunnel_interface_details(location_id, &pool).await,
     }
 }
 
+/// Returns host interface addresses overlapping with network assigned to location.
 #[tauri::command(async)]
-pub async fn update_instance(
-    instance_id: i64,
-    response: DeviceConfigResponse,
+pub async fn check_address_conflict(
+    location_id: i64,
     app_state: State<'_, AppState>,
-    app_handle: AppHandle,
-) -> Result<(), Error> {
-    debug!("Received update_instance command");
-    trace!("Processing following response:\n {response:#?}");
-    let pool = app_state.get_pool();
-
-    if let Some(mut instance) = Instance::find_by_id(&pool, instance_id).await? {
-        // fetch existing locations for given instance
-        let mut current_locations = Location::find_by_instance_id(&pool, instance_id).await?;
+) -> Result<Vec<AddressConflict>, Error> {
+    debug!("Checking address conflicts for location {location_id}");
+    if let Some(location) = Location::find_by_id(&app_state.get_pool(), location_id).await? {
+        let conflicts = find_address_conflicts(&location)?;
+        info!(
+            "Found {} address conflicts for location {location}",
+            conflicts.len()
+        );
+        Ok(conflicts)
+    } else {
+        error!("Location {location_id} not found");
+        Err(Error::NotFound)
+    }
+}
 
-        let mut transaction = pool.begin().await?;
+/// Returns the highest throughput location has reached since the last reset,
+/// `None` if there was no traffic yet.
+#[tauri::command(async)]
+pub async fn location_peak_throughput(
+    location_id: i64,
+    app_state: State<'_, AppState>,
+) -> Result<Option<PeakThroughput>, Error> {
+    debug!("Retrieving peak throughput of location {location_id}");
+    PeakThroughput::find_by_location_id(&app_state.get_pool(), location_id).await
+}
 
-        // update instance
-        let instance_info = response
-            .instance
-            .expect("Missing instance info in device config response");
-        instance.name = instance_info.name;
-        instance.url = instance_info.url;
-        instance.proxy_url = instance_info.proxy_url;
-        instance.username = instance_info.username;
-        instance.save(&mut *transaction).await?;
+/// Clears peak throughput of location. Peaks of connected location are raised again
+/// by following traffic, as its stats thread forgets peaks of current connection as well.
+#[tauri::command(async)]
+pub async fn reset_peak(location_id: i64, app_state: State<'_, AppState>) -> Result<(), Error> {
+    app_state.reset_peak_generation(location_id);
+    PeakThroughput::delete_by_location_id(&app_state.get_pool(), location_id).await?;
+    info!("Reset peak throughput of location {location_id}");
+    Ok(())
+}
 
-        // process locations received in response
-        for location in response.configs {
-            // parse device config
-            let mut new_location = device_config_to_location(location, instance_id);
+/// Returns total bytes transferred through location over all of its connections,
+/// `None` if there was no traffic yet.
+#[tauri::command(async)]
+pub async fn location_lifetime_transfer(
+    location_id: i64,
+    app_state: State<'_, AppState>,
+) -> Result<Option<LifetimeTransfer>, Error> {
+    debug!("Retrieving lifetime transfer of location {location_id}");
+    LifetimeTransfer::find_by_location_id(&app_state.get_pool(), location_id).await
+}
 
-            // check if location is already present in current locations
-            if let Some(position) = current_locations
-                .iter()
-                .position(|loc| loc.network_id == new_location.network_id)
-            {
-                // remove from list of existing locations
-                let mut current_location = current_locations.remove(position);
-                // update existing location
-                current_location.name = new_location.name;
-                current_location.address = new_location.address;
-                current_location.pubkey = new_location.pubkey;
-                current_location.endpoint = new_location.endpoint;
-                current_location.allowed_ips = new_location.allowed_ips;
-                current_location.mfa_enabled = new_location.mfa_enabled;
-                current_location.keepalive_interval = new_location.keepalive_interval;
-                current_location.save(&mut *transaction).await?;
-            } else {
-                // create new location
-                new_location.save(&mut *transaction).await?;
-            }
+/// Summarizes the latest stats of location into a single connection health state.
+#[tauri::command(async)]
+pub async fn connection_health(
+    location_id: i64,
+    app_state: State<'_, AppState>,
+) -> Result<LocationHealth, Error> {
+    debug!("Checking connection health of location {location_id}");
+    let pool = app_state.get_pool();
+    if Location::find_by_id(&pool, location_id).await?.is_none() {
+        error!("Location {location_id} not found");
+        return Err(Error::NotFound);
+    }
+    let connected = app_state
+        .get_connection_id_by_type(&ConnectionType::Location)
+        .contains(&location_id);
+    let health = location_health(&pool, location_id, connected).await?;
+    debug!(
+        "Connection health of location {location_id}: {:?}",
+        health.health
+    );
+    Ok(health)
+}
+
+/// Returns IDs of connected locations which all route all traffic and conflict over
+/// the default route, empty if there's at most one of them.
+#[tauri::command(async)]
+pub async fn check_routing_conflicts(app_state: State<'_, AppState>) -> Result<Vec<i64>, Error> {
+    debug!("Checking routing conflicts of active locations");
+    let full_tunnel = active_full_tunnel_locations(&app_state).await?;
+    if full_tunnel.len() > 1 {
+        warn!("Multiple connected locations route all traffic: {full_tunnel:?}");
+        Ok(full_tunnel)
+    } else {
+        Ok(Vec::new())
+    }
+}
+
+/// Checks if IPv6 traffic would bypass the tunnel for given location.
+#[tauri::command(async)]
+pub async fn check_ipv6_leak(
+    location_id: i64,
+    app_state: State<'_, AppState>,
+) -> Result<bool, Error> {
+    debug!("Checking IPv6 leak for location {location_id}");
+    if let Some(location) = Location::find_by_id(&app_state.get_pool(), location_id).await? {
+        let leak = check_location_ipv6_leak(&location);
+        info!("IPv6 leak check for location {location}: {leak}");
+        Ok(leak)
+    } else {
+        error!("Location {location_id} not found");
+        Err(Error::NotFound)
+    }
+}
+
+/// Saves location received from core. Location of the same network is taken out of
+/// `current_locations` and updated, otherwise a new location is created.
+async fn save_synced_location(
+    connection: &mut SqliteConnection,
+    current_locations: &mut Vec<Location>,
+    config: DeviceConfig,
+    instance_id: i64,
+) -> Result<Location, Error> {
+    let mut new_location = device_config_to_location(config, instance_id);
+    if let Some(position) = current_locations
+        .iter()
+        .position(|loc| loc.network_id == new_location.network_id)
+    {
+        let mut current_location = current_locations.remove(position);
+        current_location.name = new_location.name;
+        current_location.address = new_location.address;
+        current_location.pubkey = new_location.pubkey;
+        current_location.endpoint = new_location.endpoint;
+        current_location.allowed_ips = new_location.allowed_ips;
+        current_location.mfa_enabled = new_location.mfa_enabled;
+        current_location.keepalive_interval = new_location.keepalive_interval;
+        current_location.save(connection).await?;
+        Ok(current_location)
+    } else {
+        new_location.save(connection).await?;
+        Ok(new_location)
+    }
+}
+
+/// Updates instance and its locations with device config received from defguard server.
+/// With `dry_run` the update is rolled back and changes it would make are returned instead.
+#[tauri::command(async)]
+pub async fn update_instance(
+    instance_id: i64,
+    response: DeviceConfigResponse,
+    dry_run: Option<bool>,
+    app_state: State<'_, AppState>,
+    app_handle: AppHandle,
+) -> Result<Option<InstanceUpdatePreview>, Error> {
+    let dry_run = dry_run.unwrap_or_default();
+    debug!("Received update_instance command, dry run: {dry_run}");
+    trace!("Processing following response:\n {response:#?}");
+    let pool = app_state.get_pool();
+
+    if let Some(mut instance) = Instance::find_by_id(&pool, instance_id).await? {
+        let allow_insecure = Settings::get(&pool).await?.allow_insecure;
+        // fetch existing locations for given instance, imported ones aren't synced with core
+        let mut current_locations = Location::find_by_instance_id(&pool, instance_id).await?;
+        current_locations.retain(|location| !location.imported);
+
+        // compared before anything is changed, the same way as when checking for updates
+        let preview_locations = if dry_run {
+            Some(location_drift(
+                Location::find_by_instance_id(&pool, instance_id).await?,
+                response.configs.clone(),
+                instance_id,
+            ))
+        } else {
+            None
+        };
+        let current_instance = instance.clone();
+
+        let mut transaction = pool.begin().await?;
+
+        // update instance
+        let instance_info = response
+            .instance
+            .expect("Missing instance info in device config response");
+        instance.name = instance_info.name;
+        instance.url = normalize_stored_instance_url(
+            &instance_info.url,
+            &current_instance.url,
+            allow_insecure,
+        )?;
+        instance.proxy_url = normalize_stored_instance_url(
+            &instance_info.proxy_url,
+            &current_instance.proxy_url,
+            allow_insecure,
+        )?;
+        instance.username = instance_info.username;
+        instance.save(&mut *transaction).await?;
+
+        // process locations received in response
+        for config in response.configs {
+            save_synced_location(
+                &mut transaction,
+                &mut current_locations,
+                config,
+                instance_id,
+            )
+            .await?;
         }
 
         // remove locations which were present in current locations
@@ -347,16 +1228,63 @@ pub async fn update_instance(
             removed_location.delete(&mut *transaction).await?;
         }
 
+        if let Some(locations) = preview_locations {
+            transaction.rollback().await?;
+            let preview = InstanceUpdatePreview {
+                instance_changes: instance_changes(&current_instance, &instance),
+                locations,
+            };
+            info!(
+                "Dry run of instance {instance_id} update: {} instance fields and {} locations would change",
+                preview.instance_changes.len(),
+                preview.locations.len()
+            );
+            return Ok(Some(preview));
+        }
+
         transaction.commit().await?;
 
         info!("Instance {instance_id} updated");
-        app_handle.emit_all("instance-update", ())?;
-        Ok(())
+        emit_event(&app_handle, "instance-update", ())?;
+        refresh_tray(&app_handle);
+        Ok(None)
     } else {
         Err(Error::NotFound)
     }
 }
 
+/// Compares locations of instance with their current config on defguard server without
+/// applying any changes. Enrollment token is required, like when updating instance.
+#[tauri::command(async)]
+pub async fn check_instance_updates(
+    instance_id: i64,
+    token: String,
+    app_state: State<'_, AppState>,
+) -> Result<Vec<LocationDrift>, Error> {
+    let pool = app_state.get_pool();
+    let Some(instance) = Instance::find_by_id(&pool, instance_id).await? else {
+        error!("Instance with id: {instance_id} not found.");
+        return Err(Error::NotFound);
+    };
+    let Some(keys) = WireguardKeys::find_by_instance_id(&pool, instance_id).await? else {
+        error!("No keys found for instance: {}", instance.name);
+        return Err(Error::NotFound);
+    };
+    debug!("Checking updates of instance {}", instance.name);
+    let response = fetch_device_config(&pool, &instance, &keys.pubkey, &token).await?;
+    let drift = location_drift(
+        Location::find_by_instance_id(&pool, instance_id).await?,
+        response.configs,
+        instance_id,
+    );
+    info!(
+        "Found {} changed locations of instance {}",
+        drift.len(),
+        instance.name
+    );
+    Ok(drift)
+}
+
 /// If `datetime` is Some, parses the date string, otherwise returns `DateTime` one hour ago.
 pub(crate) fn parse_timestamp(from: Option<String>) -> Result<DateTime<Utc>, Error> {
     Ok(match from {
@@ -365,6 +1293,9 @@ pub(crate) fn parse_timestamp(from: Option<String>) -> Result<DateTime<Utc>, Err
     })
 }
 
+// gap is reported when there are no stats for more than this many expected intervals
+const STATS_GAP_INTERVALS: i32 = 3;
+
 pub enum DateTimeAggregation {
     Hour,
     Second,
@@ -380,6 +1311,18 @@ impl DateTimeAggregation {
         }
         .into()
     }
+
+    /// Returns expected time between consecutive aggregated stats, given configured
+    /// stats interval. Service never sends stats more often than its stats period.
+    #[must_use]
+    pub fn expected_interval(&self, stats_interval: std::time::Duration) -> Duration {
+        match self {
+            Self::Hour => Duration::hours(1),
+            Self::Second => {
+                Duration::seconds(stats_interval.as_secs().max(DEFAULT_STATS_PERIOD) as i64)
+            }
+        }
+    }
 }
 
 fn get_aggregation(from: NaiveDateTime) -> Result<DateTimeAggregation, Error> {
@@ -392,6 +1335,32 @@ fn get_aggregation(from: NaiveDateTime) -> Result<DateTimeAggregation, Error> {
     Ok(aggregation)
 }
 
+async fn load_stats(
+    pool: &DbPool,
+    location_id: i64,
+    connection_type: ConnectionType,
+    from: &NaiveDateTime,
+    aggregation: &DateTimeAggregation,
+) -> Result<Vec<CommonLocationStats>, Error> {
+    let stats = match connection_type {
+        ConnectionType::Location => {
+            LocationStats::all_by_location_id(pool, location_id, from, aggregation)
+                .await?
+                .into_iter()
+                .map(Into::into)
+                .collect()
+        }
+        ConnectionType::Tunnel => {
+            TunnelStats::all_by_tunnel_id(pool, location_id, from, aggregation)
+                .await?
+                .into_iter()
+                .map(Into::into)
+                .collect()
+        }
+    };
+    Ok(stats)
+}
+
 #[tauri::command]
 pub async fn location_stats(
     location_id: i64,
@@ -402,231 +1371,2277 @@ pub async fn location_stats(
     trace!("Location stats command received");
     let from = parse_timestamp(from)?.naive_utc();
     let aggregation = get_aggregation(from)?;
-    let stats: Vec<CommonLocationStats> = match connection_type {
-        ConnectionType::Location => LocationStats::all_by_location_id(
+    load_stats(
+        &app_state.get_pool(),
+        location_id,
+        connection_type,
+        &from,
+        &aggregation,
+    )
+    .await
+}
+
+/// Stats of all locations of an instance keyed by location ID, so instance overview doesn't
+/// have to request stats of every location separately.
+#[tauri::command]
+pub async fn instance_stats(
+    instance_id: i64,
+    from: Option<String>,
+    app_state: State<'_, AppState>,
+) -> Result<HashMap<i64, Vec<CommonLocationStats>>, Error> {
+    trace!("Instance stats command received");
+    let from = parse_timestamp(from)?.naive_utc();
+    let aggregation = get_aggregation(from)?;
+    let mut stats: HashMap<i64, Vec<CommonLocationStats>> = HashMap::new();
+    for location_stats in
+        LocationStats::all_by_instance_id(&app_state.get_pool(), instance_id, &from, &aggregation)
+            .await?
+    {
+        stats
+            .entry(location_stats.location_id)
+            .or_default()
+            .push(location_stats.into());
+    }
+    debug!(
+        "Returning stats of {} locations of instance {instance_id}",
+        stats.len()
+    );
+    Ok(stats)
+}
+
+#[derive(Debug, Serialize)]
+pub struct TotalTransfer {
+    pub total_upload: i64,
+    pub total_download: i64,
+    pub active_locations: usize,
+}
+
+/// Returns data transferred since `from` summed across all connected locations.
+#[tauri::command]
+pub async fn total_transfer(
+    from: Option<String>,
+    app_state: State<'_, AppState>,
+) -> Result<TotalTransfer, Error> {
+    trace!("Total transfer command received");
+    let from = parse_timestamp(from)?.naive_utc();
+    let aggregation = get_aggregation(from)?;
+    let location_ids = app_state.get_connection_id_by_type(&ConnectionType::Location);
+    let mut total_upload = 0;
+    let mut total_download = 0;
+    for location_id in &location_ids {
+        let stats = load_stats(
             &app_state.get_pool(),
-            location_id,
+            *location_id,
+            ConnectionType::Location,
             &from,
             &aggregation,
         )
-        .await?
-        .into_iter()
-        .map(Into::into)
-        .collect(),
-        ConnectionType::Tunnel => {
-            TunnelStats::all_by_tunnel_id(&app_state.get_pool(), location_id, &from, &aggregation)
-                .await?
-                .into_iter()
-                .map(Into::into)
-                .collect()
+        .await?;
+        for stats in stats {
+            total_upload += stats.upload;
+            total_download += stats.download;
+        }
+    }
+    Ok(TotalTransfer {
+        total_upload,
+        total_download,
+        active_locations: location_ids.len(),
+    })
+}
+
+/// Period without stats, e.g. when the host was asleep
+#[derive(Debug, Serialize)]
+pub struct StatsGap {
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LocationStatsWithGaps {
+    pub stats: Vec<CommonLocationStats>,
+    pub gaps: Vec<StatsGap>,
+}
+
+/// Finds periods between consecutive stats longer than expected interval.
+/// Stats don't have to be sorted by collection time.
+fn find_stats_gaps(stats: &[CommonLocationStats], expected_interval: Duration) -> Vec<StatsGap> {
+    let mut collected_at: Vec<NaiveDateTime> =
+        stats.iter().map(|stats| stats.collected_at).collect();
+    collected_at.sort_unstable();
+    collected_at
+        .windows(2)
+        .filter(|pair| pair[1] - pair[0] > expected_interval * STATS_GAP_INTERVALS)
+        .map(|pair| StatsGap {
+            start: pair[0],
+            end: pair[1],
+        })
+        .collect()
+}
+
+/// Spreads traffic of the first sample after each gap evenly over the gap
+/// by adding interpolated samples (without `id`) at expected interval.
+fn interpolate_stats_gaps(
+    stats: Vec<CommonLocationStats>,
+    expected_interval: Duration,
+) -> Vec<CommonLocationStats> {
+    let mut result: Vec<CommonLocationStats> = Vec::with_capacity(stats.len());
+    for mut sample in stats {
+        if let Some(previous_collected_at) = result.last().map(|previous| previous.collected_at) {
+            let gap = sample.collected_at - previous_collected_at;
+            if gap > expected_interval * STATS_GAP_INTERVALS {
+                let steps = (gap.num_seconds() / expected_interval.num_seconds()).max(1);
+                let (upload, download) = (sample.upload / steps, sample.download / steps);
+                for step in 1..steps {
+                    result.push(CommonLocationStats {
+                        id: None,
+                        location_id: sample.location_id,
+                        upload,
+                        download,
+                        last_handshake: sample.last_handshake,
+                        collected_at: previous_collected_at + expected_interval * step as i32,
+                        listen_port: sample.listen_port,
+                        persistent_keepalive_interval: sample.persistent_keepalive_interval,
+                        latency_ms: None,
+                        // rate of the sample already covers the whole gap
+                        upload_rate: sample.upload_rate,
+                        download_rate: sample.download_rate,
+                        connection_type: sample.connection_type.clone(),
+                    });
+                }
+                // remainder stays with the actual sample
+                sample.upload -= upload * (steps - 1);
+                sample.download -= download * (steps - 1);
+            }
         }
+        result.push(sample);
+    }
+    result
+}
+
+/// Returns location stats together with periods in which no stats were collected,
+/// so they can be displayed as missing data instead of zero throughput.
+/// Optionally interpolates traffic across the gaps.
+#[tauri::command]
+pub async fn location_stats_with_gaps(
+    location_id: i64,
+    connection_type: ConnectionType,
+    from: Option<String>,
+    interpolate: Option<bool>,
+    app_state: State<'_, AppState>,
+) -> Result<LocationStatsWithGaps, Error> {
+    trace!("Location stats with gaps command received");
+    let from = parse_timestamp(from)?.naive_utc();
+    let aggregation = get_aggregation(from)?;
+    let pool = app_state.get_pool();
+    let expected_interval = aggregation.expected_interval(Settings::stats_interval(&pool).await);
+    let stats = load_stats(&pool, location_id, connection_type, &from, &aggregation).await?;
+    let gaps = find_stats_gaps(&stats, expected_interval);
+    debug!(
+        "Found {} gaps in stats of location {location_id}",
+        gaps.len()
+    );
+    let stats = if interpolate.unwrap_or_default() {
+        interpolate_stats_gaps(stats, expected_interval)
+    } else {
+        stats
     };
+    Ok(LocationStatsWithGaps { stats, gaps })
+}
 
-    Ok(stats)
+// ISO-8601 format of timestamps in exported stats, all of them are in UTC
+const CSV_TIMESTAMP_FORMAT: &str = "%Y-%m-%dT%H:%M:%SZ";
+
+/// Formats location stats as CSV with a header row. Byte counters are raw integers,
+/// missing last handshake and latency are left empty.
+fn stats_csv(stats: &[CommonLocationStats]) -> String {
+    let mut csv = String::from("collected_at,upload,download,last_handshake,latency_ms\n");
+    for sample in stats {
+        let last_handshake = NaiveDateTime::from_timestamp_opt(sample.last_handshake, 0)
+            .filter(|_| sample.last_handshake > 0)
+            .map(|handshake| handshake.format(CSV_TIMESTAMP_FORMAT).to_string())
+            .unwrap_or_default();
+        let latency = sample
+            .latency_ms
+            .map(|latency| latency.to_string())
+            .unwrap_or_default();
+        let _ = writeln!(
+            csv,
+            "{},{},{},{last_handshake},{latency}",
+            sample.collected_at.format(CSV_TIMESTAMP_FORMAT),
+            sample.upload,
+            sample.download,
+        );
+    }
+    csv
+}
+
+/// Exports location stats collected since `from` as CSV, aggregated the same way
+/// as stats displayed by the frontend.
+#[tauri::command(async)]
+pub async fn export_stats_csv(
+    location_id: i64,
+    from: Option<String>,
+    app_state: State<'_, AppState>,
+) -> Result<String, Error> {
+    debug!("Exporting stats of location {location_id} as CSV");
+    let from = parse_timestamp(from)?.naive_utc();
+    let aggregation = get_aggregation(from)?;
+    let stats = load_stats(
+        &app_state.get_pool(),
+        location_id,
+        ConnectionType::Location,
+        &from,
+        &aggregation,
+    )
+    .await?;
+    info!(
+        "Exported {} stats samples of location {location_id} as CSV",
+        stats.len()
+    );
+    Ok(stats_csv(&stats))
+}
+
+/// Removes location stats older than `older_than_days`, returning number of removed rows.
+/// The most recent stats of every location are kept.
+#[tauri::command]
+pub async fn prune_stats(
+    older_than_days: i64,
+    app_state: State<'_, AppState>,
+) -> Result<u64, Error> {
+    debug!("Pruning location stats older than {older_than_days} days");
+    if older_than_days < 0 {
+        error!("Invalid stats age {older_than_days} days");
+        return Err(Error::CommandError(
+            "Stats age must not be negative".to_string(),
+        ));
+    }
+    prune_location_stats(&app_state.get_pool(), older_than_days).await
+}
+
+/// Removes all stats of location without touching its configuration, returning number
+/// of removed rows. The most recent stats are kept unless `keep_latest` is `false`,
+/// so interface details of the location remain available.
+#[tauri::command(async)]
+pub async fn reset_location_stats(
+    location_id: i64,
+    keep_latest: Option<bool>,
+    handle: AppHandle,
+) -> Result<u64, Error> {
+    let app_state = handle.state::<AppState>();
+    debug!("Resetting stats of location {location_id}");
+    let pool = app_state.get_pool();
+    let Some(location) = Location::find_by_id(&pool, location_id).await? else {
+        error!("Location with id: {location_id} not found.");
+        return Err(Error::NotFound);
+    };
+    // buffered samples would reappear after reset, the latest one is kept if requested
+    app_state.stats_buffer.flush(&pool).await?;
+    let mut transaction = pool.begin().await?;
+    let deleted = LocationStats::delete_by_location_id(
+        &mut *transaction,
+        location_id,
+        keep_latest.unwrap_or(true),
+    )
+    .await?;
+    transaction.commit().await?;
+    info!("Removed {deleted} stats of location {location}");
+    emit_event(
+        &handle,
+        "location-update",
+        Payload {
+            message: "Location stats reset".into(),
+        },
+    )?;
+    Ok(deleted)
+}
+
+const MAX_USAGE_MONTHS: u32 = 120;
+
+/// Returns data usage of location grouped by calendar month for the last `months` months.
+#[tauri::command]
+pub async fn monthly_usage(
+    location_id: i64,
+    months: i64,
+    app_state: State<'_, AppState>,
+) -> Result<Vec<MonthlyUsage>, Error> {
+    debug!("Computing monthly usage of location {location_id} for last {months} months");
+    let months = u32::try_from(months)
+        .ok()
+        .filter(|months| (1..=MAX_USAGE_MONTHS).contains(months))
+        .ok_or_else(|| Error::CommandError(format!("Invalid number of months: {months}")))?;
+    LocationStats::monthly_usage(&app_state.get_pool(), location_id, months).await
+}
+
+// number of connections returned when no limit is given
+const DEFAULT_CONNECTIONS_LIMIT: i64 = 50;
+
+#[derive(Debug, Serialize)]
+pub struct ConnectionsPage {
+    pub connections: Vec<CommonConnectionInfo>,
+    // number of all connections matching the filter
+    pub total: i64,
 }
 
 #[tauri::command]
 pub async fn all_connections(
     location_id: i64,
     connection_type: ConnectionType,
+    limit: Option<i64>,
+    offset: Option<i64>,
+    from: Option<String>,
+    to: Option<String>,
     app_state: State<'_, AppState>,
-) -> Result<Vec<CommonConnectionInfo>, Error> {
+) -> Result<ConnectionsPage, Error> {
     debug!("Retrieving connections for location {location_id}");
-    let connections: Vec<CommonConnectionInfo> = match connection_type {
-        ConnectionType::Location => {
-            ConnectionInfo::all_by_location_id(&app_state.get_pool(), location_id)
+    let pool = app_state.get_pool();
+    let limit = limit.unwrap_or(DEFAULT_CONNECTIONS_LIMIT).max(0);
+    let offset = offset.unwrap_or_default().max(0);
+    let from = match from {
+        Some(from) => Some(parse_timestamp(Some(from))?.naive_utc()),
+        None => None,
+    };
+    let to = match to {
+        Some(to) => Some(parse_timestamp(Some(to))?.naive_utc()),
+        None => None,
+    };
+    let (connections, total): (Vec<CommonConnectionInfo>, i64) = match connection_type {
+        ConnectionType::Location => (
+            ConnectionInfo::all_by_location_id(&pool, location_id, from, to, limit, offset)
                 .await?
                 .into_iter()
                 .map(Into::into)
-                .collect()
-        }
-        ConnectionType::Tunnel => {
-            TunnelConnectionInfo::all_by_tunnel_id(&app_state.get_pool(), location_id)
+                .collect(),
+            ConnectionInfo::count_by_location_id(&pool, location_id, from, to).await?,
+        ),
+        ConnectionType::Tunnel => (
+            TunnelConnectionInfo::all_by_tunnel_id(&pool, location_id, from, to, limit, offset)
                 .await?
                 .into_iter()
                 .map(Into::into)
-                .collect()
+                .collect(),
+            TunnelConnectionInfo::count_by_tunnel_id(&pool, location_id, from, to).await?,
+        ),
+    };
+    debug!("Connections received, returning.");
+    trace!("Connections found:\n{:#?}", connections);
+    Ok(ConnectionsPage { connections, total })
+}
+
+#[tauri::command]
+pub async fn all_tunnel_connections(
+    location_id: i64,
+    app_state: State<'_, AppState>,
+) -> Result<Vec<TunnelConnectionInfo>, Error> {
+    debug!("Retrieving connections for location {location_id}");
+    // negative limit returns all connections
+    let connections = TunnelConnectionInfo::all_by_tunnel_id(
+        &app_state.get_pool(),
+        location_id,
+        None,
+        None,
+        -1,
+        0,
+    )
+    .await?;
+    debug!("Connections received, returning.");
+    trace!("Connections found:\n{:#?}", connections);
+    Ok(connections)
+}
+
+/// Recent connections of all locations, most recently ended first, optionally only those
+/// which ended after `from`.
+#[tauri::command(async)]
+pub async fn recent_connections(
+    limit: Option<i64>,
+    from: Option<String>,
+    app_state: State<'_, AppState>,
+) -> Result<Vec<RecentConnectionInfo>, Error> {
+    let limit = limit.unwrap_or(DEFAULT_CONNECTIONS_LIMIT).max(0);
+    let from = match from {
+        Some(from) => Some(parse_timestamp(Some(from))?.naive_utc()),
+        None => None,
+    };
+    debug!("Retrieving {limit} recent connections");
+    let connections = RecentConnectionInfo::all(&app_state.get_pool(), from, limit).await?;
+    trace!("Recent connections found:\n{connections:#?}");
+    Ok(connections)
+}
+
+/// Active connection along with its current session duration.
+#[derive(Debug, Serialize)]
+pub struct ActiveConnectionInfo {
+    #[serde(flatten)]
+    pub connection: ActiveConnection,
+    pub duration_seconds: i64,
+    // name of key profile the connection was made with
+    pub profile_name: Option<String>,
+}
+
+#[tauri::command]
+pub async fn active_connection(
+    location_id: i64,
+    connection_type: ConnectionType,
+    handle: AppHandle,
+) -> Result<Option<ActiveConnectionInfo>, Error> {
+    let state = handle.state::<AppState>();
+    debug!("Retrieving active connection for location with id: {location_id}");
+    debug!("Location found");
+    let connection = match state.find_connection(location_id, connection_type) {
+        Some(connection) => {
+            let profile_name = match connection.profile_id {
+                Some(profile_id) => WireguardKeys::find_by_id(&state.get_pool(), profile_id)
+                    .await?
+                    .and_then(|profile| profile.profile_name),
+                None => None,
+            };
+            Some(ActiveConnectionInfo {
+                duration_seconds: connection.duration_seconds(),
+                connection,
+                profile_name,
+            })
+        }
+        None => None,
+    };
+    if connection.is_some() {
+        debug!("Active connection found");
+    }
+    trace!("Connection:\n{:#?}", connection);
+    debug!("Connection returned");
+    Ok(connection)
+}
+
+/// Stream logs of connected location or tunnel in given format, replacing log watcher
+/// started on connect. Returns name of event topic log lines are emitted to.
+#[tauri::command(async)]
+pub async fn get_interface_logs(
+    location_id: i64,
+    connection_type: ConnectionType,
+    from: Option<String>,
+    format: Option<LogFormat>,
+    handle: AppHandle,
+) -> Result<String, Error> {
+    let state = handle.state::<AppState>();
+    let format = format.unwrap_or_default();
+    debug!("Streaming logs of location {location_id} {connection_type:?} as {format:?}");
+    let Some(connection) = state.find_connection(location_id, connection_type.clone()) else {
+        error!("Connection for location with id: {location_id} not found");
+        return Err(Error::NotFound);
+    };
+    spawn_log_watcher_task(
+        handle.clone(),
+        location_id,
+        connection.interface_name,
+        connection_type,
+        from,
+        format,
+    )
+    .await
+}
+
+/// Bundles interface logs, location config without secrets, recent stats and versions
+/// into a zip archive meant to be attached to bug reports.
+#[tauri::command(async)]
+pub async fn collect_diagnostics(location_id: i64, handle: AppHandle) -> Result<Vec<u8>, Error> {
+    let state = handle.state::<AppState>();
+    let pool = state.get_pool();
+    let Some(location) = Location::find_by_id(&pool, location_id).await? else {
+        error!("Location with id: {location_id} not found.");
+        return Err(Error::NotFound);
+    };
+    // interface names are assigned dynamically on macOS, so only the active one is known
+    let interface_name = match state.find_connection(location_id, ConnectionType::Location) {
+        Some(connection) => connection.interface_name,
+        #[cfg(target_os = "macos")]
+        None => location.interface_name.clone().unwrap_or_default(),
+        #[cfg(not(target_os = "macos"))]
+        None => location_interface_name(&location),
+    };
+    let app_version = handle.package_info().version.to_string();
+    diagnostics::collect_diagnostics(&pool, &location, &interface_name, app_version).await
+}
+
+/// Lightweight count of active connections for compact UI widgets.
+#[tauri::command]
+pub async fn active_connection_count(app_state: State<'_, AppState>) -> Result<usize, Error> {
+    Ok(app_state.active_connection_count())
+}
+
+#[tauri::command]
+pub async fn last_connection(
+    location_id: i64,
+    connection_type: ConnectionType,
+    app_state: State<'_, AppState>,
+) -> Result<Option<CommonConnection>, Error> {
+    debug!("Retrieving last connection for location {location_id} with type {connection_type:?}");
+    if connection_type == ConnectionType::Location {
+        if let Some(connection) =
+            Connection::latest_by_location_id(&app_state.get_pool(), location_id).await?
+        {
+            trace!("Connection found");
+            Ok(Some(connection.into()))
+        } else {
+            Ok(None)
+        }
+    } else if let Some(connection) =
+        TunnelConnection::latest_by_tunnel_id(&app_state.get_pool(), location_id).await?
+    {
+        trace!("Connection found");
+        Ok(Some(connection.into()))
+    } else {
+        Ok(None)
+    }
+}
+
+#[tauri::command]
+pub async fn update_location_routing(
+    location_id: i64,
+    route_all_traffic: bool,
+    connection_type: ConnectionType,
+    handle: AppHandle,
+) -> Result<(), Error> {
+    let app_state = handle.state::<AppState>();
+    debug!("Updating location routing {location_id} with {connection_type:?}");
+
+    match connection_type {
+        ConnectionType::Location => {
+            if let Some(mut location) =
+                Location::find_by_id(&app_state.get_pool(), location_id).await?
+            {
+                // kill switch rules depend on routing, they must be removed with the interface
+                if location.kill_switch
+                    && app_state
+                        .get_connection_id_by_type(&ConnectionType::Location)
+                        .contains(&location_id)
+                {
+                    error!(
+                        "Location {location} with kill switch is connected, can't change routing"
+                    );
+                    return Err(Error::CommandError(
+                        "Disconnect location before changing routing".into(),
+                    ));
+                }
+                if route_all_traffic && !location.route_all_traffic {
+                    let conflicting_location_ids: Vec<i64> =
+                        active_full_tunnel_locations(&app_state)
+                            .await?
+                            .into_iter()
+                            .filter(|id| *id != location_id)
+                            .collect();
+                    if !conflicting_location_ids.is_empty() {
+                        warn!("Routing all traffic of location {location} while locations {conflicting_location_ids:?} already route all traffic");
+                        emit_event(
+                            &handle,
+                            "routing-conflict",
+                            RoutingConflictPayload {
+                                location_id,
+                                conflicting_location_ids,
+                            },
+                        )?;
+                    }
+                }
+                location.route_all_traffic = route_all_traffic;
+                location.save(&app_state.get_pool()).await?;
+                emit_event(
+                    &handle,
+                    "location-update",
+                    Payload {
+                        message: "Location routing updated".into(),
+                    },
+                )?;
+                Ok(())
+            } else {
+                error!("Location with id: {location_id} not found.");
+                Err(Error::NotFound)
+            }
+        }
+        ConnectionType::Tunnel => {
+            if let Some(mut tunnel) = Tunnel::find_by_id(&app_state.get_pool(), location_id).await?
+            {
+                tunnel.route_all_traffic = route_all_traffic;
+                tunnel.save(&app_state.get_pool()).await?;
+                emit_event(
+                    &handle,
+                    "location-update",
+                    Payload {
+                        message: "Tunnel routing updated".into(),
+                    },
+                )?;
+                Ok(())
+            } else {
+                error!("Tunnel with id: {location_id} not found.");
+                Err(Error::NotFound)
+            }
+        }
+    }
+}
+
+/// Configure encrypted DNS resolver used by location interface.
+/// Changes are applied on next connection.
+#[tauri::command]
+pub async fn update_location_encrypted_dns(
+    location_id: i64,
+    doh_url: Option<String>,
+    dot_host: Option<String>,
+    handle: AppHandle,
+) -> Result<(), Error> {
+    let app_state = handle.state::<AppState>();
+    debug!("Updating encrypted DNS for location {location_id}");
+    if let Some(mut location) = Location::find_by_id(&app_state.get_pool(), location_id).await? {
+        location.doh_url = doh_url.filter(|url| !url.is_empty());
+        location.dot_host = dot_host.filter(|host| !host.is_empty());
+        // make sure resolver can be configured on this platform before saving
+        encrypted_dns_commands(&location, &location.name)?;
+        location.save(&app_state.get_pool()).await?;
+        info!("Encrypted DNS for location {location} updated");
+        emit_event(
+            &handle,
+            "location-update",
+            Payload {
+                message: "Location encrypted DNS updated".into(),
+            },
+        )?;
+        Ok(())
+    } else {
+        error!("Location with id: {location_id} not found.");
+        Err(Error::NotFound)
+    }
+}
+
+/// Pin IP address used to connect to location endpoint instead of resolving its hostname.
+/// Hostname is still kept for display.
+#[tauri::command]
+pub async fn set_endpoint_ip_override(
+    location_id: i64,
+    endpoint_ip: Option<String>,
+    handle: AppHandle,
+) -> Result<(), Error> {
+    let app_state = handle.state::<AppState>();
+    debug!("Updating endpoint IP override for location {location_id}");
+    let endpoint_ip = endpoint_ip
+        .map(|ip| ip.trim().to_string())
+        .filter(|ip| !ip.is_empty());
+    if let Some(ref ip) = endpoint_ip {
+        IpAddr::from_str(ip).map_err(|err| {
+            error!("Invalid endpoint IP override {ip}: {err}");
+            err
+        })?;
+    }
+    if let Some(mut location) = Location::find_by_id(&app_state.get_pool(), location_id).await? {
+        location.endpoint_ip_override = endpoint_ip;
+        location.save(&app_state.get_pool()).await?;
+        info!("Endpoint IP override for location {location} updated");
+        emit_event(
+            &handle,
+            "location-update",
+            Payload {
+                message: "Location endpoint IP override updated".into(),
+            },
+        )?;
+        Ok(())
+    } else {
+        error!("Location with id: {location_id} not found.");
+        Err(Error::NotFound)
+    }
+}
+
+/// Set free-form JSON metadata used by the frontend to customize location UI.
+/// Metadata is kept when instance config is updated.
+#[tauri::command]
+pub async fn update_location_metadata(
+    location_id: i64,
+    metadata: Option<String>,
+    handle: AppHandle,
+) -> Result<(), Error> {
+    let app_state = handle.state::<AppState>();
+    debug!("Updating metadata for location {location_id}");
+    let metadata = metadata.filter(|metadata| !metadata.trim().is_empty());
+    if let Some(ref metadata) = metadata {
+        serde_json::from_str::<serde_json::Value>(metadata).map_err(|err| {
+            error!("Location metadata is not valid JSON: {err}");
+            err
+        })?;
+    }
+    if let Some(mut location) = Location::find_by_id(&app_state.get_pool(), location_id).await? {
+        location.metadata = metadata;
+        location.save(&app_state.get_pool()).await?;
+        info!("Metadata for location {location} updated");
+        emit_event(
+            &handle,
+            "location-update",
+            Payload {
+                message: "Location metadata updated".into(),
+            },
+        )?;
+        Ok(())
+    } else {
+        error!("Location with id: {location_id} not found.");
+        Err(Error::NotFound)
+    }
+}
+
+/// Enable or disable blocking of traffic outside of the tunnel for location
+/// routing all traffic. Location must be disconnected, so that firewall rules
+/// are never left behind.
+#[tauri::command]
+pub async fn update_location_kill_switch(
+    location_id: i64,
+    enabled: bool,
+    handle: AppHandle,
+) -> Result<(), Error> {
+    let app_state = handle.state::<AppState>();
+    debug!("Updating location kill switch {location_id} with {enabled}");
+    if app_state
+        .get_connection_id_by_type(&ConnectionType::Location)
+        .contains(&location_id)
+    {
+        error!("Location {location_id} is connected, can't change kill switch");
+        return Err(Error::CommandError(
+            "Disconnect location before changing kill switch".into(),
+        ));
+    }
+    if let Some(mut location) = Location::find_by_id(&app_state.get_pool(), location_id).await? {
+        location.kill_switch = enabled;
+        // make sure kill switch is supported on this platform before saving
+        kill_switch_commands(&location, &location.name, None)?;
+        location.save(&app_state.get_pool()).await?;
+        emit_event(
+            &handle,
+            "location-update",
+            Payload {
+                message: "Location kill switch updated".into(),
+            },
+        )?;
+        Ok(())
+    } else {
+        error!("Location with id: {location_id} not found.");
+        Err(Error::NotFound)
+    }
+}
+
+/// Enable or disable routing DNS only through location tunnel. DNS servers of location
+/// must be reachable inside the tunnel, otherwise name resolution breaks while connected.
+/// Location must be disconnected, so that firewall rules are never left behind.
+#[tauri::command]
+pub async fn update_location_force_tunnel_dns(
+    location_id: i64,
+    enabled: bool,
+    handle: AppHandle,
+) -> Result<(), Error> {
+    let app_state = handle.state::<AppState>();
+    debug!("Updating location {location_id} force tunnel DNS with {enabled}");
+    if app_state
+        .get_connection_id_by_type(&ConnectionType::Location)
+        .contains(&location_id)
+    {
+        error!("Location {location_id} is connected, can't change tunnel DNS");
+        return Err(Error::CommandError(
+            "Disconnect location before changing tunnel DNS".into(),
+        ));
+    }
+    if let Some(mut location) = Location::find_by_id(&app_state.get_pool(), location_id).await? {
+        location.force_tunnel_dns = enabled;
+        // make sure location has DNS and rules are supported on this platform before saving
+        tunnel_dns_commands(&location, &location.name)?;
+        location.save(&app_state.get_pool()).await?;
+        info!("Updated force tunnel DNS of location {location} to {enabled}");
+        emit_event(
+            &handle,
+            "location-update",
+            Payload {
+                message: "Location tunnel DNS updated".into(),
+            },
+        )?;
+        Ok(())
+    } else {
+        error!("Location with id: {location_id} not found.");
+        Err(Error::NotFound)
+    }
+}
+
+/// Sets relay preferred when connecting locations of instance. Relay is an endpoint host
+/// (or whole endpoint) and endpoints it serves are tried first, locations not served
+/// by it use their default endpoints. Empty relay removes the preference.
+#[tauri::command(async)]
+pub async fn set_preferred_relay(
+    instance_id: i64,
+    relay: Option<String>,
+    handle: AppHandle,
+) -> Result<(), Error> {
+    let app_state = handle.state::<AppState>();
+    let pool = app_state.get_pool();
+    debug!("Setting preferred relay of instance {instance_id} to {relay:?}");
+    let Some(mut instance) = Instance::find_by_id(&pool, instance_id).await? else {
+        error!("Instance with id: {instance_id} not found.");
+        return Err(Error::NotFound);
+    };
+    let relay = relay
+        .map(|relay| relay.trim().to_string())
+        .filter(|relay| !relay.is_empty());
+    if let Some(relay) = &relay {
+        let served = Location::find_by_instance_id(&pool, instance_id)
+            .await?
+            .iter()
+            .flat_map(|location| location_endpoints(location, None))
+            .any(|endpoint| served_by_relay(&endpoint, relay));
+        if !served {
+            warn!(
+                "Relay {relay} doesn't serve any location of instance {}",
+                instance.name
+            );
+        }
+    }
+    instance.preferred_relay = relay;
+    instance.save(&pool).await?;
+    info!(
+        "Set preferred relay of instance {} to {:?}",
+        instance.name, instance.preferred_relay
+    );
+    emit_event(&handle, "instance-update", ())?;
+    refresh_tray(&handle);
+    Ok(())
+}
+
+/// Sets backup endpoints of location, which are tried in order when its primary endpoint
+/// doesn't complete a handshake. Empty list removes them. Change applies to next connection.
+#[tauri::command(async)]
+pub async fn update_location_endpoints(
+    location_id: i64,
+    endpoints: Vec<String>,
+    handle: AppHandle,
+) -> Result<(), Error> {
+    let app_state = handle.state::<AppState>();
+    debug!("Updating location {location_id} endpoints with {endpoints:?}");
+    if let Some(mut location) = Location::find_by_id(&app_state.get_pool(), location_id).await? {
+        location.endpoints = parse_endpoints(endpoints)?;
+        location.save(&app_state.get_pool()).await?;
+        info!("Updated endpoints of location {location}");
+        emit_event(
+            &handle,
+            "location-update",
+            Payload {
+                message: "Location endpoints updated".into(),
+            },
+        )?;
+        Ok(())
+    } else {
+        error!("Location with id: {location_id} not found.");
+        Err(Error::NotFound)
+    }
+}
+
+/// Enable or disable connecting location automatically when the app starts.
+#[tauri::command(async)]
+pub async fn update_location_autoconnect(
+    location_id: i64,
+    enabled: bool,
+    handle: AppHandle,
+) -> Result<(), Error> {
+    let app_state = handle.state::<AppState>();
+    debug!("Updating location {location_id} autoconnect with {enabled}");
+    if let Some(mut location) = Location::find_by_id(&app_state.get_pool(), location_id).await? {
+        if enabled && location.mfa_enabled {
+            warn!("Location {location} requires MFA, it won't be connected automatically");
+        }
+        location.autoconnect = enabled;
+        location.save(&app_state.get_pool()).await?;
+        info!("Updated autoconnect of location {location} to {enabled}");
+        emit_event(
+            &handle,
+            "location-update",
+            Payload {
+                message: "Location autoconnect updated".into(),
+            },
+        )?;
+        Ok(())
+    } else {
+        error!("Location with id: {location_id} not found.");
+        Err(Error::NotFound)
+    }
+}
+
+/// Assigns location to failover group of its instance, or removes it from group if
+/// `failover_group` is empty. Stale connection of a group member is replaced with connection
+/// of the next member by `failover_priority`, lower priority is preferred.
+#[tauri::command(async)]
+pub async fn update_location_failover(
+    location_id: i64,
+    failover_group: Option<String>,
+    failover_priority: Option<i64>,
+    handle: AppHandle,
+) -> Result<(), Error> {
+    let app_state = handle.state::<AppState>();
+    debug!("Updating location {location_id} failover group with {failover_group:?}");
+    let Some(mut location) = Location::find_by_id(&app_state.get_pool(), location_id).await? else {
+        error!("Location with id: {location_id} not found.");
+        return Err(Error::NotFound);
+    };
+    location.failover_group = failover_group
+        .map(|group| group.trim().to_string())
+        .filter(|group| !group.is_empty());
+    location.failover_priority = failover_priority.unwrap_or_default();
+    if location.failover_group.is_some() && location.mfa_enabled {
+        warn!("Location {location} requires MFA, it won't be connected by failover");
+    }
+    location.save(&app_state.get_pool()).await?;
+    info!(
+        "Updated failover group of location {location} to {:?} with priority {}",
+        location.failover_group, location.failover_priority
+    );
+    emit_event(
+        &handle,
+        "location-update",
+        Payload {
+            message: "Location failover updated".into(),
+        },
+    )?;
+    Ok(())
+}
+
+/// Sets time windows in which location may be connected, JSON array like
+/// `[{"days": ["Mon", "Fri"], "start": "08:00", "end": "18:00"}]`. Empty schedule allows
+/// connecting any time. Connected location is disconnected once its window closes.
+#[tauri::command(async)]
+pub async fn update_location_schedule(
+    location_id: i64,
+    schedule: Option<String>,
+    handle: AppHandle,
+) -> Result<(), Error> {
+    let app_state = handle.state::<AppState>();
+    debug!("Updating location {location_id} schedule with {schedule:?}");
+    let Some(mut location) = Location::find_by_id(&app_state.get_pool(), location_id).await? else {
+        error!("Location with id: {location_id} not found.");
+        return Err(Error::NotFound);
+    };
+    location.schedule = match schedule.filter(|schedule| !schedule.trim().is_empty()) {
+        // stored normalized
+        Some(schedule) => Some(serde_json::to_string(&parse_schedule(&schedule)?)?),
+        None => None,
+    };
+    location.save(&app_state.get_pool()).await?;
+    info!(
+        "Updated schedule of location {location} to {:?}",
+        location.schedule
+    );
+    emit_event(
+        &handle,
+        "location-update",
+        Payload {
+            message: "Location schedule updated".into(),
+        },
+    )?;
+    Ok(())
+}
+
+/// Pin location to the top of locations list.
+#[tauri::command(async)]
+pub async fn set_location_favorite(
+    location_id: i64,
+    favorite: bool,
+    handle: AppHandle,
+) -> Result<(), Error> {
+    let app_state = handle.state::<AppState>();
+    debug!("Updating location {location_id} favorite with {favorite}");
+    if let Some(mut location) = Location::find_by_id(&app_state.get_pool(), location_id).await? {
+        location.favorite = favorite;
+        location.save(&app_state.get_pool()).await?;
+        info!("Updated favorite of location {location} to {favorite}");
+        emit_event(
+            &handle,
+            "location-update",
+            Payload {
+                message: "Location favorite updated".into(),
+            },
+        )?;
+        refresh_tray(&handle);
+        Ok(())
+    } else {
+        error!("Location with id: {location_id} not found.");
+        Err(Error::NotFound)
+    }
+}
+
+/// Sets local display name of location. Name received from core is kept, since interface name
+/// is derived from it. Empty name restores the original one.
+#[tauri::command(async)]
+pub async fn rename_location(
+    location_id: i64,
+    name: String,
+    handle: AppHandle,
+) -> Result<(), Error> {
+    let app_state = handle.state::<AppState>();
+    debug!("Renaming location {location_id} to {name}");
+    if let Some(mut location) = Location::find_by_id(&app_state.get_pool(), location_id).await? {
+        let name = name.trim();
+        location.display_name = (!name.is_empty()).then(|| name.to_string());
+        location.save(&app_state.get_pool()).await?;
+        info!("Location {location} display name set to {name:?}");
+        emit_event(
+            &handle,
+            "location-update",
+            Payload {
+                message: "Location renamed".into(),
+            },
+        )?;
+        refresh_tray(&handle);
+        Ok(())
+    } else {
+        error!("Location with id: {location_id} not found.");
+        Err(Error::NotFound)
+    }
+}
+
+/// Choose interface name of location again, e.g. after another location took the same name.
+/// Location has to be disconnected, since its interface can't be renamed.
+#[tauri::command(async)]
+pub async fn regenerate_interface_name(
+    location_id: i64,
+    handle: AppHandle,
+) -> Result<ResolvedInterfaceName, Error> {
+    let app_state = handle.state::<AppState>();
+    debug!("Regenerating interface name of location {location_id}");
+    let Some(mut location) = Location::find_by_id(&app_state.get_pool(), location_id).await? else {
+        error!("Location with id: {location_id} not found.");
+        return Err(Error::NotFound);
+    };
+    if app_state
+        .find_connection(location_id, ConnectionType::Location)
+        .is_some()
+    {
+        return Err(Error::CommandError(format!(
+            "Location {location} has to be disconnected to change its interface name"
+        )));
+    }
+    location.interface_name = None;
+    Location::set_interface_name(&app_state.get_pool(), location_id, None).await?;
+    let resolved = resolve_interface_name(&app_state, &location).await?;
+    info!(
+        "Interface name of location {location} set to {}",
+        resolved.interface_name
+    );
+    emit_event(
+        &handle,
+        "location-update",
+        Payload {
+            message: "Location interface name updated".into(),
+        },
+    )?;
+    Ok(resolved)
+}
+
+/// Order locations as in given list of IDs. Favorite locations are still listed first.
+#[tauri::command(async)]
+pub async fn reorder_locations(ordered_ids: Vec<i64>, handle: AppHandle) -> Result<(), Error> {
+    let app_state = handle.state::<AppState>();
+    debug!("Reordering locations: {ordered_ids:?}");
+    Location::reorder(&app_state.get_pool(), &ordered_ids).await?;
+    info!("Reordered {} locations", ordered_ids.len());
+    emit_event(
+        &handle,
+        "location-update",
+        Payload {
+            message: "Locations reordered".into(),
+        },
+    )?;
+    refresh_tray(&handle);
+    Ok(())
+}
+
+/// Set MTU of location interface, `None` leaves it to be auto-detected.
+/// Takes effect on next connection.
+#[tauri::command(async)]
+pub async fn update_location_mtu(
+    location_id: i64,
+    mtu: Option<u32>,
+    handle: AppHandle,
+) -> Result<(), Error> {
+    let app_state = handle.state::<AppState>();
+    debug!("Updating location {location_id} MTU with {mtu:?}");
+    if let Some(mtu) = mtu {
+        validate_mtu(mtu)?;
+    }
+    if let Some(mut location) = Location::find_by_id(&app_state.get_pool(), location_id).await? {
+        location.mtu = mtu;
+        location.save(&app_state.get_pool()).await?;
+        info!("Updated MTU of location {location} to {mtu:?}");
+        emit_event(
+            &handle,
+            "location-update",
+            Payload {
+                message: "Location MTU updated".into(),
+            },
+        )?;
+        Ok(())
+    } else {
+        error!("Location with id: {location_id} not found.");
+        Err(Error::NotFound)
+    }
+}
+
+#[tauri::command(async)]
+pub async fn update_location_dns(
+    location_id: i64,
+    dns: Option<String>,
+    handle: AppHandle,
+) -> Result<(), Error> {
+    let app_state = handle.state::<AppState>();
+    debug!("Updating location DNS {location_id} with {dns:?}");
+    let dns = dns
+        .map(|dns| dns.trim().to_string())
+        .filter(|dns| !dns.is_empty());
+    let servers = parse_dns_servers(dns.as_deref())?;
+    if let Some(mut location) = Location::find_by_id(&app_state.get_pool(), location_id).await? {
+        // store normalized list so it can be passed to interface configuration as is
+        location.dns = (!servers.is_empty()).then(|| {
+            servers
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(",")
+        });
+        location.save(&app_state.get_pool()).await?;
+        info!("Location {location} DNS updated, changes apply on next connection");
+        emit_event(
+            &handle,
+            "location-update",
+            Payload {
+                message: "Location DNS updated".into(),
+            },
+        )?;
+        Ok(())
+    } else {
+        error!("Location with id: {location_id} not found.");
+        Err(Error::NotFound)
+    }
+}
+
+/// Set WireGuard preshared key used with location peer, `None` or empty string removes it.
+/// Takes effect on next connection.
+#[tauri::command(async)]
+pub async fn update_location_preshared_key(
+    location_id: i64,
+    preshared_key: Option<String>,
+    handle: AppHandle,
+) -> Result<(), Error> {
+    let app_state = handle.state::<AppState>();
+    // key value must never be logged
+    debug!("Updating location {location_id} preshared key");
+    let preshared_key = preshared_key
+        .map(|key| key.trim().to_string())
+        .filter(|key| !key.is_empty());
+    if let Some(key) = &preshared_key {
+        Key::from_str(key).map_err(|_| {
+            error!("Invalid preshared key for location {location_id}");
+            Error::CommandError("Preshared key must be a base64 encoded 32-byte key".into())
+        })?;
+    }
+    if let Some(mut location) = Location::find_by_id(&app_state.get_pool(), location_id).await? {
+        location.preshared_key = preshared_key.map(PresharedKey);
+        location.save(&app_state.get_pool()).await?;
+        info!(
+            "Preshared key of location {location} {}",
+            if location.preshared_key.is_some() {
+                "set"
+            } else {
+                "removed"
+            }
+        );
+        emit_event(
+            &handle,
+            "location-update",
+            Payload {
+                message: "Location preshared key updated".into(),
+            },
+        )?;
+        Ok(())
+    } else {
+        error!("Location with id: {location_id} not found.");
+        Err(Error::NotFound)
+    }
+}
+
+/// Set DNS search domains of location, `None` or empty string removes them.
+#[tauri::command(async)]
+pub async fn update_location_dns_search(
+    location_id: i64,
+    dns_search: Option<String>,
+    handle: AppHandle,
+) -> Result<(), Error> {
+    let app_state = handle.state::<AppState>();
+    debug!("Updating location DNS search domains {location_id} with {dns_search:?}");
+    let domains = parse_dns_search(dns_search.as_deref())?;
+    if let Some(mut location) = Location::find_by_id(&app_state.get_pool(), location_id).await? {
+        location.dns_search = (!domains.is_empty()).then(|| domains.join(","));
+        location.save(&app_state.get_pool()).await?;
+        info!("Location {location} DNS search domains updated, changes apply on next connection");
+        emit_event(
+            &handle,
+            "location-update",
+            Payload {
+                message: "Location DNS search domains updated".into(),
+            },
+        )?;
+        Ok(())
+    } else {
+        error!("Location with id: {location_id} not found.");
+        Err(Error::NotFound)
+    }
+}
+
+/// Narrow down networks routed through location tunnel.
+/// Connected location is reconnected to apply new routes, unless it requires MFA.
+#[tauri::command(async)]
+pub async fn update_location_allowed_ips(
+    location_id: i64,
+    allowed_ips: String,
+    handle: AppHandle,
+) -> Result<(), Error> {
+    let app_state = handle.state::<AppState>();
+    debug!("Updating location allowed IPs {location_id} with {allowed_ips}");
+    let networks = parse_allowed_ips(&allowed_ips).map_err(|err| {
+        error!("Invalid allowed IPs for location {location_id}: {err}");
+        err
+    })?;
+    let Some(mut location) = Location::find_by_id(&app_state.get_pool(), location_id).await? else {
+        error!("Location with id: {location_id} not found.");
+        return Err(Error::NotFound);
+    };
+    location.allowed_ips = networks
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<String>>()
+        .join(",");
+    location.save(&app_state.get_pool()).await?;
+    info!(
+        "Location {location} allowed IPs updated to {}",
+        location.allowed_ips
+    );
+    emit_event(
+        &handle,
+        "location-update",
+        Payload {
+            message: "Location allowed IPs updated".into(),
+        },
+    )?;
+
+    let connected = app_state
+        .get_connection_id_by_type(&ConnectionType::Location)
+        .contains(&location_id);
+    if connected && !location.mfa_enabled {
+        info!("Reconnecting location {location} to apply new allowed IPs");
+        reconnect(location_id, Some(true), handle.clone()).await?;
+    } else if connected {
+        info!("Location {location} requires MFA, new allowed IPs apply on next connection");
+    }
+    Ok(())
+}
+
+/// Set networks routed through location tunnel in addition to allowed IPs, `None` or empty
+/// string removes them. Connected location is reconnected to apply new routes, unless it
+/// requires MFA.
+#[tauri::command(async)]
+pub async fn update_location_routes(
+    location_id: i64,
+    extra_routes: Option<String>,
+    handle: AppHandle,
+) -> Result<(), Error> {
+    let app_state = handle.state::<AppState>();
+    debug!("Updating location extra routes {location_id} with {extra_routes:?}");
+    let Some(mut location) = Location::find_by_id(&app_state.get_pool(), location_id).await? else {
+        error!("Location with id: {location_id} not found.");
+        return Err(Error::NotFound);
+    };
+    let routes = parse_extra_routes(
+        extra_routes.as_deref().unwrap_or_default(),
+        location.route_all_traffic,
+    )
+    .map_err(|err| {
+        error!("Invalid extra routes for location {location}: {err}");
+        err
+    })?;
+    location.extra_routes = (!routes.is_empty()).then(|| {
+        routes
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<String>>()
+            .join(",")
+    });
+    location.save(&app_state.get_pool()).await?;
+    info!(
+        "Location {location} extra routes updated to {:?}",
+        location.extra_routes
+    );
+    emit_event(
+        &handle,
+        "location-update",
+        Payload {
+            message: "Location routes updated".into(),
+        },
+    )?;
+
+    let connected = app_state
+        .get_connection_id_by_type(&ConnectionType::Location)
+        .contains(&location_id);
+    if connected && !location.mfa_enabled {
+        info!("Reconnecting location {location} to apply new routes");
+        reconnect(location_id, Some(true), handle.clone()).await?;
+    } else if connected {
+        info!("Location {location} requires MFA, new routes apply on next connection");
+    }
+    Ok(())
+}
+
+/// Creates a location of given instance from standard WireGuard config file.
+/// Device keys are shared by all locations of an instance, so config private key must match
+/// the instance key, unless instance has no keys yet.
+#[tauri::command(async)]
+pub async fn import_location_config(
+    instance_id: i64,
+    config: String,
+    handle: AppHandle,
+) -> Result<Location, Error> {
+    let app_state = handle.state::<AppState>();
+    debug!("Importing location config for instance {instance_id}");
+    let LocationConfig {
+        mut location,
+        pubkey,
+        prvkey,
+    } = parse_location_config(&config, instance_id).map_err(|error| {
+        error!("Failed to parse location config: {error}");
+        Error::ConfigParseError(error.to_string())
+    })?;
+    location.allowed_ips = parse_allowed_ips(&location.allowed_ips)?
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<String>>()
+        .join(",");
+
+    let pool = app_state.get_pool();
+    let Some(instance) = Instance::find_by_id(&pool, instance_id).await? else {
+        error!("Instance with id: {instance_id} not found.");
+        return Err(Error::NotFound);
+    };
+    let keys = WireguardKeys::find_by_instance_id(&pool, instance_id).await?;
+    if keys.as_ref().is_some_and(|keys| keys.prvkey != prvkey) {
+        error!(
+            "Imported config private key doesn't match keys of instance {}",
+            instance.name
+        );
+        return Err(Error::CommandError(format!(
+            "Config private key doesn't match device key of instance {}",
+            instance.name
+        )));
+    }
+
+    let mut transaction = pool.begin().await?;
+    if keys.is_none() {
+        debug!(
+            "Saving keys from imported config for instance {}",
+            instance.name
+        );
+        WireguardKeys::new(instance_id, pubkey, prvkey)
+            .save(&mut *transaction)
+            .await?;
+    }
+    location.save(&mut *transaction).await?;
+    transaction.commit().await?;
+    info!("Imported location {location} to instance {}", instance.name);
+    emit_event(
+        &handle,
+        "location-update",
+        Payload {
+            message: "Location imported".into(),
+        },
+    )?;
+    refresh_tray(&handle);
+    Ok(location)
+}
+
+#[derive(Debug, Serialize)]
+pub struct TestResult {
+    pub endpoint_reachable: bool,
+    pub dns_resolvable: bool,
+    pub resolved_endpoint: String,
+}
+
+/// Checks if location endpoint responds to WireGuard handshake without creating an interface.
+#[tauri::command(async)]
+pub async fn test_location(location_id: i64, handle: AppHandle) -> Result<TestResult, Error> {
+    let app_state = handle.state::<AppState>();
+    let pool = app_state.get_pool();
+    debug!("Testing connectivity of location {location_id}");
+    let Some(location) = Location::find_by_id(&pool, location_id).await? else {
+        error!("Location with id: {location_id} not found.");
+        return Err(Error::NotFound);
+    };
+    // handshake from another port would make the peer switch endpoint of an active tunnel
+    if app_state
+        .get_connection_id_by_type(&ConnectionType::Location)
+        .contains(&location_id)
+    {
+        error!("Location {location} is connected, can't test it");
+        return Err(Error::CommandError("Location is already connected".into()));
+    }
+    let Some(keys) = WireguardKeys::find_by_instance_id(&pool, location.instance_id).await? else {
+        error!("No keys found for instance: {}", location.instance_id);
+        return Err(Error::NotFound);
+    };
+    let resolution = Settings::endpoint_resolution(&pool).await;
+    let endpoint = match location_endpoint(&location, &resolution).await {
+        Ok(endpoint) => endpoint,
+        Err(err) => {
+            warn!("Failed to resolve endpoint of location {location}: {err}");
+            return Ok(TestResult {
+                endpoint_reachable: false,
+                dns_resolvable: false,
+                resolved_endpoint: String::new(),
+            });
+        }
+    };
+    let server_pubkey = location.pubkey.clone();
+    let endpoint_reachable = tokio::task::spawn_blocking(move || {
+        probe_handshake(
+            endpoint,
+            &keys.prvkey,
+            &server_pubkey,
+            HANDSHAKE_PROBE_TIMEOUT,
+        )
+    })
+    .await
+    .map_err(|_| Error::InternalError)??;
+    info!("Location {location} endpoint {endpoint} reachable: {endpoint_reachable}");
+    Ok(TestResult {
+        endpoint_reachable,
+        dns_resolvable: true,
+        resolved_endpoint: endpoint.to_string(),
+    })
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DnsSource {
+    Location,
+    Tunnel,
+    Fallback,
+    System,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EffectiveDns {
+    pub servers: Vec<IpAddr>,
+    pub source: DnsSource,
+    // interface the DNS is configured on, `None` for system DNS
+    pub interface_name: Option<String>,
+}
+
+/// Returns DNS servers currently in use. DNS of the most recently connected interface
+/// takes precedence. While no tunnel is up, `defguard-service` applies fallback DNS if set,
+/// system DNS is used otherwise.
+#[tauri::command(async)]
+pub async fn get_effective_dns(app_state: State<'_, AppState>) -> Result<EffectiveDns, Error> {
+    let pool = app_state.get_pool();
+    let mut connections = app_state.get_connections();
+    if connections.is_empty() {
+        let fallback_dns = parse_dns_servers(Settings::get(&pool).await?.fallback_dns.as_deref())?;
+        if !fallback_dns.is_empty() {
+            return Ok(EffectiveDns {
+                servers: fallback_dns,
+                source: DnsSource::Fallback,
+                interface_name: None,
+            });
+        }
+    }
+    connections.sort_by_key(|connection| std::cmp::Reverse(connection.connected_since));
+    for connection in connections {
+        let (dns, source) = match connection.connection_type {
+            ConnectionType::Location => (
+                Location::find_by_id(&pool, connection.location_id)
+                    .await?
+                    .and_then(|location| location.dns),
+                DnsSource::Location,
+            ),
+            ConnectionType::Tunnel => (
+                Tunnel::find_by_id(&pool, connection.location_id)
+                    .await?
+                    .and_then(|tunnel| tunnel.dns),
+                DnsSource::Tunnel,
+            ),
+        };
+        // tunnel DNS may contain search domains as well
+        let (servers, _) = split_dns_entries(dns.as_deref());
+        let servers = parse_dns_servers(servers.as_deref())?;
+        if !servers.is_empty() {
+            return Ok(EffectiveDns {
+                servers,
+                source,
+                interface_name: Some(connection.interface_name),
+            });
+        }
+    }
+    Ok(EffectiveDns {
+        servers: system_dns_servers(),
+        source: DnsSource::System,
+        interface_name: None,
+    })
+}
+
+/// Returns version of `defguard-service`. Unreachable service is reported as not running
+/// instead of an error, so the user can be asked to start or install it.
+#[tauri::command(async)]
+pub async fn service_status() -> Result<ServiceStatus, Error> {
+    Ok(query_service_status().await)
+}
+
+/// Interfaces managed by `defguard-service` compared with connections known to the app
+#[derive(Debug, Serialize)]
+pub struct ServiceInterfaces {
+    pub interfaces: Vec<ManagedInterface>,
+    // interfaces managed by the service which the app doesn't know about
+    pub orphaned: Vec<ManagedInterface>,
+    // active connections whose interfaces don't exist in the service
+    pub zombies: Vec<ActiveConnection>,
+}
+
+/// Lists live interfaces reported by `defguard-service` to diagnose desynchronized state.
+#[tauri::command(async)]
+pub async fn list_service_interfaces(handle: AppHandle) -> Result<ServiceInterfaces, Error> {
+    let app_state = handle.state::<AppState>();
+    debug!("Listing interfaces managed by defguard-service");
+    let interfaces = list_managed_interfaces().await?;
+    let connections = app_state.get_connections();
+    let orphaned: Vec<ManagedInterface> = interfaces
+        .iter()
+        .filter(|interface| {
+            !connections
+                .iter()
+                .any(|connection| connection.interface_name == interface.name)
+        })
+        .cloned()
+        .collect();
+    let zombies: Vec<ActiveConnection> = connections
+        .into_iter()
+        .filter(|connection| {
+            !interfaces
+                .iter()
+                .any(|interface| interface.name == connection.interface_name)
+        })
+        .collect();
+    if !orphaned.is_empty() || !zombies.is_empty() {
+        warn!(
+            "Interfaces out of sync with defguard-service, orphaned: {}, zombies: {}",
+            orphaned.len(),
+            zombies.len()
+        );
+    }
+    Ok(ServiceInterfaces {
+        interfaces,
+        orphaned,
+        zombies,
+    })
+}
+
+/// Lists routes installed for interface of connected location, e.g. to see whether
+/// `route_all_traffic` or allowed IPs send traffic through the tunnel.
+#[tauri::command(async)]
+pub async fn location_routes(
+    location_id: i64,
+    app_state: State<'_, AppState>,
+) -> Result<Vec<InterfaceRoute>, Error> {
+    debug!("Listing routes of location {location_id}");
+    let Some(connection) = app_state.find_connection(location_id, ConnectionType::Location) else {
+        error!("Location {location_id} is not connected, can't list its routes");
+        return Err(Error::CommandError("Location is not connected".into()));
+    };
+    let routes = list_interface_routes(&connection.interface_name).await?;
+    info!(
+        "Interface {} of location {location_id} has {} routes",
+        connection.interface_name,
+        routes.len()
+    );
+    Ok(routes)
+}
+
+/// Removes interface through `defguard-service`. Interface of a tracked connection is
+/// disconnected with full teardown of its location and the connection is saved, orphaned
+/// interface is removed without looking up its location. Returns whether a tracked connection
+/// was removed.
+#[tauri::command(async)]
+pub async fn disconnect_by_interface(
+    interface_name: String,
+    handle: AppHandle,
+) -> Result<bool, Error> {
+    let state = handle.state::<AppState>();
+    let removed = state.remove_connections_by_interface(&interface_name);
+    let tracked = !removed.is_empty();
+    let mut result = Ok(());
+    for connection in removed {
+        info!(
+            "Disconnecting {:?} {} using interface {interface_name}",
+            connection.connection_type, connection.location_id
+        );
+        let (location_id, connection_type, profile_id) = (
+            connection.location_id,
+            connection.connection_type.clone(),
+            connection.profile_id,
+        );
+        // connection is saved with service error reason if interface removal fails
+        if let Err(err) =
+            disconnect_interface(connection, &state, DisconnectReason::UserInitiated).await
+        {
+            error!("Failed to disconnect interface {interface_name}: {err}");
+            result = Err(err);
+        }
+        if profile_id.is_none() {
+            state.set_connection_state(location_id, &connection_type, ConnectionState::Idle);
+        }
+    }
+    if !tracked {
+        let request = RemoveInterfaceRequest {
+            interface_name: interface_name.clone(),
+            pre_down: None,
+            post_down: None,
+        };
+        match state.client().remove_interface(request).await {
+            Ok(_) => info!("Removed orphaned interface {interface_name}"),
+            Err(status) => {
+                let err = Error::remove_interface(&status);
+                error!("Failed to remove interface {interface_name}: {err}");
+                return Err(err);
+            }
+        }
+    }
+    let _ = stop_log_watcher_task(handle.clone(), interface_name);
+    if tracked {
+        let payload = Payload {
+            message: "Interface removed".into(),
+        };
+        emit_event(&handle, "connection-changed", payload)?;
+        refresh_tray(&handle);
+    }
+    result.map(|()| tracked)
+}
+
+#[tauri::command]
+pub async fn get_settings(handle: AppHandle) -> Result<Settings, Error> {
+    let app_state = handle.state::<AppState>();
+    let settings = Settings::get(&app_state.get_pool()).await?;
+    Ok(settings)
+}
+
+/// Forgets interface names of locations after interface name template changed.
+/// Connected locations keep their interface, others are named again on next connection.
+async fn forget_interface_names(app_state: &AppState, pool: &DbPool) -> Result<(), Error> {
+    let connected = app_state.get_connection_id_by_type(&ConnectionType::Location);
+    for location in Location::all(pool).await? {
+        let location_id = location.id.expect("Missing location ID");
+        if location.interface_name.is_some() && !connected.contains(&location_id) {
+            Location::set_interface_name(pool, location_id, None).await?;
+        }
+    }
+    info!("Interface name template changed, new connections use new interface names");
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn update_settings(data: SettingsPatch, handle: AppHandle) -> Result<Settings, Error> {
+    let app_state = handle.state::<AppState>();
+    let pool = &app_state.get_pool();
+    trace!("Pool received");
+    let mut settings = Settings::get(pool).await?;
+    trace!("Settings read from table");
+    let previous = settings.clone();
+    settings.apply(data);
+    if settings.stats_interval_seconds < MIN_STATS_INTERVAL_SECONDS {
+        error!(
+            "Invalid stats interval {}s, minimum is {MIN_STATS_INTERVAL_SECONDS}s",
+            settings.stats_interval_seconds
+        );
+        return Err(Error::CommandError(format!(
+            "Stats interval must be at least {MIN_STATS_INTERVAL_SECONDS} second"
+        )));
+    }
+    if settings.connection_timeout_seconds < 1 {
+        error!(
+            "Invalid connection timeout {}s",
+            settings.connection_timeout_seconds
+        );
+        return Err(Error::CommandError(
+            "Connection timeout must be at least 1 second".to_string(),
+        ));
+    }
+    if settings.stale_handshake_seconds < 0 {
+        error!(
+            "Invalid stale handshake threshold {}s",
+            settings.stale_handshake_seconds
+        );
+        return Err(Error::CommandError(
+            "Stale handshake threshold must not be negative".to_string(),
+        ));
+    }
+    if settings.retention_days < 0 {
+        error!("Invalid stats retention {} days", settings.retention_days);
+        return Err(Error::CommandError(
+            "Stats retention must not be negative".to_string(),
+        ));
+    }
+    // empty proxy URL removes proxy
+    settings.proxy_url = normalize_text_setting(settings.proxy_url.take());
+    if let Some(proxy_url) = &settings.proxy_url {
+        validate_proxy_url(proxy_url)?;
+    }
+    // empty fallback DNS removes it, addresses are stored normalized
+    settings.fallback_dns = match settings.fallback_dns.take() {
+        Some(fallback_dns) if !fallback_dns.trim().is_empty() => {
+            let servers = parse_dns_servers(Some(&fallback_dns))?;
+            Some(
+                servers
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(","),
+            )
+        }
+        _ => None,
+    };
+    // empty template restores names derived from location names
+    settings.interface_name_template =
+        normalize_text_setting(settings.interface_name_template.take());
+    if let Some(template) = &settings.interface_name_template {
+        validate_interface_name_template(template)?;
+    }
+    // empty service URL restores default address
+    settings.service_url = normalize_text_setting(settings.service_url.take());
+    if let Some(service_url) = &settings.service_url {
+        ServiceAddress::parse(service_url)?;
+    }
+    settings.public_ip_echo_url = normalize_text_setting(settings.public_ip_echo_url.take());
+    if let Some(echo_url) = &settings.public_ip_echo_url {
+        validate_echo_url(echo_url)?;
+    }
+    if settings.launch_on_startup != previous.launch_on_startup {
+        // registration is changed first, so failure leaves the setting untouched
+        autostart::set_enabled(settings.launch_on_startup)?;
+    }
+    debug!("Saving settings");
+    settings.save(pool).await?;
+    if settings.interface_name_template != previous.interface_name_template {
+        forget_interface_names(&app_state, pool).await?;
+    }
+    apply_settings(&handle, &settings, Some(&previous)).await?;
+    info!("Settings updated");
+    Ok(settings)
+}
+
+/// Applies settings which take effect outside of the database: defguard-service address,
+/// fallback DNS, log level of interface logs and tray icon. Only settings which differ from
+/// `previous` are applied, all of them if there are no previous settings.
+async fn apply_settings(
+    handle: &AppHandle,
+    settings: &Settings,
+    previous: Option<&Settings>,
+) -> Result<(), Error> {
+    let app_state = handle.state::<AppState>();
+    if previous.map_or(true, |previous| {
+        previous.service_url != settings.service_url
+    }) {
+        app_state.set_service_url(settings.service_url.as_deref().unwrap_or(DAEMON_BASE_URL))?;
+    }
+    if previous.map_or(true, |previous| {
+        previous.fallback_dns != settings.fallback_dns
+    }) {
+        push_fallback_dns(settings.fallback_dns.as_deref()).await;
+    }
+    if previous.map_or(true, |previous| previous.log_level != settings.log_level) {
+        // running log watchers apply new level to following log lines
+        app_state
+            .log_watcher_level
+            .send_replace(settings.log_level.clone().into());
+    }
+    debug!("Reconfiguring tray icon");
+    if let Err(err) = update_tray_icon(handle).await {
+        error!("Failed to reconfigure tray icon after settings change: {err}");
+    }
+    debug!("Tray icon updated");
+    Ok(())
+}
+
+/// Restores all settings to their defaults, also when stored settings can't be read.
+#[tauri::command]
+pub async fn reset_settings(handle: AppHandle) -> Result<Settings, Error> {
+    let app_state = handle.state::<AppState>();
+    let pool = &app_state.get_pool();
+    debug!("Resetting settings to defaults");
+    let previous = match Settings::get(pool).await {
+        Ok(settings) => Some(settings),
+        Err(err) => {
+            warn!("Failed to read settings before reset: {err}");
+            None
+        }
+    };
+    if autostart::is_enabled()? {
+        // registration is changed first, so failure leaves settings untouched
+        autostart::set_enabled(false)?;
+    }
+    let settings = Settings::reset(pool).await?;
+    if previous.as_ref().map_or(true, |previous| {
+        previous.service_url != settings.service_url
+    }) {
+        app_state.set_service_url(DAEMON_BASE_URL)?;
+    }
+    if previous.as_ref().map_or(true, |previous| {
+        previous.interface_name_template != settings.interface_name_template
+    }) {
+        forget_interface_names(&app_state, pool).await?;
+    }
+    if previous
+        .as_ref()
+        .map_or(true, |previous| previous.fallback_dns.is_some())
+    {
+        push_fallback_dns(None).await;
+    }
+    app_state
+        .log_watcher_level
+        .send_replace(DEFAULT_LOG_WATCHER_LEVEL);
+    if let Err(err) = update_tray_icon(&handle).await {
+        error!("Failed to reconfigure tray icon after settings reset: {err}");
+    }
+    info!("Settings reset to defaults");
+    Ok(settings)
+}
+
+#[derive(Debug, Serialize)]
+pub struct SettingsChangeImpact {
+    pub location_id: i64,
+    pub connection_type: ConnectionType,
+    pub interface_name: String,
+    pub changed_settings: Vec<String>,
+}
+
+/// Lists active connections which would have to be re-established if `patch` was applied.
+/// Settings are not modified.
+#[tauri::command]
+pub async fn settings_change_impact(
+    patch: SettingsPatch,
+    handle: AppHandle,
+) -> Result<Vec<SettingsChangeImpact>, Error> {
+    let app_state = handle.state::<AppState>();
+    let settings = Settings::get(&app_state.get_pool()).await?;
+    let changed_settings = settings.changed_connection_settings(patch);
+    if changed_settings.is_empty() {
+        debug!("Settings patch doesn't affect active connections");
+        return Ok(Vec::new());
+    }
+    let impact: Vec<SettingsChangeImpact> = app_state
+        .get_connections()
+        .into_iter()
+        .map(|connection| SettingsChangeImpact {
+            location_id: connection.location_id,
+            connection_type: connection.connection_type,
+            interface_name: connection.interface_name,
+            changed_settings: changed_settings.clone(),
+        })
+        .collect();
+    debug!(
+        "Settings change {changed_settings:?} affects {} active connections",
+        impact.len()
+    );
+    Ok(impact)
+}
+
+#[tauri::command(async)]
+pub async fn all_instances_including_archived(
+    app_state: State<'_, AppState>,
+) -> Result<Vec<InstanceInfo>, Error> {
+    debug!("Retrieving all instances including archived ones.");
+    let instances = Instance::all_including_archived(&app_state.get_pool()).await?;
+    let instance_info = instances_info(&app_state, instances).await?;
+    info!("Instances retrieved({})", instance_info.len());
+    Ok(instance_info)
+}
+
+/// Hide instance without removing its data. Active connections of its locations are closed.
+#[tauri::command(async)]
+pub async fn archive_instance(instance_id: i64, handle: AppHandle) -> Result<(), Error> {
+    debug!("Archiving instance {instance_id}");
+    let app_state = handle.state::<AppState>();
+    let pool = app_state.get_pool();
+    let Some(mut instance) = Instance::find_by_id(&pool, instance_id).await? else {
+        error!("Instance {instance_id} not found");
+        return Err(Error::NotFound);
+    };
+    for location in Location::find_by_instance_id(&pool, instance_id).await? {
+        let Some(location_id) = location.id else {
+            continue;
+        };
+        for connection in app_state.remove_location_connections(location_id) {
+            debug!("Closing active connection of location {location}");
+            let interface_name = connection.interface_name.clone();
+            let profile_id = connection.profile_id;
+            disconnect_interface(connection, &app_state, DisconnectReason::UserInitiated).await?;
+            if profile_id.is_none() {
+                app_state.set_connection_state(
+                    location_id,
+                    &ConnectionType::Location,
+                    ConnectionState::Idle,
+                );
+            }
+            let _ = stop_log_watcher_task(handle.clone(), interface_name);
+        }
+    }
+    instance.archived_at = Some(Utc::now().naive_utc());
+    instance.save(&pool).await?;
+    emit_event(&handle, "instance-update", ())?;
+    emit_event(
+        &handle,
+        "connection-changed",
+        Payload {
+            message: "Instance archived".into(),
+        },
+    )?;
+    refresh_tray(&handle);
+    info!("Instance {instance_id} archived");
+    Ok(())
+}
+
+#[tauri::command(async)]
+pub async fn unarchive_instance(instance_id: i64, handle: AppHandle) -> Result<(), Error> {
+    debug!("Restoring archived instance {instance_id}");
+    let app_state = handle.state::<AppState>();
+    let pool = app_state.get_pool();
+    let Some(mut instance) = Instance::find_by_id(&pool, instance_id).await? else {
+        error!("Instance {instance_id} not found");
+        return Err(Error::NotFound);
+    };
+    instance.archived_at = None;
+    instance.save(&pool).await?;
+    emit_event(&handle, "instance-update", ())?;
+    refresh_tray(&handle);
+    info!("Instance {instance_id} restored from archive");
+    Ok(())
+}
+
+/// Delete instance with all its data. Active connections of its locations are closed first.
+#[tauri::command(async)]
+pub async fn delete_instance(instance_id: i64, handle: AppHandle) -> Result<(), Error> {
+    debug!("Deleting instance {instance_id}");
+    let app_state = handle.state::<AppState>();
+    let pool = app_state.get_pool();
+    let Some(instance) = Instance::find_by_id(&pool, instance_id).await? else {
+        error!("Instance {instance_id} not found");
+        return Err(Error::NotFound);
+    };
+    let location_ids: Vec<i64> = Location::find_by_instance_id(&pool, instance_id)
+        .await?
+        .into_iter()
+        .filter_map(|location| location.id)
+        .collect();
+    for location_id in &location_ids {
+        for connection in app_state.remove_location_connections(*location_id) {
+            debug!("Found active connection for location({location_id}), closing...");
+            let interface_name = connection.interface_name.clone();
+            let profile_id = connection.profile_id;
+            if let Err(err) =
+                disconnect_interface(connection, &app_state, DisconnectReason::UserInitiated).await
+            {
+                error!("Failed to remove interface {interface_name}: {err}");
+                return Err(Error::InternalError);
+            }
+            if profile_id.is_none() {
+                app_state.set_connection_state(
+                    *location_id,
+                    &ConnectionType::Location,
+                    ConnectionState::Idle,
+                );
+            }
+            let _ = stop_log_watcher_task(handle.clone(), interface_name);
+            debug!("Connection closed and interface removed");
         }
-    };
-    debug!("Connections received, returning.");
-    trace!("Connections found:\n{:#?}", connections);
-    Ok(connections)
-}
+    }
 
-#[tauri::command]
-pub async fn all_tunnel_connections(
-    location_id: i64,
-    app_state: State<'_, AppState>,
-) -> Result<Vec<TunnelConnectionInfo>, Error> {
-    debug!("Retrieving connections for location {location_id}");
-    let connections =
-        TunnelConnectionInfo::all_by_tunnel_id(&app_state.get_pool(), location_id).await?;
-    debug!("Connections received, returning.");
-    trace!("Connections found:\n{:#?}", connections);
-    Ok(connections)
+    let mut transaction = pool.begin().await?;
+    instance.delete_with_related(&mut transaction).await?;
+    // location could have been connected again while interfaces were removed
+    if app_state
+        .get_connection_id_by_type(&ConnectionType::Location)
+        .iter()
+        .any(|id| location_ids.contains(id))
+    {
+        error!("Instance {instance_id} still has active connections, aborting delete");
+        return Err(Error::InternalError);
+    }
+    transaction.commit().await?;
+
+    emit_event(&handle, "instance-update", ())?;
+    emit_event(
+        &handle,
+        "connection-changed",
+        Payload {
+            message: "Instance deleted".into(),
+        },
+    )?;
+    refresh_tray(&handle);
+    info!("Instance {instance_id}, deleted");
+    Ok(())
 }
 
+/// Start recording events, commands, daemon RPC results and stats for support.
+/// Any capture already in progress is discarded.
 #[tauri::command]
-pub async fn active_connection(
-    location_id: i64,
-    connection_type: ConnectionType,
-    handle: AppHandle,
-) -> Result<Option<ActiveConnection>, Error> {
-    let state = handle.state::<AppState>();
-    debug!("Retrieving active connection for location with id: {location_id}");
-    debug!("Location found");
-    let connection = state.find_connection(location_id, connection_type);
-    if connection.is_some() {
-        debug!("Active connection found");
+pub async fn start_issue_capture(handle: AppHandle) -> Result<(), Error> {
+    let app_state = handle.state::<AppState>();
+    let app_version = handle.package_info().version.to_string();
+    let mut issue_capture = app_state
+        .issue_capture
+        .lock()
+        .map_err(|_| Error::MutexError)?;
+    if issue_capture
+        .replace(IssueCapture::new(app_version))
+        .is_some()
+    {
+        warn!("Issue capture already in progress, discarding previous capture");
     }
-    trace!("Connection:\n{:#?}", connection);
-    debug!("Connection returned");
-    Ok(connection)
+    info!("Issue capture started");
+    Ok(())
 }
 
+/// Stop recording and save captured session. Returns path to the session file.
 #[tauri::command]
-pub async fn last_connection(
-    location_id: i64,
-    connection_type: ConnectionType,
-    app_state: State<'_, AppState>,
-) -> Result<Option<CommonConnection>, Error> {
-    debug!("Retrieving last connection for location {location_id} with type {connection_type:?}");
-    if connection_type == ConnectionType::Location {
-        if let Some(connection) =
-            Connection::latest_by_location_id(&app_state.get_pool(), location_id).await?
-        {
-            trace!("Connection found");
-            Ok(Some(connection.into()))
-        } else {
-            Ok(None)
-        }
-    } else if let Some(connection) =
-        TunnelConnection::latest_by_tunnel_id(&app_state.get_pool(), location_id).await?
-    {
-        trace!("Connection found");
-        Ok(Some(connection.into()))
+pub async fn stop_issue_capture(handle: AppHandle) -> Result<String, Error> {
+    let app_state = handle.state::<AppState>();
+    let capture = app_state
+        .issue_capture
+        .lock()
+        .map_err(|_| Error::MutexError)?
+        .take();
+    if let Some(capture) = capture {
+        let path = capture.save(&handle)?;
+        Ok(path.to_string_lossy().into_owned())
     } else {
-        Ok(None)
+        error!("No issue capture in progress");
+        Err(Error::NotFound)
     }
 }
 
-#[tauri::command]
-pub async fn update_location_routing(
+/// Exports location as `wg-quick` compatible config.
+/// Private key is only included when explicitly requested.
+#[tauri::command(async)]
+pub async fn export_location_config(
     location_id: i64,
-    route_all_traffic: bool,
-    connection_type: ConnectionType,
+    include_private_key: Option<bool>,
     handle: AppHandle,
-) -> Result<(), Error> {
+) -> Result<String, Error> {
     let app_state = handle.state::<AppState>();
-    debug!("Updating location routing {location_id} with {connection_type:?}");
-
-    match connection_type {
-        ConnectionType::Location => {
-            if let Some(mut location) =
-                Location::find_by_id(&app_state.get_pool(), location_id).await?
-            {
-                location.route_all_traffic = route_all_traffic;
-                location.save(&app_state.get_pool()).await?;
-                handle.emit_all(
-                    "location-update",
-                    Payload {
-                        message: "Location routing updated".into(),
-                    },
-                )?;
-                Ok(())
-            } else {
-                error!("Location with id: {location_id} not found.");
-                Err(Error::NotFound)
-            }
-        }
-        ConnectionType::Tunnel => {
-            if let Some(mut tunnel) = Tunnel::find_by_id(&app_state.get_pool(), location_id).await?
-            {
-                tunnel.route_all_traffic = route_all_traffic;
-                tunnel.save(&app_state.get_pool()).await?;
-                handle.emit_all(
-                    "location-update",
-                    Payload {
-                        message: "Tunnel routing updated".into(),
-                    },
-                )?;
-                Ok(())
-            } else {
-                error!("Tunnel with id: {location_id} not found.");
-                Err(Error::NotFound)
-            }
-        }
-    }
+    let include_private_key = include_private_key.unwrap_or_default();
+    debug!(
+        "Exporting config of location {location_id}, including private key: {include_private_key}"
+    );
+    let (location, config) =
+        location_config(&app_state.get_pool(), location_id, include_private_key).await?;
+    info!("Exported config of location {location}");
+    Ok(config)
 }
 
-#[tauri::command]
-pub async fn get_settings(handle: AppHandle) -> Result<Settings, Error> {
+/// Encodes WireGuard config of location as QR code SVG, for scanning with mobile clients.
+/// Like export, private key is only included when explicitly requested.
+#[tauri::command(async)]
+pub async fn location_config_qr(
+    location_id: i64,
+    include_private_key: Option<bool>,
+    handle: AppHandle,
+) -> Result<String, Error> {
     let app_state = handle.state::<AppState>();
-    let settings = Settings::get(&app_state.get_pool()).await?;
-    Ok(settings)
+    let include_private_key = include_private_key.unwrap_or_default();
+    debug!(
+        "Generating config QR code of location {location_id}, including private key: {include_private_key}"
+    );
+    let (location, config) =
+        location_config(&app_state.get_pool(), location_id, include_private_key).await?;
+    let code = QrCode::new(config.as_bytes()).map_err(|err| {
+        error!("Failed to encode config of location {location} as QR code: {err}");
+        Error::CommandError(err.to_string())
+    })?;
+    let svg = code.render::<svg::Color>().min_dimensions(256, 256).build();
+    info!("Generated config QR code of location {location}");
+    Ok(svg)
 }
 
-#[tauri::command]
-pub async fn update_settings(data: SettingsPatch, handle: AppHandle) -> Result<Settings, Error> {
-    let app_state = handle.state::<AppState>();
-    let pool = &app_state.get_pool();
-    trace!("Pool received");
-    let mut settings = Settings::get(pool).await?;
-    trace!("Settings read from table");
-    settings.apply(data);
-    debug!("Saving settings");
-    settings.save(pool).await?;
-    debug!("Settings saved, reconfiguring tray icon.");
-    match configure_tray_icon(&handle, &settings.tray_icon_theme) {
-        Ok(_) => {}
-        Err(e) => {
-            error!(
-                "During settings update, tray configuration update failed. err : {}",
-                e.to_string()
-            );
-        }
+/// Builds WireGuard config of location shared by export and QR code.
+async fn location_config(
+    pool: &DbPool,
+    location_id: i64,
+    include_private_key: bool,
+) -> Result<(Location, String), Error> {
+    let Some(location) = Location::find_by_id(pool, location_id).await? else {
+        error!("Location with id: {location_id} not found.");
+        return Err(Error::NotFound);
+    };
+    let prvkey = if include_private_key {
+        let Some(keys) = WireguardKeys::find_by_instance_id(pool, location.instance_id).await?
+        else {
+            error!("No keys found for instance: {}", location.instance_id);
+            return Err(Error::NotFound);
+        };
+        Some(keys.prvkey)
+    } else {
+        None
+    };
+    let config = location_to_wireguard_config(&location, prvkey.as_deref())?;
+    Ok((location, config))
+}
+
+/// Public IP of traffic leaving through location tunnel
+#[derive(Debug, Serialize)]
+pub struct TunnelPublicIp {
+    // address observed by echo service, `None` if it couldn't be checked
+    pub public_ip: Option<String>,
+    // address of the host in its local network
+    pub local_ip: Option<String>,
+    // public IP differs from local address
+    pub differs_from_local: bool,
+    // reason why public IP couldn't be checked
+    pub error: Option<String>,
+}
+
+/// Checks public IP of traffic sent through tunnel of connected location using configured
+/// echo service, so the UI can show where the traffic exits. Failed request is reported
+/// in the result instead of failing the command. Echo service is reached through the tunnel
+/// only if location routes all traffic, split tunnel doesn't route its address.
+#[tauri::command(async)]
+pub async fn tunnel_public_ip(
+    location_id: i64,
+    app_state: State<'_, AppState>,
+) -> Result<TunnelPublicIp, Error> {
+    debug!("Checking public IP of location {location_id} tunnel");
+    let pool = app_state.get_pool();
+    let Some(connection) = app_state.find_connection(location_id, ConnectionType::Location) else {
+        error!("Location {location_id} is not connected, can't check its public IP");
+        return Err(Error::CommandError("Location is not connected".into()));
+    };
+    let Some(location) = Location::find_by_id(&pool, location_id).await? else {
+        error!("Location with id: {location_id} not found.");
+        return Err(Error::NotFound);
+    };
+    let local_ip = local_ip().ok();
+    if !location.route_all_traffic {
+        warn!("Location {location} doesn't route all traffic, can't check its public IP");
+        return Ok(TunnelPublicIp {
+            public_ip: None,
+            local_ip: local_ip.map(|ip| ip.to_string()),
+            differs_from_local: false,
+            error: Some("Public IP can only be checked for locations routing all traffic".into()),
+        });
     }
-    debug!("Tray icon updated");
-    info!("Settings updated");
-    Ok(settings)
+    // device registered with profile key has its own address
+    let address = match connection.profile_id {
+        Some(profile_id) => WireguardKeys::find_by_id(&pool, profile_id)
+            .await?
+            .and_then(|keys| keys.address),
+        None => None,
+    }
+    .unwrap_or_else(|| location.address.clone());
+    let tunnel_address =
+        IpAddrMask::from_str(address.split(',').next().unwrap_or_default().trim())?.ip;
+    let echo_url = Settings::get(&pool)
+        .await?
+        .public_ip_echo_url
+        .unwrap_or_else(|| DEFAULT_PUBLIC_IP_ECHO_URL.into());
+    let (public_ip, error) = match fetch_public_ip(&echo_url, tunnel_address).await {
+        Ok(public_ip) => {
+            info!("Traffic of location {location} exits from {public_ip}");
+            (Some(public_ip), None)
+        }
+        Err(err) => (None, Some(err.to_string())),
+    };
+    Ok(TunnelPublicIp {
+        public_ip: public_ip.map(|ip| ip.to_string()),
+        local_ip: local_ip.map(|ip| ip.to_string()),
+        differs_from_local: public_ip.is_some() && public_ip != local_ip,
+        error,
+    })
+}
+
+/// Integrity of key pair stored for instance
+#[derive(Debug, Serialize)]
+pub struct KeysValidation {
+    // private key is a base64 encoded 32 byte WireGuard key
+    pub valid: bool,
+    // public key derived from private key equals stored public key
+    pub pubkey_matches: bool,
 }
 
+/// Checks that stored key pair of instance is usable, so corrupted keys are found
+/// before they show up as failed handshakes.
 #[tauri::command(async)]
-pub async fn delete_instance(instance_id: i64, handle: AppHandle) -> Result<(), Error> {
-    debug!("Deleting instance {instance_id}");
-    let app_state = handle.state::<AppState>();
-    let mut client = app_state.client.clone();
-    let pool = &app_state.get_pool();
-    if let Some(instance) = Instance::find_by_id(pool, instance_id).await? {
-        let instance_locations = Location::find_by_instance_id(pool, instance_id).await?;
-        for location in instance_locations.iter() {
-            if let Some(location_id) = location.id {
-                if let Some(connection) =
-                    app_state.find_and_remove_connection(location_id, &ConnectionType::Location)
-                {
-                    debug!("Found active connection for location({location_id}), closing...",);
-                    let request = RemoveInterfaceRequest {
-                        interface_name: connection.interface_name.clone(),
-                        pre_down: None,
-                        post_down: None,
-                    };
-                    client
-                        .remove_interface(request)
-                        .await
-                        .map_err(|_| Error::InternalError)?;
-                    debug!("Connection closed and interface removed");
-                }
+pub async fn validate_keys(
+    instance_id: i64,
+    app_state: State<'_, AppState>,
+) -> Result<KeysValidation, Error> {
+    debug!("Validating keys of instance {instance_id}");
+    let Some(keys) = WireguardKeys::find_by_instance_id(&app_state.get_pool(), instance_id).await?
+    else {
+        error!("No keys found for instance: {instance_id}");
+        return Err(Error::NotFound);
+    };
+    let validation = match derive_pubkey(&keys.prvkey) {
+        Ok(pubkey) => KeysValidation {
+            valid: true,
+            pubkey_matches: pubkey == keys.pubkey,
+        },
+        Err(err) => {
+            warn!("Private key of instance {instance_id} is invalid: {err}");
+            KeysValidation {
+                valid: false,
+                pubkey_matches: false,
             }
         }
-        instance.delete(pool).await?;
-    } else {
-        error!("Instance {instance_id} not found");
-        return Err(Error::NotFound);
+    };
+    if validation.valid && !validation.pubkey_matches {
+        warn!("Public key of instance {instance_id} doesn't match its private key");
     }
-    handle.emit_all("instance-update", ())?;
-    info!("Instance {instance_id}, deleted");
-    Ok(())
+    info!("Validated keys of instance {instance_id}: {validation:?}");
+    Ok(validation)
+}
+
+/// Exports instances, locations and settings as JSON backup.
+/// Keys are only included when explicitly requested.
+#[tauri::command(async)]
+pub async fn export_configuration(
+    include_secrets: Option<bool>,
+    handle: AppHandle,
+) -> Result<String, Error> {
+    let app_state = handle.state::<AppState>();
+    let include_secrets = include_secrets.unwrap_or_default();
+    debug!("Exporting configuration, including secrets: {include_secrets}");
+    let backup = backup::export_configuration(&app_state.get_pool(), include_secrets).await?;
+    info!(
+        "Exported configuration of {} instances",
+        backup.instances.len()
+    );
+    Ok(serde_json::to_string_pretty(&backup)?)
+}
+
+/// Restores configuration from JSON backup, skipping instances which already exist.
+#[tauri::command(async)]
+pub async fn import_configuration(
+    json: String,
+    include_secrets: Option<bool>,
+    handle: AppHandle,
+) -> Result<ImportSummary, Error> {
+    let app_state = handle.state::<AppState>();
+    let include_secrets = include_secrets.unwrap_or_default();
+    debug!("Importing configuration, including secrets: {include_secrets}");
+    let backup = backup::parse_backup(&json)?;
+    let pool = app_state.get_pool();
+    let summary = backup::import_configuration(&pool, backup, include_secrets).await?;
+    info!(
+        "Imported {} instances, skipped {} existing ones",
+        summary.imported.len(),
+        summary.skipped.len()
+    );
+    emit_event(&handle, "instance-update", ())?;
+    refresh_tray(&handle);
+    Ok(summary)
 }
+
 #[tauri::command(async)]
 pub async fn parse_tunnel_config(config: String) -> Result<Tunnel, Error> {
     debug!("Parsing config file");
@@ -641,7 +3656,8 @@ pub async fn save_tunnel(mut tunnel: Tunnel, handle: AppHandle) -> Result<(), Er
     debug!("Received tunnel configuration: {tunnel:#?}");
     tunnel.save(&app_state.get_pool()).await?;
     info!("Saved tunnel {tunnel:#?}");
-    handle.emit_all(
+    emit_event(
+        &handle,
         "location-update",
         Payload {
             message: "Tunnel saved".into(),
@@ -703,7 +3719,7 @@ pub async fn tunnel_details(
 pub async fn delete_tunnel(tunnel_id: i64, handle: AppHandle) -> Result<(), Error> {
     debug!("Deleting tunnel {tunnel_id}");
     let app_state = handle.state::<AppState>();
-    let mut client = app_state.client.clone();
+    let mut client = app_state.client();
     let pool = &app_state.get_pool();
     if let Some(tunnel) = Tunnel::find_by_id(pool, tunnel_id).await? {
         if let Some(connection) =
@@ -718,7 +3734,7 @@ pub async fn delete_tunnel(tunnel_id: i64, handle: AppHandle) -> Result<(), Erro
             client
                 .remove_interface(request)
                 .await
-                .map_err(|_| Error::InternalError)?;
+                .map_err(|status| Error::remove_interface(&status))?;
             debug!("Connection closed and interface removed");
         }
         tunnel.delete(pool).await?;
@@ -761,7 +3777,7 @@ pub async fn get_latest_app_version(handle: AppHandle) -> Result<AppVersionInfo,
 
     info!("Fetching latest application version with args: current version {current_version} and operating system {operating_system}");
 
-    let client = reqwest::Client::new();
+    let client = http_client(&handle.state::<AppState>().get_pool()).await?;
     let res = client
         .post("https://pkgs.defguard.net/api/update/check")
         .json(&request_data)
@@ -782,3 +3798,209 @@ pub async fn get_latest_app_version(handle: AppHandle) -> Result<AppVersionInfo,
         Err(Error::CommandError(err.to_string()))
     }
 }
+
+/// Estimate battery/power impact of active connections.
+#[tauri::command(async)]
+pub async fn power_impact(app_state: State<'_, AppState>) -> Result<PowerImpact, Error> {
+    debug!("Estimating power impact of active connections");
+    let connections = app_state.get_connections();
+    let impact = estimate_power_impact(&app_state.get_pool(), &connections).await?;
+    info!(
+        "Estimated power impact of {} active connections: {:?}",
+        connections.len(),
+        impact.rating
+    );
+    Ok(impact)
+}
+
+#[tauri::command]
+pub async fn list_data_profiles(handle: AppHandle) -> Result<Vec<DataProfile>, Error> {
+    debug!("Listing data profiles");
+    list_profiles(&handle)
+}
+
+#[tauri::command]
+pub async fn create_data_profile(name: String, handle: AppHandle) -> Result<(), Error> {
+    debug!("Creating data profile {name}");
+    validate_profile_name(&name)?;
+    if profile_exists(&handle, &name) {
+        error!("Data profile {name} already exists");
+        return Err(Error::InvalidDataProfile(format!(
+            "{name}: profile already exists"
+        )));
+    }
+    // initialize profile database right away so it's ready to switch to
+    let pool = open_db(&profile_dir(&handle, &name)?).await?;
+    pool.close().await;
+    info!("Data profile {name} created");
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn delete_data_profile(name: String, handle: AppHandle) -> Result<(), Error> {
+    debug!("Deleting data profile {name}");
+    delete_profile(&handle, &name)?;
+    info!("Data profile {name} deleted");
+    Ok(())
+}
+
+/// Disconnect all tunnels and replace database pool with the one of selected profile.
+/// Nothing of the previous profile is carried over: buffered statistics are written to its
+/// database, state of its locations is forgotten and settings of the new profile are applied.
+#[tauri::command]
+pub async fn switch_data_profile(name: String, handle: AppHandle) -> Result<(), Error> {
+    debug!("Switching data profile to {name}");
+    if !profile_exists(&handle, &name) {
+        error!("Data profile {name} not found");
+        return Err(Error::NotFound);
+    }
+    let app_state = handle.state::<AppState>();
+    let new_pool = open_db(&profile_dir(&handle, &name)?).await?;
+    // pending connection attempts would create connections after closing them
+    app_state.forget_location_state();
+    let connections = app_state.get_connections();
+    let failed = app_state.close_all_connections().await;
+    for connection in connections {
+        if !failed.contains(&connection.interface_name) {
+            let _ = stop_log_watcher_task(handle.clone(), connection.interface_name);
+        }
+    }
+    if !failed.is_empty() {
+        // switching would leave these interfaces up without any way to close them
+        new_pool.close().await;
+        emit_event(
+            &handle,
+            "connection-changed",
+            Payload {
+                message: "Connections closed".into(),
+            },
+        )?;
+        refresh_tray(&handle);
+        return Err(Error::CommandError(format!(
+            "Failed to disconnect interfaces: {}",
+            failed.join(", ")
+        )));
+    }
+
+    let previous_pool = app_state.get_pool();
+    if let Err(err) = app_state.stats_buffer.flush(&previous_pool).await {
+        error!("Failed to write statistics of previous data profile, dropping them: {err}");
+    }
+    app_state.stats_buffer.clear();
+    // closed connections are no longer paused, so they aren't resumed after switching back
+    if let Err(err) = PausedConnection::delete_all(&previous_pool).await {
+        error!("Failed to forget paused connections of previous data profile: {err}");
+    }
+    // state may have been updated by connections closed in the meantime
+    app_state.forget_location_state();
+    let previous_pool = app_state
+        .db
+        .lock()
+        .expect("Failed to lock dbpool mutex")
+        .replace(new_pool);
+    if let Some(previous_pool) = previous_pool {
+        previous_pool.close().await;
+    }
+    set_active_profile(&handle, &name)?;
+
+    let settings = Settings::get(&app_state.get_pool()).await?;
+    // profile is switched already, so launch at login failure doesn't fail the switch
+    match autostart::is_enabled() {
+        Ok(enabled) if enabled != settings.launch_on_startup => {
+            if let Err(err) = autostart::set_enabled(settings.launch_on_startup) {
+                error!("Failed to apply launch at login setting of data profile {name}: {err}");
+            }
+        }
+        Ok(_) => {}
+        Err(err) => error!("Failed to read launch at login registration: {err}"),
+    }
+    apply_settings(&handle, &settings, None).await?;
+    // locations come from the new profile
+    refresh_tray(&handle);
+    info!("Switched data profile to {name}");
+    emit_event(&handle, "data-profile-changed", &name)?;
+    Ok(())
+}
+
+/// Verify that WireGuard backend is available on this platform.
+/// Emits `driver-missing` event if it's not, so connecting can be blocked in UI.
+#[tauri::command]
+pub async fn check_wireguard_driver(handle: AppHandle) -> Result<(), Error> {
+    debug!("Checking WireGuard driver");
+    if let Err(err) = check_wireguard_backend() {
+        error!("{err}");
+        emit_event(
+            &handle,
+            "driver-missing",
+            Payload {
+                message: err.to_string(),
+            },
+        )?;
+        return Err(err);
+    }
+    info!("WireGuard driver is available");
+    Ok(())
+}
+
+/// Returns current state of location connection and how long it's been in that state.
+#[tauri::command]
+pub async fn connection_state(
+    location_id: i64,
+    connection_type: ConnectionType,
+    app_state: State<'_, AppState>,
+) -> Result<ConnectionStateInfo, Error> {
+    debug!("Getting connection state of location {location_id} {connection_type:?}");
+    Ok(app_state.connection_state(location_id, &connection_type))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(collected_at: NaiveDateTime) -> CommonLocationStats {
+        CommonLocationStats {
+            id: None,
+            location_id: 1,
+            upload: 0,
+            download: 0,
+            last_handshake: 0,
+            collected_at,
+            listen_port: 51820,
+            persistent_keepalive_interval: None,
+            latency_ms: None,
+            upload_rate: 0,
+            download_rate: 0,
+            connection_type: ConnectionType::Location,
+        }
+    }
+
+    #[test]
+    fn test_find_stats_gaps() {
+        let start = chrono::NaiveDate::from_ymd_opt(2024, 2, 10)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        let interval = Duration::minutes(1);
+        let at = |minutes| stats(start + Duration::minutes(minutes));
+        let gaps = |stats: &[CommonLocationStats]| -> Vec<(NaiveDateTime, NaiveDateTime)> {
+            find_stats_gaps(stats, interval)
+                .into_iter()
+                .map(|gap| (gap.start, gap.end))
+                .collect()
+        };
+        assert!(gaps(&[]).is_empty());
+        assert!(gaps(&[at(0)]).is_empty());
+        assert!(gaps(&[at(0), at(1), at(2)]).is_empty());
+        // gap has to be longer than `STATS_GAP_INTERVALS` expected intervals
+        assert!(gaps(&[at(0), at(3)]).is_empty());
+        let after_threshold = stats(start + Duration::minutes(3) + Duration::seconds(1));
+        assert_eq!(
+            gaps(&[at(0), after_threshold]),
+            [(start, start + Duration::minutes(3) + Duration::seconds(1))]
+        );
+        assert_eq!(
+            gaps(&[at(10), at(0), at(1)]),
+            [(start + Duration::minutes(1), start + Duration::minutes(10))]
+        );
+    }
+}
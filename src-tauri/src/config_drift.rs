@@ -0,0 +1,234 @@
+//! Detection of location config changes on defguard server
+//!
+//! Current device config is fetched from enrollment proxy with an enrollment token, the same way
+//! as when instance is updated, but it's only compared with stored locations. Nothing is saved,
+//! so the user can decide whether to update the instance.
+
+use reqwest::header::{COOKIE, SET_COOKIE};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::{
+    commands::device_config_to_location,
+    database::{DbPool, Instance, Location},
+    error::Error,
+    proto::{DeviceConfig, DeviceConfigResponse},
+    proxy::http_client,
+};
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DriftStatus {
+    Added,
+    Removed,
+    Changed,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FieldChange {
+    pub field: &'static str,
+    pub current: Value,
+    pub updated: Value,
+}
+
+/// Difference between stored location and its config on defguard server.
+#[derive(Debug, Serialize)]
+pub struct LocationDrift {
+    pub network_id: i64,
+    // `None` for locations which aren't stored yet
+    pub location_id: Option<i64>,
+    pub name: String,
+    pub status: DriftStatus,
+    pub changes: Vec<FieldChange>,
+}
+
+/// Changes which updating instance would make, previewed without saving them.
+#[derive(Debug, Serialize)]
+pub struct InstanceUpdatePreview {
+    pub instance_changes: Vec<FieldChange>,
+    pub locations: Vec<LocationDrift>,
+}
+
+fn request_error(err: reqwest::Error) -> Error {
+    error!("Request to enrollment proxy failed: {err}");
+    Error::CommandError(err.to_string())
+}
+
+/// Fetches current device config of instance from enrollment proxy.
+pub async fn fetch_device_config(
+    pool: &DbPool,
+    instance: &Instance,
+    pubkey: &str,
+    token: &str,
+) -> Result<DeviceConfigResponse, Error> {
+    let base_url = format!(
+        "{}/api/v1/enrollment",
+        instance.proxy_url.trim_end_matches('/')
+    );
+    let client = http_client(pool).await?;
+    debug!("Starting enrollment session at {base_url}");
+    let response = client
+        .post(format!("{base_url}/start"))
+        .json(&json!({ "token": token }))
+        .send()
+        .await
+        .map_err(request_error)?;
+    if !response.status().is_success() {
+        error!("Enrollment proxy rejected token: {}", response.status());
+        return Err(Error::CommandError(format!(
+            "Enrollment proxy responded with {}",
+            response.status()
+        )));
+    }
+    // only the session cookie itself is sent back, without its attributes
+    let cookie = response
+        .headers()
+        .get(SET_COOKIE)
+        .and_then(|cookie| cookie.to_str().ok())
+        .and_then(|cookie| cookie.split(';').next())
+        .unwrap_or_default()
+        .to_string();
+    let session: Value = response.json().await.map_err(request_error)?;
+    if session["instance"]["id"].as_str() != Some(instance.uuid.as_str()) {
+        error!(
+            "Enrollment token doesn't belong to instance {}",
+            instance.name
+        );
+        return Err(Error::CommandError(format!(
+            "Token doesn't belong to instance {}",
+            instance.name
+        )));
+    }
+    let response = client
+        .post(format!("{base_url}/network_info"))
+        .header(COOKIE, cookie)
+        .json(&json!({ "pubkey": pubkey }))
+        .send()
+        .await
+        .map_err(request_error)?;
+    if !response.status().is_success() {
+        error!("Failed to fetch network info: {}", response.status());
+        return Err(Error::CommandError(format!(
+            "Enrollment proxy responded with {}",
+            response.status()
+        )));
+    }
+    response.json().await.map_err(request_error)
+}
+
+fn compare<T: Serialize + PartialEq>(
+    changes: &mut Vec<FieldChange>,
+    field: &'static str,
+    current: &T,
+    updated: &T,
+) {
+    if current != updated {
+        changes.push(FieldChange {
+            field,
+            current: json!(current),
+            updated: json!(updated),
+        });
+    }
+}
+
+/// Lists instance fields which are overwritten when instance is updated and differ.
+#[must_use]
+pub fn instance_changes(current: &Instance, updated: &Instance) -> Vec<FieldChange> {
+    let mut changes = Vec::new();
+    compare(&mut changes, "name", &current.name, &updated.name);
+    compare(&mut changes, "url", &current.url, &updated.url);
+    compare(
+        &mut changes,
+        "proxy_url",
+        &current.proxy_url,
+        &updated.proxy_url,
+    );
+    compare(
+        &mut changes,
+        "username",
+        &current.username,
+        &updated.username,
+    );
+    changes
+}
+
+/// Lists fields which are overwritten when instance is updated and differ between locations.
+fn changed_fields(current: &Location, updated: &Location) -> Vec<FieldChange> {
+    let mut changes = Vec::new();
+    compare(&mut changes, "name", &current.name, &updated.name);
+    compare(&mut changes, "address", &current.address, &updated.address);
+    compare(&mut changes, "pubkey", &current.pubkey, &updated.pubkey);
+    compare(
+        &mut changes,
+        "endpoint",
+        &current.endpoint,
+        &updated.endpoint,
+    );
+    compare(
+        &mut changes,
+        "allowed_ips",
+        &current.allowed_ips,
+        &updated.allowed_ips,
+    );
+    compare(
+        &mut changes,
+        "mfa_enabled",
+        &current.mfa_enabled,
+        &updated.mfa_enabled,
+    );
+    compare(
+        &mut changes,
+        "keepalive_interval",
+        &current.keepalive_interval,
+        &updated.keepalive_interval,
+    );
+    changes
+}
+
+/// Compares stored locations of instance with configs received from defguard server.
+/// Locations imported from config files aren't managed by defguard, so they're ignored.
+#[must_use]
+pub fn location_drift(
+    mut current_locations: Vec<Location>,
+    configs: Vec<DeviceConfig>,
+    instance_id: i64,
+) -> Vec<LocationDrift> {
+    current_locations.retain(|location| !location.imported);
+    let mut drift = Vec::new();
+    for config in configs {
+        let updated = device_config_to_location(config, instance_id);
+        match current_locations
+            .iter()
+            .position(|location| location.network_id == updated.network_id)
+        {
+            Some(position) => {
+                let current = current_locations.remove(position);
+                let changes = changed_fields(&current, &updated);
+                if !changes.is_empty() {
+                    drift.push(LocationDrift {
+                        network_id: current.network_id,
+                        location_id: current.id,
+                        name: current.name,
+                        status: DriftStatus::Changed,
+                        changes,
+                    });
+                }
+            }
+            None => drift.push(LocationDrift {
+                network_id: updated.network_id,
+                location_id: None,
+                name: updated.name,
+                status: DriftStatus::Added,
+                changes: Vec::new(),
+            }),
+        }
+    }
+    drift.extend(current_locations.into_iter().map(|location| LocationDrift {
+        network_id: location.network_id,
+        location_id: location.id,
+        name: location.name,
+        status: DriftStatus::Removed,
+        changes: Vec::new(),
+    }));
+    drift
+}
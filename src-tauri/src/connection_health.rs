@@ -0,0 +1,170 @@
+//! Connection health of locations
+//!
+//! The most recent location stats are summarized into a single health state, so the UI can show
+//! a status dot instead of raw handshake and transfer numbers.
+
+use std::time::Duration;
+
+use chrono::Utc;
+use serde::Serialize;
+use sqlx::query;
+
+use crate::{
+    database::{models::settings::DEFAULT_STALE_HANDSHAKE_SECONDS, DbPool, Settings},
+    error::Error,
+};
+
+// round-trip time above which connection is degraded
+const DEGRADED_LATENCY_MS: i64 = 300;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionHealth {
+    // recent handshake, idle tunnel is healthy as well
+    Healthy,
+    // recent handshake, but high latency
+    Degraded,
+    // no handshake within stale handshake threshold
+    Stale,
+    Disconnected,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LocationHealth {
+    pub health: ConnectionHealth,
+    pub last_handshake: Option<i64>,
+    pub latency_ms: Option<i64>,
+}
+
+/// Single location stats sample
+struct StatsSample {
+    last_handshake: i64,
+    latency_ms: Option<i64>,
+}
+
+/// Returns the most recent stats sample of location written to the database.
+async fn latest_sample(pool: &DbPool, location_id: i64) -> Result<Option<StatsSample>, Error> {
+    let sample = query!(
+        "SELECT last_handshake, latency_ms FROM location_stats \
+        WHERE location_id = $1 ORDER BY collected_at DESC LIMIT 1;",
+        location_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .map(|record| StatsSample {
+        last_handshake: record.last_handshake,
+        latency_ms: record.latency_ms,
+    });
+    Ok(sample)
+}
+
+/// Classifies connection from its most recent stats sample. Connection without any handshake
+/// yet is stale, so is connection whose latest handshake is older than `stale_after`.
+/// Handshakes are renewed while the tunnel works even without traffic, so an idle tunnel
+/// with a recent handshake is healthy.
+fn assess_health(
+    connected: bool,
+    latest: Option<&StatsSample>,
+    stale_after: Duration,
+    now: i64,
+) -> ConnectionHealth {
+    if !connected {
+        return ConnectionHealth::Disconnected;
+    }
+    let Some(latest) = latest else {
+        return ConnectionHealth::Stale;
+    };
+    let handshake_age = now - latest.last_handshake;
+    if latest.last_handshake == 0 || handshake_age > stale_after.as_secs() as i64 {
+        return ConnectionHealth::Stale;
+    }
+    if latest
+        .latency_ms
+        .is_some_and(|latency_ms| latency_ms > DEGRADED_LATENCY_MS)
+    {
+        ConnectionHealth::Degraded
+    } else {
+        ConnectionHealth::Healthy
+    }
+}
+
+/// Health of location connection based on its latest stats.
+pub async fn location_health(
+    pool: &DbPool,
+    location_id: i64,
+    connected: bool,
+) -> Result<LocationHealth, Error> {
+    // samples are flushed from stats buffer every few seconds, which is well below
+    // stale handshake threshold
+    let latest = latest_sample(pool, location_id).await?;
+    // disabled stale handshake check still needs some threshold
+    let stale_after = Settings::stale_handshake(pool).await.map_or(
+        Duration::from_secs(DEFAULT_STALE_HANDSHAKE_SECONDS as u64),
+        |(stale_after, _)| stale_after,
+    );
+    let health = assess_health(
+        connected,
+        latest.as_ref(),
+        stale_after,
+        Utc::now().timestamp(),
+    );
+    let latest = latest.as_ref();
+    Ok(LocationHealth {
+        health,
+        last_handshake: latest
+            .map(|sample| sample.last_handshake)
+            .filter(|handshake| *handshake > 0),
+        latency_ms: latest.and_then(|sample| sample.latency_ms),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(last_handshake: i64, latency_ms: Option<i64>) -> StatsSample {
+        StatsSample {
+            last_handshake,
+            latency_ms,
+        }
+    }
+
+    #[test]
+    fn test_assess_health() {
+        let stale_after = Duration::from_secs(180);
+        let now = 10_000;
+        let fresh = sample(now - 10, Some(20));
+        assert_eq!(
+            assess_health(false, Some(&fresh), stale_after, now),
+            ConnectionHealth::Disconnected
+        );
+        assert_eq!(
+            assess_health(true, Some(&fresh), stale_after, now),
+            ConnectionHealth::Healthy
+        );
+        // idle tunnel without measured latency
+        let idle = sample(now - 10, None);
+        assert_eq!(
+            assess_health(true, Some(&idle), stale_after, now),
+            ConnectionHealth::Healthy
+        );
+        let slow = sample(now - 10, Some(500));
+        assert_eq!(
+            assess_health(true, Some(&slow), stale_after, now),
+            ConnectionHealth::Degraded
+        );
+        let old = sample(now - 200, None);
+        assert_eq!(
+            assess_health(true, Some(&old), stale_after, now),
+            ConnectionHealth::Stale
+        );
+        assert_eq!(
+            assess_health(true, None, stale_after, now),
+            ConnectionHealth::Stale
+        );
+        assert_eq!(
+            assess_health(true, Some(&sample(0, None)), stale_after, now),
+            ConnectionHealth::Stale
+        );
+    }
+}
@@ -0,0 +1,148 @@
+//! Tracking of connection states
+//!
+//! Connection attempts are tracked so the UI can tell whether a location is being connected,
+//! and a watchdog resolves connection attempts which got stuck without result.
+
+use std::time::Duration;
+
+use chrono::{Duration as ChronoDuration, NaiveDateTime, Utc};
+use serde::Serialize;
+use tauri::{AppHandle, Manager};
+use tokio::time::interval;
+
+use crate::{
+    appstate::AppState,
+    commands::Payload,
+    database::{DbPool, Location, Settings},
+    issue_capture::emit_event,
+    tray::refresh_tray,
+    utils::location_endpoints,
+    ConnectionType,
+};
+
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(5);
+// time for setting up interface on top of waiting for handshakes
+const CONNECT_TIMEOUT_MARGIN_SECONDS: i64 = 10;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "state", content = "error", rename_all = "snake_case")]
+pub enum ConnectionState {
+    Idle,
+    Connecting,
+    Connected,
+    Error(String),
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ConnectionStateInfo {
+    #[serde(flatten)]
+    pub state: ConnectionState,
+    pub since: NaiveDateTime,
+    // how long (in seconds) connection has been in current state
+    pub elapsed_seconds: i64,
+}
+
+impl ConnectionStateInfo {
+    #[must_use]
+    pub fn new(state: ConnectionState) -> Self {
+        Self {
+            state,
+            since: Utc::now().naive_utc(),
+            elapsed_seconds: 0,
+        }
+    }
+
+    #[must_use]
+    pub fn elapsed(&self) -> ChronoDuration {
+        Utc::now().naive_utc() - self.since
+    }
+}
+
+/// The most recent failure of connecting location, kept until it's connected successfully.
+#[derive(Clone, Debug, Serialize)]
+pub struct LastError {
+    pub message: String,
+    pub at: NaiveDateTime,
+}
+
+#[derive(Clone, Serialize)]
+struct ConnectionTimeoutPayload {
+    location_id: i64,
+    connection_type: ConnectionType,
+}
+
+/// Time after which connection attempt is considered stuck. Each endpoint of location pool
+/// may take the whole connection timeout before the next one is tried.
+async fn connect_timeout(
+    pool: &DbPool,
+    location_id: i64,
+    connection_type: &ConnectionType,
+) -> ChronoDuration {
+    let timeout = Settings::connection_timeout(pool).await.as_secs() as i64;
+    let endpoints = match connection_type {
+        ConnectionType::Location => match Location::find_by_id(pool, location_id).await {
+            Ok(Some(location)) => location_endpoints(&location, None).len() as i64,
+            _ => 1,
+        },
+        ConnectionType::Tunnel => 1,
+    };
+    ChronoDuration::seconds(timeout * endpoints + CONNECT_TIMEOUT_MARGIN_SECONDS)
+}
+
+/// Periodically looks for connection attempts stuck in connecting state and resolves them,
+/// either as connected if connection became active or as failed otherwise.
+pub async fn connection_watchdog_loop(app_handle: AppHandle) {
+    let state = app_handle.state::<AppState>();
+    let mut interval = interval(WATCHDOG_INTERVAL);
+    loop {
+        interval.tick().await;
+        let connecting: Vec<((i64, ConnectionType), ChronoDuration)> = state
+            .connection_states
+            .lock()
+            .expect("Failed to lock connection states mutex")
+            .iter()
+            .filter(|(_, info)| info.state == ConnectionState::Connecting)
+            .map(|(key, info)| (key.clone(), info.elapsed()))
+            .collect();
+        let pool = state.get_pool();
+        for ((location_id, connection_type), elapsed) in connecting {
+            if elapsed <= connect_timeout(&pool, location_id, &connection_type).await {
+                continue;
+            }
+            let is_active = state.get_connections().iter().any(|con| {
+                con.location_id == location_id && con.connection_type.eq(&connection_type)
+            });
+            if is_active {
+                warn!("Location {location_id} {connection_type:?} connected without finishing connect");
+                state.set_connection_state(
+                    location_id,
+                    &connection_type,
+                    ConnectionState::Connected,
+                );
+                continue;
+            }
+            error!("Connecting location {location_id} {connection_type:?} timed out");
+            state.set_connection_state(
+                location_id,
+                &connection_type,
+                ConnectionState::Error("Connection attempt timed out".into()),
+            );
+            let _ = emit_event(
+                &app_handle,
+                "connection-timeout",
+                ConnectionTimeoutPayload {
+                    location_id,
+                    connection_type,
+                },
+            );
+            let _ = emit_event(
+                &app_handle,
+                "connection-changed",
+                Payload {
+                    message: "Connection attempt timed out".into(),
+                },
+            );
+            refresh_tray(&app_handle);
+        }
+    }
+}
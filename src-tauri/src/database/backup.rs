@@ -0,0 +1,219 @@
+//! Backup of client configuration
+//!
+//! Instances with their locations and settings are exported to a versioned JSON document,
+//! so configuration can be moved to another machine. Device keys and preshared keys are only
+//! exported and imported when explicitly requested. Instances already present in the database
+//! (matched by UUID) are skipped on import.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    database::{DbPool, Instance, Location, PresharedKey, Settings, WireguardKeys},
+    error::Error,
+};
+
+/// Version of backup document format, bumped whenever the format changes.
+pub const BACKUP_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConfigurationBackup {
+    pub version: u32,
+    pub settings: Settings,
+    pub instances: Vec<InstanceBackup>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InstanceBackup {
+    #[serde(flatten)]
+    pub instance: Instance,
+    pub locations: Vec<LocationBackup>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keys: Option<KeysBackup>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LocationBackup {
+    #[serde(flatten)]
+    pub location: Location,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preshared_key: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeysBackup {
+    pub pubkey: String,
+    pub prvkey: String,
+}
+
+/// Names of instances restored from backup and of those skipped as already present.
+#[derive(Debug, Default, Serialize)]
+pub struct ImportSummary {
+    pub imported: Vec<String>,
+    pub skipped: Vec<String>,
+}
+
+// only the version is read first, so documents in other formats can be migrated or rejected
+#[derive(Deserialize)]
+struct BackupVersion {
+    version: u32,
+}
+
+/// Collects all instances, their locations and settings. Keys are included if `include_secrets`.
+pub async fn export_configuration(
+    pool: &DbPool,
+    include_secrets: bool,
+) -> Result<ConfigurationBackup, Error> {
+    let mut instances = Vec::new();
+    for instance in Instance::all_including_archived(pool).await? {
+        let instance_id = instance.id.expect("Missing instance ID");
+        let locations = Location::find_by_instance_id(pool, instance_id)
+            .await?
+            .into_iter()
+            .map(|location| LocationBackup {
+                preshared_key: location
+                    .preshared_key
+                    .as_ref()
+                    .filter(|_| include_secrets)
+                    .map(|key| key.0.clone()),
+                location,
+            })
+            .collect();
+        let keys = if include_secrets {
+            WireguardKeys::find_by_instance_id(pool, instance_id)
+                .await?
+                .map(|keys| KeysBackup {
+                    pubkey: keys.pubkey,
+                    prvkey: keys.prvkey,
+                })
+        } else {
+            None
+        };
+        instances.push(InstanceBackup {
+            instance,
+            locations,
+            keys,
+        });
+    }
+    Ok(ConfigurationBackup {
+        version: BACKUP_VERSION,
+        settings: Settings::get(pool).await?,
+        instances,
+    })
+}
+
+/// Parses backup document, rejecting versions this client doesn't understand.
+pub fn parse_backup(json: &str) -> Result<ConfigurationBackup, Error> {
+    let BackupVersion { version } = serde_json::from_str(json)?;
+    if version != BACKUP_VERSION {
+        return Err(Error::CommandError(format!(
+            "Unsupported backup version {version}, expected {BACKUP_VERSION}"
+        )));
+    }
+    Ok(serde_json::from_str(json)?)
+}
+
+/// Restores settings and instances missing from the database in a single transaction.
+/// Keys present in backup are ignored unless `include_secrets` is set.
+pub async fn import_configuration(
+    pool: &DbPool,
+    backup: ConfigurationBackup,
+    include_secrets: bool,
+) -> Result<ImportSummary, Error> {
+    let existing: Vec<String> = Instance::all_including_archived(pool)
+        .await?
+        .into_iter()
+        .map(|instance| instance.uuid)
+        .collect();
+    let mut summary = ImportSummary::default();
+    let mut transaction = pool.begin().await?;
+    for InstanceBackup {
+        mut instance,
+        locations,
+        keys,
+    } in backup.instances
+    {
+        if existing.contains(&instance.uuid) {
+            info!("Instance {} already exists, skipping", instance.name);
+            summary.skipped.push(instance.name);
+            continue;
+        }
+        instance.id = None;
+        instance.save(&mut *transaction).await?;
+        let instance_id = instance.id.expect("Missing instance ID");
+        for LocationBackup {
+            mut location,
+            preshared_key,
+        } in locations
+        {
+            location.id = None;
+            location.instance_id = instance_id;
+            // interface names are chosen again on the new machine
+            location.interface_name = None;
+            location.preshared_key = preshared_key.filter(|_| include_secrets).map(PresharedKey);
+            location.save(&mut *transaction).await?;
+        }
+        if let Some(keys) = keys.filter(|_| include_secrets) {
+            WireguardKeys::new(instance_id, keys.pubkey, keys.prvkey)
+                .save(&mut *transaction)
+                .await?;
+        }
+        info!("Imported instance {}", instance.name);
+        summary.imported.push(instance.name);
+    }
+    let mut settings = backup.settings;
+    settings.save(&mut *transaction).await?;
+    transaction.commit().await?;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{seed_instance, seed_location, test_pool};
+
+    #[tokio::test]
+    async fn test_backup_round_trip() {
+        let pool = test_pool().await;
+        let instance_id = seed_instance(&pool).await;
+        let location_id = seed_location(&pool, instance_id, "location").await;
+        sqlx::query("UPDATE location SET preshared_key = 'psk' WHERE id = $1;")
+            .bind(location_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        WireguardKeys::new(instance_id, "pubkey".into(), "prvkey".into())
+            .save(&pool)
+            .await
+            .unwrap();
+
+        // secrets are left out unless requested
+        let json =
+            serde_json::to_string(&export_configuration(&pool, false).await.unwrap()).unwrap();
+        assert!(!json.contains("prvkey") && !json.contains("psk"));
+        let json =
+            serde_json::to_string(&export_configuration(&pool, true).await.unwrap()).unwrap();
+
+        let restored = test_pool().await;
+        let summary = import_configuration(&restored, parse_backup(&json).unwrap(), true)
+            .await
+            .unwrap();
+        assert_eq!(summary.imported, vec!["instance".to_string()]);
+        let location = Location::all(&restored).await.unwrap().remove(0);
+        assert_eq!(location.name, "location");
+        assert_eq!(location.preshared_key.unwrap().0, "psk");
+        let keys = WireguardKeys::find_by_instance_id(&restored, location.instance_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(keys.prvkey, "prvkey");
+
+        // instances are matched by UUID, so importing again doesn't duplicate them
+        let summary = import_configuration(&restored, parse_backup(&json).unwrap(), true)
+            .await
+            .unwrap();
+        assert_eq!(summary.skipped, vec!["instance".to_string()]);
+        assert_eq!(Location::all(&restored).await.unwrap().len(), 1);
+
+        assert!(parse_backup(r#"{"version": 2}"#).is_err());
+    }
+}
@@ -1,10 +1,15 @@
+pub mod backup;
 pub mod models;
+pub mod profiles;
+pub mod stats_buffer;
+pub mod stats_retention;
 
-use std::fs;
+use std::{fs, path::Path};
 
 use tauri::AppHandle;
 
 use crate::error::Error;
+use profiles::{active_profile, profile_dir};
 
 const DB_NAME: &str = "defguard.db";
 
@@ -12,15 +17,18 @@ pub type DbPool = sqlx::SqlitePool;
 
 // Check if a database file exists, and create one if it does not.
 pub async fn init_db(app_handle: &AppHandle) -> Result<DbPool, Error> {
-    let app_dir = app_handle
-        .path_resolver()
-        .app_data_dir()
-        .ok_or(Error::Config)?;
-    // Create app data directory if it doesnt exist
-    debug!("Creating app data dir at: {}", app_dir.to_string_lossy());
-    fs::create_dir_all(&app_dir)?;
-    info!("Created app data dir at: {}", app_dir.to_string_lossy());
-    let db_path = app_dir.join(DB_NAME);
+    let profile = active_profile(app_handle)?;
+    info!("Using data profile {profile}");
+    open_db(&profile_dir(app_handle, &profile)?).await
+}
+
+/// Open database stored in given directory, creating the directory and database if needed.
+pub async fn open_db(dir: &Path) -> Result<DbPool, Error> {
+    // Create data directory if it doesnt exist
+    debug!("Creating data dir at: {}", dir.to_string_lossy());
+    fs::create_dir_all(dir)?;
+    info!("Created data dir at: {}", dir.to_string_lossy());
+    let db_path = dir.join(DB_NAME);
     if db_path.exists() {
         info!(
             "Database exists skipping creating database. Database path: {}",
@@ -50,6 +58,48 @@ pub async fn init_db(app_handle: &AppHandle) -> Result<DbPool, Error> {
     Ok(pool)
 }
 
+/// In-memory database with applied migrations.
+#[cfg(test)]
+pub(crate) async fn test_pool() -> DbPool {
+    // in-memory database exists only as long as its single connection
+    let pool = sqlx::sqlite::SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    sqlx::migrate!().run(&pool).await.unwrap();
+    pool
+}
+
+/// Insert test instance, returns its ID.
+#[cfg(test)]
+pub(crate) async fn seed_instance(pool: &DbPool) -> i64 {
+    sqlx::query(
+        "INSERT INTO instance (uuid, name, url, proxy_url, username) \
+        VALUES ('uuid', 'instance', 'http://localhost', 'http://localhost', 'user');",
+    )
+    .execute(pool)
+    .await
+    .unwrap()
+    .last_insert_rowid()
+}
+
+/// Insert test location of given instance, `name` is also used as its public key.
+/// Returns location ID.
+#[cfg(test)]
+pub(crate) async fn seed_location(pool: &DbPool, instance_id: i64, name: &str) -> i64 {
+    sqlx::query(
+        "INSERT INTO location (instance_id, network_id, name, address, pubkey, endpoint, allowed_ips, mfa_enabled, keepalive_interval) \
+        VALUES ($1, 1, $2, '10.0.0.2/24', $2, '1.2.3.4:51820', '10.0.0.0/24', false, 25);",
+    )
+    .bind(instance_id)
+    .bind(name)
+    .execute(pool)
+    .await
+    .unwrap()
+    .last_insert_rowid()
+}
+
 pub async fn info(pool: &DbPool) -> Result<(), Error> {
     let instances = Instance::all(pool).await?;
     let locations = Location::all(pool).await?;
@@ -64,10 +114,16 @@ pub async fn info(pool: &DbPool) -> Result<(), Error> {
 }
 
 pub use models::{
-    connection::{ActiveConnection, Connection, ConnectionInfo},
+    connection::{
+        ActiveConnection, Connection, ConnectionInfo, DisconnectReason, RecentConnectionInfo,
+    },
     instance::{Instance, InstanceInfo},
-    location::{Location, LocationStats},
-    settings::{Settings, SettingsLogLevel, SettingsTheme, TrayIconTheme},
+    lifetime_transfer::LifetimeTransfer,
+    location::{Location, LocationStats, PresharedKey},
+    paused_connection::PausedConnection,
+    peak_throughput::PeakThroughput,
+    settings::{EndpointResolution, Settings, SettingsLogLevel, SettingsTheme, TrayIconTheme},
     tunnel::{Tunnel, TunnelConnection, TunnelConnectionInfo, TunnelStats},
-    wireguard_keys::WireguardKeys,
+    wireguard_keys::{KeyPair, WireguardKeys},
 };
+pub use stats_buffer::StatsBuffer;
@@ -1,11 +1,24 @@
-use chrono::{NaiveDateTime, Utc};
+use chrono::{DateTime, NaiveDateTime, Utc};
 use serde::Serialize;
-use sqlx::{query, query_as, FromRow};
+use sqlx::{query, query_as, FromRow, Type};
 
 use crate::{
     database::DbPool, error::Error, CommonConnection, CommonConnectionInfo, ConnectionType,
 };
 
+/// Why connection was closed
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq, Type)]
+#[sqlx(type_name = "disconnect_reason", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum DisconnectReason {
+    UserInitiated,
+    ServiceError,
+    AppShutdown,
+    NetworkFailure,
+    Paused,
+    Schedule,
+}
+
 #[derive(FromRow, Debug, Serialize, Clone)]
 pub struct Connection {
     pub id: Option<i64>,
@@ -13,18 +26,23 @@ pub struct Connection {
     pub connected_from: String,
     pub start: NaiveDateTime,
     pub end: NaiveDateTime,
+    pub disconnect_reason: Option<DisconnectReason>,
+    // endpoint address reported by WireGuard when connection was closed
+    pub resolved_endpoint: Option<String>,
 }
 
 impl Connection {
     pub async fn save(&mut self, pool: &DbPool) -> Result<(), Error> {
         let result = query!(
-            "INSERT INTO connection (location_id, connected_from, start, end) \
-            VALUES ($1, $2, $3, $4) \
+            "INSERT INTO connection (location_id, connected_from, start, end, disconnect_reason, resolved_endpoint) \
+            VALUES ($1, $2, $3, $4, $5, $6) \
             RETURNING id;",
             self.location_id,
             self.connected_from,
             self.start,
             self.end,
+            self.disconnect_reason,
+            self.resolved_endpoint,
         )
         .fetch_one(pool)
         .await?;
@@ -36,7 +54,8 @@ impl Connection {
         let connections = query_as!(
             Connection,
             r#"
-            SELECT id, location_id, connected_from, start, end 
+            SELECT id, location_id, connected_from, start, end,
+              disconnect_reason as "disconnect_reason: DisconnectReason", resolved_endpoint
             FROM connection
             WHERE location_id = $1
             "#,
@@ -54,7 +73,8 @@ impl Connection {
         let connection = query_as!(
             Connection,
             r#"
-            SELECT id, location_id, connected_from, start, end
+            SELECT id, location_id, connected_from, start, end,
+              disconnect_reason as "disconnect_reason: DisconnectReason", resolved_endpoint
             FROM connection
             WHERE location_id = $1
             ORDER BY end DESC
@@ -78,6 +98,8 @@ pub struct ConnectionInfo {
     pub end: NaiveDateTime,
     pub upload: Option<i32>,
     pub download: Option<i32>,
+    pub disconnect_reason: Option<DisconnectReason>,
+    pub resolved_endpoint: Option<String>,
 }
 impl From<ConnectionInfo> for CommonConnectionInfo {
     fn from(val: ConnectionInfo) -> Self {
@@ -89,12 +111,22 @@ impl From<ConnectionInfo> for CommonConnectionInfo {
             end: val.end,
             upload: val.upload,
             download: val.download,
+            disconnect_reason: val.disconnect_reason,
+            resolved_endpoint: val.resolved_endpoint,
         }
     }
 }
 
 impl ConnectionInfo {
-    pub async fn all_by_location_id(pool: &DbPool, location_id: i64) -> Result<Vec<Self>, Error> {
+    /// Returns connections overlapping given time range, newest first.
+    pub async fn all_by_location_id(
+        pool: &DbPool,
+        location_id: i64,
+        from: Option<NaiveDateTime>,
+        to: Option<NaiveDateTime>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<Self>, Error> {
         // Because we store interface information for given timestamp select last upload and download
         // before connection ended
         // FIXME: Optimize query
@@ -124,17 +156,122 @@ impl ConnectionInfo {
                       AND ls.collected_at <= c.end
                       ORDER BY ls.collected_at DESC
                       LIMIT 1
-                  ), 0) as "download: _"
-              FROM connection AS c WHERE location_id = $1
-              ORDER BY start DESC;
+                  ), 0) as "download: _",
+                  c.disconnect_reason as "disconnect_reason: DisconnectReason",
+                  c.resolved_endpoint
+              FROM connection AS c
+              WHERE location_id = $1
+              AND ($2 IS NULL OR c.end >= $2)
+              AND ($3 IS NULL OR c.start <= $3)
+              ORDER BY start DESC
+              LIMIT $4 OFFSET $5;
             "#,
-            location_id
+            location_id,
+            from,
+            to,
+            limit,
+            offset
         )
         .fetch_all(pool)
         .await?;
 
         Ok(connections)
     }
+
+    /// Counts connections overlapping given time range.
+    pub async fn count_by_location_id(
+        pool: &DbPool,
+        location_id: i64,
+        from: Option<NaiveDateTime>,
+        to: Option<NaiveDateTime>,
+    ) -> Result<i64, Error> {
+        let result = query!(
+            r#"
+            SELECT COUNT(*) as "count!: i64" FROM connection
+            WHERE location_id = $1
+            AND ($2 IS NULL OR end >= $2)
+            AND ($3 IS NULL OR start <= $3);
+            "#,
+            location_id,
+            from,
+            to
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(result.count)
+    }
+}
+
+/// Historical connection along with names of its location and instance
+#[derive(FromRow, Debug, Serialize)]
+pub struct RecentConnectionInfo {
+    pub id: i64,
+    pub location_id: i64,
+    pub location_name: String,
+    pub instance_id: i64,
+    pub instance_name: String,
+    pub connected_from: String,
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+    pub upload: Option<i32>,
+    pub download: Option<i32>,
+    pub disconnect_reason: Option<DisconnectReason>,
+    pub resolved_endpoint: Option<String>,
+}
+
+impl RecentConnectionInfo {
+    /// Returns connections of all locations which ended after `from`, most recently ended first.
+    pub async fn all(
+        pool: &DbPool,
+        from: Option<NaiveDateTime>,
+        limit: i64,
+    ) -> Result<Vec<Self>, Error> {
+        let connections = query_as!(
+            RecentConnectionInfo,
+            r#"
+              SELECT
+                  c.id as "id!",
+                  c.location_id as "location_id!",
+                  l.name as "location_name!",
+                  i.id as "instance_id!",
+                  i.name as "instance_name!",
+                  c.connected_from as "connected_from!",
+                  c.start as "start!",
+                  c.end as "end!",
+                  COALESCE((
+                      SELECT ls.upload
+                      FROM location_stats AS ls
+                      WHERE ls.location_id = c.location_id
+                      AND ls.collected_at >= c.start
+                      AND ls.collected_at <= c.end
+                      ORDER BY ls.collected_at DESC
+                      LIMIT 1
+                  ), 0) as "upload: _",
+                  COALESCE((
+                      SELECT ls.download
+                      FROM location_stats AS ls
+                      WHERE ls.location_id = c.location_id
+                      AND ls.collected_at >= c.start
+                      AND ls.collected_at <= c.end
+                      ORDER BY ls.collected_at DESC
+                      LIMIT 1
+                  ), 0) as "download: _",
+                  c.disconnect_reason as "disconnect_reason: DisconnectReason",
+                  c.resolved_endpoint
+              FROM connection AS c
+              JOIN location AS l ON l.id = c.location_id
+              JOIN instance AS i ON i.id = l.instance_id
+              WHERE ($1 IS NULL OR c.end >= $1)
+              ORDER BY c.end DESC
+              LIMIT $2;
+            "#,
+            from,
+            limit
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(connections)
+    }
 }
 
 /// Connections stored in memory after creating interface
@@ -145,6 +282,18 @@ pub struct ActiveConnection {
     pub start: NaiveDateTime,
     pub interface_name: String,
     pub connection_type: ConnectionType,
+    // timezone aware `start`, so frontend doesn't have to guess it
+    pub connected_since: DateTime<Utc>,
+    // key profile used instead of the default key pair of instance
+    pub profile_id: Option<i64>,
+    // endpoint of location pool which connected
+    pub endpoint: Option<String>,
+    // endpoint address resolved when connecting
+    pub resolved_endpoint: Option<String>,
+    // public key of key pair interface was created with
+    pub pubkey: Option<String>,
+    // connected with `force` regardless of location schedule, left up when its window closes
+    pub forced: bool,
 }
 impl ActiveConnection {
     #[must_use]
@@ -154,28 +303,43 @@ impl ActiveConnection {
         interface_name: String,
         connection_type: ConnectionType,
     ) -> Self {
-        let start = Utc::now().naive_utc();
+        let connected_since = Utc::now();
         Self {
             location_id,
             connected_from,
-            start,
+            start: connected_since.naive_utc(),
             interface_name,
             connection_type,
+            connected_since,
+            profile_id: None,
+            endpoint: None,
+            resolved_endpoint: None,
+            pubkey: None,
+            forced: false,
         }
     }
-}
 
-impl From<ActiveConnection> for Connection {
-    fn from(active_connection: ActiveConnection) -> Self {
+    /// Returns how long the connection has been up.
+    #[must_use]
+    pub fn duration_seconds(&self) -> i64 {
+        (Utc::now() - self.connected_since).num_seconds().max(0)
+    }
+
+    /// Converts active connection into historical connection ending now.
+    #[must_use]
+    pub fn into_connection(self, disconnect_reason: DisconnectReason) -> Connection {
         Connection {
             id: None,
-            location_id: active_connection.location_id,
-            connected_from: active_connection.connected_from,
-            start: active_connection.start,
+            location_id: self.location_id,
+            connected_from: self.connected_from,
+            start: self.start,
             end: Utc::now().naive_utc(),
+            disconnect_reason: Some(disconnect_reason),
+            resolved_endpoint: self.resolved_endpoint,
         }
     }
 }
+
 // Implementing From for Connection into CommonConnection
 impl From<Connection> for CommonConnection {
     fn from(connection: Connection) -> Self {
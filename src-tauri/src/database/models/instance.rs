@@ -1,8 +1,9 @@
 use crate::{database::DbPool, error::Error, proto};
+use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
-use sqlx::{query, query_as, FromRow};
+use sqlx::{query, query_as, FromRow, Sqlite, Transaction};
 
-#[derive(FromRow, Serialize, Deserialize, Debug)]
+#[derive(FromRow, Serialize, Deserialize, Debug, Clone)]
 pub struct Instance {
     pub id: Option<i64>,
     pub name: String,
@@ -10,6 +11,10 @@ pub struct Instance {
     pub url: String,
     pub proxy_url: String,
     pub username: String,
+    // archived instances are hidden but can be restored
+    pub archived_at: Option<NaiveDateTime>,
+    // relay (endpoint host) preferred when connecting locations with multiple endpoints
+    pub preferred_relay: Option<String>,
 }
 
 impl From<proto::InstanceInfo> for Instance {
@@ -21,6 +26,8 @@ impl From<proto::InstanceInfo> for Instance {
             url: instance_info.url,
             proxy_url: instance_info.proxy_url,
             username: instance_info.username,
+            archived_at: None,
+            preferred_relay: None,
         }
     }
 }
@@ -41,6 +48,8 @@ impl Instance {
             url,
             proxy_url,
             username,
+            archived_at: None,
+            preferred_relay: None,
         }
     }
 
@@ -53,12 +62,14 @@ impl Instance {
         match self.id {
             None => {
                 let result = query!(
-                    "INSERT INTO instance (name, uuid, url, proxy_url, username) VALUES ($1, $2, $3, $4, $5) RETURNING id;",
+                    "INSERT INTO instance (name, uuid, url, proxy_url, username, archived_at, preferred_relay) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id;",
                     self.name,
                     self.uuid,
                     url,
                     proxy_url,
                     self.username,
+                    self.archived_at,
+                    self.preferred_relay,
                 )
                 .fetch_one(executor)
                 .await?;
@@ -68,12 +79,14 @@ impl Instance {
             Some(id) => {
                 // Update the existing record when there is an ID
                 query!(
-                    "UPDATE instance SET name = $1, uuid = $2, url = $3, proxy_url = $4, username = $5 WHERE id = $6;",
+                    "UPDATE instance SET name = $1, uuid = $2, url = $3, proxy_url = $4, username = $5, archived_at = $6, preferred_relay = $7 WHERE id = $8;",
                     self.name,
                     self.uuid,
                     url,
                     proxy_url,
                     self.username,
+                    self.archived_at,
+                    self.preferred_relay,
                     id
                 )
                 .execute(executor)
@@ -83,10 +96,21 @@ impl Instance {
         }
     }
 
+    /// Returns all instances which are not archived.
     pub async fn all(pool: &DbPool) -> Result<Vec<Self>, Error> {
         let instances = query_as!(
             Self,
-            "SELECT id \"id?\", name, uuid, url, proxy_url, username FROM instance;"
+            "SELECT id \"id?\", name, uuid, url, proxy_url, username, archived_at, preferred_relay FROM instance WHERE archived_at IS NULL;"
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(instances)
+    }
+
+    pub async fn all_including_archived(pool: &DbPool) -> Result<Vec<Self>, Error> {
+        let instances = query_as!(
+            Self,
+            "SELECT id \"id?\", name, uuid, url, proxy_url, username, archived_at, preferred_relay FROM instance;"
         )
         .fetch_all(pool)
         .await?;
@@ -96,7 +120,7 @@ impl Instance {
     pub async fn find_by_id(pool: &DbPool, id: i64) -> Result<Option<Self>, Error> {
         let instance = query_as!(
             Self,
-            "SELECT id \"id?\", name, uuid, url, proxy_url, username FROM instance WHERE id = $1;",
+            "SELECT id \"id?\", name, uuid, url, proxy_url, username, archived_at, preferred_relay FROM instance WHERE id = $1;",
             id
         )
         .fetch_optional(pool)
@@ -104,22 +128,46 @@ impl Instance {
         Ok(instance)
     }
 
-    pub async fn delete_by_id(pool: &DbPool, id: i64) -> Result<(), Error> {
-        // delete instance
-        query!("DELETE FROM instance WHERE id = $1", id)
-            .execute(pool)
-            .await?;
-        Ok(())
+    /// Finds instance by UUID assigned by defguard, including archived ones.
+    pub async fn find_by_uuid(pool: &DbPool, uuid: &str) -> Result<Option<Self>, Error> {
+        let instance = query_as!(
+            Self,
+            "SELECT id \"id?\", name, uuid, url, proxy_url, username, archived_at, preferred_relay FROM instance WHERE uuid = $1;",
+            uuid
+        )
+        .fetch_optional(pool)
+        .await?;
+        Ok(instance)
     }
 
-    pub async fn delete(&self, pool: &DbPool) -> Result<(), Error> {
-        match self.id {
-            Some(id) => {
-                Instance::delete_by_id(pool, id).await?;
-                Ok(())
-            }
-            None => Err(Error::NotFound),
-        }
+    /// Delete instance together with its keys, locations and their connections and stats.
+    pub async fn delete_with_related(
+        &self,
+        transaction: &mut Transaction<'_, Sqlite>,
+    ) -> Result<(), Error> {
+        let id = self.id.ok_or(Error::NotFound)?;
+        query!(
+            "DELETE FROM location_stats WHERE location_id IN (SELECT id FROM location WHERE instance_id = $1);",
+            id
+        )
+        .execute(&mut **transaction)
+        .await?;
+        query!(
+            "DELETE FROM connection WHERE location_id IN (SELECT id FROM location WHERE instance_id = $1);",
+            id
+        )
+        .execute(&mut **transaction)
+        .await?;
+        query!("DELETE FROM location WHERE instance_id = $1;", id)
+            .execute(&mut **transaction)
+            .await?;
+        query!("DELETE FROM wireguard_keys WHERE instance_id = $1;", id)
+            .execute(&mut **transaction)
+            .await?;
+        query!("DELETE FROM instance WHERE id = $1;", id)
+            .execute(&mut **transaction)
+            .await?;
+        Ok(())
     }
 }
 
@@ -132,4 +180,6 @@ pub struct InstanceInfo {
     pub proxy_url: String,
     pub active: bool,
     pub pubkey: String,
+    pub archived_at: Option<NaiveDateTime>,
+    pub preferred_relay: Option<String>,
 }
@@ -0,0 +1,58 @@
+use chrono::{NaiveDateTime, Utc};
+use serde::Serialize;
+use sqlx::{query, query_as, FromRow};
+
+use crate::{database::DbPool, error::Error};
+
+/// Total bytes transferred through location over all of its connections.
+/// Unlike WireGuard counters the totals survive interface recreation.
+#[derive(FromRow, Debug, Serialize)]
+pub struct LifetimeTransfer {
+    pub location_id: i64,
+    pub upload: i64,
+    pub download: i64,
+    // when totals were increased last time
+    pub updated_at: NaiveDateTime,
+}
+
+impl LifetimeTransfer {
+    /// Add transferred bytes to lifetime totals of location.
+    pub async fn add(
+        pool: &DbPool,
+        location_id: i64,
+        upload: i64,
+        download: i64,
+    ) -> Result<(), Error> {
+        let now = Utc::now().naive_utc();
+        query!(
+            "INSERT INTO location_lifetime_transfer (location_id, upload, download, updated_at) \
+            VALUES ($1, $2, $3, $4) \
+            ON CONFLICT(location_id) DO UPDATE SET \
+            upload = upload + excluded.upload, \
+            download = download + excluded.download, \
+            updated_at = excluded.updated_at;",
+            location_id,
+            upload,
+            download,
+            now,
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn find_by_location_id(
+        pool: &DbPool,
+        location_id: i64,
+    ) -> Result<Option<Self>, Error> {
+        let transfer = query_as!(
+            Self,
+            "SELECT location_id, upload, download, updated_at \
+            FROM location_lifetime_transfer WHERE location_id = $1;",
+            location_id
+        )
+        .fetch_optional(pool)
+        .await?;
+        Ok(transfer)
+    }
+}
@@ -1,18 +1,32 @@
-use chrono::{NaiveDateTime, Utc};
-use sqlx::{query, query_as, Error as SqlxError, FromRow};
+use chrono::{Datelike, Local, Months, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use sqlx::{query, query_as, Error as SqlxError, FromRow, QueryBuilder, Sqlite, Transaction, Type};
 use std::{
-    fmt::{Display, Formatter},
+    fmt::{Debug, Display, Formatter},
     time::SystemTime,
 };
 
 use crate::{
-    commands::DateTimeAggregation, database::DbPool, error::Error, CommonLocationStats,
-    ConnectionType,
+    commands::DateTimeAggregation,
+    database::{stats_buffer::STATS_BATCH_SIZE, DbPool},
+    error::Error,
+    CommonLocationStats, ConnectionType,
 };
 use defguard_wireguard_rs::host::Peer;
 use serde::{Deserialize, Serialize};
+use tonic::codegen::tokio_stream::StreamExt;
 
-#[derive(FromRow, Debug, Serialize, Deserialize)]
+/// WireGuard preshared key of location peer, hidden in debug output so it's never logged.
+#[derive(Clone, Type)]
+#[sqlx(transparent)]
+pub struct PresharedKey(pub String);
+
+impl Debug for PresharedKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("PresharedKey(***)")
+    }
+}
+
+#[derive(FromRow, Debug, Serialize, Deserialize, Clone)]
 pub struct Location {
     pub id: Option<i64>,
     pub instance_id: i64,
@@ -27,6 +41,49 @@ pub struct Location {
     pub route_all_traffic: bool,
     pub mfa_enabled: bool,
     pub keepalive_interval: i64,
+    pub doh_url: Option<String>,
+    pub dot_host: Option<String>,
+    pub metadata: Option<String>,
+    pub endpoint_ip_override: Option<String>,
+    pub kill_switch: bool,
+    pub mtu: Option<u32>,
+    pub autoconnect: bool,
+    pub dns_search: Option<String>,
+    pub favorite: bool,
+    pub sort_order: i64,
+    // never sent to frontend, only presence is reported
+    #[serde(skip)]
+    pub preshared_key: Option<PresharedKey>,
+    pub display_name: Option<String>,
+    pub interface_name: Option<String>,
+    // comma-separated networks routed through the tunnel in addition to allowed IPs
+    pub extra_routes: Option<String>,
+    // locations of the same instance sharing a group back each other up when connection goes stale
+    pub failover_group: Option<String>,
+    // order of failover within group, lower is preferred
+    #[serde(default)]
+    pub failover_priority: i64,
+    // JSON array of time windows in which location may be connected, `None` allows any time
+    pub schedule: Option<String>,
+    // endpoint address which connected the last time, preferred in sticky endpoint resolution
+    pub sticky_endpoint: Option<String>,
+    // route DNS servers through the tunnel and block DNS queries to other resolvers
+    #[serde(default)]
+    pub force_tunnel_dns: bool,
+    // newline-separated backup endpoints tried in order when `endpoint` doesn't connect
+    pub endpoints: Option<String>,
+    // imported from WireGuard config file, not managed by defguard and left out of syncing
+    #[serde(default)]
+    pub imported: bool,
+}
+
+/// Data transferred through location in a single calendar month
+#[derive(Debug, Serialize)]
+pub struct MonthlyUsage {
+    pub year: i32,
+    pub month: u32,
+    pub upload: i64,
+    pub download: i64,
 }
 
 #[derive(FromRow, Debug, Serialize, Deserialize)]
@@ -39,6 +96,20 @@ pub struct LocationStats {
     collected_at: NaiveDateTime,
     listen_port: u32,
     persistent_keepalive_interval: Option<u16>,
+    // round-trip time to location endpoint, `None` if not measured or endpoint didn't respond
+    latency_ms: Option<i64>,
+    // throughput in bytes per second, only computed by stats queries
+    upload_rate: Option<i64>,
+    download_rate: Option<i64>,
+}
+
+/// Payload of `location-stats-{interface_name}` event emitted on every stats collection.
+#[derive(Debug, Serialize)]
+pub struct LocationStatsUpdate<'a> {
+    #[serde(flatten)]
+    pub stats: &'a LocationStats,
+    pub upload_delta: i64,
+    pub download_delta: i64,
 }
 
 impl From<LocationStats> for CommonLocationStats {
@@ -52,6 +123,9 @@ impl From<LocationStats> for CommonLocationStats {
             collected_at: location_stats.collected_at,
             listen_port: location_stats.listen_port,
             persistent_keepalive_interval: location_stats.persistent_keepalive_interval,
+            latency_ms: location_stats.latency_ms,
+            upload_rate: location_stats.upload_rate.unwrap_or_default(),
+            download_rate: location_stats.download_rate.unwrap_or_default(),
             connection_type: ConnectionType::Location,
         }
     }
@@ -75,6 +149,9 @@ pub async fn peer_to_location_stats(
         collected_at: Utc::now().naive_utc(),
         listen_port,
         persistent_keepalive_interval: peer.persistent_keepalive_interval,
+        latency_ms: None,
+        upload_rate: None,
+        download_rate: None,
     })
 }
 
@@ -92,8 +169,8 @@ impl Location {
         let locations = query_as!(
             Self,
             "SELECT id \"id?\", instance_id, name, address, pubkey, endpoint, allowed_ips, dns, network_id,\
-             route_all_traffic, mfa_enabled, keepalive_interval \
-        FROM location;"
+             route_all_traffic, mfa_enabled, keepalive_interval, doh_url, dot_host, metadata, endpoint_ip_override, kill_switch, mtu \"mtu?: u32\", autoconnect, dns_search, favorite, sort_order, preshared_key \"preshared_key?: PresharedKey\", display_name, interface_name, extra_routes, failover_group, failover_priority, schedule, sticky_endpoint, force_tunnel_dns, endpoints, imported \
+        FROM location ORDER BY favorite DESC, sort_order, id;"
         )
         .fetch_all(pool)
         .await?;
@@ -108,8 +185,8 @@ impl Location {
             None => {
                 // Insert a new record when there is no ID
                 let result = query!(
-                    "INSERT INTO location (instance_id, name, address, pubkey, endpoint, allowed_ips, dns, network_id, route_all_traffic, mfa_enabled, keepalive_interval) \
-                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11) \
+                    "INSERT INTO location (instance_id, name, address, pubkey, endpoint, allowed_ips, dns, network_id, route_all_traffic, mfa_enabled, keepalive_interval, doh_url, dot_host, metadata, endpoint_ip_override, kill_switch, mtu, autoconnect, dns_search, favorite, sort_order, preshared_key, display_name, interface_name, extra_routes, failover_group, failover_priority, schedule, sticky_endpoint, force_tunnel_dns, endpoints, imported) \
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22, $23, $24, $25, $26, $27, $28, $29, $30, $31, $32) \
                     RETURNING id;",
                     self.instance_id,
                     self.name,
@@ -121,7 +198,28 @@ impl Location {
                     self.network_id,
                     self.route_all_traffic,
                     self.mfa_enabled,
-                    self.keepalive_interval
+                    self.keepalive_interval,
+                    self.doh_url,
+                    self.dot_host,
+                    self.metadata,
+                    self.endpoint_ip_override,
+                    self.kill_switch,
+                    self.mtu,
+                    self.autoconnect,
+                    self.dns_search,
+                    self.favorite,
+                    self.sort_order,
+                    self.preshared_key,
+                    self.display_name,
+                    self.interface_name,
+                    self.extra_routes,
+                    self.failover_group,
+                    self.failover_priority,
+                    self.schedule,
+                    self.sticky_endpoint,
+                    self.force_tunnel_dns,
+                    self.endpoints,
+                    self.imported
             )
             .fetch_one(executor)
             .await?;
@@ -131,7 +229,7 @@ impl Location {
                 // Update the existing record when there is an ID
                 query!(
                     "UPDATE location SET instance_id = $1, name = $2, address = $3, pubkey = $4, endpoint = $5, allowed_ips = $6, dns = $7, \
-                    network_id = $8, route_all_traffic = $9, mfa_enabled = $10, keepalive_interval = $11 WHERE id = $12;",
+                    network_id = $8, route_all_traffic = $9, mfa_enabled = $10, keepalive_interval = $11, doh_url = $12, dot_host = $13, metadata = $14, endpoint_ip_override = $15, kill_switch = $16, mtu = $17, autoconnect = $18, dns_search = $19, favorite = $20, sort_order = $21, preshared_key = $22, display_name = $23, interface_name = $24, extra_routes = $25, failover_group = $26, failover_priority = $27, schedule = $28, sticky_endpoint = $29, force_tunnel_dns = $30, endpoints = $31, imported = $32 WHERE id = $33;",
                     self.instance_id,
                     self.name,
                     self.address,
@@ -143,6 +241,27 @@ impl Location {
                     self.route_all_traffic,
                     self.mfa_enabled,
                     self.keepalive_interval,
+                    self.doh_url,
+                    self.dot_host,
+                    self.metadata,
+                    self.endpoint_ip_override,
+                    self.kill_switch,
+                    self.mtu,
+                    self.autoconnect,
+                    self.dns_search,
+                    self.favorite,
+                    self.sort_order,
+                    self.preshared_key,
+                    self.display_name,
+                    self.interface_name,
+                    self.extra_routes,
+                    self.failover_group,
+                    self.failover_priority,
+                    self.schedule,
+                    self.sticky_endpoint,
+                    self.force_tunnel_dns,
+                    self.endpoints,
+                    self.imported,
                     id,
             )
             .execute(executor)
@@ -157,7 +276,7 @@ impl Location {
         query_as!(
             Self,
             "SELECT id \"id?\", instance_id, name, address, pubkey, endpoint, allowed_ips, dns, network_id, \
-            route_all_traffic, mfa_enabled, keepalive_interval \
+            route_all_traffic, mfa_enabled, keepalive_interval, doh_url, dot_host, metadata, endpoint_ip_override, kill_switch, mtu \"mtu?: u32\", autoconnect, dns_search, favorite, sort_order, preshared_key \"preshared_key?: PresharedKey\", display_name, interface_name, extra_routes, failover_group, failover_priority, schedule, sticky_endpoint, force_tunnel_dns, endpoints, imported \
             FROM location WHERE id = $1;",
             location_id
         )
@@ -172,8 +291,8 @@ impl Location {
         query_as!(
             Self,
             "SELECT id \"id?\", instance_id, name, address, pubkey, endpoint, allowed_ips, dns, network_id, \
-            route_all_traffic, mfa_enabled, keepalive_interval \
-            FROM location WHERE instance_id = $1;",
+            route_all_traffic, mfa_enabled, keepalive_interval, doh_url, dot_host, metadata, endpoint_ip_override, kill_switch, mtu \"mtu?: u32\", autoconnect, dns_search, favorite, sort_order, preshared_key \"preshared_key?: PresharedKey\", display_name, interface_name, extra_routes, failover_group, failover_priority, schedule, sticky_endpoint, force_tunnel_dns, endpoints, imported \
+            FROM location WHERE instance_id = $1 ORDER BY favorite DESC, sort_order, id;",
             instance_id
         )
         .fetch_all(pool)
@@ -184,7 +303,7 @@ impl Location {
         query_as!(
             Self,
             "SELECT id \"id?\", instance_id, name, address, pubkey, endpoint, allowed_ips, dns, network_id, \
-            route_all_traffic, mfa_enabled, keepalive_interval \
+            route_all_traffic, mfa_enabled, keepalive_interval, doh_url, dot_host, metadata, endpoint_ip_override, kill_switch, mtu \"mtu?: u32\", autoconnect, dns_search, favorite, sort_order, preshared_key \"preshared_key?: PresharedKey\", display_name, interface_name, extra_routes, failover_group, failover_priority, schedule, sticky_endpoint, force_tunnel_dns, endpoints, imported \
             FROM location WHERE pubkey = $1;",
             pubkey
         )
@@ -204,6 +323,59 @@ impl Location {
         }
         Ok(())
     }
+
+    /// Store interface name chosen for location, so it's reused on following connections.
+    pub async fn set_interface_name(
+        pool: &DbPool,
+        location_id: i64,
+        interface_name: Option<&str>,
+    ) -> Result<(), SqlxError> {
+        query!(
+            "UPDATE location SET interface_name = $1 WHERE id = $2;",
+            interface_name,
+            location_id
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Store endpoint address location connected through, `None` clears it after a failure.
+    pub async fn set_sticky_endpoint(
+        pool: &DbPool,
+        location_id: i64,
+        endpoint: Option<&str>,
+    ) -> Result<(), SqlxError> {
+        query!(
+            "UPDATE location SET sticky_endpoint = $1 WHERE id = $2;",
+            endpoint,
+            location_id
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Set sort order of locations to their position in `ordered_ids`.
+    /// Locations missing from the list keep their current sort order.
+    pub async fn reorder(pool: &DbPool, ordered_ids: &[i64]) -> Result<(), Error> {
+        let mut transaction = pool.begin().await?;
+        for (sort_order, id) in (0_i64..).zip(ordered_ids) {
+            let result = query!(
+                "UPDATE location SET sort_order = $1 WHERE id = $2;",
+                sort_order,
+                id
+            )
+            .execute(&mut *transaction)
+            .await?;
+            if result.rows_affected() == 0 {
+                error!("Location with id: {id} not found, can't reorder locations");
+                return Err(Error::NotFound);
+            }
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
 }
 
 impl LocationStats {
@@ -226,13 +398,42 @@ impl LocationStats {
             collected_at,
             listen_port,
             persistent_keepalive_interval,
+            latency_ms: None,
+            upload_rate: None,
+            download_rate: None,
         }
     }
 
+    pub fn set_latency(&mut self, latency_ms: Option<i64>) {
+        self.latency_ms = latency_ms;
+    }
+
+    /// Builds live update with bytes transferred since `previous` sample of the same location.
+    /// Counters start from zero when interface is created, so the first sample of a connection
+    /// is transferred entirely in it and lower value than `previous` means a reset.
+    #[must_use]
+    pub fn update_since(&self, previous: Option<(i64, i64)>) -> LocationStatsUpdate<'_> {
+        let delta = |current: i64, previous: Option<i64>| match previous {
+            Some(previous) if current >= previous => current - previous,
+            _ => current,
+        };
+        LocationStatsUpdate {
+            stats: self,
+            upload_delta: delta(self.upload, previous.map(|(upload, _)| upload)),
+            download_delta: delta(self.download, previous.map(|(_, download)| download)),
+        }
+    }
+
+    /// Returns location ID and transfer counters, used as base for next update.
+    #[must_use]
+    pub fn counters(&self) -> (i64, (i64, i64)) {
+        (self.location_id, (self.upload, self.download))
+    }
+
     pub async fn save(&mut self, pool: &DbPool) -> Result<(), Error> {
         let result = query!(
-            "INSERT INTO location_stats (location_id, upload, download, last_handshake, collected_at, listen_port, persistent_keepalive_interval) \
-            VALUES ($1, $2, $3, $4, $5, $6, $7) \
+            "INSERT INTO location_stats (location_id, upload, download, last_handshake, collected_at, listen_port, persistent_keepalive_interval, latency_ms) \
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8) \
             RETURNING id;",
             self.location_id,
             self.upload,
@@ -241,6 +442,7 @@ impl LocationStats {
             self.collected_at,
             self.listen_port,
             self.persistent_keepalive_interval,
+            self.latency_ms,
         )
         .fetch_one(pool)
         .await?;
@@ -248,11 +450,57 @@ impl LocationStats {
         Ok(())
     }
 
+    /// Insert multiple stats samples within given transaction using multi-row `INSERT` statements.
+    pub async fn save_batch(
+        transaction: &mut Transaction<'_, Sqlite>,
+        stats: &[Self],
+    ) -> Result<(), Error> {
+        for chunk in stats.chunks(STATS_BATCH_SIZE) {
+            let mut builder = QueryBuilder::<Sqlite>::new(
+                "INSERT INTO location_stats (location_id, upload, download, last_handshake, collected_at, listen_port, persistent_keepalive_interval, latency_ms) ",
+            );
+            builder.push_values(chunk, |mut row, stats| {
+                row.push_bind(stats.location_id)
+                    .push_bind(stats.upload)
+                    .push_bind(stats.download)
+                    .push_bind(stats.last_handshake)
+                    .push_bind(stats.collected_at)
+                    .push_bind(stats.listen_port)
+                    .push_bind(stats.persistent_keepalive_interval)
+                    .push_bind(stats.latency_ms);
+            });
+            builder.build().execute(&mut **transaction).await?;
+        }
+        Ok(())
+    }
+
     pub async fn all_by_location_id(
         pool: &DbPool,
         location_id: i64,
         from: &NaiveDateTime,
         aggregation: &DateTimeAggregation,
+    ) -> Result<Vec<Self>, Error> {
+        Self::aggregated(pool, None, Some(location_id), from, aggregation).await
+    }
+
+    /// Aggregated stats of all locations of instance, fetched with a single query.
+    pub async fn all_by_instance_id(
+        pool: &DbPool,
+        instance_id: i64,
+        from: &NaiveDateTime,
+        aggregation: &DateTimeAggregation,
+    ) -> Result<Vec<Self>, Error> {
+        Self::aggregated(pool, Some(instance_id), None, from, aggregation).await
+    }
+
+    /// Stats aggregated by location and time period, optionally limited to single instance
+    /// or location.
+    async fn aggregated(
+        pool: &DbPool,
+        instance_id: Option<i64>,
+        location_id: Option<i64>,
+        from: &NaiveDateTime,
+        aggregation: &DateTimeAggregation,
     ) -> Result<Vec<Self>, Error> {
         let aggregation = aggregation.fstring();
         let stats = query_as!(
@@ -263,7 +511,8 @@ impl LocationStats {
                     id, location_id,
                     COALESCE(upload - LAG(upload) OVER (PARTITION BY location_id ORDER BY collected_at), 0) as upload,
                     COALESCE(download - LAG(download) OVER (PARTITION BY location_id ORDER BY collected_at), 0) as download,
-                    last_handshake, strftime($1, collected_at) as collected_at, listen_port, persistent_keepalive_interval
+                    COALESCE((julianday(collected_at) - julianday(LAG(collected_at) OVER (PARTITION BY location_id ORDER BY collected_at))) * 86400, 0) as seconds,
+                    last_handshake, strftime($1, collected_at) as collected_at, listen_port, persistent_keepalive_interval, latency_ms
                 FROM location_stats
                 ORDER BY collected_at
 	            LIMIT -1 OFFSET 1
@@ -275,14 +524,19 @@ impl LocationStats {
             	last_handshake,
             	collected_at as "collected_at!: NaiveDateTime",
             	listen_port as "listen_port!: u32",
-            	persistent_keepalive_interval as "persistent_keepalive_interval?: u16"
+            	persistent_keepalive_interval as "persistent_keepalive_interval?: u16",
+            	CAST(AVG(latency_ms) AS INTEGER) as "latency_ms?: i64",
+            	CAST(ROUND(COALESCE(SUM(MAX(upload, 0)) / NULLIF(SUM(seconds), 0), 0)) AS INTEGER) as "upload_rate?: i64",
+            	CAST(ROUND(COALESCE(SUM(MAX(download, 0)) / NULLIF(SUM(seconds), 0), 0)) AS INTEGER) as "download_rate?: i64"
             FROM cte
-            WHERE location_id = $2
-            AND collected_at >= $3
-            GROUP BY collected_at
-            ORDER BY collected_at;
+            WHERE ($2 IS NULL OR location_id IN (SELECT id FROM location WHERE instance_id = $2))
+            AND ($3 IS NULL OR location_id = $3)
+            AND collected_at >= $4
+            GROUP BY location_id, collected_at
+            ORDER BY location_id, collected_at;
             "#,
             aggregation,
+            instance_id,
             location_id,
             from
         )
@@ -290,4 +544,130 @@ impl LocationStats {
         .await?;
         Ok(stats)
     }
+
+    /// Returns last handshake timestamp from the most recent stats of location.
+    pub async fn latest_handshake(pool: &DbPool, location_id: i64) -> Result<Option<i64>, Error> {
+        let result = query!(
+            "SELECT last_handshake FROM location_stats WHERE location_id = $1 \
+            ORDER BY collected_at DESC LIMIT 1;",
+            location_id
+        )
+        .fetch_optional(pool)
+        .await?;
+        Ok(result.map(|record| record.last_handshake))
+    }
+
+    /// Delete all stats of location, returning number of removed rows. With `keep_latest`
+    /// the most recent stats are kept, so details of the location can still be read.
+    pub async fn delete_by_location_id<'e, E>(
+        executor: E,
+        location_id: i64,
+        keep_latest: bool,
+    ) -> Result<u64, Error>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Sqlite>,
+    {
+        let result = if keep_latest {
+            query!(
+                "DELETE FROM location_stats WHERE location_id = $1 \
+                AND id != (SELECT id FROM location_stats latest WHERE latest.location_id = $1 \
+                ORDER BY collected_at DESC LIMIT 1);",
+                location_id
+            )
+            .execute(executor)
+            .await?
+        } else {
+            query!(
+                "DELETE FROM location_stats WHERE location_id = $1;",
+                location_id
+            )
+            .execute(executor)
+            .await?
+        };
+        Ok(result.rows_affected())
+    }
+
+    /// Delete stats collected before `older_than`, returning number of removed rows.
+    /// The most recent stats of every location are always kept.
+    pub async fn prune(pool: &DbPool, older_than: NaiveDateTime) -> Result<u64, Error> {
+        let result = query!(
+            "DELETE FROM location_stats WHERE collected_at < $1 \
+            AND collected_at < (SELECT MAX(collected_at) FROM location_stats latest \
+            WHERE latest.location_id = location_stats.location_id);",
+            older_than
+        )
+        .execute(pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Returns upload/download totals for each calendar month (in local timezone)
+    /// of the last `months` months, including the current one.
+    pub async fn monthly_usage(
+        pool: &DbPool,
+        location_id: i64,
+        months: u32,
+    ) -> Result<Vec<MonthlyUsage>, Error> {
+        let months = months.max(1);
+        let first_month = Local::now()
+            .date_naive()
+            .with_day(1)
+            .expect("First day of month is always valid")
+            - Months::new(months - 1);
+        let first_month_start = first_month.and_time(NaiveTime::MIN);
+        let from = Local
+            .from_local_datetime(&first_month_start)
+            .earliest()
+            .map_or(first_month_start, |start| start.naive_utc());
+        let mut usage: Vec<MonthlyUsage> = (0..months)
+            .map(|offset| {
+                let month = first_month + Months::new(offset);
+                MonthlyUsage {
+                    year: month.year(),
+                    month: month.month(),
+                    upload: 0,
+                    download: 0,
+                }
+            })
+            .collect();
+        // samples are streamed since long periods may contain millions of them
+        let mut samples = query!(
+            "SELECT upload, download, collected_at FROM location_stats \
+            WHERE location_id = $1 AND collected_at >= $2 ORDER BY collected_at;",
+            location_id,
+            from
+        )
+        .fetch(pool);
+        let mut previous: Option<(i64, i64)> = None;
+        while let Some(current) = samples.next().await {
+            let current = current?;
+            let Some((previous_upload, previous_download)) =
+                previous.replace((current.upload, current.download))
+            else {
+                continue;
+            };
+            let collected_at = Local.from_utc_datetime(&current.collected_at);
+            let index = (collected_at.year() - first_month.year()) * 12
+                + collected_at.month() as i32
+                - first_month.month() as i32;
+            let Some(bucket) = usize::try_from(index)
+                .ok()
+                .and_then(|index| usage.get_mut(index))
+            else {
+                continue;
+            };
+            // counters start from zero when interface is recreated
+            bucket.upload += if current.upload >= previous_upload {
+                current.upload - previous_upload
+            } else {
+                current.upload
+            };
+            bucket.download += if current.download >= previous_download {
+                current.download - previous_download
+            } else {
+                current.download
+            };
+        }
+        Ok(usage)
+    }
 }
@@ -1,6 +1,9 @@
 pub mod connection;
 pub mod instance;
+pub mod lifetime_transfer;
 pub mod location;
+pub mod paused_connection;
+pub mod peak_throughput;
 pub mod settings;
 pub mod tunnel;
 pub mod wireguard_keys;
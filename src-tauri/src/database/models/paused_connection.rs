@@ -0,0 +1,67 @@
+use serde::Serialize;
+use sqlx::{query, query_as, FromRow};
+
+use crate::{database::DbPool, error::Error, ConnectionType};
+
+/// Connection closed by pausing all connections, restored when connections are resumed.
+/// Kept in the database, so connections can be resumed after restart.
+#[derive(FromRow, Debug, Serialize)]
+pub struct PausedConnection {
+    pub id: Option<i64>,
+    pub location_id: i64,
+    pub connection_type: ConnectionType,
+    // key profile location was connected with, `None` for the default key pair
+    pub profile_id: Option<i64>,
+}
+
+impl PausedConnection {
+    #[must_use]
+    pub fn new(location_id: i64, connection_type: ConnectionType, profile_id: Option<i64>) -> Self {
+        Self {
+            id: None,
+            location_id,
+            connection_type,
+            profile_id,
+        }
+    }
+
+    /// Saves paused connection. Connection which is already paused isn't saved again,
+    /// so pausing repeatedly doesn't resume the same connection multiple times.
+    pub async fn save<'e, E>(&mut self, executor: E) -> Result<(), Error>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Sqlite>,
+    {
+        let result = query!(
+            "INSERT INTO paused_connection (location_id, connection_type, profile_id) \
+            SELECT $1, $2, $3 WHERE NOT EXISTS (SELECT 1 FROM paused_connection \
+            WHERE location_id = $1 AND connection_type = $2 AND profile_id IS $3) RETURNING id;",
+            self.location_id,
+            self.connection_type,
+            self.profile_id,
+        )
+        .fetch_optional(executor)
+        .await?;
+        if let Some(result) = result {
+            self.id = Some(result.id);
+        }
+        Ok(())
+    }
+
+    pub async fn all(pool: &DbPool) -> Result<Vec<Self>, Error> {
+        let connections = query_as!(
+            Self,
+            "SELECT id \"id?\", location_id, connection_type \"connection_type: ConnectionType\", profile_id \
+            FROM paused_connection ORDER BY id;"
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(connections)
+    }
+
+    pub async fn delete_all(pool: &DbPool) -> Result<(), Error> {
+        query!("DELETE FROM paused_connection;")
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,68 @@
+use chrono::{NaiveDateTime, Utc};
+use serde::Serialize;
+use sqlx::{query, query_as, FromRow};
+
+use crate::{database::DbPool, error::Error};
+
+/// Highest throughput (bytes per second) location has reached since the last reset.
+#[derive(FromRow, Debug, Serialize)]
+pub struct PeakThroughput {
+    pub location_id: i64,
+    pub upload_rate: i64,
+    pub download_rate: i64,
+    // when either peak was raised last time
+    pub updated_at: NaiveDateTime,
+}
+
+impl PeakThroughput {
+    /// Raise stored peaks of location to given rates, lower rates don't change them.
+    pub async fn record(
+        pool: &DbPool,
+        location_id: i64,
+        upload_rate: i64,
+        download_rate: i64,
+    ) -> Result<(), Error> {
+        let now = Utc::now().naive_utc();
+        query!(
+            "INSERT INTO location_peak_throughput (location_id, upload_rate, download_rate, updated_at) \
+            VALUES ($1, $2, $3, $4) \
+            ON CONFLICT(location_id) DO UPDATE SET \
+            upload_rate = MAX(upload_rate, excluded.upload_rate), \
+            download_rate = MAX(download_rate, excluded.download_rate), \
+            updated_at = excluded.updated_at \
+            WHERE excluded.upload_rate > upload_rate OR excluded.download_rate > download_rate;",
+            location_id,
+            upload_rate,
+            download_rate,
+            now,
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn find_by_location_id(
+        pool: &DbPool,
+        location_id: i64,
+    ) -> Result<Option<Self>, Error> {
+        let peak = query_as!(
+            Self,
+            "SELECT location_id, upload_rate, download_rate, updated_at \
+            FROM location_peak_throughput WHERE location_id = $1;",
+            location_id
+        )
+        .fetch_optional(pool)
+        .await?;
+        Ok(peak)
+    }
+
+    pub async fn delete_by_location_id(pool: &DbPool, location_id: i64) -> Result<(), Error> {
+        query!(
+            "DELETE FROM location_peak_throughput WHERE location_id = $1;",
+            location_id
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+}
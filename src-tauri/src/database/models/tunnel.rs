@@ -1,6 +1,6 @@
 use crate::{
     commands::DateTimeAggregation,
-    database::{ActiveConnection, DbPool},
+    database::{stats_buffer::STATS_BATCH_SIZE, ActiveConnection, DbPool, DisconnectReason},
     error::Error,
     CommonConnection, CommonConnectionInfo, CommonLocationStats, ConnectionType,
 };
@@ -8,7 +8,7 @@ use chrono::{NaiveDateTime, Utc};
 use defguard_wireguard_rs::host::Peer;
 use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, NoneAsEmptyString};
-use sqlx::{query, query_as, Error as SqlxError, FromRow};
+use sqlx::{query, query_as, Error as SqlxError, FromRow, QueryBuilder, Sqlite, Transaction};
 use std::time::SystemTime;
 
 #[serde_as]
@@ -193,6 +193,9 @@ pub struct TunnelStats {
     collected_at: NaiveDateTime,
     listen_port: u32,
     persistent_keepalive_interval: Option<u16>,
+    // throughput in bytes per second, only computed by stats queries
+    upload_rate: Option<i64>,
+    download_rate: Option<i64>,
 }
 
 impl TunnelStats {
@@ -215,6 +218,8 @@ impl TunnelStats {
             collected_at,
             listen_port,
             persistent_keepalive_interval,
+            upload_rate: None,
+            download_rate: None,
         }
     }
 
@@ -237,6 +242,29 @@ impl TunnelStats {
         Ok(())
     }
 
+    /// Insert multiple stats samples within given transaction using multi-row `INSERT` statements.
+    pub async fn save_batch(
+        transaction: &mut Transaction<'_, Sqlite>,
+        stats: &[Self],
+    ) -> Result<(), SqlxError> {
+        for chunk in stats.chunks(STATS_BATCH_SIZE) {
+            let mut builder = QueryBuilder::<Sqlite>::new(
+                "INSERT INTO tunnel_stats (tunnel_id, upload, download, last_handshake, collected_at, listen_port, persistent_keepalive_interval) ",
+            );
+            builder.push_values(chunk, |mut row, stats| {
+                row.push_bind(stats.tunnel_id)
+                    .push_bind(stats.upload)
+                    .push_bind(stats.download)
+                    .push_bind(stats.last_handshake)
+                    .push_bind(stats.collected_at)
+                    .push_bind(stats.listen_port)
+                    .push_bind(stats.persistent_keepalive_interval);
+            });
+            builder.build().execute(&mut **transaction).await?;
+        }
+        Ok(())
+    }
+
     pub async fn all_by_tunnel_id(
         pool: &DbPool,
         tunnel_id: i64,
@@ -252,6 +280,7 @@ impl TunnelStats {
                     id, tunnel_id,
                     COALESCE(upload - LAG(upload) OVER (PARTITION BY tunnel_id ORDER BY collected_at), 0) as upload,
                     COALESCE(download - LAG(download) OVER (PARTITION BY tunnel_id ORDER BY collected_at), 0) as download,
+                    COALESCE((julianday(collected_at) - julianday(LAG(collected_at) OVER (PARTITION BY tunnel_id ORDER BY collected_at))) * 86400, 0) as seconds,
                     last_handshake, strftime($1, collected_at) as collected_at, listen_port, persistent_keepalive_interval
                 FROM tunnel_stats
                 ORDER BY collected_at
@@ -264,7 +293,9 @@ impl TunnelStats {
                 last_handshake,
                 collected_at as "collected_at!: NaiveDateTime",
                 listen_port as "listen_port!: u32",
-                persistent_keepalive_interval as "persistent_keepalive_interval?: u16"
+                persistent_keepalive_interval as "persistent_keepalive_interval?: u16",
+                CAST(ROUND(COALESCE(SUM(MAX(upload, 0)) / NULLIF(SUM(seconds), 0), 0)) AS INTEGER) as "upload_rate?: i64",
+                CAST(ROUND(COALESCE(SUM(MAX(download, 0)) / NULLIF(SUM(seconds), 0), 0)) AS INTEGER) as "download_rate?: i64"
             FROM cte
             WHERE tunnel_id = $2
             AND collected_at >= $3
@@ -298,6 +329,8 @@ pub async fn peer_to_tunnel_stats(
         collected_at: Utc::now().naive_utc(),
         listen_port,
         persistent_keepalive_interval: peer.persistent_keepalive_interval,
+        upload_rate: None,
+        download_rate: None,
     })
 }
 
@@ -308,6 +341,7 @@ pub struct TunnelConnection {
     pub connected_from: String,
     pub start: NaiveDateTime,
     pub end: NaiveDateTime,
+    pub disconnect_reason: Option<DisconnectReason>,
 }
 
 impl From<TunnelConnectionInfo> for CommonConnectionInfo {
@@ -320,6 +354,8 @@ impl From<TunnelConnectionInfo> for CommonConnectionInfo {
             end: val.end,
             upload: val.upload,
             download: val.download,
+            disconnect_reason: val.disconnect_reason,
+            resolved_endpoint: None,
         }
     }
 }
@@ -327,13 +363,14 @@ impl From<TunnelConnectionInfo> for CommonConnectionInfo {
 impl TunnelConnection {
     pub async fn save(&mut self, pool: &DbPool) -> Result<(), Error> {
         let result = query!(
-            "INSERT INTO tunnel_connection (tunnel_id, connected_from, start, end) \
-            VALUES ($1, $2, $3, $4) \
+            "INSERT INTO tunnel_connection (tunnel_id, connected_from, start, end, disconnect_reason) \
+            VALUES ($1, $2, $3, $4, $5) \
             RETURNING id;",
             self.tunnel_id,
             self.connected_from,
             self.start,
             self.end,
+            self.disconnect_reason,
         )
         .fetch_one(pool)
         .await?;
@@ -345,7 +382,8 @@ impl TunnelConnection {
         let connections = query_as!(
             TunnelConnection,
             r#"
-            SELECT id, tunnel_id, connected_from, start, end 
+            SELECT id, tunnel_id, connected_from, start, end,
+              disconnect_reason as "disconnect_reason: DisconnectReason"
             FROM tunnel_connection
             WHERE tunnel_id = $1
             "#,
@@ -360,7 +398,8 @@ impl TunnelConnection {
         let connection = query_as!(
             TunnelConnection,
             r#"
-            SELECT id, tunnel_id, connected_from, start, end
+            SELECT id, tunnel_id, connected_from, start, end,
+              disconnect_reason as "disconnect_reason: DisconnectReason"
             FROM tunnel_connection
             WHERE tunnel_id = $1
             ORDER BY end DESC
@@ -384,10 +423,19 @@ pub struct TunnelConnectionInfo {
     pub end: NaiveDateTime,
     pub upload: Option<i32>,
     pub download: Option<i32>,
+    pub disconnect_reason: Option<DisconnectReason>,
 }
 
 impl TunnelConnectionInfo {
-    pub async fn all_by_tunnel_id(pool: &DbPool, tunnel_id: i64) -> Result<Vec<Self>, Error> {
+    /// Returns connections overlapping given time range, newest first.
+    pub async fn all_by_tunnel_id(
+        pool: &DbPool,
+        tunnel_id: i64,
+        from: Option<NaiveDateTime>,
+        to: Option<NaiveDateTime>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<Self>, Error> {
         // Because we store interface information for given timestamp select last upload and download
         // before connection ended
         // FIXME: Optimize query
@@ -417,26 +465,61 @@ impl TunnelConnectionInfo {
                       AND ls.collected_at <= c.end
                       ORDER BY ls.collected_at DESC
                       LIMIT 1
-                  ), 0) as "download: _"
-              FROM tunnel_connection AS c WHERE tunnel_id = $1
-              ORDER BY start DESC;
+                  ), 0) as "download: _",
+                  c.disconnect_reason as "disconnect_reason: DisconnectReason"
+              FROM tunnel_connection AS c
+              WHERE tunnel_id = $1
+              AND ($2 IS NULL OR c.end >= $2)
+              AND ($3 IS NULL OR c.start <= $3)
+              ORDER BY start DESC
+              LIMIT $4 OFFSET $5;
             "#,
-            tunnel_id
+            tunnel_id,
+            from,
+            to,
+            limit,
+            offset
         )
         .fetch_all(pool)
         .await?;
 
         Ok(connections)
     }
+
+    /// Counts connections overlapping given time range.
+    pub async fn count_by_tunnel_id(
+        pool: &DbPool,
+        tunnel_id: i64,
+        from: Option<NaiveDateTime>,
+        to: Option<NaiveDateTime>,
+    ) -> Result<i64, Error> {
+        let result = query!(
+            r#"
+            SELECT COUNT(*) as "count!: i64" FROM tunnel_connection
+            WHERE tunnel_id = $1
+            AND ($2 IS NULL OR end >= $2)
+            AND ($3 IS NULL OR start <= $3);
+            "#,
+            tunnel_id,
+            from,
+            to
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(result.count)
+    }
 }
-impl From<ActiveConnection> for TunnelConnection {
-    fn from(active_connection: ActiveConnection) -> Self {
+impl ActiveConnection {
+    /// Converts active connection into historical tunnel connection ending now.
+    #[must_use]
+    pub fn into_tunnel_connection(self, disconnect_reason: DisconnectReason) -> TunnelConnection {
         TunnelConnection {
             id: None,
-            tunnel_id: active_connection.location_id,
-            connected_from: active_connection.connected_from,
-            start: active_connection.start,
+            tunnel_id: self.location_id,
+            connected_from: self.connected_from,
+            start: self.start,
             end: Utc::now().naive_utc(),
+            disconnect_reason: Some(disconnect_reason),
         }
     }
 }
@@ -466,6 +549,9 @@ impl From<TunnelStats> for CommonLocationStats {
             collected_at: tunnel_stats.collected_at,
             listen_port: tunnel_stats.listen_port,
             persistent_keepalive_interval: tunnel_stats.persistent_keepalive_interval, // Set the appropriate value
+            latency_ms: None,
+            upload_rate: tunnel_stats.upload_rate.unwrap_or_default(),
+            download_rate: tunnel_stats.download_rate.unwrap_or_default(),
             connection_type: ConnectionType::Tunnel,
         }
     }
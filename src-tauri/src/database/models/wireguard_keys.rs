@@ -8,6 +8,20 @@ pub struct WireguardKeys {
     pub instance_id: i64,
     pub pubkey: String,
     pub prvkey: String,
+    // key pair replaced during key migration, kept until server accepts the current one
+    pub previous_pubkey: Option<String>,
+    pub previous_prvkey: Option<String>,
+    // name of additional key profile, `None` for the default key pair of instance
+    pub profile_name: Option<String>,
+    // interface address of device registered with profile key, location address is used if unset
+    pub address: Option<String>,
+}
+
+// Single key pair used to configure interface
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyPair {
+    pub pubkey: String,
+    pub prvkey: String,
 }
 
 impl WireguardKeys {
@@ -18,6 +32,26 @@ impl WireguardKeys {
             instance_id,
             pubkey,
             prvkey,
+            previous_pubkey: None,
+            previous_prvkey: None,
+            profile_name: None,
+            address: None,
+        }
+    }
+
+    /// Additional named key pair of instance, e.g. to connect with another device identity.
+    #[must_use]
+    pub fn new_profile(
+        instance_id: i64,
+        profile_name: String,
+        pubkey: String,
+        prvkey: String,
+        address: Option<String>,
+    ) -> Self {
+        WireguardKeys {
+            profile_name: Some(profile_name),
+            address,
+            ..Self::new(instance_id, pubkey, prvkey)
         }
     }
 
@@ -25,32 +59,124 @@ impl WireguardKeys {
     where
         E: sqlx::Executor<'e, Database = sqlx::Sqlite>,
     {
-        let result = query!(
-            "INSERT INTO wireguard_keys (instance_id, pubkey, prvkey) \
-            VALUES ($1, $2, $3) \
-            RETURNING id;
-            ",
-            self.instance_id,
-            self.pubkey,
-            self.prvkey,
-        )
-        .fetch_one(executor)
-        .await?;
-        self.id = Some(result.id);
+        match self.id {
+            None => {
+                let result = query!(
+                    "INSERT INTO wireguard_keys (instance_id, pubkey, prvkey, previous_pubkey, previous_prvkey, profile_name, address) \
+                    VALUES ($1, $2, $3, $4, $5, $6, $7) \
+                    RETURNING id;
+                    ",
+                    self.instance_id,
+                    self.pubkey,
+                    self.prvkey,
+                    self.previous_pubkey,
+                    self.previous_prvkey,
+                    self.profile_name,
+                    self.address,
+                )
+                .fetch_one(executor)
+                .await?;
+                self.id = Some(result.id);
+            }
+            Some(id) => {
+                query!(
+                    "UPDATE wireguard_keys \
+                    SET instance_id = $1, pubkey = $2, prvkey = $3, previous_pubkey = $4, previous_prvkey = $5, \
+                    profile_name = $6, address = $7 \
+                    WHERE id = $8;",
+                    self.instance_id,
+                    self.pubkey,
+                    self.prvkey,
+                    self.previous_pubkey,
+                    self.previous_prvkey,
+                    self.profile_name,
+                    self.address,
+                    id,
+                )
+                .execute(executor)
+                .await?;
+            }
+        }
         Ok(())
     }
 
+    /// Returns the default key pair of instance.
     pub async fn find_by_instance_id(
         pool: &DbPool,
         instance_id: i64,
     ) -> Result<Option<Self>, SqlxError> {
         query_as!(
             Self,
-            "SELECT id \"id?\", instance_id, pubkey, prvkey \
-            FROM wireguard_keys WHERE instance_id = $1;",
+            "SELECT id \"id?\", instance_id, pubkey, prvkey, previous_pubkey, previous_prvkey, profile_name, address \
+            FROM wireguard_keys WHERE instance_id = $1 AND profile_name IS NULL;",
             instance_id
         )
         .fetch_optional(pool)
         .await
     }
+
+    pub async fn find_by_id(pool: &DbPool, id: i64) -> Result<Option<Self>, SqlxError> {
+        query_as!(
+            Self,
+            "SELECT id \"id?\", instance_id, pubkey, prvkey, previous_pubkey, previous_prvkey, profile_name, address \
+            FROM wireguard_keys WHERE id = $1;",
+            id
+        )
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Returns additional key profiles of instance ordered by name.
+    pub async fn find_profiles_by_instance_id(
+        pool: &DbPool,
+        instance_id: i64,
+    ) -> Result<Vec<Self>, SqlxError> {
+        query_as!(
+            Self,
+            "SELECT id \"id?\", instance_id, pubkey, prvkey, previous_pubkey, previous_prvkey, profile_name, address \
+            FROM wireguard_keys WHERE instance_id = $1 AND profile_name IS NOT NULL ORDER BY profile_name;",
+            instance_id
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    pub async fn delete(&self, pool: &DbPool) -> Result<(), SqlxError> {
+        query!("DELETE FROM wireguard_keys WHERE id = $1;", self.id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn key_pair(&self) -> KeyPair {
+        KeyPair {
+            pubkey: self.pubkey.clone(),
+            prvkey: self.prvkey.clone(),
+        }
+    }
+
+    #[must_use]
+    pub fn previous_key_pair(&self) -> Option<KeyPair> {
+        match (&self.previous_pubkey, &self.previous_prvkey) {
+            (Some(pubkey), Some(prvkey)) => Some(KeyPair {
+                pubkey: pubkey.clone(),
+                prvkey: prvkey.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Replace current key pair, keeping it as previous one until the new pair is accepted.
+    pub fn rotate(&mut self, pubkey: String, prvkey: String) {
+        self.previous_pubkey = Some(std::mem::replace(&mut self.pubkey, pubkey));
+        self.previous_prvkey = Some(std::mem::replace(&mut self.prvkey, prvkey));
+    }
+
+    /// Forget previous key pair once the server accepted the current one.
+    pub async fn clear_previous(&mut self, pool: &DbPool) -> Result<(), Error> {
+        self.previous_pubkey = None;
+        self.previous_prvkey = None;
+        self.save(pool).await
+    }
 }
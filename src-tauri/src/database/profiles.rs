@@ -0,0 +1,123 @@
+//! Data profiles
+//!
+//! Each data profile has its own database with separate instances, tunnels and settings.
+//! The default profile uses database stored directly in the app data directory, other profiles
+//! are stored in `profiles/<name>` subdirectories. Name of the active profile is kept in
+//! a plain text file so the same profile is opened after restart.
+
+use std::{fs, path::PathBuf};
+
+use serde::Serialize;
+use tauri::AppHandle;
+
+use crate::error::Error;
+
+pub const DEFAULT_PROFILE: &str = "default";
+const PROFILES_DIR: &str = "profiles";
+const ACTIVE_PROFILE_FILE: &str = "active_profile";
+
+#[derive(Debug, Serialize)]
+pub struct DataProfile {
+    pub name: String,
+    pub active: bool,
+}
+
+fn app_data_dir(app_handle: &AppHandle) -> Result<PathBuf, Error> {
+    app_handle
+        .path_resolver()
+        .app_data_dir()
+        .ok_or(Error::Config)
+}
+
+/// Profile names are used as directory names, so only a safe subset of characters is allowed.
+pub fn validate_profile_name(name: &str) -> Result<(), Error> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(Error::InvalidDataProfile(format!(
+            "{name}: only letters, digits, '-' and '_' are allowed"
+        )));
+    }
+    Ok(())
+}
+
+/// Returns directory containing database of given profile.
+pub fn profile_dir(app_handle: &AppHandle, name: &str) -> Result<PathBuf, Error> {
+    let app_dir = app_data_dir(app_handle)?;
+    if name == DEFAULT_PROFILE {
+        Ok(app_dir)
+    } else {
+        validate_profile_name(name)?;
+        Ok(app_dir.join(PROFILES_DIR).join(name))
+    }
+}
+
+#[must_use]
+pub fn profile_exists(app_handle: &AppHandle, name: &str) -> bool {
+    name == DEFAULT_PROFILE
+        || profile_dir(app_handle, name)
+            .map(|dir| dir.is_dir())
+            .unwrap_or_default()
+}
+
+/// Returns name of the active profile, falls back to default profile.
+pub fn active_profile(app_handle: &AppHandle) -> Result<String, Error> {
+    let path = app_data_dir(app_handle)?.join(ACTIVE_PROFILE_FILE);
+    let name = fs::read_to_string(path)
+        .map(|name| name.trim().to_string())
+        .unwrap_or_default();
+    if name.is_empty() || !profile_exists(app_handle, &name) {
+        Ok(DEFAULT_PROFILE.into())
+    } else {
+        Ok(name)
+    }
+}
+
+pub fn set_active_profile(app_handle: &AppHandle, name: &str) -> Result<(), Error> {
+    let app_dir = app_data_dir(app_handle)?;
+    fs::create_dir_all(&app_dir)?;
+    fs::write(app_dir.join(ACTIVE_PROFILE_FILE), name)?;
+    Ok(())
+}
+
+pub fn list_profiles(app_handle: &AppHandle) -> Result<Vec<DataProfile>, Error> {
+    let active = active_profile(app_handle)?;
+    let mut names = vec![DEFAULT_PROFILE.to_string()];
+    let profiles_dir = app_data_dir(app_handle)?.join(PROFILES_DIR);
+    if profiles_dir.is_dir() {
+        for entry in fs::read_dir(profiles_dir)? {
+            let entry = entry?;
+            if entry.path().is_dir() {
+                names.push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+    }
+    names[1..].sort();
+    Ok(names
+        .into_iter()
+        .map(|name| DataProfile {
+            active: name == active,
+            name,
+        })
+        .collect())
+}
+
+pub fn delete_profile(app_handle: &AppHandle, name: &str) -> Result<(), Error> {
+    if name == DEFAULT_PROFILE {
+        return Err(Error::InvalidDataProfile(
+            "default profile can't be deleted".into(),
+        ));
+    }
+    if active_profile(app_handle)? == name {
+        return Err(Error::InvalidDataProfile(format!(
+            "{name}: active profile can't be deleted"
+        )));
+    }
+    if !profile_exists(app_handle, name) {
+        return Err(Error::NotFound);
+    }
+    fs::remove_dir_all(profile_dir(app_handle, name)?)?;
+    Ok(())
+}
@@ -0,0 +1,181 @@
+//! Buffering of interface stats
+//!
+//! Stats are received every second for each active interface. Instead of writing every sample
+//! in a separate query, samples are buffered in memory and periodically written to the database
+//! in a single transaction.
+
+use std::{mem, sync::Mutex, time::Duration};
+
+use tauri::{AppHandle, Manager};
+use tokio::time::interval;
+
+use crate::{
+    appstate::AppState,
+    database::{DbPool, LocationStats, TunnelStats},
+    error::Error,
+};
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+// max number of rows in a single `INSERT` to stay below SQLite bound parameters limit
+pub(crate) const STATS_BATCH_SIZE: usize = 100;
+// max number of buffered samples of each kind, oldest samples are dropped when database
+// is unavailable for a long time
+const MAX_BUFFERED_STATS: usize = 10_000;
+
+#[derive(Debug, Default)]
+pub struct StatsBuffer {
+    location_stats: Mutex<Vec<LocationStats>>,
+    tunnel_stats: Mutex<Vec<TunnelStats>>,
+}
+
+/// Drop oldest samples exceeding [`MAX_BUFFERED_STATS`].
+fn truncate_buffer<T>(buffer: &mut Vec<T>) {
+    if buffer.len() > MAX_BUFFERED_STATS {
+        let excess = buffer.len() - MAX_BUFFERED_STATS;
+        warn!("Stats buffer is full, dropping {excess} oldest samples");
+        buffer.drain(..excess);
+    }
+}
+
+impl StatsBuffer {
+    pub fn push_location_stats(&self, stats: LocationStats) {
+        let mut buffer = self
+            .location_stats
+            .lock()
+            .expect("Failed to lock location stats buffer");
+        buffer.push(stats);
+        truncate_buffer(&mut buffer);
+    }
+
+    pub fn push_tunnel_stats(&self, stats: TunnelStats) {
+        let mut buffer = self
+            .tunnel_stats
+            .lock()
+            .expect("Failed to lock tunnel stats buffer");
+        buffer.push(stats);
+        truncate_buffer(&mut buffer);
+    }
+
+    /// Drop all buffered samples.
+    pub fn clear(&self) {
+        self.location_stats
+            .lock()
+            .expect("Failed to lock location stats buffer")
+            .clear();
+        self.tunnel_stats
+            .lock()
+            .expect("Failed to lock tunnel stats buffer")
+            .clear();
+    }
+
+    /// Write all buffered samples to the database.
+    /// If writing fails samples are put back into the buffer and retried on next flush.
+    pub async fn flush(&self, pool: &DbPool) -> Result<(), Error> {
+        let location_stats = mem::take(
+            &mut *self
+                .location_stats
+                .lock()
+                .expect("Failed to lock location stats buffer"),
+        );
+        let tunnel_stats = mem::take(
+            &mut *self
+                .tunnel_stats
+                .lock()
+                .expect("Failed to lock tunnel stats buffer"),
+        );
+        if location_stats.is_empty() && tunnel_stats.is_empty() {
+            return Ok(());
+        }
+        debug!(
+            "Flushing {} location stats and {} tunnel stats",
+            location_stats.len(),
+            tunnel_stats.len()
+        );
+        if let Err(err) = Self::write(pool, &location_stats, &tunnel_stats).await {
+            error!("Failed to flush stats: {err}");
+            let mut buffer = self
+                .location_stats
+                .lock()
+                .expect("Failed to lock location stats buffer");
+            buffer.splice(0..0, location_stats);
+            truncate_buffer(&mut buffer);
+            drop(buffer);
+            let mut buffer = self
+                .tunnel_stats
+                .lock()
+                .expect("Failed to lock tunnel stats buffer");
+            buffer.splice(0..0, tunnel_stats);
+            truncate_buffer(&mut buffer);
+            return Err(err);
+        }
+        trace!("Stats flushed");
+        Ok(())
+    }
+
+    async fn write(
+        pool: &DbPool,
+        location_stats: &[LocationStats],
+        tunnel_stats: &[TunnelStats],
+    ) -> Result<(), Error> {
+        let mut transaction = pool.begin().await?;
+        LocationStats::save_batch(&mut transaction, location_stats).await?;
+        TunnelStats::save_batch(&mut transaction, tunnel_stats).await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+}
+
+pub async fn flush_stats_loop(app_handle: AppHandle) {
+    let state = app_handle.state::<AppState>();
+    let mut interval = interval(FLUSH_INTERVAL);
+    loop {
+        interval.tick().await;
+        // pool is retrieved on every flush since it changes when data profile is switched
+        let _ = state.stats_buffer.flush(&state.get_pool()).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    use crate::database::{seed_instance, seed_location, test_pool};
+
+    #[tokio::test]
+    async fn test_flush_buffered_stats() {
+        let pool = test_pool().await;
+        let instance_id = seed_instance(&pool).await;
+        seed_location(&pool, instance_id, "location").await;
+
+        let buffer = StatsBuffer::default();
+        let collected_at = Utc::now().naive_utc();
+        for i in 0..(STATS_BATCH_SIZE as i64 + 5) {
+            buffer.push_location_stats(LocationStats::new(
+                1,
+                i * 100,
+                i * 200,
+                i,
+                collected_at,
+                51820,
+                Some(25),
+            ));
+        }
+        buffer.flush(&pool).await.unwrap();
+        // buffer is empty after flush so nothing gets written twice
+        buffer.flush(&pool).await.unwrap();
+
+        let rows: Vec<(i64, i64, i64, u32, Option<u16>)> = sqlx::query_as(
+            "SELECT upload, download, last_handshake, listen_port, persistent_keepalive_interval \
+            FROM location_stats WHERE location_id = 1 ORDER BY id;",
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(rows.len(), STATS_BATCH_SIZE + 5);
+        for (i, row) in rows.into_iter().enumerate() {
+            let i = i as i64;
+            assert_eq!(row, (i * 100, i * 200, i, 51820, Some(25)));
+        }
+    }
+}
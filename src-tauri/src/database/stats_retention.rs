@@ -0,0 +1,99 @@
+//! Retention of collected interface stats
+//!
+//! Location stats are saved on every collection cycle, so without pruning the table grows
+//! indefinitely. Stats older than configured `retention_days` setting are removed on startup
+//! and then periodically while the app is running.
+
+use std::time::Duration;
+
+use chrono::{Duration as ChronoDuration, NaiveDateTime, Utc};
+use tauri::{AppHandle, Manager};
+use tokio::time::interval;
+
+use crate::{
+    appstate::AppState,
+    database::{models::settings::Settings, DbPool, LocationStats},
+    error::Error,
+};
+
+const PRUNE_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Returns the point in time before which stats older than `days` were collected.
+#[must_use]
+pub fn retention_cutoff(days: i64) -> NaiveDateTime {
+    Utc::now().naive_utc() - ChronoDuration::days(days)
+}
+
+/// Remove location stats older than `days`, keeping the most recent stats of every location.
+pub async fn prune_location_stats(pool: &DbPool, days: i64) -> Result<u64, Error> {
+    let removed = LocationStats::prune(pool, retention_cutoff(days)).await?;
+    info!("Pruned {removed} location stats older than {days} days");
+    Ok(removed)
+}
+
+/// Apply `retention_days` setting on startup and then once a day.
+pub async fn prune_stats_loop(app_handle: AppHandle) {
+    let state = app_handle.state::<AppState>();
+    let mut interval = interval(PRUNE_INTERVAL);
+    loop {
+        interval.tick().await;
+        // pool is retrieved on every run since it changes when data profile is switched
+        let pool = state.get_pool();
+        let days = match Settings::get(&pool).await {
+            Ok(settings) => settings.retention_days,
+            Err(err) => {
+                error!("Failed to read stats retention setting: {err}");
+                continue;
+            }
+        };
+        if days <= 0 {
+            debug!("Stats retention disabled, skipping pruning");
+            continue;
+        }
+        if let Err(err) = prune_location_stats(&pool, days).await {
+            error!("Failed to prune location stats: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{seed_instance, seed_location, test_pool};
+
+    #[tokio::test]
+    async fn test_prune_keeps_latest_stats() {
+        let pool = test_pool().await;
+        let instance_id = seed_instance(&pool).await;
+        for location in ["first", "second"] {
+            seed_location(&pool, instance_id, location).await;
+        }
+
+        // first location has recent stats, second one only old stats
+        let samples = [(1, 1), (1, 40), (1, 50), (2, 40), (2, 50)];
+        for (location_id, age_days) in samples {
+            LocationStats::new(
+                location_id,
+                0,
+                0,
+                0,
+                retention_cutoff(age_days),
+                51820,
+                Some(25),
+            )
+            .save(&pool)
+            .await
+            .unwrap();
+        }
+
+        let removed = prune_location_stats(&pool, 30).await.unwrap();
+        assert_eq!(removed, 3);
+
+        let rows: Vec<(i64,)> =
+            sqlx::query_as("SELECT location_id FROM location_stats ORDER BY location_id;")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        assert_eq!(rows, vec![(1,), (2,)]);
+    }
+}
@@ -0,0 +1,88 @@
+//! Diagnostics bundle for bug reports
+//!
+//! Logs of a location interface from `defguard-service`, location config with secrets left out,
+//! recent stats and versions of the app and the service are packed into a single zip archive,
+//! which can be attached to an issue as is.
+
+use std::io::{Cursor, Write};
+
+use chrono::{Duration, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+use crate::{
+    commands::DateTimeAggregation,
+    database::{DbPool, Location, LocationStats},
+    error::Error,
+    issue_capture::redact_secrets,
+    service::{
+        log_watcher::read_interface_logs,
+        status::{query_service_status, ServiceStatus},
+    },
+    wg_config::location_to_wireguard_config,
+};
+
+// how far back logs and stats are collected
+const DIAGNOSTICS_LOG_PERIOD: i64 = 24;
+const DIAGNOSTICS_STATS_PERIOD: i64 = 1;
+
+#[derive(Debug, Serialize)]
+struct Versions {
+    app_version: String,
+    operating_system: &'static str,
+    service: ServiceStatus,
+}
+
+/// Builds zip archive with diagnostics of given location, logs being filtered
+/// to `interface_name`.
+pub async fn collect_diagnostics(
+    pool: &DbPool,
+    location: &Location,
+    interface_name: &str,
+    app_version: String,
+) -> Result<Vec<u8>, Error> {
+    let location_id = location.id.expect("Missing location ID");
+    info!("Collecting diagnostics of location {location}, interface {interface_name}");
+
+    let versions = Versions {
+        app_version,
+        operating_system: std::env::consts::OS,
+        service: query_service_status().await,
+    };
+    let mut location_json = serde_json::to_value(location)?;
+    redact_secrets(&mut location_json);
+    // private and preshared keys are only included when private key is given
+    let config = location_to_wireguard_config(location, None)?;
+    let from = Utc::now() - Duration::hours(DIAGNOSTICS_STATS_PERIOD);
+    let stats = LocationStats::all_by_location_id(
+        pool,
+        location_id,
+        &from.naive_utc(),
+        &DateTimeAggregation::Second,
+    )
+    .await?;
+    let from = Utc::now() - Duration::hours(DIAGNOSTICS_LOG_PERIOD);
+    let logs = read_interface_logs(interface_name, from)?;
+    debug!(
+        "Collected {} log lines and {} stats of location {location}",
+        logs.len(),
+        stats.len()
+    );
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    zip.start_file("versions.json", options)?;
+    zip.write_all(&serde_json::to_vec_pretty(&versions)?)?;
+    zip.start_file("location.json", options)?;
+    zip.write_all(&serde_json::to_vec_pretty::<Value>(&location_json)?)?;
+    zip.start_file("location.conf", options)?;
+    zip.write_all(config.as_bytes())?;
+    zip.start_file("stats.json", options)?;
+    zip.write_all(&serde_json::to_vec_pretty(&stats)?)?;
+    zip.start_file("defguard-service.log", options)?;
+    for line in logs {
+        writeln!(zip, "{line}")?;
+    }
+    Ok(zip.finish()?.into_inner())
+}
@@ -3,8 +3,10 @@ use std::net::AddrParseError;
 use base64;
 use defguard_wireguard_rs::{error::WireguardInterfaceError, net::IpAddrParseError};
 use local_ip_address::Error as LocalIpError;
+use serde::ser::SerializeStruct;
 use sqlx;
 use thiserror::Error;
+use tonic::{Code, Status};
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -44,14 +46,122 @@ pub enum Error {
     MutexError,
     #[error("Command failed: {0}")]
     CommandError(String),
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Not supported on this platform: {0}")]
+    Unsupported(String),
+    #[error("Invalid DNS configuration: {0}")]
+    InvalidDns(String),
+    #[error("Location address conflicts with local network: {0}")]
+    AddressConflict(String),
+    #[error("Tunnel routes would capture daemon control channel: {0}")]
+    ControlChannelBlackhole(String),
+    #[error("Invalid data profile: {0}")]
+    InvalidDataProfile(String),
+    #[error("WireGuard driver missing: {0}")]
+    DriverMissing(String),
+    #[error("defguard-service is unavailable: {0}")]
+    ServiceUnavailable(String),
+    #[error("Failed to remove interface: {0}")]
+    RemoveInterface(String),
+    #[error("Invalid allowed IPs: {0}")]
+    InvalidAllowedIps(String),
+    #[error("Connection timed out after {0} seconds")]
+    ConnectionTimeout(u64),
+    #[error("Invalid URL: {0}")]
+    InvalidUrl(String),
+    #[error("Location {0} can't be connected outside of its schedule")]
+    OutsideSchedule(String),
+    #[error("Failed to resolve endpoint hostname {0}")]
+    EndpointResolution(String),
+    #[error("Failed to register launch at login: {0}")]
+    Autostart(String),
+    #[error("Connection attempt cancelled")]
+    Cancelled,
+    #[error("Zip archive error: {0}")]
+    Zip(#[from] zip::result::ZipError),
+}
+
+impl Error {
+    /// Maps failed `remove_interface` call to an error telling whether service could be reached.
+    #[must_use]
+    pub fn remove_interface(status: &Status) -> Self {
+        match status.code() {
+            Code::Unavailable => Self::ServiceUnavailable(status.message().into()),
+            _ => Self::RemoveInterface(status.message().into()),
+        }
+    }
+
+    /// Stable identifier of error kind, which frontend can use to localize the message.
+    #[must_use]
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Io(_) => "IO_ERROR",
+            Self::Config => "CONFIG_DIRECTORY_ERROR",
+            Self::Database(_) => "DATABASE_ERROR",
+            Self::Migration(_) => "MIGRATION_ERROR",
+            Self::WireguardError(_) => "WIREGUARD_ERROR",
+            Self::KeyDecode(_) => "INVALID_KEY",
+            Self::IpAddrMask(_) | Self::AddrParse(_) => "INVALID_ADDRESS",
+            Self::LocalIpError(_) => "LOCAL_IP_ERROR",
+            Self::InternalError => "INTERNAL_ERROR",
+            Self::Datetime => "INVALID_TIMESTAMP",
+            Self::NotFound => "NOT_FOUND",
+            Self::Tauri(_) => "TAURI_ERROR",
+            Self::StrumError(_) => "INVALID_ENUM_VALUE",
+            Self::ResourceNotFound(_) => "RESOURCE_NOT_FOUND",
+            Self::ConfigParseError(_) => "CONFIG_PARSE_ERROR",
+            Self::MutexError => "MUTEX_POISONED",
+            Self::CommandError(_) => "COMMAND_ERROR",
+            Self::Json(_) => "JSON_ERROR",
+            Self::Unsupported(_) => "UNSUPPORTED",
+            Self::InvalidDns(_) => "INVALID_DNS",
+            Self::AddressConflict(_) => "ADDRESS_CONFLICT",
+            Self::ControlChannelBlackhole(_) => "CONTROL_CHANNEL_BLACKHOLE",
+            Self::InvalidDataProfile(_) => "INVALID_DATA_PROFILE",
+            Self::DriverMissing(_) => "DRIVER_MISSING",
+            Self::ServiceUnavailable(_) => "SERVICE_UNAVAILABLE",
+            Self::RemoveInterface(_) => "REMOVE_INTERFACE_FAILED",
+            Self::InvalidAllowedIps(_) => "INVALID_ALLOWED_IPS",
+            Self::ConnectionTimeout(_) => "CONNECTION_TIMEOUT",
+            Self::InvalidUrl(_) => "INVALID_URL",
+            Self::OutsideSchedule(_) => "OUTSIDE_SCHEDULE",
+            Self::EndpointResolution(_) => "ENDPOINT_RESOLUTION_FAILED",
+            Self::Autostart(_) => "AUTOSTART_FAILED",
+            Self::Cancelled => "CANCELLED",
+            Self::Zip(_) => "ZIP_ERROR",
+        }
+    }
 }
 
 // we must manually implement serde::Serialize
+// errors are sent to frontend as `{ "code": "NOT_FOUND", "message": "Object not found" }`
 impl serde::Serialize for Error {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: serde::ser::Serializer,
     {
-        serializer.serialize_str(self.to_string().as_ref())
+        let mut error = serializer.serialize_struct("Error", 2)?;
+        error.serialize_field("code", self.code())?;
+        error.serialize_field("message", &self.to_string())?;
+        error.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialize_error() {
+        let value = serde_json::to_value(Error::NotFound).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({ "code": "NOT_FOUND", "message": "Object not found" })
+        );
+        let error = Error::remove_interface(&Status::unavailable("connection refused"));
+        assert_eq!(error.code(), "SERVICE_UNAVAILABLE");
+        let error = Error::remove_interface(&Status::internal("no such device"));
+        assert_eq!(error.code(), "REMOVE_INTERFACE_FAILED");
     }
 }
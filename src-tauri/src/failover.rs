@@ -0,0 +1,257 @@
+//! Automatic failover between locations
+//!
+//! Locations of an instance which share `failover_group` back each other up. When the connected
+//! member of a group goes stale, it's disconnected and the next member by `failover_priority`
+//! is connected instead. Locations without a group are never failed over, neither are locations
+//! without persistent keepalive, whose handshakes get old whenever the tunnel is idle.
+
+use std::{collections::HashMap, time::Duration};
+
+use chrono::{Duration as ChronoDuration, NaiveDateTime, Utc};
+use serde::Serialize;
+use tauri::{AppHandle, Manager};
+use tokio::time::interval;
+
+use crate::{
+    appstate::AppState,
+    commands::{connect, Payload},
+    connection_health::{location_health, ConnectionHealth},
+    database::{
+        models::settings::DEFAULT_STALE_HANDSHAKE_SECONDS, DisconnectReason, Location, Settings,
+    },
+    error::Error,
+    issue_capture::emit_event,
+    tray::refresh_tray,
+    utils::disconnect_interface,
+    ConnectionType,
+};
+
+const FAILOVER_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+// minimum time between failovers of a group, so flapping endpoints don't cycle connections
+const FAILOVER_COOLDOWN_SECONDS: i64 = 300;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct FailoverPayload {
+    pub failover_group: String,
+    pub from_location_id: i64,
+    pub to_location_id: i64,
+    // set when backup location failed to connect
+    pub error: Option<String>,
+}
+
+/// Group member following location `current` by priority, wrapping around.
+/// Locations requiring MFA are skipped, because they can't be connected without user.
+fn next_member(members: &[Location], current: i64) -> Option<&Location> {
+    let mut members: Vec<&Location> = members.iter().collect();
+    members.sort_by_key(|location| (location.failover_priority, location.id));
+    let position = members
+        .iter()
+        .position(|location| location.id == Some(current))?;
+    members
+        .iter()
+        .cycle()
+        .skip(position + 1)
+        .take(members.len() - 1)
+        .find(|location| !location.mfa_enabled)
+        .copied()
+}
+
+/// Periodically checks health of connected locations which belong to a failover group
+/// and fails over the stale ones.
+pub async fn failover_loop(app_handle: AppHandle) {
+    let state = app_handle.state::<AppState>();
+    let mut last_failover: HashMap<(i64, String), NaiveDateTime> = HashMap::new();
+    let mut interval = interval(FAILOVER_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let pool = state.get_pool();
+        let stale_after = Settings::stale_handshake(&pool).await.map_or(
+            Duration::from_secs(DEFAULT_STALE_HANDSHAKE_SECONDS as u64),
+            |(stale_after, _)| stale_after,
+        );
+        let now = Utc::now().naive_utc();
+        for connection in state.get_connections() {
+            // connections made with key profiles are managed by user
+            if !connection.connection_type.eq(&ConnectionType::Location)
+                || connection.profile_id.is_some()
+            {
+                continue;
+            }
+            // new connection may not have completed its first handshake yet
+            if now - connection.start < ChronoDuration::from_std(stale_after).unwrap_or_default() {
+                continue;
+            }
+            let location_id = connection.location_id;
+            let location = match Location::find_by_id(&pool, location_id).await {
+                Ok(Some(location)) => location,
+                Ok(None) => continue,
+                Err(err) => {
+                    error!("Failed to load location {location_id} for failover check: {err}");
+                    continue;
+                }
+            };
+            let Some(group) = location.failover_group.clone() else {
+                continue;
+            };
+            // without keepalive, handshakes are renewed only by traffic, so idle tunnel looks stale
+            if location.keepalive_interval <= 0 {
+                debug!("Location {location} has no persistent keepalive, skipping failover check");
+                continue;
+            }
+            match location_health(&pool, location_id, true).await {
+                Ok(health) if health.health == ConnectionHealth::Stale => {}
+                Ok(_) => continue,
+                Err(err) => {
+                    error!("Failed to check health of location {location}: {err}");
+                    continue;
+                }
+            }
+            let key = (location.instance_id, group.clone());
+            if let Some(previous) = last_failover.get(&key) {
+                if now - *previous < ChronoDuration::seconds(FAILOVER_COOLDOWN_SECONDS) {
+                    debug!("Location {location} is stale, but group {group} failed over recently");
+                    continue;
+                }
+            }
+            last_failover.insert(key, now);
+            if let Err(err) = fail_over(&app_handle, &location, &group).await {
+                error!("Failover of location {location} in group {group} failed: {err}");
+            }
+        }
+    }
+}
+
+/// Replaces stale connection of location with connection of the next member of its group.
+async fn fail_over(app_handle: &AppHandle, location: &Location, group: &str) -> Result<(), Error> {
+    let state = app_handle.state::<AppState>();
+    let location_id = location.id.expect("Missing location ID");
+    let members: Vec<Location> =
+        Location::find_by_instance_id(&state.get_pool(), location.instance_id)
+            .await?
+            .into_iter()
+            .filter(|member| member.failover_group.as_deref() == Some(group))
+            .collect();
+    let Some(next) = next_member(&members, location_id) else {
+        warn!(
+            "Location {location} is stale, but group {group} has no other member to fail over to"
+        );
+        return Ok(());
+    };
+    let next_id = next.id.expect("Missing location ID");
+    if state
+        .get_connection_id_by_type(&ConnectionType::Location)
+        .contains(&next_id)
+    {
+        warn!("Location {location} is stale, but backup location {next} is already connected");
+        return Ok(());
+    }
+    warn!("Location {location} is stale, failing over to location {next} of group {group}");
+    if let Some(connection) =
+        state.find_and_remove_connection(location_id, &ConnectionType::Location)
+    {
+        disconnect_interface(connection, &state, DisconnectReason::NetworkFailure).await?;
+    }
+    let result = connect(
+        next_id,
+        ConnectionType::Location,
+        None,
+        None,
+        None,
+        app_handle.clone(),
+    )
+    .await;
+    if let Err(err) = &result {
+        error!("Failed to connect backup location {next}: {err}");
+    } else {
+        info!("Failed over from location {location} to location {next}");
+    }
+    emit_event(
+        &app_handle,
+        "failover",
+        FailoverPayload {
+            failover_group: group.to_string(),
+            from_location_id: location_id,
+            to_location_id: next_id,
+            error: result.as_ref().err().map(ToString::to_string),
+        },
+    )?;
+    emit_event(
+        &app_handle,
+        "connection-changed",
+        Payload {
+            message: "Connection failed over".into(),
+        },
+    )?;
+    refresh_tray(app_handle);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn member(id: i64, failover_priority: i64, mfa_enabled: bool) -> Location {
+        Location {
+            id: Some(id),
+            instance_id: 1,
+            network_id: id,
+            name: format!("location {id}"),
+            address: "10.0.0.2/24".into(),
+            pubkey: "BvUB3iZq3U0jZrY6b4KbGhz0IVZzpAdbJiRZGdci9ZU=".into(),
+            endpoint: "vpn.example.com:51820".into(),
+            allowed_ips: "10.0.0.0/24".into(),
+            dns: None,
+            route_all_traffic: false,
+            mfa_enabled,
+            keepalive_interval: 25,
+            doh_url: None,
+            dot_host: None,
+            metadata: None,
+            endpoint_ip_override: None,
+            kill_switch: false,
+            mtu: None,
+            autoconnect: false,
+            dns_search: None,
+            favorite: false,
+            sort_order: 0,
+            preshared_key: None,
+            display_name: None,
+            interface_name: None,
+            extra_routes: None,
+            failover_group: Some("group".into()),
+            failover_priority,
+            schedule: None,
+            sticky_endpoint: None,
+            force_tunnel_dns: false,
+            endpoints: None,
+            imported: false,
+        }
+    }
+
+    #[test]
+    fn test_next_member() {
+        let next = |members: &[Location], current| {
+            next_member(members, current).and_then(|location| location.id)
+        };
+        // ordered by priority, then by ID
+        let members = [
+            member(1, 20, false),
+            member(2, 10, false),
+            member(3, 10, false),
+        ];
+        assert_eq!(next(&members, 2), Some(3));
+        assert_eq!(next(&members, 3), Some(1));
+        // wraps around to the highest priority
+        assert_eq!(next(&members, 1), Some(2));
+        // locations requiring MFA are skipped
+        let members = [member(1, 0, false), member(2, 1, true), member(3, 2, false)];
+        assert_eq!(next(&members, 1), Some(3));
+        assert_eq!(next(&members, 3), Some(1));
+        // current location is never picked again
+        let members = [member(1, 0, false), member(2, 1, true)];
+        assert_eq!(next(&members, 1), None);
+        assert_eq!(next(&[member(1, 0, false)], 1), None);
+        // location outside of the group
+        assert_eq!(next(&members, 4), None);
+    }
+}
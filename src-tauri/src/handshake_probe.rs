@@ -0,0 +1,219 @@
+//! WireGuard handshake probe
+//!
+//! Sends a single handshake initiation to a peer and waits for its reply. Peers silently drop
+//! anything which isn't a valid WireGuard message, so a reply tells the endpoint is reachable
+//! and knows our key, without creating an interface or touching routes.
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+use blake2::{
+    digest::{consts::U16, Mac},
+    Blake2s256, Blake2sMac, Digest,
+};
+use chacha20poly1305::{
+    aead::{Aead, Payload},
+    ChaCha20Poly1305, KeyInit,
+};
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+
+use crate::error::Error;
+
+const CONSTRUCTION: &[u8] = b"Noise_IKpsk2_25519_ChaChaPoly_BLAKE2s";
+const IDENTIFIER: &[u8] = b"WireGuard v1 zx2c4 Jason@zx2c4.com";
+const LABEL_MAC1: &[u8] = b"mac1----";
+const HANDSHAKE_INITIATION: u8 = 1;
+const HANDSHAKE_RESPONSE: u8 = 2;
+const COOKIE_REPLY: u8 = 3;
+const INITIATION_SIZE: usize = 148;
+// TAI64 label of UNIX epoch
+const TAI64_EPOCH: u64 = 0x4000_0000_0000_000a;
+// how long to wait for handshake reply
+pub(crate) const HANDSHAKE_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn decode_key(key: &str) -> Result<[u8; 32], Error> {
+    BASE64_STANDARD
+        .decode(key)
+        .ok()
+        .and_then(|key| key.try_into().ok())
+        .ok_or_else(|| Error::ConfigParseError(format!("Invalid WireGuard key {key}")))
+}
+
+fn hash(parts: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = Blake2s256::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+/// HMAC-BLAKE2s for 32 byte keys.
+fn hmac(key: &[u8; 32], parts: &[&[u8]]) -> [u8; 32] {
+    let mut ipad = [0x36; 64];
+    let mut opad = [0x5c; 64];
+    for (i, byte) in key.iter().enumerate() {
+        ipad[i] ^= byte;
+        opad[i] ^= byte;
+    }
+    let mut inner = vec![&ipad[..]];
+    inner.extend_from_slice(parts);
+    hash(&[&opad, &hash(&inner)])
+}
+
+fn kdf1(key: &[u8; 32], input: &[u8]) -> [u8; 32] {
+    let prk = hmac(key, &[input]);
+    hmac(&prk, &[&[1]])
+}
+
+fn kdf2(key: &[u8; 32], input: &[u8]) -> ([u8; 32], [u8; 32]) {
+    let prk = hmac(key, &[input]);
+    let first = hmac(&prk, &[&[1]]);
+    let second = hmac(&prk, &[&first, &[2]]);
+    (first, second)
+}
+
+fn aead(key: &[u8; 32], plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, Error> {
+    // each key is used once during initiation, so nonce is always zero
+    ChaCha20Poly1305::new(key.into())
+        .encrypt(
+            &[0; 12].into(),
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .map_err(|_| Error::InternalError)
+}
+
+fn tai64n() -> [u8; 12] {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut timestamp = [0; 12];
+    timestamp[..8].copy_from_slice(&(TAI64_EPOCH + now.as_secs()).to_be_bytes());
+    timestamp[8..].copy_from_slice(&now.subsec_nanos().to_be_bytes());
+    timestamp
+}
+
+/// Builds handshake initiation message as described in WireGuard whitepaper.
+fn handshake_initiation(
+    sender_index: u32,
+    prvkey: &[u8; 32],
+    server_pubkey: &[u8; 32],
+) -> Result<Vec<u8>, Error> {
+    let secret = StaticSecret::from(*prvkey);
+    let pubkey = PublicKey::from(&secret);
+    let server_pubkey = PublicKey::from(*server_pubkey);
+
+    let mut message = Vec::with_capacity(INITIATION_SIZE);
+    message.extend_from_slice(&[HANDSHAKE_INITIATION, 0, 0, 0]);
+    message.extend_from_slice(&sender_index.to_le_bytes());
+
+    let chaining_key = hash(&[CONSTRUCTION]);
+    let handshake_hash = hash(&[&chaining_key, IDENTIFIER]);
+    let handshake_hash = hash(&[&handshake_hash, server_pubkey.as_bytes()]);
+
+    let ephemeral = EphemeralSecret::random();
+    let ephemeral_pubkey = PublicKey::from(&ephemeral);
+    let chaining_key = kdf1(&chaining_key, ephemeral_pubkey.as_bytes());
+    message.extend_from_slice(ephemeral_pubkey.as_bytes());
+    let handshake_hash = hash(&[&handshake_hash, ephemeral_pubkey.as_bytes()]);
+
+    let (chaining_key, key) = kdf2(
+        &chaining_key,
+        ephemeral.diffie_hellman(&server_pubkey).as_bytes(),
+    );
+    let encrypted_static = aead(&key, pubkey.as_bytes(), &handshake_hash)?;
+    message.extend_from_slice(&encrypted_static);
+    let handshake_hash = hash(&[&handshake_hash, &encrypted_static]);
+
+    let (_, key) = kdf2(
+        &chaining_key,
+        secret.diffie_hellman(&server_pubkey).as_bytes(),
+    );
+    let encrypted_timestamp = aead(&key, &tai64n(), &handshake_hash)?;
+    message.extend_from_slice(&encrypted_timestamp);
+
+    let mut mac = Blake2sMac::<U16>::new_from_slice(&hash(&[LABEL_MAC1, server_pubkey.as_bytes()]))
+        .map_err(|_| Error::InternalError)?;
+    mac.update(&message);
+    message.extend_from_slice(&mac.finalize().into_bytes());
+    // mac2 is only required when peer is under load and sent us a cookie
+    message.extend_from_slice(&[0; 16]);
+    Ok(message)
+}
+
+/// Checks if received message is a reply to initiation with given sender index.
+fn is_reply(message: &[u8], sender_index: u32) -> bool {
+    let receiver_index = match message.first() {
+        Some(&HANDSHAKE_RESPONSE) if message.len() >= 12 => &message[8..12],
+        // peer under load replies with cookie, which still proves it's reachable
+        Some(&COOKIE_REPLY) if message.len() >= 8 => &message[4..8],
+        _ => return false,
+    };
+    receiver_index == sender_index.to_le_bytes()
+}
+
+/// Sends handshake initiation to endpoint and waits for reply. Blocks for up to `timeout`.
+pub fn probe_handshake(
+    endpoint: SocketAddr,
+    prvkey: &str,
+    server_pubkey: &str,
+    timeout: Duration,
+) -> Result<bool, Error> {
+    let sender_index = rand::random::<u32>();
+    let message = handshake_initiation(
+        sender_index,
+        &decode_key(prvkey)?,
+        &decode_key(server_pubkey)?,
+    )?;
+    let unspecified = match endpoint {
+        SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    let socket = UdpSocket::bind(SocketAddr::new(unspecified, 0))?;
+    socket.connect(endpoint)?;
+    socket.set_read_timeout(Some(timeout))?;
+    socket.send(&message)?;
+    debug!("Sent handshake initiation to {endpoint}");
+
+    let mut buffer = [0; 256];
+    loop {
+        match socket.recv(&mut buffer) {
+            Ok(size) if is_reply(&buffer[..size], sender_index) => {
+                debug!("Received handshake reply from {endpoint}");
+                return Ok(true);
+            }
+            Ok(_) => continue,
+            Err(err) => {
+                debug!("No handshake reply from {endpoint}: {err}");
+                return Ok(false);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handshake_initiation() {
+        let prvkey = decode_key("GAA2X3DW0WakGVx+DsGjhDpTgg50s1MlmrLf24Psrlg=").unwrap();
+        let server_pubkey = decode_key("BvUB3iZq3U0jZrY6b4KbGhz0IVZzpAdbJiRZGdci9ZU=").unwrap();
+        let message = handshake_initiation(42, &prvkey, &server_pubkey).unwrap();
+        assert_eq!(message.len(), INITIATION_SIZE);
+        assert_eq!(message[..4], [HANDSHAKE_INITIATION, 0, 0, 0]);
+        assert_eq!(message[4..8], 42u32.to_le_bytes());
+
+        let mut response = [0; 92];
+        response[0] = HANDSHAKE_RESPONSE;
+        response[8..12].copy_from_slice(&42u32.to_le_bytes());
+        assert!(is_reply(&response, 42));
+        assert!(!is_reply(&response, 43));
+        assert!(!is_reply(&message, 42));
+    }
+}
@@ -0,0 +1,239 @@
+//! Recording of connection issues for support
+//!
+//! While a capture is active, events, command invocations, daemon RPC results and stats
+//! are recorded in memory in the order they happened. When the capture is stopped the
+//! whole timeline is written into a single JSON session file with secrets redacted.
+
+use std::{fs, path::PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use tauri::{AppHandle, Manager};
+
+use crate::{appstate::AppState, error::Error};
+
+const CAPTURE_DIR: &str = "issue-captures";
+const REDACTED: &str = "[REDACTED]";
+// keys containing any of those fragments are treated as secrets, compared without separators
+// so that both `preshared_key` and `presharedKey` command arguments match
+const SECRET_KEYS: [&str; 6] = [
+    "prvkey",
+    "privatekey",
+    "presharedkey",
+    "psk",
+    "token",
+    "password",
+];
+
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptureEntryKind {
+    Event,
+    Command,
+    Rpc,
+    Stats,
+}
+
+/// Single record in captured timeline
+#[derive(Debug, Serialize)]
+pub struct CaptureEntry {
+    pub timestamp: DateTime<Utc>,
+    pub kind: CaptureEntryKind,
+    pub name: String,
+    pub payload: Value,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IssueCapture {
+    pub app_version: String,
+    pub operating_system: String,
+    pub started_at: DateTime<Utc>,
+    pub stopped_at: Option<DateTime<Utc>>,
+    pub entries: Vec<CaptureEntry>,
+}
+
+impl IssueCapture {
+    #[must_use]
+    pub fn new(app_version: String) -> Self {
+        Self {
+            app_version,
+            operating_system: std::env::consts::OS.into(),
+            started_at: Utc::now(),
+            stopped_at: None,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Append a new entry to the timeline. Secrets are redacted before storing the payload.
+    pub fn record<T: Serialize>(&mut self, kind: CaptureEntryKind, name: &str, payload: T) {
+        let mut payload = serde_json::to_value(payload).unwrap_or(Value::Null);
+        redact_secrets(&mut payload);
+        self.entries.push(CaptureEntry {
+            timestamp: Utc::now(),
+            kind,
+            name: name.into(),
+            payload,
+        });
+    }
+
+    /// Finish the capture and write the session file into the app data directory.
+    /// Returns path to the created file.
+    pub fn save(mut self, handle: &AppHandle) -> Result<PathBuf, Error> {
+        let stopped_at = Utc::now();
+        self.stopped_at = Some(stopped_at);
+        let capture_dir = handle
+            .path_resolver()
+            .app_data_dir()
+            .ok_or(Error::Config)?
+            .join(CAPTURE_DIR);
+        fs::create_dir_all(&capture_dir)?;
+        let path = capture_dir.join(format!(
+            "capture-{}.json",
+            stopped_at.format("%Y-%m-%dT%H-%M-%S")
+        ));
+        fs::write(&path, serde_json::to_vec_pretty(&self)?)?;
+        info!(
+            "Saved issue capture with {} entries to {}",
+            self.entries.len(),
+            path.to_string_lossy()
+        );
+        Ok(path)
+    }
+}
+
+/// Emits event to all windows and records it in issue capture in progress.
+pub fn emit_event<S: Serialize + Clone>(
+    handle: &AppHandle,
+    event: &str,
+    payload: S,
+) -> Result<(), tauri::Error> {
+    handle
+        .state::<AppState>()
+        .record_capture(CaptureEntryKind::Event, event, &payload);
+    handle.emit_all(event, payload)
+}
+
+fn is_secret_key(key: &str) -> bool {
+    let key = key.to_lowercase().replace(['_', '-'], "");
+    SECRET_KEYS.iter().any(|secret| key.contains(secret))
+}
+
+/// Replace values of secret `key = value` lines, e.g. `PrivateKey` of WireGuard config.
+fn redact_config_lines(text: &str) -> Option<String> {
+    let is_secret_line = |line: &str| {
+        line.split_once('=')
+            .is_some_and(|(key, _)| is_secret_key(key.trim()))
+    };
+    if !text.lines().any(is_secret_line) {
+        return None;
+    }
+    let lines: Vec<String> = text
+        .lines()
+        .map(|line| match line.split_once('=') {
+            Some((key, _)) if is_secret_line(line) => format!("{key}= {REDACTED}"),
+            _ => line.to_string(),
+        })
+        .collect();
+    Some(lines.join("\n"))
+}
+
+/// Recursively replace values of secret fields. Strings are redacted as well, since raw
+/// WireGuard configs and JSON backups are passed to commands as single string arguments.
+pub fn redact_secrets(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if is_secret_key(key) {
+                    *value = Value::String(REDACTED.into());
+                } else {
+                    redact_secrets(value);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(redact_secrets),
+        Value::String(text) => match serde_json::from_str::<Value>(text) {
+            Ok(mut nested @ (Value::Object(_) | Value::Array(_))) => {
+                redact_secrets(&mut nested);
+                *text = nested.to_string();
+            }
+            _ => {
+                if let Some(redacted) = redact_config_lines(text) {
+                    *text = redacted;
+                }
+            }
+        },
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_redact_secrets() {
+        let mut capture = IssueCapture::new("0.2.1".into());
+        capture.record(
+            CaptureEntryKind::Command,
+            "connect",
+            json!({
+                "location_id": 1,
+                "preshared_key": "secret",
+                "presharedKey": "secret",
+                "keys": [{ "pubkey": "public", "prvkey": "secret" }],
+            }),
+        );
+        assert_eq!(
+            capture.entries[0].payload,
+            json!({
+                "location_id": 1,
+                "preshared_key": REDACTED,
+                "presharedKey": REDACTED,
+                "keys": [{ "pubkey": "public", "prvkey": REDACTED }],
+            })
+        );
+    }
+
+    #[test]
+    fn test_redact_secrets_in_config_text() {
+        let config = "[Interface]
+PrivateKey = GAA2X3DW0WakGVx+DsGjhDpTgg50s1MlmrLf24Psrlg=
+Address = 10.0.0.2/24
+DNS = 10.0.0.1
+
+[Peer]
+PublicKey = BvUB4SwJqiUbSEIKj/EFlO9i+GMcsNmjEK/mKBfNWqU=
+PresharedKey = qOcOQ/hTTvl1Gy5mNS9J9nYVm7HqR/ZvbrCH8U4ztW4=
+AllowedIPs = 10.0.0.0/24
+Endpoint = 192.168.1.10:51820
+PersistentKeepalive = 25";
+        let mut capture = IssueCapture::new("0.2.1".into());
+        capture.record(
+            CaptureEntryKind::Command,
+            "parse_tunnel_config",
+            json!({ "config": config }),
+        );
+        let backup = json!({ "keys": [{ "pubkey": "public", "prvkey": "secret" }] }).to_string();
+        capture.record(
+            CaptureEntryKind::Command,
+            "import_configuration",
+            json!({ "json": backup }),
+        );
+
+        let redacted = capture.entries[0].payload["config"].as_str().unwrap();
+        assert!(!redacted.contains("GAA2X3DW0WakGVx+DsGjhDpTgg50s1MlmrLf24Psrlg="));
+        assert!(!redacted.contains("qOcOQ/hTTvl1Gy5mNS9J9nYVm7HqR/ZvbrCH8U4ztW4="));
+        assert!(redacted.contains(&format!("PrivateKey = {REDACTED}")));
+        assert!(redacted.contains(&format!("PresharedKey = {REDACTED}")));
+        assert!(redacted.contains("PublicKey = BvUB4SwJqiUbSEIKj/EFlO9i+GMcsNmjEK/mKBfNWqU="));
+        assert!(redacted.contains("Endpoint = 192.168.1.10:51820"));
+        assert_eq!(
+            capture.entries[1].payload,
+            json!({
+                "json": json!({ "keys": [{ "pubkey": "public", "prvkey": REDACTED }] }).to_string()
+            })
+        );
+    }
+}
@@ -2,19 +2,21 @@ use std::time::Duration;
 use tauri::{AppHandle, Manager};
 use tokio::time::sleep;
 
-use crate::{appstate::AppState, commands::get_latest_app_version, database::Settings};
+use crate::{
+    appstate::AppState, commands::get_latest_app_version, database::Settings,
+    issue_capture::emit_event,
+};
 
 const INTERVAL_IN_SECONDS: Duration = Duration::from_secs(12 * 60 * 60); // 12 hours
 
 pub async fn fetch_latest_app_version_loop(app_handle: AppHandle) {
     let state = app_handle.state::<AppState>();
-    let pool = &state.get_pool();
 
     loop {
         debug!("Waiting to fetch latest application version");
         sleep(INTERVAL_IN_SECONDS).await;
 
-        let settings = Settings::get(pool).await;
+        let settings = Settings::get(&state.get_pool()).await;
 
         if let Ok(settings) = settings {
             if settings.check_for_updates {
@@ -23,7 +25,7 @@ pub async fn fetch_latest_app_version_loop(app_handle: AppHandle) {
                 if let Ok(result) = response {
                     debug!("Fetched latest application version info: {result:?}");
 
-                    let _ = app_handle.emit_all("app-version-fetch", &result);
+                    let _ = emit_event(&app_handle, "app-version-fetch", &result);
                 } else {
                     let err = response.err().unwrap();
                     error!("Error while fetching latest application version: {err}");
@@ -1,10 +1,25 @@
 use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
+
+use crate::database::DisconnectReason;
+
 pub mod appstate;
+pub mod autostart;
 pub mod commands;
+pub mod config_drift;
+pub mod connection_health;
+pub mod connection_state;
 pub mod database;
+pub mod diagnostics;
 pub mod error;
+pub mod failover;
+pub mod handshake_probe;
+pub mod issue_capture;
 pub mod latest_app_version;
+pub mod log_limiter;
+pub mod power_impact;
+pub mod proxy;
+pub mod schedule;
 pub mod service;
 pub mod tray;
 pub mod utils;
@@ -21,7 +36,8 @@ struct Payload {
 }
 
 /// Location type used in commands to check if we using tunnel or location
-#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Deserialize, Serialize, Clone, sqlx::Type)]
+#[sqlx(type_name = "connection_type", rename_all = "snake_case")]
 pub enum ConnectionType {
     Tunnel,
     Location,
@@ -67,6 +83,10 @@ pub struct CommonLocationStats {
     pub collected_at: NaiveDateTime,
     pub listen_port: u32,
     pub persistent_keepalive_interval: Option<u16>,
+    pub latency_ms: Option<i64>,
+    // bytes per second since previous sample
+    pub upload_rate: i64,
+    pub download_rate: i64,
     pub connection_type: ConnectionType,
 }
 // Common fields for ConnectionInfo and TunnelConnectionInfo due to shared command
@@ -79,4 +99,6 @@ pub struct CommonConnectionInfo {
     pub end: NaiveDateTime,
     pub upload: Option<i32>,
     pub download: Option<i32>,
+    pub disconnect_reason: Option<DisconnectReason>,
+    pub resolved_endpoint: Option<String>,
 }
@@ -0,0 +1,116 @@
+//! Rate-limited error logging
+//!
+//! Loops which fail the same way on every iteration would flood the logs with identical errors.
+//! Repeated error is logged at most once per window together with number of suppressed
+//! repetitions, while a different error or a success starts over.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+struct LoggedError {
+    message: String,
+    logged_at: Instant,
+    // repetitions since the error was logged last time
+    suppressed: u64,
+}
+
+/// Errors of independent operations are tracked separately by their key.
+pub struct RateLimitedLog {
+    window: Duration,
+    errors: HashMap<&'static str, LoggedError>,
+}
+
+impl RateLimitedLog {
+    #[must_use]
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            errors: HashMap::new(),
+        }
+    }
+
+    /// Logs error of operation `key` unless the same error was logged within the window.
+    pub fn error(&mut self, key: &'static str, message: String) {
+        self.log(key, message, Instant::now());
+    }
+
+    /// Marks operation `key` as successful, so its next error is logged right away.
+    pub fn success(&mut self, key: &'static str) {
+        if let Some(error) = self.errors.remove(key) {
+            if error.suppressed > 0 {
+                info!(
+                    "Recovered from error repeated {} more times: {}",
+                    error.suppressed, error.message
+                );
+            }
+        }
+    }
+
+    /// Returns `true` if error was logged, `false` if it was suppressed.
+    fn log(&mut self, key: &'static str, message: String, now: Instant) -> bool {
+        if let Some(error) = self.errors.get_mut(key) {
+            if error.message == message {
+                if now.duration_since(error.logged_at) < self.window {
+                    error.suppressed += 1;
+                    return false;
+                }
+                if error.suppressed > 0 {
+                    error!(
+                        "{message} (repeated {} times in the last {}s)",
+                        error.suppressed,
+                        self.window.as_secs()
+                    );
+                } else {
+                    error!("{message}");
+                }
+                error.logged_at = now;
+                error.suppressed = 0;
+                return true;
+            }
+            if error.suppressed > 0 {
+                error!(
+                    "Previous error repeated {} more times: {}",
+                    error.suppressed, error.message
+                );
+            }
+        }
+        error!("{message}");
+        self.errors.insert(
+            key,
+            LoggedError {
+                message,
+                logged_at: now,
+                suppressed: 0,
+            },
+        );
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limited_log() {
+        let window = Duration::from_secs(60);
+        let mut log = RateLimitedLog::new(window);
+        let start = Instant::now();
+        assert!(log.log("stats", "failed".into(), start));
+        assert!(!log.log("stats", "failed".into(), start + Duration::from_secs(1)));
+        assert!(!log.log("stats", "failed".into(), start + Duration::from_secs(59)));
+        assert_eq!(log.errors["stats"].suppressed, 2);
+        // other operations aren't affected
+        assert!(log.log("peaks", "failed".into(), start + Duration::from_secs(2)));
+        // different error is logged right away
+        assert!(log.log("stats", "timed out".into(), start + Duration::from_secs(3)));
+        assert_eq!(log.errors["stats"].suppressed, 0);
+        assert!(!log.log("stats", "timed out".into(), start + Duration::from_secs(4)));
+        assert!(log.log("stats", "timed out".into(), start + Duration::from_secs(63)));
+        // success starts over
+        log.success("stats");
+        assert!(log.log("stats", "timed out".into(), start + Duration::from_secs(64)));
+    }
+}
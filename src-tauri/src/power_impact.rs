@@ -0,0 +1,245 @@
+//! Estimation of battery/power impact of active connections
+//!
+//! Impact is estimated from recently collected interface stats: how often stats are collected,
+//! how often keepalive packets are sent and how much traffic goes through the tunnel.
+
+use chrono::{Duration, NaiveDateTime, Utc};
+use serde::Serialize;
+use sqlx::query;
+
+use crate::{
+    database::{ActiveConnection, DbPool, Location, Tunnel},
+    error::Error,
+    ConnectionType,
+};
+
+// stats collected within this window are used for estimation
+const SAMPLE_WINDOW_SECONDS: i64 = 60;
+// stats interval (seconds) thresholds
+const STATS_INTERVAL_HIGH: i64 = 2;
+const STATS_INTERVAL_MEDIUM: i64 = 10;
+// keepalive interval (seconds) thresholds
+const KEEPALIVE_HIGH: u16 = 10;
+const KEEPALIVE_MEDIUM: u16 = 25;
+// throughput (bytes per second) thresholds
+const THROUGHPUT_HIGH: i64 = 1024 * 1024;
+const THROUGHPUT_MEDIUM: i64 = 100 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PowerImpactRating {
+    Low,
+    Medium,
+    High,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PowerImpactFactor {
+    pub location_id: i64,
+    pub connection_type: ConnectionType,
+    pub name: String,
+    pub factor: String,
+    pub value: String,
+    pub rating: PowerImpactRating,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PowerImpact {
+    pub rating: PowerImpactRating,
+    pub factors: Vec<PowerImpactFactor>,
+    pub suggestions: Vec<String>,
+}
+
+/// Summary of stats collected for single connection within sample window
+struct RecentStats {
+    samples: i64,
+    transferred: i64,
+    first_collected_at: Option<NaiveDateTime>,
+    last_collected_at: Option<NaiveDateTime>,
+    keepalive_interval: Option<u16>,
+}
+
+impl RecentStats {
+    /// Average number of seconds between stats samples
+    fn stats_interval(&self) -> Option<i64> {
+        match (self.first_collected_at, self.last_collected_at) {
+            (Some(first), Some(last)) if self.samples > 1 => {
+                Some((last - first).num_seconds() / (self.samples - 1))
+            }
+            _ => None,
+        }
+    }
+
+    /// Average throughput in bytes per second
+    fn throughput(&self) -> i64 {
+        match (self.first_collected_at, self.last_collected_at) {
+            (Some(first), Some(last)) if last > first => {
+                self.transferred / (last - first).num_seconds().max(1)
+            }
+            _ => 0,
+        }
+    }
+}
+
+async fn recent_stats(
+    pool: &DbPool,
+    connection: &ActiveConnection,
+    from: &NaiveDateTime,
+) -> Result<RecentStats, Error> {
+    let stats = match connection.connection_type {
+        ConnectionType::Location => {
+            let record = query!(
+                r#"
+                SELECT COUNT(*) as "samples!: i64",
+                  COALESCE(MAX(upload) - MIN(upload) + MAX(download) - MIN(download), 0) as "transferred!: i64",
+                  MIN(collected_at) as "first_collected_at?: NaiveDateTime",
+                  MAX(collected_at) as "last_collected_at?: NaiveDateTime",
+                  MIN(persistent_keepalive_interval) as "keepalive_interval?: u16"
+                FROM location_stats
+                WHERE location_id = $1 AND collected_at >= $2
+                "#,
+                connection.location_id,
+                from
+            )
+            .fetch_one(pool)
+            .await?;
+            RecentStats {
+                samples: record.samples,
+                transferred: record.transferred,
+                first_collected_at: record.first_collected_at,
+                last_collected_at: record.last_collected_at,
+                keepalive_interval: record.keepalive_interval,
+            }
+        }
+        ConnectionType::Tunnel => {
+            let record = query!(
+                r#"
+                SELECT COUNT(*) as "samples!: i64",
+                  COALESCE(MAX(upload) - MIN(upload) + MAX(download) - MIN(download), 0) as "transferred!: i64",
+                  MIN(collected_at) as "first_collected_at?: NaiveDateTime",
+                  MAX(collected_at) as "last_collected_at?: NaiveDateTime",
+                  MIN(persistent_keepalive_interval) as "keepalive_interval?: u16"
+                FROM tunnel_stats
+                WHERE tunnel_id = $1 AND collected_at >= $2
+                "#,
+                connection.location_id,
+                from
+            )
+            .fetch_one(pool)
+            .await?;
+            RecentStats {
+                samples: record.samples,
+                transferred: record.transferred,
+                first_collected_at: record.first_collected_at,
+                last_collected_at: record.last_collected_at,
+                keepalive_interval: record.keepalive_interval,
+            }
+        }
+    };
+    Ok(stats)
+}
+
+async fn connection_name(pool: &DbPool, connection: &ActiveConnection) -> Result<String, Error> {
+    let name = match connection.connection_type {
+        ConnectionType::Location => Location::find_by_id(pool, connection.location_id)
+            .await?
+            .map(|location| location.name),
+        ConnectionType::Tunnel => Tunnel::find_by_id(pool, connection.location_id)
+            .await?
+            .map(|tunnel| tunnel.name),
+    };
+    Ok(name.unwrap_or_else(|| connection.interface_name.clone()))
+}
+
+/// Estimate power impact of given active connections.
+pub async fn estimate_power_impact(
+    pool: &DbPool,
+    connections: &[ActiveConnection],
+) -> Result<PowerImpact, Error> {
+    let from = Utc::now().naive_utc() - Duration::seconds(SAMPLE_WINDOW_SECONDS);
+    let mut factors = Vec::new();
+    let mut suggestions = Vec::new();
+    let mut frequent_stats = false;
+
+    for connection in connections {
+        let name = connection_name(pool, connection).await?;
+        let stats = recent_stats(pool, connection, &from).await?;
+        let mut add_factor = |factor: &str, value: String, rating: PowerImpactRating| {
+            factors.push(PowerImpactFactor {
+                location_id: connection.location_id,
+                connection_type: connection.connection_type.clone(),
+                name: name.clone(),
+                factor: factor.into(),
+                value,
+                rating,
+            });
+        };
+
+        if let Some(interval) = stats.stats_interval() {
+            let rating = match interval {
+                interval if interval <= STATS_INTERVAL_HIGH => PowerImpactRating::High,
+                interval if interval <= STATS_INTERVAL_MEDIUM => PowerImpactRating::Medium,
+                _ => PowerImpactRating::Low,
+            };
+            frequent_stats |= rating > PowerImpactRating::Low;
+            add_factor("stats_interval", format!("{interval}s"), rating);
+        }
+
+        if let Some(keepalive) = stats.keepalive_interval.filter(|interval| *interval > 0) {
+            let rating = match keepalive {
+                keepalive if keepalive <= KEEPALIVE_HIGH => PowerImpactRating::High,
+                keepalive if keepalive <= KEEPALIVE_MEDIUM => PowerImpactRating::Medium,
+                _ => PowerImpactRating::Low,
+            };
+            if rating > PowerImpactRating::Low {
+                // location keepalive is configured in defguard and overwritten on every sync
+                let suggestion = match connection.connection_type {
+                    ConnectionType::Location => format!(
+                        "Ask your defguard administrator to raise keepalive interval of {name} (currently {keepalive}s) if the connection doesn't need to keep NAT mappings open"
+                    ),
+                    ConnectionType::Tunnel => format!(
+                        "Raise keepalive interval of {name} (currently {keepalive}s) if the connection doesn't need to keep NAT mappings open"
+                    ),
+                };
+                suggestions.push(suggestion);
+            }
+            add_factor("keepalive_interval", format!("{keepalive}s"), rating);
+        }
+
+        let throughput = stats.throughput();
+        let rating = match throughput {
+            throughput if throughput >= THROUGHPUT_HIGH => PowerImpactRating::High,
+            throughput if throughput >= THROUGHPUT_MEDIUM => PowerImpactRating::Medium,
+            _ => PowerImpactRating::Low,
+        };
+        if rating > PowerImpactRating::Low {
+            suggestions.push(format!(
+                "Disconnect {name} when it's not needed or route only required networks through it"
+            ));
+        }
+        add_factor("throughput", format!("{throughput} B/s"), rating);
+    }
+
+    if frequent_stats {
+        suggestions.push(
+            "Lower stats collection frequency by increasing stats interval in settings".into(),
+        );
+    }
+
+    // single high factor or several medium ones make overall impact high
+    let medium_factors = factors
+        .iter()
+        .filter(|factor| factor.rating == PowerImpactRating::Medium)
+        .count();
+    let rating = match factors.iter().map(|factor| factor.rating).max() {
+        Some(PowerImpactRating::High) => PowerImpactRating::High,
+        Some(PowerImpactRating::Medium) if medium_factors >= 3 => PowerImpactRating::High,
+        Some(PowerImpactRating::Medium) => PowerImpactRating::Medium,
+        _ => PowerImpactRating::Low,
+    };
+    Ok(PowerImpact {
+        rating,
+        factors,
+        suggestions,
+    })
+}
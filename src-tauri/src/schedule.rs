@@ -0,0 +1,210 @@
+//! Connection schedules of locations
+//!
+//! Location with a schedule can only be connected within its time windows and is disconnected
+//! when its window closes. Windows are evaluated in local wall-clock time, so a window keeps
+//! its hours across DST transitions.
+
+use std::time::Duration;
+
+use chrono::{Datelike, Local, NaiveDateTime, NaiveTime, Weekday};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+use tokio::time::interval;
+
+use crate::{
+    appstate::AppState,
+    commands::Payload,
+    database::{DisconnectReason, Location},
+    error::Error,
+    issue_capture::emit_event,
+    tray::refresh_tray,
+    utils::disconnect_interface,
+    ConnectionType,
+};
+
+const SCHEDULE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Time of day in `HH:MM` format
+mod hour_minute {
+    use chrono::NaiveTime;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    const FORMAT: &str = "%H:%M";
+
+    pub fn serialize<S: Serializer>(time: &NaiveTime, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&time.format(FORMAT).to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveTime, D::Error> {
+        let time = String::deserialize(deserializer)?;
+        NaiveTime::parse_from_str(&time, FORMAT).map_err(Error::custom)
+    }
+}
+
+/// Time window in which location may be connected, e.g.
+/// `{"days": ["Mon", "Tue"], "start": "08:00", "end": "18:00"}`.
+/// Window ending before it starts spans midnight and ends on the following day.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ScheduleWindow {
+    // days on which window starts
+    pub days: Vec<Weekday>,
+    #[serde(with = "hour_minute")]
+    pub start: NaiveTime,
+    #[serde(with = "hour_minute")]
+    pub end: NaiveTime,
+}
+
+impl ScheduleWindow {
+    fn contains(&self, datetime: NaiveDateTime) -> bool {
+        let (weekday, time) = (datetime.weekday(), datetime.time());
+        if self.start < self.end {
+            self.days.contains(&weekday) && time >= self.start && time < self.end
+        } else {
+            (self.days.contains(&weekday) && time >= self.start)
+                || (self.days.contains(&weekday.pred()) && time < self.end)
+        }
+    }
+}
+
+/// Parses JSON array of schedule windows.
+pub fn parse_schedule(schedule: &str) -> Result<Vec<ScheduleWindow>, Error> {
+    let windows: Vec<ScheduleWindow> = serde_json::from_str(schedule)
+        .map_err(|err| Error::CommandError(format!("Invalid schedule: {err}")))?;
+    if windows.is_empty() {
+        return Err(Error::CommandError(
+            "Schedule must contain at least one time window".into(),
+        ));
+    }
+    for window in &windows {
+        if window.days.is_empty() {
+            return Err(Error::CommandError(
+                "Schedule window must apply to at least one day".into(),
+            ));
+        }
+        if window.start == window.end {
+            return Err(Error::CommandError(
+                "Schedule window must not start and end at the same time".into(),
+            ));
+        }
+    }
+    Ok(windows)
+}
+
+/// Checks if location may be connected at given local time. Location without schedule
+/// may always be connected, so may location with schedule which can't be parsed.
+#[must_use]
+pub fn is_within_schedule(location: &Location, now: NaiveDateTime) -> bool {
+    let Some(schedule) = &location.schedule else {
+        return true;
+    };
+    match parse_schedule(schedule) {
+        Ok(windows) => windows.iter().any(|window| window.contains(now)),
+        Err(err) => {
+            error!("Ignoring invalid schedule of location {location}: {err}");
+            true
+        }
+    }
+}
+
+/// Fails if location may not be connected now according to its schedule.
+pub fn verify_schedule(location: &Location) -> Result<(), Error> {
+    if is_within_schedule(location, Local::now().naive_local()) {
+        Ok(())
+    } else {
+        warn!("Location {location} is outside of its schedule");
+        Err(Error::OutsideSchedule(location.name.clone()))
+    }
+}
+
+/// Periodically disconnects locations whose schedule window has closed, except for locations
+/// connected with `force`.
+pub async fn schedule_loop(app_handle: AppHandle) {
+    let state = app_handle.state::<AppState>();
+    let mut interval = interval(SCHEDULE_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let pool = state.get_pool();
+        let now = Local::now().naive_local();
+        for connection in state.get_connections() {
+            // locations connected with `force` stay up until user disconnects them
+            if connection.connection_type != ConnectionType::Location || connection.forced {
+                continue;
+            }
+            let location_id = connection.location_id;
+            let location = match Location::find_by_id(&pool, location_id).await {
+                Ok(Some(location)) => location,
+                Ok(None) => continue,
+                Err(err) => {
+                    error!("Failed to load location {location_id} for schedule check: {err}");
+                    continue;
+                }
+            };
+            if is_within_schedule(&location, now) {
+                continue;
+            }
+            info!(
+                "Schedule window of location {location} closed, disconnecting interface {}",
+                connection.interface_name
+            );
+            for connection in state.remove_connections_by_interface(&connection.interface_name) {
+                if let Err(err) =
+                    disconnect_interface(connection, &state, DisconnectReason::Schedule).await
+                {
+                    error!("Failed to disconnect location {location} outside of schedule: {err}");
+                }
+            }
+            let _ = emit_event(&app_handle, "schedule-disconnect", location_id);
+            let _ = emit_event(
+                &app_handle,
+                "connection-changed",
+                Payload {
+                    message: "Connection closed by schedule".into(),
+                },
+            );
+            refresh_tray(&app_handle);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        // 2024-01-01 is Monday
+        NaiveDate::from_ymd_opt(2024, 1, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_schedule_windows() {
+        let windows = parse_schedule(
+            r#"[
+                {"days": ["Mon", "Tue"], "start": "08:00", "end": "18:00"},
+                {"days": ["Fri"], "start": "22:00", "end": "02:00"}
+            ]"#,
+        )
+        .unwrap();
+        let allowed = |datetime| windows.iter().any(|window| window.contains(datetime));
+        assert!(allowed(at(1, 8, 0)));
+        assert!(allowed(at(2, 17, 59)));
+        assert!(!allowed(at(1, 18, 0)));
+        assert!(!allowed(at(3, 12, 0)));
+        // overnight window continues on Saturday
+        assert!(allowed(at(5, 23, 0)));
+        assert!(allowed(at(6, 1, 30)));
+        assert!(!allowed(at(6, 2, 0)));
+        assert!(!allowed(at(5, 1, 0)));
+
+        assert!(parse_schedule("[]").is_err());
+        assert!(parse_schedule(r#"[{"days": [], "start": "08:00", "end": "18:00"}]"#).is_err());
+        assert!(parse_schedule(r#"[{"days": ["Mon"], "start": "8am", "end": "18:00"}]"#).is_err());
+        assert!(
+            parse_schedule(r#"[{"days": ["Mon"], "start": "08:00", "end": "08:00"}]"#).is_err()
+        );
+    }
+}
@@ -1,5 +1,7 @@
 use clap::Parser;
 
+pub const DEFAULT_STATS_PERIOD: u64 = 10;
+
 #[derive(Debug, Parser, Clone)]
 #[clap(about = "Defguard VPN client interface management service")]
 #[command(version)]
@@ -9,6 +11,6 @@ pub struct Config {
     pub log_level: String,
 
     /// Defines how often (in seconds) interface statistics are sent to defguard client
-    #[arg(long, short = 'p', env = "DEFGUARD_STATS_PERIOD", default_value = "10")]
+    #[arg(long, short = 'p', env = "DEFGUARD_STATS_PERIOD", default_value_t = DEFAULT_STATS_PERIOD)]
     pub stats_period: u64,
 }
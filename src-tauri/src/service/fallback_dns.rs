@@ -0,0 +1,186 @@
+//! Fallback DNS used while no tunnel is up
+//!
+//! DNS of locations and tunnels is configured on their WireGuard interfaces. Fallback DNS
+//! from client settings is set on the interface of the default route only after the last
+//! managed interface is removed, and reverted before a new one is created, so it never
+//! overrides DNS of a connected tunnel.
+
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+use std::process::Command;
+use std::{
+    net::IpAddr,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use tonic::Status;
+
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+use super::routes::run_command;
+
+#[derive(Debug, Default)]
+struct State {
+    servers: Vec<IpAddr>,
+    // system interface fallback servers are currently set on
+    applied_on: Option<String>,
+}
+
+/// Fallback DNS servers, shared by daemon and status services.
+#[derive(Clone, Debug, Default)]
+pub struct FallbackDns(Arc<Mutex<State>>);
+
+impl FallbackDns {
+    fn lock(&self) -> Result<MutexGuard<'_, State>, Status> {
+        self.0
+            .lock()
+            .map_err(|_| Status::internal("Failed to acquire fallback DNS lock"))
+    }
+
+    /// Replaces fallback servers. They are applied right away if no managed interface is up.
+    pub fn configure(&self, servers: Vec<IpAddr>, interfaces_up: bool) -> Result<(), Status> {
+        let mut state = self.lock()?;
+        revert(&mut state);
+        state.servers = servers;
+        if interfaces_up {
+            Ok(())
+        } else {
+            apply(&mut state)
+        }
+    }
+
+    /// Restores system DNS before a managed interface is created.
+    pub fn interface_created(&self) {
+        match self.lock() {
+            Ok(mut state) => revert(&mut state),
+            Err(status) => error!("{}", status.message()),
+        }
+    }
+
+    /// Applies fallback servers once the last managed interface was removed.
+    pub fn all_interfaces_removed(&self) {
+        if let Err(status) = self.lock().and_then(|mut state| apply(&mut state)) {
+            error!("Failed to apply fallback DNS: {}", status.message());
+        }
+    }
+}
+
+fn apply(state: &mut State) -> Result<(), Status> {
+    if state.servers.is_empty() || state.applied_on.is_some() {
+        return Ok(());
+    }
+    let link = default_route_interface()?;
+    set_dns(&link, &state.servers)?;
+    info!(
+        "Applied fallback DNS {:?} on interface {link}",
+        state.servers
+    );
+    state.applied_on = Some(link);
+    Ok(())
+}
+
+fn revert(state: &mut State) {
+    if let Some(link) = state.applied_on.take() {
+        match reset_dns(&link) {
+            Ok(()) => info!("Reverted fallback DNS on interface {link}"),
+            Err(status) => warn!(
+                "Failed to revert fallback DNS on interface {link}: {}",
+                status.message()
+            ),
+        }
+    }
+}
+
+/// Name of the interface default route goes through.
+#[cfg(target_os = "linux")]
+fn default_route_interface() -> Result<String, Status> {
+    #[derive(serde::Deserialize)]
+    struct IpRoute {
+        dev: String,
+    }
+
+    let output = run_command(Command::new("ip").args(["-j", "route", "show", "default"]))?;
+    let routes: Vec<IpRoute> = if output.trim().is_empty() {
+        Vec::new()
+    } else {
+        serde_json::from_str(&output)
+            .map_err(|err| Status::internal(format!("Failed to parse routes: {err}")))?
+    };
+    routes
+        .into_iter()
+        .next()
+        .map(|route| route.dev)
+        .ok_or_else(|| Status::failed_precondition("There is no default route"))
+}
+
+#[cfg(target_os = "linux")]
+fn set_dns(link: &str, servers: &[IpAddr]) -> Result<(), Status> {
+    run_command(
+        Command::new("resolvectl")
+            .args(["dns", link])
+            .args(servers.iter().map(ToString::to_string)),
+    )
+    .map(drop)
+}
+
+#[cfg(target_os = "linux")]
+fn reset_dns(link: &str) -> Result<(), Status> {
+    run_command(Command::new("resolvectl").args(["revert", link])).map(drop)
+}
+
+/// Index of the interface default route with the lowest metric goes through.
+#[cfg(target_os = "windows")]
+fn default_route_interface() -> Result<String, Status> {
+    let script = "Get-NetRoute -DestinationPrefix '0.0.0.0/0' | Sort-Object RouteMetric | \
+        Select-Object -First 1 -ExpandProperty InterfaceIndex";
+    let output = run_command(Command::new("powershell").args(["-NoProfile", "-Command", script]))?;
+    let index = output.trim();
+    index
+        .parse::<u32>()
+        .map(|index| index.to_string())
+        .map_err(|_| Status::failed_precondition("There is no default route"))
+}
+
+#[cfg(target_os = "windows")]
+fn set_dns(link: &str, servers: &[IpAddr]) -> Result<(), Status> {
+    // values are passed through environment, so they're never parsed as part of the script
+    let script = "Set-DnsClientServerAddress -InterfaceIndex $env:DEFGUARD_INTERFACE \
+        -ServerAddresses ($env:DEFGUARD_DNS -split ',')";
+    let servers: Vec<String> = servers.iter().map(ToString::to_string).collect();
+    run_command(
+        Command::new("powershell")
+            .args(["-NoProfile", "-Command", script])
+            .env("DEFGUARD_INTERFACE", link)
+            .env("DEFGUARD_DNS", servers.join(",")),
+    )
+    .map(drop)
+}
+
+#[cfg(target_os = "windows")]
+fn reset_dns(link: &str) -> Result<(), Status> {
+    let script = "Set-DnsClientServerAddress -InterfaceIndex $env:DEFGUARD_INTERFACE \
+        -ResetServerAddresses";
+    run_command(
+        Command::new("powershell")
+            .args(["-NoProfile", "-Command", script])
+            .env("DEFGUARD_INTERFACE", link),
+    )
+    .map(drop)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+fn default_route_interface() -> Result<String, Status> {
+    Err(Status::unimplemented(
+        "Fallback DNS isn't supported on this platform",
+    ))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+fn set_dns(_link: &str, _servers: &[IpAddr]) -> Result<(), Status> {
+    Err(Status::unimplemented(
+        "Fallback DNS isn't supported on this platform",
+    ))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+fn reset_dns(_link: &str) -> Result<(), Status> {
+    Ok(())
+}
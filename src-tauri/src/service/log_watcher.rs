@@ -11,16 +11,18 @@ use notify_debouncer_mini::{
     notify::{self, RecursiveMode},
 };
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use serde_with::{serde_as, DisplayFromStr};
 use std::{
-    fs::{read_dir, File},
-    io::{BufRead, BufReader},
-    path::PathBuf,
+    fs::{metadata, read_dir, File, Metadata},
+    io::{self, BufRead, BufReader, ErrorKind, Seek, SeekFrom},
+    path::{Path, PathBuf},
     str::FromStr,
     time::{Duration, SystemTime},
 };
 use tauri::{async_runtime::TokioJoinHandle, AppHandle, Manager};
 use thiserror::Error;
+use tokio::sync::watch;
 use tokio_util::sync::CancellationToken;
 use tracing::Level;
 
@@ -58,19 +60,152 @@ struct Span {
 #[derive(Clone, Debug, Deserialize, Serialize)]
 struct LogLineFields {
     message: String,
+    // structured fields logged along with the message
+    #[serde(flatten)]
+    other: Map<String, Value>,
+}
+
+/// Format of log lines emitted to the frontend.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Log lines as read from service log, formatted for display by the frontend.
+    #[default]
+    Text,
+    /// Flat log entries meant for filtering and machine-readable diagnostics.
+    Json,
+}
+
+/// Log line in `LogFormat::Json`.
+#[derive(Debug, Serialize)]
+struct LogEntry {
+    timestamp: DateTime<Utc>,
+    level: String,
+    interface: Option<String>,
+    message: String,
+    fields: Map<String, Value>,
+}
+
+impl From<LogLine> for LogEntry {
+    fn from(line: LogLine) -> Self {
+        Self {
+            timestamp: line.timestamp,
+            level: line.level.to_string(),
+            interface: line.span.and_then(|span| span.interface_name),
+            message: line.fields.message,
+            fields: line.fields.other,
+        }
+    }
+}
+
+// identifies a file regardless of its path, so renamed log files can be told apart
+type FileIdentity = (u64, u64);
+
+#[cfg(unix)]
+fn file_identity(metadata: &Metadata) -> Option<FileIdentity> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_identity(metadata: &Metadata) -> Option<FileIdentity> {
+    let created = metadata
+        .created()
+        .ok()?
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .ok()?;
+    Some((created.as_secs(), created.subsec_nanos().into()))
+}
+
+/// Follows the latest log file similarly to `tail -F`.
+///
+/// Log file is kept open between reads. When it's replaced by a new file (renamed or removed
+/// and created again) or truncated, lines remaining in the old file are read first
+/// and then the new file is read from the start.
+#[derive(Debug, Default)]
+struct LogTail {
+    path: Option<PathBuf>,
+    reader: Option<BufReader<File>>,
+    identity: Option<FileIdentity>,
+    position: u64,
+    // incomplete line at the end of file, completed by following writes
+    partial_line: String,
+}
+
+impl LogTail {
+    /// Returns complete lines written since previous call, `latest_log_file` being
+    /// the current log file.
+    fn read_lines(&mut self, latest_log_file: Option<PathBuf>) -> io::Result<Vec<String>> {
+        let mut lines = Vec::new();
+        let reopen = match (&latest_log_file, &self.path) {
+            // newer log file appeared
+            (Some(latest), current) if current.as_ref() != Some(latest) => Some(latest.clone()),
+            (_, Some(current)) => match metadata(current) {
+                Ok(metadata)
+                    if file_identity(&metadata) != self.identity
+                        || metadata.len() < self.position =>
+                {
+                    debug!("Log file {current:?} rotated, reopening");
+                    Some(current.clone())
+                }
+                // file was moved away and new one is not created yet, keep reading the old one
+                Err(err) if err.kind() == ErrorKind::NotFound => None,
+                Err(err) => return Err(err),
+                Ok(_) => None,
+            },
+            _ => None,
+        };
+        if let Some(path) = reopen {
+            self.read_available(&mut lines)?;
+            self.open(path)?;
+        }
+        self.read_available(&mut lines)?;
+        Ok(lines)
+    }
+
+    fn open(&mut self, path: PathBuf) -> io::Result<()> {
+        info!("Reading log file {path:?}");
+        let file = File::open(&path)?;
+        self.identity = file_identity(&file.metadata()?);
+        self.reader = Some(BufReader::new(file));
+        self.path = Some(path);
+        self.position = 0;
+        self.partial_line.clear();
+        Ok(())
+    }
+
+    fn read_available(&mut self, lines: &mut Vec<String>) -> io::Result<()> {
+        let Some(reader) = &mut self.reader else {
+            return Ok(());
+        };
+        // file might have been truncated in place, so read position is restored explicitly
+        reader.seek(SeekFrom::Start(self.position))?;
+        loop {
+            let read = reader.read_line(&mut self.partial_line)?;
+            if read == 0 {
+                break;
+            }
+            self.position += read as u64;
+            if self.partial_line.ends_with('\n') {
+                lines.push(self.partial_line.trim_end().to_string());
+                self.partial_line.clear();
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
 pub struct ServiceLogWatcher {
     interface_name: String,
-    log_level: Level,
+    // updated whenever log level setting changes
+    log_level: watch::Receiver<Level>,
     from: Option<DateTime<Utc>>,
     log_dir: PathBuf,
-    current_log_file: Option<PathBuf>,
-    current_position: u64,
+    tail: LogTail,
     handle: AppHandle,
     cancellation_token: CancellationToken,
     event_topic: String,
+    format: LogFormat,
 }
 
 impl ServiceLogWatcher {
@@ -80,8 +215,9 @@ impl ServiceLogWatcher {
         cancellation_token: CancellationToken,
         event_topic: String,
         interface_name: String,
-        log_level: Level,
+        log_level: watch::Receiver<Level>,
         from: Option<DateTime<Utc>>,
+        format: LogFormat,
     ) -> Self {
         // get log file directory
         let log_dir = get_service_log_dir();
@@ -91,11 +227,11 @@ impl ServiceLogWatcher {
             log_level,
             from,
             log_dir,
-            current_log_file: None,
-            current_position: 0,
+            tail: LogTail::default(),
             handle,
             cancellation_token,
             event_topic,
+            format,
         }
     }
 
@@ -136,41 +272,32 @@ impl ServiceLogWatcher {
     ///
     /// Analyzing the directory consists of finding the latest log file,
     /// parsing log lines and emitting tauri events whenever relevant logs are found.
-    /// Current log file and latest read position are stored between runs
-    /// so only new log lines are sent to the frontend whenever a change in
-    /// the directory is detected.
+    /// Current log file is kept open between runs, so only new log lines are sent
+    /// to the frontend whenever a change in the directory is detected, also after
+    /// the log file has been rotated.
     fn parse_log_dir(&mut self) -> Result<(), LogWatcherError> {
         // get latest log file
         let latest_log_file = self.get_latest_log_file()?;
         info!("found latest log file: {latest_log_file:?}");
 
-        // check if latest file changed
-        if latest_log_file.is_some() && latest_log_file != self.current_log_file {
-            self.current_log_file = latest_log_file;
-            // reset read position
-            self.current_position = 0;
-        }
-
-        // read and parse file from last position
-        if let Some(log_file) = &self.current_log_file {
-            let file = File::open(log_file)?;
-            let size = file.metadata()?.len();
-            let mut reader = BufReader::new(file);
-            reader.seek_relative(self.current_position as i64)?;
-            let mut parsed_lines = Vec::new();
-            for line in reader.lines() {
-                let line = line?;
-                if let Some(parsed_line) = self.parse_log_line(line)? {
-                    parsed_lines.push(parsed_line);
-                }
+        let mut parsed_lines = Vec::new();
+        for line in self.tail.read_lines(latest_log_file)? {
+            if let Some(parsed_line) = self.parse_log_line(line)? {
+                parsed_lines.push(parsed_line);
             }
-            // emit event with all relevant log lines
-            if !parsed_lines.is_empty() {
-                self.handle.emit_all(&self.event_topic, parsed_lines)?;
+        }
+        // emit event with all relevant log lines
+        if !parsed_lines.is_empty() {
+            match self.format {
+                LogFormat::Text => self.handle.emit_all(&self.event_topic, parsed_lines)?,
+                LogFormat::Json => self.handle.emit_all(
+                    &self.event_topic,
+                    parsed_lines
+                        .into_iter()
+                        .map(LogEntry::from)
+                        .collect::<Vec<_>>(),
+                )?,
             }
-
-            // update read position to end of file
-            self.current_position = size;
         }
         Ok(())
     }
@@ -180,64 +307,108 @@ impl ServiceLogWatcher {
     /// Deserializes the log line into a known struct and checks if the line is relevant
     /// to the specified interface. Also performs filtering by log level and optional timestamp.
     fn parse_log_line(&self, line: String) -> Result<Option<LogLine>, LogWatcherError> {
-        debug!("Parsing log line: {line}");
-        let log_line = serde_json::from_str::<LogLine>(&line)?;
-        debug!("Parsed log line into: {log_line:?}");
-
-        // filter by log level
-        if log_line.level > self.log_level {
-            debug!(
-                "Log level {} is above configured verbosity threshold {}. Skipping line...",
-                log_line.level, self.log_level
-            );
+        let log_level = *self.log_level.borrow();
+        Ok(filter_log_line(
+            &line,
+            &self.interface_name,
+            log_level,
+            self.from,
+        )?)
+    }
+
+    /// Find the latest log file in directory
+    fn get_latest_log_file(&self) -> Result<Option<PathBuf>, LogWatcherError> {
+        debug!("Getting latest log file");
+        Ok(list_log_files(&self.log_dir)?
+            .pop()
+            .map(|(_, log_file)| log_file))
+    }
+}
+
+fn filter_log_line(
+    line: &str,
+    interface_name: &str,
+    log_level: Level,
+    from: Option<DateTime<Utc>>,
+) -> Result<Option<LogLine>, serde_json::Error> {
+    debug!("Parsing log line: {line}");
+    let log_line = serde_json::from_str::<LogLine>(line)?;
+    debug!("Parsed log line into: {log_line:?}");
+
+    // filter by log level
+    if log_line.level > log_level {
+        debug!(
+            "Log level {} is above configured verbosity threshold {log_level}. Skipping line...",
+            log_line.level
+        );
+        return Ok(None);
+    }
+
+    // filter by optional timestamp
+    if let Some(from) = from {
+        if log_line.timestamp < from {
+            debug!("Timestamp is before configured threshold {from}. Skipping line...");
             return Ok(None);
         }
+    }
 
-        // filter by optional timestamp
-        if let Some(from) = self.from {
-            if log_line.timestamp < from {
-                debug!("Timestamp is before configured threshold {from}. Skipping line...");
+    // publish all log lines with a matching interface name or with no interface name specified
+    if let Some(ref span) = log_line.span {
+        if let Some(line_interface_name) = &span.interface_name {
+            if line_interface_name != interface_name {
+                debug!("Interface name {line_interface_name} is not the configured name {interface_name}. Skipping line...");
                 return Ok(None);
             }
         }
+    }
 
-        // publish all log lines with a matching interface name or with no interface name specified
-        if let Some(ref span) = log_line.span {
-            if let Some(interface_name) = &span.interface_name {
-                if interface_name != &self.interface_name {
-                    debug!("Interface name {interface_name} is not the configured name {}. Skipping line...", self.interface_name);
-                    return Ok(None);
-                }
+    Ok(Some(log_line))
+}
+
+/// List log files in directory, oldest first
+///
+/// Log files are rotated daily and have a knows naming format,
+/// with the last 10 characters specifying a date (e.g. `2023-12-15`).
+fn list_log_files(log_dir: &Path) -> io::Result<Vec<(SystemTime, PathBuf)>> {
+    let mut log_files = Vec::new();
+    for entry in read_dir(log_dir)?.flatten() {
+        // skip directories
+        if entry.metadata()?.is_file() {
+            let filename = entry.file_name().to_string_lossy().into_owned();
+            if let Some(timestamp) = extract_timestamp(&filename) {
+                log_files.push((timestamp, entry.path()));
             }
         }
-
-        Ok(Some(log_line))
     }
+    log_files.sort();
+    Ok(log_files)
+}
 
-    /// Find the latest log file in directory
-    ///
-    /// Log files are rotated daily and have a knows naming format,
-    /// with the last 10 characters specifying a date (e.g. `2023-12-15`).
-    fn get_latest_log_file(&self) -> Result<Option<PathBuf>, LogWatcherError> {
-        debug!("Getting latest log file");
-        let entries = read_dir(&self.log_dir)?;
-
-        let mut latest_log = None;
-        let mut latest_time = SystemTime::UNIX_EPOCH;
-        for entry in entries.flatten() {
-            // skip directories
-            if entry.metadata()?.is_file() {
-                let filename = entry.file_name().to_string_lossy().into_owned();
-                if let Some(timestamp) = extract_timestamp(&filename) {
-                    if timestamp > latest_time {
-                        latest_time = timestamp;
-                        latest_log = Some(entry.path());
-                    }
-                }
+/// Reads `defguard-service` log lines relevant to given interface, written since `from`.
+///
+/// Lines are returned as found in log files, so they can be attached to bug reports as is.
+/// Lines which can't be parsed are skipped.
+pub fn read_interface_logs(interface_name: &str, from: DateTime<Utc>) -> io::Result<Vec<String>> {
+    let log_dir = get_service_log_dir();
+    // every log file contains lines of a single day
+    let from_day = from.date_naive().and_time(NaiveTime::default()).timestamp();
+    let from_day = SystemTime::UNIX_EPOCH + Duration::from_secs(from_day.max(0) as u64);
+    let mut lines = Vec::new();
+    for (_, log_file) in list_log_files(&log_dir)?
+        .into_iter()
+        .filter(|(timestamp, _)| *timestamp >= from_day)
+    {
+        debug!("Reading interface {interface_name} logs from {log_file:?}");
+        for line in BufReader::new(File::open(log_file)?).lines() {
+            let line = line?;
+            match filter_log_line(&line, interface_name, Level::TRACE, Some(from)) {
+                Ok(Some(_)) => lines.push(line),
+                Ok(None) => {}
+                Err(err) => debug!("Skipping malformed log line: {err}"),
             }
         }
-        Ok(latest_log)
     }
+    Ok(lines)
 }
 
 fn extract_timestamp(filename: &str) -> Option<SystemTime> {
@@ -258,17 +429,20 @@ fn extract_timestamp(filename: &str) -> Option<SystemTime> {
 /// The watcher parses `defguard-service` log files and extracts logs relevant
 /// to the WireGuard interface for a given location.
 /// Logs are then transmitted to the frontend by using `tauri` `Events`.
+/// Logs are filtered by level set in `AppState::log_watcher_level`, which can change while
+/// the watcher is running.
 /// Returned value is the name of an event topic to monitor.
 pub async fn spawn_log_watcher_task(
     handle: AppHandle,
     location_id: i64,
     interface_name: String,
     connection_type: ConnectionType,
-    log_level: Level,
     from: Option<String>,
+    format: LogFormat,
 ) -> Result<String, Error> {
     info!("Spawning log watcher task for location ID {location_id}, interface {interface_name}");
     let app_state = handle.state::<AppState>();
+    let log_level = app_state.log_watcher_level.subscribe();
 
     // parse `from` timestamp
     let from = from.and_then(|from| DateTime::<Utc>::from_str(&from).ok());
@@ -299,6 +473,7 @@ pub async fn spawn_log_watcher_task(
             interface_name_clone,
             log_level,
             from,
+            format,
         );
         log_watcher.run()?;
         Ok(())
@@ -341,3 +516,67 @@ pub fn stop_log_watcher_task(handle: AppHandle, interface_name: String) -> Resul
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        fs::{create_dir_all, remove_dir_all, rename, OpenOptions},
+        io::Write,
+        path::Path,
+    };
+
+    fn append(path: &Path, lines: &[&str]) {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        for line in lines {
+            writeln!(file, "{line}").unwrap();
+        }
+    }
+
+    #[test]
+    fn test_log_tail_follows_rotation() {
+        let dir = std::env::temp_dir().join(format!("defguard-log-tail-{}", std::process::id()));
+        create_dir_all(&dir).unwrap();
+        let log_file = dir.join("defguard-service.log.2024-02-08");
+        let latest = || Some(log_file.clone());
+        let mut tail = LogTail::default();
+
+        append(&log_file, &["first", "second"]);
+        assert_eq!(tail.read_lines(latest()).unwrap(), ["first", "second"]);
+
+        // incomplete line is only returned once it's finished
+        let mut file = OpenOptions::new().append(true).open(&log_file).unwrap();
+        write!(file, "thi").unwrap();
+        assert!(tail.read_lines(latest()).unwrap().is_empty());
+        writeln!(file, "rd").unwrap();
+        append(&log_file, &["fourth"]);
+
+        // rotate by renaming, line written to the old file before reading is still delivered
+        rename(&log_file, dir.join("defguard-service.log.2024-02-08.1")).unwrap();
+        append(&log_file, &["fifth"]);
+        assert_eq!(
+            tail.read_lines(latest()).unwrap(),
+            ["third", "fourth", "fifth"]
+        );
+
+        // rotate by truncating in place, detected as the file is now shorter than read position
+        File::create(&log_file).unwrap();
+        append(&log_file, &["6th"]);
+        assert_eq!(tail.read_lines(latest()).unwrap(), ["6th"]);
+
+        // switch to log file of the next day
+        let next_log_file = dir.join("defguard-service.log.2024-02-09");
+        append(&log_file, &["seventh"]);
+        append(&next_log_file, &["eighth"]);
+        assert_eq!(
+            tail.read_lines(Some(next_log_file)).unwrap(),
+            ["seventh", "eighth"]
+        );
+
+        remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,8 +1,11 @@
 pub mod config;
+pub mod fallback_dns;
 pub mod proto {
     tonic::include_proto!("client");
 }
 pub mod log_watcher;
+pub mod routes;
+pub mod status;
 pub mod utils;
 #[cfg(windows)]
 pub mod windows_service;
@@ -11,6 +14,7 @@ use std::{
     net::{IpAddr, Ipv4Addr, SocketAddr},
     ops::Add,
     pin::Pin,
+    sync::Arc,
     time::{Duration, UNIX_EPOCH},
 };
 
@@ -27,7 +31,14 @@ use tonic::{
 };
 use tracing::{debug, error, info, info_span, Instrument};
 
-use self::config::Config;
+use self::{
+    config::Config,
+    fallback_dns::FallbackDns,
+    status::{
+        proto::service_status_service_server::ServiceStatusServiceServer, ManagedInterfaces,
+        StatusService,
+    },
+};
 use crate::utils::{execute_command, IS_MACOS};
 
 use proto::{
@@ -51,6 +62,8 @@ pub enum DaemonError {
 #[derive(Debug, Default)]
 pub struct DaemonService {
     stats_period: u64,
+    interfaces: ManagedInterfaces,
+    fallback_dns: FallbackDns,
 }
 
 impl DaemonService {
@@ -58,6 +71,35 @@ impl DaemonService {
     pub fn new(config: &Config) -> Self {
         Self {
             stats_period: config.stats_period,
+            interfaces: ManagedInterfaces::default(),
+            fallback_dns: FallbackDns::default(),
+        }
+    }
+
+    /// Interfaces created by this service, shared with status service which lists them.
+    #[must_use]
+    pub fn managed_interfaces(&self) -> ManagedInterfaces {
+        Arc::clone(&self.interfaces)
+    }
+
+    /// Fallback DNS, shared with status service which configures it.
+    #[must_use]
+    pub fn fallback_dns(&self) -> FallbackDns {
+        self.fallback_dns.clone()
+    }
+
+    fn track_interface(&self, ifname: &str, created: bool) {
+        match self.interfaces.lock() {
+            Ok(mut interfaces) if created => {
+                interfaces.insert(ifname.to_string());
+            }
+            Ok(mut interfaces) => {
+                interfaces.remove(ifname);
+                if interfaces.is_empty() {
+                    self.fallback_dns.all_interfaces_removed();
+                }
+            }
+            Err(_) => error!("Failed to acquire managed interfaces lock"),
         }
     }
 }
@@ -92,6 +134,8 @@ impl DesktopDaemonService for DaemonService {
         info!("Creating interface {ifname}");
         // setup WireGuard API
         let wgapi = setup_wgapi(ifname.clone())?;
+        // system DNS is restored, tunnel decides about DNS while it's up
+        self.fallback_dns.interface_created();
 
         if let Some(pre_up) = request.pre_up {
             debug!("Executing specified PreUp command: {pre_up}");
@@ -117,7 +161,12 @@ impl DesktopDaemonService for DaemonService {
             .collect();
 
         // configure interface
-        debug!("Configuring new interface {ifname} with configuration: {config:?}");
+        // configuration isn't logged as it contains private and preshared keys
+        debug!(
+            "Configuring new interface {ifname} with address {} and {} peer(s)",
+            config.address,
+            config.peers.len()
+        );
 
         #[cfg(not(windows))]
         let configure_interface_result = wgapi.configure_interface(&config);
@@ -157,6 +206,7 @@ impl DesktopDaemonService for DaemonService {
             let _ = execute_command(&post_up);
             info!("Executed specified PostUp command: {post_up}");
         }
+        self.track_interface(ifname, true);
 
         Ok(Response::new(()))
     }
@@ -182,6 +232,7 @@ impl DesktopDaemonService for DaemonService {
             error!("{msg}");
             Status::new(Code::Internal, msg)
         })?;
+        self.track_interface(&ifname, false);
         if let Some(post_down) = request.post_down {
             debug!("Executing specified PostDown command: {post_down}");
             let _ = execute_command(&post_down);
@@ -249,12 +300,17 @@ pub async fn run_server(config: Config) -> anyhow::Result<()> {
 
     let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), DAEMON_HTTP_PORT);
     let daemon_service = DaemonService::new(&config);
+    let status_service = StatusService::new(
+        daemon_service.managed_interfaces(),
+        daemon_service.fallback_dns(),
+    );
 
     info!("defguard daemon listening on {addr}");
 
     Server::builder()
         .trace_fn(|_| tracing::info_span!("defguard_service"))
         .add_service(DesktopDaemonServiceServer::new(daemon_service))
+        .add_service(ServiceStatusServiceServer::new(status_service))
         .serve(addr)
         .await?;
 
@@ -0,0 +1,190 @@
+//! Routes of WireGuard interfaces
+//!
+//! Reads system routing table with platform tools and keeps only routes going through
+//! given interface, so users can see why traffic does or doesn't use the tunnel.
+
+use std::process::Command;
+
+use tonic::Status;
+
+use super::status::proto::InterfaceRoute;
+use crate::utils::validate_interface_name;
+
+fn run(program: &str, args: &[&str]) -> Result<String, Status> {
+    run_command(Command::new(program).args(args))
+}
+
+pub(super) fn run_command(command: &mut Command) -> Result<String, Status> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = command.output().map_err(|err| {
+        let msg = format!("Failed to run {program}: {err}");
+        error!("{msg}");
+        Status::internal(msg)
+    })?;
+    if !output.status.success() {
+        let msg = format!(
+            "{program} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        error!("{msg}");
+        return Err(Status::internal(msg));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Lists routes going through interface `ifname`, in all routing tables.
+pub fn interface_routes(ifname: &str) -> Result<Vec<InterfaceRoute>, Status> {
+    validate_interface_name(ifname).map_err(|err| Status::invalid_argument(err.to_string()))?;
+
+    #[cfg(target_os = "linux")]
+    {
+        let mut routes = Vec::new();
+        for family in ["-4", "-6"] {
+            let output = run(
+                "ip",
+                &[family, "-j", "route", "show", "table", "all", "dev", ifname],
+            )?;
+            routes.extend(parse_ip_routes(&output)?);
+        }
+        Ok(routes)
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        let output = run("netstat", &["-rn"])?;
+        Ok(parse_netstat_routes(&output, ifname))
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        // interface name is passed through environment, so it's never parsed as part of the script
+        let script = "Get-NetRoute -InterfaceAlias $env:DEFGUARD_INTERFACE | ForEach-Object { \
+            \"$($_.DestinationPrefix) $($_.NextHop) $($_.RouteMetric)\" }";
+        let output = run_command(
+            Command::new("powershell")
+                .args(["-NoProfile", "-Command", script])
+                .env("DEFGUARD_INTERFACE", ifname),
+        )?;
+        Ok(parse_net_routes(&output))
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+    {
+        Err(Status::unimplemented(format!(
+            "Listing routes of interface {ifname} isn't supported on this platform"
+        )))
+    }
+}
+
+/// Parses JSON output of `ip -j route show`. Local and broadcast routes of kernel's
+/// `local` table are skipped, only unicast routes decide where traffic goes.
+#[cfg(target_os = "linux")]
+fn parse_ip_routes(output: &str) -> Result<Vec<InterfaceRoute>, Status> {
+    #[derive(serde::Deserialize)]
+    struct IpRoute {
+        dst: String,
+        gateway: Option<String>,
+        metric: Option<u32>,
+        #[serde(rename = "type")]
+        kind: Option<String>,
+    }
+
+    // `ip` prints nothing instead of empty array if there are no routes
+    if output.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    let routes: Vec<IpRoute> = serde_json::from_str(output)
+        .map_err(|err| Status::internal(format!("Failed to parse routes: {err}")))?;
+    Ok(routes
+        .into_iter()
+        .filter(|route| route.kind.as_deref().map_or(true, |kind| kind == "unicast"))
+        .map(|route| InterfaceRoute {
+            destination: route.dst,
+            gateway: route.gateway,
+            metric: route.metric.unwrap_or_default(),
+        })
+        .collect())
+}
+
+/// Parses `netstat -rn` output. Interface is in the 4th column of all routing tables,
+/// routes directly through the interface have its name or link address as gateway.
+#[cfg(target_os = "macos")]
+fn parse_netstat_routes(output: &str, ifname: &str) -> Vec<InterfaceRoute> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let columns: Vec<&str> = line.split_whitespace().collect();
+            match columns.as_slice() {
+                [destination, gateway, _flags, netif, ..] if *netif == ifname => {
+                    Some(InterfaceRoute {
+                        destination: (*destination).to_string(),
+                        gateway: Some((*gateway).to_string())
+                            .filter(|gateway| gateway != ifname && !gateway.starts_with("link#")),
+                        metric: 0,
+                    })
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Parses `<destination> <next hop> <metric>` lines printed for `Get-NetRoute` results.
+/// Unspecified next hop means the route goes directly through the interface.
+#[cfg(target_os = "windows")]
+fn parse_net_routes(output: &str) -> Vec<InterfaceRoute> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut columns = line.split_whitespace();
+            let destination = columns.next()?.to_string();
+            let gateway = columns.next()?;
+            let metric = columns.next()?.parse().ok()?;
+            Some(InterfaceRoute {
+                destination,
+                gateway: Some(gateway.to_string())
+                    .filter(|gateway| gateway != "0.0.0.0" && gateway != "::"),
+                metric,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_parse_ip_routes() {
+        let output = r#"[
+            {"dst":"default","gateway":"10.6.0.1","metric":10,"table":"51820","flags":[]},
+            {"dst":"10.6.0.0/24","protocol":"kernel","scope":"link","prefsrc":"10.6.0.2","flags":[]},
+            {"type":"local","dst":"10.6.0.2","table":"local","protocol":"kernel","scope":"host","flags":[]},
+            {"type":"broadcast","dst":"10.6.0.255","table":"local","protocol":"kernel","scope":"link","flags":[]}
+        ]"#;
+        let routes = parse_ip_routes(output).unwrap();
+        assert_eq!(routes.len(), 2);
+        assert_eq!(routes[0].destination, "default");
+        assert_eq!(routes[0].gateway.as_deref(), Some("10.6.0.1"));
+        assert_eq!(routes[0].metric, 10);
+        assert_eq!(routes[1].destination, "10.6.0.0/24");
+        assert_eq!(routes[1].gateway, None);
+        assert!(parse_ip_routes("\n").unwrap().is_empty());
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn test_parse_netstat_routes() {
+        let output = "Routing tables\n\nInternet:\n\
+            Destination        Gateway            Flags               Netif Expire\n\
+            default            192.168.1.1        UGScg                 en0\n\
+            0/1                utun3              USc                 utun3\n\
+            10.6.0.1           10.6.0.2           UH                  utun3\n";
+        let routes = parse_netstat_routes(output, "utun3");
+        assert_eq!(routes.len(), 2);
+        assert_eq!(routes[0].destination, "0/1");
+        assert_eq!(routes[0].gateway, None);
+        assert_eq!(routes[1].gateway.as_deref(), Some("10.6.0.2"));
+    }
+}
@@ -0,0 +1,292 @@
+//! Health check of `defguard-service`
+//!
+//! Lets the client verify that the daemon is running and which version it is
+//! before trying to manage interfaces through it, and list interfaces it manages
+//! together with their routes. Also receives fallback DNS from client settings.
+
+use std::{
+    collections::BTreeSet,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use defguard_wireguard_rs::WireguardInterfaceApi;
+use serde::Serialize;
+use tonic::{transport::Channel, Code, Request, Response, Status};
+
+use super::{
+    fallback_dns::FallbackDns, routes::interface_routes, setup_wgapi, utils::service_address,
+};
+use crate::error::Error;
+
+pub mod proto {
+    tonic::include_proto!("service_status");
+}
+
+use proto::{
+    service_status_service_client::ServiceStatusServiceClient,
+    service_status_service_server::ServiceStatusService, InterfaceRoute, ListInterfacesResponse,
+    ListRoutesRequest, ListRoutesResponse, ManagedInterface, ManagedPeer, ServiceStatusResponse,
+    SetFallbackDnsRequest,
+};
+
+/// Names of interfaces created by `defguard-service` and not removed yet.
+pub type ManagedInterfaces = Arc<Mutex<BTreeSet<String>>>;
+
+/// Version of the protocol between client and `defguard-service`,
+/// bumped on incompatible changes of daemon RPCs.
+pub const PROTO_VERSION: u32 = 1;
+// how long to wait for service to respond to status request
+const STATUS_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Default)]
+pub struct StatusService {
+    interfaces: ManagedInterfaces,
+    fallback_dns: FallbackDns,
+}
+
+impl StatusService {
+    #[must_use]
+    pub fn new(interfaces: ManagedInterfaces, fallback_dns: FallbackDns) -> Self {
+        Self {
+            interfaces,
+            fallback_dns,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl ServiceStatusService for StatusService {
+    async fn status(
+        &self,
+        _request: Request<()>,
+    ) -> Result<Response<ServiceStatusResponse>, Status> {
+        Ok(Response::new(ServiceStatusResponse {
+            version: env!("CARGO_PKG_VERSION").into(),
+            proto_version: PROTO_VERSION,
+        }))
+    }
+
+    async fn list_interfaces(
+        &self,
+        _request: Request<()>,
+    ) -> Result<Response<ListInterfacesResponse>, Status> {
+        let mut interfaces = self
+            .interfaces
+            .lock()
+            .map_err(|_| Status::internal("Failed to acquire managed interfaces lock"))?;
+        let mut managed = Vec::with_capacity(interfaces.len());
+        // interfaces which can't be read anymore were removed outside of the service
+        interfaces.retain(|ifname| {
+            match setup_wgapi(ifname.clone()).and_then(|wgapi| {
+                wgapi
+                    .read_interface_data()
+                    .map_err(|err| Status::internal(err.to_string()))
+            }) {
+                Ok(host) => {
+                    let peers = host
+                        .peers
+                        .into_values()
+                        .map(|peer| ManagedPeer {
+                            public_key: peer.public_key.to_string(),
+                            last_handshake: peer.last_handshake.and_then(unix_timestamp),
+                            tx_bytes: peer.tx_bytes,
+                            rx_bytes: peer.rx_bytes,
+                        })
+                        .collect();
+                    managed.push(ManagedInterface {
+                        name: ifname.clone(),
+                        peers,
+                    });
+                    true
+                }
+                Err(status) => {
+                    warn!("Managed interface {ifname} no longer exists: {status}");
+                    false
+                }
+            }
+        });
+        Ok(Response::new(ListInterfacesResponse {
+            interfaces: managed,
+        }))
+    }
+
+    async fn list_routes(
+        &self,
+        request: Request<ListRoutesRequest>,
+    ) -> Result<Response<ListRoutesResponse>, Status> {
+        let ifname = request.into_inner().interface_name;
+        // only routes of interfaces created by the service are listed
+        let managed = self
+            .interfaces
+            .lock()
+            .map_err(|_| Status::internal("Failed to acquire managed interfaces lock"))?
+            .contains(&ifname);
+        if !managed {
+            return Err(Status::not_found(format!(
+                "Interface {ifname} isn't managed by defguard-service"
+            )));
+        }
+        // routes are read with blocking system tools
+        let routes = {
+            let ifname = ifname.clone();
+            tokio::task::spawn_blocking(move || interface_routes(&ifname))
+                .await
+                .map_err(|err| Status::internal(format!("Failed to list routes: {err}")))??
+        };
+        debug!("Interface {ifname} has {} routes", routes.len());
+        Ok(Response::new(ListRoutesResponse { routes }))
+    }
+
+    async fn set_fallback_dns(
+        &self,
+        request: Request<SetFallbackDnsRequest>,
+    ) -> Result<Response<()>, Status> {
+        let servers = request
+            .into_inner()
+            .servers
+            .iter()
+            .map(|server| {
+                server
+                    .parse()
+                    .map_err(|_| Status::invalid_argument(format!("Invalid DNS server {server}")))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let interfaces_up = !self
+            .interfaces
+            .lock()
+            .map_err(|_| Status::internal("Failed to acquire managed interfaces lock"))?
+            .is_empty();
+        debug!("Setting fallback DNS to {servers:?}");
+        let fallback_dns = self.fallback_dns.clone();
+        // DNS is configured with blocking system tools
+        tokio::task::spawn_blocking(move || fallback_dns.configure(servers, interfaces_up))
+            .await
+            .map_err(|err| Status::internal(format!("Failed to set fallback DNS: {err}")))??;
+        Ok(Response::new(()))
+    }
+}
+
+// handshake time is reported as UNIX epoch if there was no handshake yet
+fn unix_timestamp(time: SystemTime) -> Option<u64> {
+    time.duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|duration| duration.as_secs())
+        .filter(|seconds| *seconds > 0)
+}
+
+#[derive(Debug, Serialize)]
+pub struct ServiceStatus {
+    pub running: bool,
+    pub version: String,
+    pub proto_version: u32,
+}
+
+impl ServiceStatus {
+    fn not_running() -> Self {
+        Self {
+            running: false,
+            version: String::new(),
+            proto_version: 0,
+        }
+    }
+}
+
+async fn connect_status_client() -> Result<ServiceStatusServiceClient<Channel>, Error> {
+    let channel = service_address().connect(STATUS_TIMEOUT).await?;
+    Ok(ServiceStatusServiceClient::new(channel))
+}
+
+/// Queries `defguard-service` for its version. Unreachable service is reported as not running.
+pub async fn query_service_status() -> ServiceStatus {
+    let mut client = match connect_status_client().await {
+        Ok(client) => client,
+        Err(err) => {
+            warn!("Failed to connect to defguard-service: {err}");
+            return ServiceStatus::not_running();
+        }
+    };
+    match client.status(()).await {
+        Ok(response) => {
+            let response = response.into_inner();
+            debug!(
+                "defguard-service {} is running, protocol version {}",
+                response.version, response.proto_version
+            );
+            ServiceStatus {
+                running: true,
+                version: response.version,
+                proto_version: response.proto_version,
+            }
+        }
+        Err(status) => {
+            warn!("defguard-service status request failed: {status}");
+            ServiceStatus::not_running()
+        }
+    }
+}
+
+/// Lists WireGuard interfaces which `defguard-service` created and which still exist.
+pub async fn list_managed_interfaces() -> Result<Vec<ManagedInterface>, Error> {
+    let mut client = connect_status_client().await.map_err(|err| {
+        error!("Failed to connect to defguard-service: {err}");
+        err
+    })?;
+    match client.list_interfaces(()).await {
+        Ok(response) => Ok(response.into_inner().interfaces),
+        Err(status) => {
+            error!("Failed to list interfaces managed by defguard-service: {status}");
+            if status.code() == Code::Unavailable {
+                Err(Error::ServiceUnavailable(status.message().into()))
+            } else {
+                Err(Error::CommandError(status.message().into()))
+            }
+        }
+    }
+}
+
+/// Lists routes which go through interface created by `defguard-service`.
+pub async fn list_interface_routes(interface_name: &str) -> Result<Vec<InterfaceRoute>, Error> {
+    let mut client = connect_status_client().await.map_err(|err| {
+        error!("Failed to connect to defguard-service: {err}");
+        err
+    })?;
+    let request = ListRoutesRequest {
+        interface_name: interface_name.into(),
+    };
+    match client.list_routes(request).await {
+        Ok(response) => Ok(response.into_inner().routes),
+        Err(status) => {
+            error!("Failed to list routes of interface {interface_name}: {status}");
+            match status.code() {
+                Code::Unavailable => Err(Error::ServiceUnavailable(status.message().into())),
+                Code::Unimplemented => Err(Error::CommandError(
+                    "Installed defguard-service can't list routes, please update it".into(),
+                )),
+                _ => Err(Error::CommandError(status.message().into())),
+            }
+        }
+    }
+}
+
+/// Sends fallback DNS servers to `defguard-service`, which applies them while no interface is up.
+pub async fn set_fallback_dns(servers: Vec<String>) -> Result<(), Error> {
+    let mut client = connect_status_client().await.map_err(|err| {
+        error!("Failed to connect to defguard-service: {err}");
+        err
+    })?;
+    match client
+        .set_fallback_dns(SetFallbackDnsRequest { servers })
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(status) => {
+            error!("Failed to set fallback DNS: {status}");
+            match status.code() {
+                Code::Unavailable => Err(Error::ServiceUnavailable(status.message().into())),
+                Code::Unimplemented => Err(Error::Unsupported(status.message().into())),
+                _ => Err(Error::CommandError(status.message().into())),
+            }
+        }
+    }
+}
@@ -1,13 +1,18 @@
 use crate::{
-    service::{
-        proto::desktop_daemon_service_client::DesktopDaemonServiceClient, DaemonError,
-        DAEMON_BASE_URL,
-    },
+    error::Error,
+    service::{proto::desktop_daemon_service_client::DesktopDaemonServiceClient, DAEMON_BASE_URL},
     utils::get_service_log_dir,
 };
-use std::io::stdout;
+use reqwest::Url;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::{io::stdout, net::SocketAddr, sync::RwLock, time::Duration};
+#[cfg(unix)]
+use tokio::net::UnixStream;
 use tonic::transport::channel::{Channel, Endpoint};
-use tracing::debug;
+#[cfg(unix)]
+use tower::service_fn;
+use tracing::{debug, error};
 use tracing_appender::non_blocking::WorkerGuard;
 use tracing_subscriber::{
     fmt, fmt::writer::MakeWriterExt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter,
@@ -16,10 +21,129 @@ use tracing_subscriber::{
 
 use super::config::Config;
 
-pub fn setup_client() -> Result<DesktopDaemonServiceClient<Channel>, DaemonError> {
-    debug!("Setting up gRPC client");
-    let endpoint = Endpoint::from_shared(DAEMON_BASE_URL)?;
-    let channel = endpoint.connect_lazy();
+// gRPC requests need an URI even when connecting through unix socket, it's otherwise unused
+#[cfg(unix)]
+const UNIX_SOCKET_URI: &str = "http://[::]:54127";
+
+// address of `defguard-service` configured by `service_url` setting
+static SERVICE_URL: RwLock<Option<String>> = RwLock::new(None);
+
+/// Address of `defguard-service`, either `http://host:port` or `unix:///path/to/socket`.
+#[derive(Clone, Debug)]
+pub enum ServiceAddress {
+    Tcp(Url),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl ServiceAddress {
+    pub fn parse(service_url: &str) -> Result<Self, Error> {
+        let url = Url::parse(service_url)
+            .map_err(|err| Error::InvalidUrl(format!("{service_url}: {err}")))?;
+        match url.scheme() {
+            "http" => {
+                if url.host_str().is_none() || url.port_or_known_default().is_none() {
+                    return Err(Error::InvalidUrl(format!(
+                        "{service_url}: missing host or port"
+                    )));
+                }
+                Ok(Self::Tcp(url))
+            }
+            #[cfg(unix)]
+            "unix" => {
+                let path = PathBuf::from(url.path());
+                if !path.is_absolute() || url.path() == "/" {
+                    return Err(Error::InvalidUrl(format!(
+                        "{service_url}: socket path must be absolute"
+                    )));
+                }
+                Ok(Self::Unix(path))
+            }
+            #[cfg(not(unix))]
+            "unix" => Err(Error::Unsupported(
+                "connecting to defguard-service through unix socket".into(),
+            )),
+            scheme => Err(Error::InvalidUrl(format!(
+                "{service_url}: unsupported scheme {scheme}, use http or unix"
+            ))),
+        }
+    }
+
+    /// Socket addresses of TCP service address, unix socket has none.
+    pub fn socket_addrs(&self) -> Result<Vec<SocketAddr>, Error> {
+        match self {
+            Self::Tcp(url) => Ok(url.socket_addrs(|| None)?),
+            #[cfg(unix)]
+            Self::Unix(_) => Ok(Vec::new()),
+        }
+    }
+
+    fn endpoint(&self) -> Result<Endpoint, Error> {
+        let uri = match self {
+            Self::Tcp(url) => url.to_string(),
+            #[cfg(unix)]
+            Self::Unix(_) => UNIX_SOCKET_URI.to_string(),
+        };
+        Endpoint::from_shared(uri).map_err(|err| Error::InvalidUrl(err.to_string()))
+    }
+
+    /// Channel which connects on first request.
+    pub fn connect_lazy(&self) -> Result<Channel, Error> {
+        let endpoint = self.endpoint()?;
+        match self {
+            Self::Tcp(_) => Ok(endpoint.connect_lazy()),
+            #[cfg(unix)]
+            Self::Unix(path) => {
+                let path = path.clone();
+                Ok(endpoint.connect_with_connector_lazy(service_fn(move |_| {
+                    UnixStream::connect(path.clone())
+                })))
+            }
+        }
+    }
+
+    /// Connects right away, failing if service doesn't respond within `timeout`.
+    pub async fn connect(&self, timeout: Duration) -> Result<Channel, Error> {
+        let endpoint = self.endpoint()?.connect_timeout(timeout).timeout(timeout);
+        let channel = match self {
+            Self::Tcp(_) => endpoint.connect().await,
+            #[cfg(unix)]
+            Self::Unix(path) => {
+                let path = path.clone();
+                endpoint
+                    .connect_with_connector(service_fn(move |_| UnixStream::connect(path.clone())))
+                    .await
+            }
+        };
+        channel.map_err(|err| Error::ServiceUnavailable(err.to_string()))
+    }
+}
+
+/// Configured address of `defguard-service`, [`DAEMON_BASE_URL`] by default.
+pub fn service_url() -> String {
+    SERVICE_URL
+        .read()
+        .expect("Failed to lock service URL")
+        .clone()
+        .unwrap_or_else(|| DAEMON_BASE_URL.to_string())
+}
+
+/// Parsed [`service_url`]. Invalid stored URL falls back to [`DAEMON_BASE_URL`].
+pub fn service_address() -> ServiceAddress {
+    let service_url = service_url();
+    ServiceAddress::parse(&service_url).unwrap_or_else(|err| {
+        error!("Invalid defguard-service URL {service_url}, using default: {err}");
+        ServiceAddress::parse(DAEMON_BASE_URL).expect("Invalid default service URL")
+    })
+}
+
+pub fn set_service_url(service_url: &str) {
+    *SERVICE_URL.write().expect("Failed to lock service URL") = Some(service_url.to_string());
+}
+
+pub fn setup_client() -> Result<DesktopDaemonServiceClient<Channel>, Error> {
+    debug!("Setting up gRPC client for {}", service_url());
+    let channel = service_address().connect_lazy()?;
     let client = DesktopDaemonServiceClient::new(channel);
     Ok(client)
 }
@@ -55,3 +179,35 @@ pub fn logging_setup(config: &Config) -> WorkerGuard {
 
     _guard
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_service_address() {
+        assert!(matches!(
+            ServiceAddress::parse(DAEMON_BASE_URL),
+            Ok(ServiceAddress::Tcp(_))
+        ));
+        assert!(matches!(
+            ServiceAddress::parse("https://localhost:54127"),
+            Err(Error::InvalidUrl(_))
+        ));
+        assert!(matches!(
+            ServiceAddress::parse("localhost:54127"),
+            Err(Error::InvalidUrl(_))
+        ));
+        #[cfg(unix)]
+        {
+            assert!(matches!(
+                ServiceAddress::parse("unix:///run/defguard-service.sock"),
+                Ok(ServiceAddress::Unix(path)) if path == PathBuf::from("/run/defguard-service.sock")
+            ));
+            assert!(matches!(
+                ServiceAddress::parse("unix:///"),
+                Err(Error::InvalidUrl(_))
+            ));
+        }
+    }
+}
@@ -1,15 +1,36 @@
+use std::time::Duration;
+
+use serde_json::json;
 use tauri::{
     AppHandle, CustomMenuItem, Manager, State, SystemTrayEvent, SystemTrayMenu, SystemTrayMenuItem,
+    SystemTraySubmenu,
 };
 
-use crate::{appstate::AppState, database::TrayIconTheme, error::Error};
+use crate::{
+    appstate::AppState,
+    commands::{connect, disconnect},
+    database::{Instance, Location, Settings, TrayIconTheme},
+    error::Error,
+    issue_capture::CaptureEntryKind,
+    service::status::query_service_status,
+    ConnectionType,
+};
 
 static SUBSCRIBE_UPDATES_LINK: &str = "https://defguard.net/newsletter";
 static JOIN_COMMUNITY_LINK: &str = "https://matrix.to/#/#defguard:teonite.com";
 static FOLLOW_US_LINK: &str = "https://floss.social/@defguard";
+// prefix of menu item IDs toggling location connection, followed by location ID
+static LOCATION_ITEM_PREFIX: &str = "location-";
+// how often `defguard-service` availability is checked for tray icon status
+const SERVICE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
 
+/// Static tray menu, used before locations are loaded from database.
 #[must_use]
 pub fn create_tray_menu() -> SystemTrayMenu {
+    add_app_items(SystemTrayMenu::new())
+}
+
+fn add_app_items(menu: SystemTrayMenu) -> SystemTrayMenu {
     let quit = CustomMenuItem::new("quit".to_string(), "Quit");
     let show = CustomMenuItem::new("show".to_string(), "Show");
     let hide = CustomMenuItem::new("hide".to_string(), "Hide");
@@ -17,8 +38,7 @@ pub fn create_tray_menu() -> SystemTrayMenu {
         CustomMenuItem::new("subscribe_updates".to_string(), "Subscribe for updates");
     let join_community = CustomMenuItem::new("join_community".to_string(), "Join our Community");
     let follow_us = CustomMenuItem::new("follow_us".to_string(), "Follow us");
-    SystemTrayMenu::new()
-        .add_item(show)
+    menu.add_item(show)
         .add_item(hide)
         .add_native_item(SystemTrayMenuItem::Separator)
         .add_item(subscribe_updates)
@@ -28,6 +48,104 @@ pub fn create_tray_menu() -> SystemTrayMenu {
         .add_item(quit)
 }
 
+/// Builds tray menu with submenu of every instance, listing its locations.
+/// Connected locations are checked and clicking a location toggles its connection.
+async fn create_locations_tray_menu(app: &AppHandle) -> Result<SystemTrayMenu, Error> {
+    let state = app.state::<AppState>();
+    let pool = state.get_pool();
+    let active_locations = state.get_connection_id_by_type(&ConnectionType::Location);
+    let mut menu = SystemTrayMenu::new();
+    let instances = Instance::all(&pool).await?;
+    for instance in &instances {
+        let Some(instance_id) = instance.id else {
+            continue;
+        };
+        let mut submenu = SystemTrayMenu::new();
+        for location in Location::find_by_instance_id(&pool, instance_id).await? {
+            let Some(location_id) = location.id else {
+                continue;
+            };
+            let mut item = CustomMenuItem::new(
+                format!("{LOCATION_ITEM_PREFIX}{location_id}"),
+                location.display_name.unwrap_or(location.name),
+            );
+            if active_locations.contains(&location_id) {
+                item = item.selected();
+            }
+            submenu = submenu.add_item(item);
+        }
+        menu = menu.add_submenu(SystemTraySubmenu::new(instance.name.clone(), submenu));
+    }
+    if !instances.is_empty() {
+        menu = menu.add_native_item(SystemTrayMenuItem::Separator);
+    }
+    Ok(add_app_items(menu))
+}
+
+/// Rebuild tray menu, so it reflects current instances and connection states.
+pub async fn reload_tray_menu(app: &AppHandle) -> Result<(), Error> {
+    let menu = create_locations_tray_menu(app).await?;
+    app.tray_handle().set_menu(menu)?;
+    debug!("Tray menu reloaded");
+    Ok(())
+}
+
+/// Rebuild tray menu and update tray icon status in the background. Called whenever
+/// `connection-changed` or `instance-update` event is emitted, since these only reach webviews.
+pub fn refresh_tray(app: &AppHandle) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        if let Err(err) = reload_tray_menu(&app).await {
+            error!("Failed to reload tray menu: {err}");
+        }
+        if let Err(err) = update_tray_icon(&app).await {
+            error!("Failed to update tray icon: {err}");
+        }
+    });
+}
+
+/// Connect or disconnect location selected in tray menu.
+fn toggle_location(app: &AppHandle, location_id: i64) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let state = app.state::<AppState>();
+        let result = if state
+            .find_connection(location_id, ConnectionType::Location)
+            .is_some()
+        {
+            info!("Disconnecting location {location_id} from tray menu");
+            disconnect(location_id, ConnectionType::Location, None, app.clone()).await
+        } else {
+            match Location::find_by_id(&state.get_pool(), location_id).await {
+                // preshared key can only be obtained through MFA initiated in main window
+                Ok(Some(location)) if location.mfa_enabled => {
+                    info!("Location {location} requires MFA, opening main window");
+                    show_main_window(&app);
+                    Ok(())
+                }
+                Ok(_) => {
+                    info!("Connecting location {location_id} from tray menu");
+                    connect(
+                        location_id,
+                        ConnectionType::Location,
+                        None,
+                        None,
+                        None,
+                        app.clone(),
+                    )
+                    .await
+                }
+                Err(err) => Err(err.into()),
+            }
+        };
+        if let Err(err) = result {
+            error!("Failed to toggle connection of location {location_id} from tray menu: {err}");
+        }
+        // native menu toggles checkmark on click, so it has to be restored if connection failed
+        refresh_tray(&app);
+    });
+}
+
 fn show_main_window(app: &AppHandle) {
     if let Some(main_window) = app.get_window("main") {
         // if this fails tauri has a problem
@@ -57,45 +175,121 @@ pub fn handle_tray_event(app: &AppHandle, event: SystemTrayEvent) {
                 }
             }
         }
-        SystemTrayEvent::MenuItemClick { id, .. } => match id.as_str() {
-            "quit" => {
-                info!("Received QUIT request. Initiating shutdown...");
-                let app_state: State<AppState> = app.state();
-                tokio::task::block_in_place(|| {
-                    tokio::runtime::Handle::current().block_on(async {
-                        let _ = app_state.close_all_connections().await;
-                        app.exit(0);
+        SystemTrayEvent::MenuItemClick { id, .. } => {
+            // tray actions are recorded like commands invoked from the frontend
+            app.state::<AppState>().record_capture(
+                CaptureEntryKind::Command,
+                "tray_menu_click",
+                json!({ "id": id }),
+            );
+            match id.as_str() {
+                "quit" => {
+                    info!("Received QUIT request. Initiating shutdown...");
+                    let app_state: State<AppState> = app.state();
+                    tokio::task::block_in_place(|| {
+                        tokio::runtime::Handle::current().block_on(async {
+                            app_state.shutdown_connections().await;
+                            app.exit(0);
+                        });
                     });
-                });
-            }
-            "show" => show_main_window(app),
-            "hide" => {
-                if let Some(main_window) = app.get_window("main") {
-                    if main_window
-                        .is_visible()
-                        .expect("Failed to check main window visibility")
+                }
+                "show" => show_main_window(app),
+                "hide" => {
+                    if let Some(main_window) = app.get_window("main") {
+                        if main_window
+                            .is_visible()
+                            .expect("Failed to check main window visibility")
+                        {
+                            main_window.hide().expect("Failed to hide main window");
+                        }
+                    }
+                }
+                "subscribe_updates" => {
+                    let _ = webbrowser::open(SUBSCRIBE_UPDATES_LINK);
+                }
+                "join_community" => {
+                    let _ = webbrowser::open(JOIN_COMMUNITY_LINK);
+                }
+                "follow_us" => {
+                    let _ = webbrowser::open(FOLLOW_US_LINK);
+                }
+                id => {
+                    if let Some(location_id) = id
+                        .strip_prefix(LOCATION_ITEM_PREFIX)
+                        .and_then(|location_id| location_id.parse().ok())
                     {
-                        main_window.hide().expect("Failed to hide main window");
+                        toggle_location(app, location_id);
                     }
                 }
             }
-            "subscribe_updates" => {
-                let _ = webbrowser::open(SUBSCRIBE_UPDATES_LINK);
-            }
-            "join_community" => {
-                let _ = webbrowser::open(JOIN_COMMUNITY_LINK);
-            }
-            "follow_us" => {
-                let _ = webbrowser::open(FOLLOW_US_LINK);
-            }
-            _ => {}
-        },
+        }
         _ => {}
     }
 }
 
-pub fn configure_tray_icon(app: &AppHandle, theme: &TrayIconTheme) -> Result<(), Error> {
-    let resource_str = format!("resources/icons/tray-32x32-{}.png", theme.as_ref());
+/// Connection status shown as a badge on tray icon.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrayIconStatus {
+    Disconnected,
+    Connected,
+    // `defguard-service` is unreachable
+    Error,
+}
+
+impl TrayIconStatus {
+    #[must_use]
+    pub fn new(connection_count: usize, service_running: bool) -> Self {
+        if !service_running {
+            Self::Error
+        } else if connection_count > 0 {
+            Self::Connected
+        } else {
+            Self::Disconnected
+        }
+    }
+
+    // suffix of icon resource name
+    fn icon_suffix(self) -> &'static str {
+        match self {
+            Self::Disconnected => "",
+            Self::Connected => "-connected",
+            Self::Error => "-error",
+        }
+    }
+}
+
+/// Set tray icon according to configured theme and current connection status.
+pub async fn update_tray_icon(app: &AppHandle) -> Result<(), Error> {
+    let state = app.state::<AppState>();
+    let settings = Settings::get(&state.get_pool()).await?;
+    let status = TrayIconStatus::new(
+        state.active_connection_count(),
+        query_service_status().await.running,
+    );
+    configure_tray_icon(app, &settings.tray_icon_theme, status)
+}
+
+/// Periodically update tray icon, so it shows when `defguard-service` becomes unreachable.
+pub async fn tray_icon_loop(app: AppHandle) {
+    let mut interval = tokio::time::interval(SERVICE_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(err) = update_tray_icon(&app).await {
+            error!("Failed to update tray icon: {err}");
+        }
+    }
+}
+
+pub fn configure_tray_icon(
+    app: &AppHandle,
+    theme: &TrayIconTheme,
+    status: TrayIconStatus,
+) -> Result<(), Error> {
+    let resource_str = format!(
+        "resources/icons/tray-32x32-{}{}.png",
+        theme.as_ref(),
+        status.icon_suffix()
+    );
     debug!("Tray icon loading from {:?}", &resource_str);
     match app.path_resolver().resolve_resource(&resource_str) {
         Some(icon_path) => {
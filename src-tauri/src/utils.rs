@@ -1,76 +1,178 @@
 use std::{
-    net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, ToSocketAddrs},
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, UdpSocket},
     path::PathBuf,
     process::Command,
     str::FromStr,
+    time::{Duration, Instant, SystemTime},
 };
-use tauri::AppHandle;
+use tauri::{api::notification::Notification, AppHandle};
 
 use defguard_wireguard_rs::{host::Peer, key::Key, net::IpAddrMask, InterfaceConfiguration};
 use sqlx::query;
 use tauri::Manager;
-use tonic::{codegen::tokio_stream::StreamExt, transport::Channel};
+use tokio_util::sync::CancellationToken;
+use tonic::{codegen::tokio_stream::StreamExt, transport::Channel, Status};
 
 use crate::{
     appstate::AppState,
-    commands::{LocationInterfaceDetails, Payload},
+    commands::{connect, LocationInterfaceDetails, Payload},
+    connection_state::ConnectionState,
     database::{
         models::location::peer_to_location_stats, models::tunnel::peer_to_tunnel_stats,
-        ActiveConnection, Connection, DbPool, Location, Tunnel, TunnelConnection, WireguardKeys,
+        ActiveConnection, DbPool, DisconnectReason, EndpointResolution, Instance, KeyPair,
+        LifetimeTransfer, Location, PeakThroughput, Settings, Tunnel, WireguardKeys,
     },
     error::Error,
+    handshake_probe::{probe_handshake, HANDSHAKE_PROBE_TIMEOUT},
+    issue_capture::{emit_event, CaptureEntryKind},
+    log_limiter::RateLimitedLog,
     service::{
-        log_watcher::spawn_log_watcher_task,
+        log_watcher::{spawn_log_watcher_task, LogFormat},
         proto::{
             desktop_daemon_service_client::DesktopDaemonServiceClient, CreateInterfaceRequest,
             ReadInterfaceDataRequest, RemoveInterfaceRequest,
         },
+        status::set_fallback_dns,
+        utils::service_address,
     },
+    tray::refresh_tray,
     ConnectionType,
 };
+use if_addrs::{get_if_addrs, IfAddr};
 use local_ip_address::local_ip;
-use tracing::Level;
+use serde::Serialize;
+use serde_json::json;
 
 pub static IS_MACOS: bool = cfg!(target_os = "macos");
 pub static STATS_PERIOD: u64 = 60;
 pub static DEFAULT_ROUTE: &str = "0.0.0.0/0";
+pub static IPV6_DEFAULT_ROUTE: &str = "::/0";
+// range of MTU values accepted for location interfaces (minimum required by IPv4)
+const MIN_MTU: u32 = 576;
+const MAX_MTU: u32 = 65535;
+// number of attempts to connect location on startup and delay before first retry
+const AUTOCONNECT_ATTEMPTS: u32 = 5;
+const AUTOCONNECT_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+// number of attempts to remove interface on disconnect and delay before first retry
+const REMOVE_INTERFACE_ATTEMPTS: u32 = 3;
+const REMOVE_INTERFACE_INITIAL_BACKOFF: Duration = Duration::from_millis(250);
+// longest interface name accepted by the kernel (`IFNAMSIZ` without terminating null byte)
+#[cfg(not(target_os = "macos"))]
+const MAX_INTERFACE_NAME_LENGTH: usize = 15;
+// placeholders of interface name template
+#[cfg(not(target_os = "macos"))]
+const TEMPLATE_LOCATION: &str = "{location}";
+#[cfg(not(target_os = "macos"))]
+const TEMPLATE_INSTANCE: &str = "{instance}";
+// room kept in templated names for numeric suffix of colliding names, e.g. `-2`
+#[cfg(not(target_os = "macos"))]
+const INTERFACE_NAME_SUFFIX_LENGTH: usize = 3;
+// port used when endpoint doesn't specify one
+const DEFAULT_WIREGUARD_PORT: u16 = 51820;
+// interface data updates arriving slightly earlier than stats interval are still collected
+const STATS_INTERVAL_TOLERANCE: Duration = Duration::from_millis(500);
+// how often location endpoint latency is measured
+const LATENCY_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+// time to wait for interface data when reading peer endpoint before disconnecting
+const ENDPOINT_READ_TIMEOUT: Duration = Duration::from_secs(2);
+// time to wait for interface data when checking if interface exists
+#[cfg(not(target_os = "macos"))]
+const INTERFACE_PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+// time allowed for public IP request through the tunnel
+const PUBLIC_IP_TIMEOUT: Duration = Duration::from_secs(10);
+// repeated error of stats thread is logged at most once per this window
+const STATS_ERROR_LOG_WINDOW: Duration = Duration::from_secs(60);
+// prefix of firewall chain with kill switch rules, followed by interface name
+#[cfg(target_os = "linux")]
+static KILL_SWITCH_CHAIN_PREFIX: &str = "defguard-ks-";
+// prefix of firewall chain blocking DNS outside of the tunnel, followed by interface name
+#[cfg(target_os = "linux")]
+static TUNNEL_DNS_CHAIN_PREFIX: &str = "defguard-dns-";
+// resolver configuration listing system DNS servers
+#[cfg(unix)]
+static RESOLV_CONF: &str = "/etc/resolv.conf";
+// public IPv6 address used to check if host has a native IPv6 route
+static IPV6_PROBE_ADDRESS: &str = "[2001:4860:4860::8888]:53";
+// characters allowed in DNS-over-HTTPS URL besides ASCII letters and digits
+static DOH_URL_CHARS: &str = "-._~:/?=&%{}[]";
+
+/// Networks routed through location tunnel: default route when routing all traffic,
+/// otherwise allowed IPs together with extra routes and routes of forced tunnel DNS.
+pub fn location_allowed_ips(location: &Location) -> Result<Vec<String>, Error> {
+    // extra routes are covered by the default route when routing all traffic
+    if location.route_all_traffic {
+        debug!("Using all traffic routing: {DEFAULT_ROUTE}");
+        return Ok(vec![DEFAULT_ROUTE.into()]);
+    }
+    debug!("Using predefined location traffic");
+    let allowed_ips = location
+        .allowed_ips
+        .split(',')
+        .chain(
+            location
+                .extra_routes
+                .as_deref()
+                .unwrap_or_default()
+                .split(','),
+        )
+        .map(str::trim)
+        .filter(|network| !network.is_empty())
+        .map(str::to_string)
+        .chain(tunnel_dns_routes(location)?)
+        .collect();
+    Ok(allowed_ips)
+}
+
+/// Interface created for location
+#[derive(Debug)]
+pub struct InterfaceSetup {
+    pub endpoint: SocketAddr,
+    // public key of key pair interface was created with, previous key during key migration
+    pub pubkey: String,
+}
 
 /// Setup client interface
 pub async fn setup_interface(
     location: &Location,
     interface_name: String,
     preshared_key: Option<String>,
+    profile_id: Option<i64>,
     pool: &DbPool,
     mut client: DesktopDaemonServiceClient<Channel>,
-) -> Result<(), Error> {
-    if let Some(keys) = WireguardKeys::find_by_instance_id(pool, location.instance_id).await? {
+    cancel: &CancellationToken,
+) -> Result<InterfaceSetup, Error> {
+    validate_interface_name(&interface_name)?;
+    let keys = match profile_id {
+        Some(profile_id) => WireguardKeys::find_by_id(pool, profile_id)
+            .await?
+            .filter(|keys| keys.instance_id == location.instance_id),
+        None => WireguardKeys::find_by_instance_id(pool, location.instance_id).await?,
+    };
+    if let Some(mut keys) = keys {
         // prepare peer config
         debug!("Decoding location public key: {}.", location.pubkey);
         let peer_key: Key = Key::from_str(&location.pubkey)?;
         let mut peer = Peer::new(peer_key);
 
         debug!("Parsing location endpoint: {}", location.endpoint);
-        let endpoint: SocketAddr = location.endpoint.to_socket_addrs()?.next().expect("Unable to resolve domain");
+        let resolution = Settings::endpoint_resolution(pool).await;
+        let endpoint = location_endpoint(location, &resolution).await?;
         peer.endpoint = Some(endpoint);
+        let key_pair = select_key_pair(&mut keys, location, endpoint, pool).await?;
         peer.persistent_keepalive_interval = Some(25);
 
+        // key received after MFA authorization replaces the one configured for location
+        let preshared_key =
+            preshared_key.or_else(|| location.preshared_key.as_ref().map(|key| key.0.clone()));
         if let Some(psk) = preshared_key {
             let peer_psk = Key::from_str(&psk)?;
             peer.preshared_key = Some(peer_psk);
         }
 
         debug!("Parsing location allowed ips: {}", location.allowed_ips);
-        let allowed_ips: Vec<String> = if location.route_all_traffic {
-            debug!("Using all traffic routing: {DEFAULT_ROUTE}");
-            vec![DEFAULT_ROUTE.into()]
-        } else {
-            debug!("Using predefined location traffic");
-            location
-                .allowed_ips
-                .split(',')
-                .map(str::to_string)
-                .collect()
-        };
+        let allowed_ips = location_allowed_ips(location)?;
+        let allowed_ips = exclude_control_channel(allowed_ips)?;
         for allowed_ip in &allowed_ips {
             match IpAddrMask::from_str(allowed_ip) {
                 Ok(addr) => {
@@ -89,25 +191,65 @@ pub async fn setup_interface(
         if let Some(port) = find_random_free_port() {
             let interface_config = InterfaceConfiguration {
                 name: interface_name,
-                prvkey: keys.prvkey,
-                address: location.address.clone(),
+                prvkey: key_pair.prvkey,
+                // device registered with profile key has its own address
+                address: keys
+                    .address
+                    .clone()
+                    .unwrap_or_else(|| location.address.clone()),
                 port: port.into(),
                 peers: vec![peer.clone()],
             };
-            debug!("Creating interface {interface_config:#?}");
+            // full configuration contains keys, so it's not logged
+            debug!(
+                "Creating interface {} with address {}",
+                interface_config.name, interface_config.address
+            );
+            let (dns_post_up, _) = encrypted_dns_commands(location, &interface_config.name)?;
+            let (kill_switch_post_up, kill_switch_pre_down) =
+                kill_switch_commands(location, &interface_config.name, Some(endpoint))?;
+            let (tunnel_dns_post_up, tunnel_dns_pre_down) =
+                tunnel_dns_commands(location, &interface_config.name)?;
             let request = CreateInterfaceRequest {
                 config: Some(interface_config.clone().into()),
                 allowed_ips,
                 dns: location.dns.clone(),
                 pre_up: None,
-                post_up: None,
+                post_up: encode_commands([
+                    mtu_command(location, &interface_config.name),
+                    dns_search_command(location, &interface_config.name)?,
+                    dns_post_up,
+                    kill_switch_post_up,
+                    tunnel_dns_post_up,
+                ]),
             };
+            // endpoint resolution may take a while, don't create interface if user gave up
+            if cancel.is_cancelled() {
+                info!(
+                    "Not creating interface {}, connection cancelled",
+                    interface_config.name
+                );
+                return Err(Error::Cancelled);
+            }
             if let Err(error) = client.create_interface(request).await {
                 error!("Failed to create interface: {error}");
+                let pre_down = encode_commands([kill_switch_pre_down, tunnel_dns_pre_down]);
+                if pre_down.is_some() {
+                    // make sure traffic isn't left blocked by partially configured interface
+                    let request = RemoveInterfaceRequest {
+                        interface_name: interface_config.name.clone(),
+                        pre_down,
+                        post_down: None,
+                    };
+                    let _ = client.remove_interface(request).await;
+                }
                 Err(Error::InternalError)
             } else {
-                info!("Created interface {interface_config:#?}");
-                Ok(())
+                info!("Created interface {}", interface_config.name);
+                Ok(InterfaceSetup {
+                    endpoint,
+                    pubkey: key_pair.pubkey,
+                })
             }
         } else {
             error!("Error finding free port");
@@ -119,10 +261,173 @@ pub async fn setup_interface(
     }
 }
 
-/// Helper function to remove whitespace from location name
-#[must_use]
-pub fn remove_whitespace(s: &str) -> String {
-    s.chars().filter(|c| !c.is_whitespace()).collect()
+/// Selects key pair used to connect to location. While previous key pair is kept for key
+/// migration, handshake with current key is probed first and previous pair is used only if
+/// the server doesn't accept current key. Previous key pair is dropped once current is accepted.
+async fn select_key_pair(
+    keys: &mut WireguardKeys,
+    location: &Location,
+    endpoint: SocketAddr,
+    pool: &DbPool,
+) -> Result<KeyPair, Error> {
+    let current = keys.key_pair();
+    let Some(previous) = keys.previous_key_pair() else {
+        return Ok(current);
+    };
+    if probe_key_pair(endpoint, &current, &location.pubkey).await {
+        info!("Current key accepted by location {location}, dropping previous key");
+        keys.clear_previous(pool).await?;
+        return Ok(current);
+    }
+    if probe_key_pair(endpoint, &previous, &location.pubkey).await {
+        warn!("Current key not accepted by location {location}, using previous key");
+        return Ok(previous);
+    }
+    warn!("No key accepted by location {location}, using current key");
+    Ok(current)
+}
+
+async fn probe_key_pair(endpoint: SocketAddr, key_pair: &KeyPair, server_pubkey: &str) -> bool {
+    let prvkey = key_pair.prvkey.clone();
+    let server_pubkey = server_pubkey.to_string();
+    let result = tokio::task::spawn_blocking(move || {
+        probe_handshake(endpoint, &prvkey, &server_pubkey, HANDSHAKE_PROBE_TIMEOUT)
+    })
+    .await;
+    match result {
+        Ok(Ok(accepted)) => accepted,
+        Ok(Err(err)) => {
+            warn!("Handshake probe with key {} failed: {err}", key_pair.pubkey);
+            false
+        }
+        Err(err) => {
+            warn!("Handshake probe task failed: {err}");
+            false
+        }
+    }
+}
+
+/// Get location endpoint address. If endpoint IP override is set it's used
+/// together with endpoint port instead of resolving endpoint hostname.
+/// In sticky resolution address which connected the last time is preferred while
+/// hostname still resolves to it.
+pub async fn location_endpoint(
+    location: &Location,
+    resolution: &EndpointResolution,
+) -> Result<SocketAddr, Error> {
+    if let Some(ip) = &location.endpoint_ip_override {
+        let (_, port) = split_endpoint(&location.endpoint)?;
+        let endpoint = SocketAddr::new(IpAddr::from_str(ip)?, port);
+        debug!(
+            "Using endpoint IP override {endpoint} for {}",
+            location.endpoint
+        );
+        return Ok(endpoint);
+    }
+    let sticky = match resolution {
+        EndpointResolution::Sticky => location
+            .sticky_endpoint
+            .as_deref()
+            .and_then(|address| address.parse().ok()),
+        _ => None,
+    };
+    resolve_endpoint(&location.endpoint, resolution, sticky).await
+}
+
+/// Splits endpoint into host and port. Supports `host:port`, `ipv4:port`, `[ipv6]:port`
+/// and bare IPv6 addresses. Default WireGuard port is used if port is missing.
+fn split_endpoint(endpoint: &str) -> Result<(&str, u16), Error> {
+    let endpoint = endpoint.trim();
+    let invalid = || Error::ConfigParseError(format!("Invalid endpoint {endpoint}"));
+    // bare IPv6 address
+    if endpoint.parse::<Ipv6Addr>().is_ok() {
+        return Ok((endpoint, DEFAULT_WIREGUARD_PORT));
+    }
+    let (host, port) = if let Some(rest) = endpoint.strip_prefix('[') {
+        let (host, rest) = rest.split_once(']').ok_or_else(invalid)?;
+        match rest {
+            "" => (host, None),
+            rest => (host, Some(rest.strip_prefix(':').ok_or_else(invalid)?)),
+        }
+    } else {
+        match endpoint.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (endpoint, None),
+        }
+    };
+    if host.is_empty() {
+        return Err(invalid());
+    }
+    let port = match port {
+        Some(port) => port.parse().map_err(|_| invalid())?,
+        None => DEFAULT_WIREGUARD_PORT,
+    };
+    Ok((host, port))
+}
+
+/// Picks endpoint address from resolved addresses, preferring given address family
+/// when hostname resolves to both IPv4 and IPv6 addresses.
+fn select_endpoint_address(addresses: &[SocketAddr], prefer_ipv6: bool) -> Option<SocketAddr> {
+    addresses
+        .iter()
+        .find(|address| address.is_ipv6() == prefer_ipv6)
+        .or_else(|| addresses.first())
+        .copied()
+}
+
+/// Resolves WireGuard endpoint into socket address. `sticky` address is used if endpoint
+/// still resolves to it.
+async fn resolve_endpoint(
+    endpoint: &str,
+    resolution: &EndpointResolution,
+    sticky: Option<SocketAddr>,
+) -> Result<SocketAddr, Error> {
+    let (host, port) = split_endpoint(endpoint)?;
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, port));
+    }
+    let addresses: Vec<SocketAddr> = match tokio::net::lookup_host((host, port)).await {
+        Ok(addresses) => addresses.collect(),
+        Err(err) => {
+            warn!("Failed to resolve endpoint {endpoint}: {err}");
+            return Err(Error::EndpointResolution(host.into()));
+        }
+    };
+    if let Some(sticky) = sticky.filter(|sticky| addresses.contains(sticky)) {
+        debug!("Using sticky address {sticky} of endpoint {endpoint}");
+        return Ok(sticky);
+    }
+    let prefer_ipv6 = match resolution {
+        EndpointResolution::Ipv4First => false,
+        EndpointResolution::Ipv6First => true,
+        EndpointResolution::Auto | EndpointResolution::Sticky => has_native_ipv6(),
+    };
+    let address = select_endpoint_address(&addresses, prefer_ipv6)
+        .ok_or_else(|| Error::EndpointResolution(host.into()))?;
+    debug!("Resolved endpoint {endpoint} to {address}");
+    Ok(address)
+}
+
+/// Returns local address used to reach given endpoint, so it matches endpoint address family.
+fn local_address_for(endpoint: SocketAddr) -> Result<IpAddr, Error> {
+    let unspecified = match endpoint {
+        SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    // connecting an UDP socket doesn't send any packets
+    let address = UdpSocket::bind(SocketAddr::new(unspecified, 0))
+        .and_then(|socket| {
+            socket.connect(endpoint)?;
+            socket.local_addr()
+        })
+        .map(|address| address.ip());
+    match address {
+        Ok(address) => Ok(address),
+        Err(err) => {
+            warn!("Failed to find local address used for {endpoint}: {err}");
+            Ok(local_ip()?)
+        }
+    }
 }
 
 fn find_random_free_port() -> Option<u16> {
@@ -162,11 +467,251 @@ pub fn get_interface_name() -> String {
     "utun0".into()
 }
 
+/// Checks if character may be used in interface name. Interface names are put into commands
+/// run with root privileges, so only letters, digits, `-`, `_` and `.` are allowed.
+fn is_interface_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')
+}
+
+/// Fails if interface name is empty or contains characters which aren't allowed in it.
+pub fn validate_interface_name(interface_name: &str) -> Result<(), Error> {
+    if interface_name.is_empty() || !interface_name.chars().all(is_interface_name_char) {
+        error!("Invalid interface name {interface_name:?}");
+        return Err(Error::CommandError(format!(
+            "Invalid interface name {interface_name:?}"
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "macos"))]
+/// Removes characters which aren't allowed in interface names.
+fn sanitize_interface_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| is_interface_name_char(*c))
+        .collect()
+}
+
 #[cfg(not(target_os = "macos"))]
-/// Returns interface name for location
+/// Returns interface name for location, falls back to `wg` if no character of name is allowed.
 #[must_use]
 pub fn get_interface_name(name: &str) -> String {
-    remove_whitespace(name)
+    let interface_name = sanitize_interface_name(name);
+    if interface_name.is_empty() {
+        "wg".into()
+    } else {
+        interface_name
+    }
+}
+
+/// Checks interface name template, e.g. `dg-{location}`. Placeholders are replaced with names
+/// of location and its instance and the result is truncated to the kernel limit, so text outside
+/// of placeholders has to leave room for them. Interfaces are always named `utunN` on macOS.
+pub fn validate_interface_name_template(template: &str) -> Result<(), Error> {
+    #[cfg(not(target_os = "macos"))]
+    {
+        if !template.contains(TEMPLATE_LOCATION) {
+            return Err(Error::CommandError(format!(
+                "Interface name template must contain {TEMPLATE_LOCATION}"
+            )));
+        }
+        let fixed = template
+            .replace(TEMPLATE_LOCATION, "")
+            .replace(TEMPLATE_INSTANCE, "");
+        if let Some(invalid) = fixed.chars().find(|c| !is_interface_name_char(*c)) {
+            return Err(Error::CommandError(format!(
+                "Interface name template contains invalid character {invalid:?}"
+            )));
+        }
+        // every placeholder takes at least one character
+        let placeholders = template.matches(TEMPLATE_LOCATION).count()
+            + template.matches(TEMPLATE_INSTANCE).count();
+        if fixed.len() + placeholders + INTERFACE_NAME_SUFFIX_LENGTH > MAX_INTERFACE_NAME_LENGTH {
+            return Err(Error::CommandError(format!(
+                "Interface name template {template} doesn't fit in {MAX_INTERFACE_NAME_LENGTH} characters"
+            )));
+        }
+        Ok(())
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        let _ = template;
+        Err(Error::Unsupported("interface name template".into()))
+    }
+}
+
+#[cfg(not(target_os = "macos"))]
+/// Returns interface name of location rendered from template.
+fn render_interface_name_template(
+    template: &str,
+    location_name: &str,
+    instance_name: &str,
+) -> String {
+    template
+        .replace(TEMPLATE_LOCATION, &sanitize_interface_name(location_name))
+        .replace(TEMPLATE_INSTANCE, &sanitize_interface_name(instance_name))
+}
+
+/// Interface name chosen for location along with names of locations and tunnels it would
+/// otherwise collide with.
+#[derive(Debug, Serialize)]
+pub struct ResolvedInterfaceName {
+    pub interface_name: String,
+    pub collisions: Vec<String>,
+}
+
+#[cfg(not(target_os = "macos"))]
+/// Returns interface name of location, either stored one or derived from location name.
+#[must_use]
+pub fn location_interface_name(location: &Location) -> String {
+    location
+        .interface_name
+        .clone()
+        .unwrap_or_else(|| get_interface_name(&location.name))
+}
+
+/// Interface name of location connected with key profile, so it doesn't collide
+/// with interface of the default key pair.
+#[cfg(not(target_os = "macos"))]
+fn profile_interface_name(interface_name: &str, profile_id: i64) -> String {
+    let suffix = format!("-p{profile_id}");
+    format!(
+        "{}{suffix}",
+        truncate_interface_name(interface_name, MAX_INTERFACE_NAME_LENGTH - suffix.len())
+    )
+}
+
+#[cfg(not(target_os = "macos"))]
+/// Truncates interface name to `max_length` bytes, keeping whole characters.
+fn truncate_interface_name(name: &str, max_length: usize) -> String {
+    let mut length = 0;
+    name.chars()
+        .take_while(|c| {
+            length += c.len_utf8();
+            length <= max_length
+        })
+        .collect()
+}
+
+#[cfg(not(target_os = "macos"))]
+/// Picks interface name for location, which is unique across all locations and tunnels.
+/// Names derived from location names may collide, e.g. when truncated to fit the kernel limit,
+/// so a numeric suffix is appended. Chosen name is stored, so it's stable across reconnects.
+pub async fn resolve_interface_name(
+    state: &AppState,
+    location: &Location,
+) -> Result<ResolvedInterfaceName, Error> {
+    if let Some(interface_name) = &location.interface_name {
+        // names stored by older versions may contain characters which are no longer allowed
+        if validate_interface_name(interface_name).is_ok() {
+            return Ok(ResolvedInterfaceName {
+                interface_name: interface_name.clone(),
+                collisions: Vec::new(),
+            });
+        }
+        warn!("Stored interface name of location {location} is invalid, picking a new one");
+    }
+    let pool = state.get_pool();
+    let mut taken: Vec<(String, String)> = Location::all(&pool)
+        .await?
+        .iter()
+        .filter(|other| other.id != location.id)
+        .map(|other| (location_interface_name(other), format!("location {other}")))
+        .collect();
+    taken.extend(Tunnel::all(&pool).await?.iter().map(|tunnel| {
+        (
+            get_interface_name(&tunnel.name),
+            format!("tunnel {}", tunnel.name),
+        )
+    }));
+    let is_taken = |name: &str| taken.iter().any(|(taken_name, _)| taken_name == name);
+
+    let base_name = match Settings::get(&pool).await?.interface_name_template {
+        Some(template) => {
+            let instance_name = crate::database::Instance::find_by_id(&pool, location.instance_id)
+                .await?
+                .map(|instance| instance.name)
+                .unwrap_or_default();
+            let rendered =
+                render_interface_name_template(&template, &location.name, &instance_name);
+            // placeholders may render empty if no character of the name is allowed
+            if validate_interface_name(&rendered).is_ok() {
+                rendered
+            } else {
+                warn!("Interface name {rendered:?} rendered for location {location} is invalid, using default name");
+                get_interface_name(&location.name)
+            }
+        }
+        None => get_interface_name(&location.name),
+    };
+    let mut interface_name = truncate_interface_name(&base_name, MAX_INTERFACE_NAME_LENGTH);
+    let collisions: Vec<String> = taken
+        .iter()
+        .filter(|(taken_name, _)| *taken_name == interface_name)
+        .map(|(_, owner)| owner.clone())
+        .collect();
+    if !collisions.is_empty() {
+        warn!(
+            "Interface name {interface_name} of location {location} collides with: {}",
+            collisions.join(", ")
+        );
+        let mut index = 2;
+        interface_name = loop {
+            let suffix = format!("-{index}");
+            let candidate = format!(
+                "{}{suffix}",
+                truncate_interface_name(&base_name, MAX_INTERFACE_NAME_LENGTH - suffix.len())
+            );
+            if !is_taken(&candidate) {
+                break candidate;
+            }
+            index += 1;
+        };
+        info!("Using interface name {interface_name} for location {location}");
+    }
+    validate_interface_name(&interface_name)?;
+    if let Some(location_id) = location.id {
+        Location::set_interface_name(&pool, location_id, Some(&interface_name)).await?;
+    }
+    Ok(ResolvedInterfaceName {
+        interface_name,
+        collisions,
+    })
+}
+
+#[cfg(target_os = "macos")]
+/// Picks next `utun` interface, which isn't used by the system nor by other active connections.
+/// Interface names are assigned by the system on macOS, so they aren't stored.
+pub async fn resolve_interface_name(
+    state: &AppState,
+    location: &Location,
+) -> Result<ResolvedInterfaceName, Error> {
+    let active: Vec<String> = state
+        .active_connections
+        .lock()
+        .map_err(|_| Error::MutexError)?
+        .iter()
+        .map(|connection| connection.interface_name.clone())
+        .collect();
+    let mut interfaces: Vec<String> = nix::net::if_::if_nameindex()
+        .map(|interfaces| {
+            interfaces
+                .iter()
+                .map(|interface| interface.name().to_string_lossy().to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+    interfaces.extend(active);
+    let interface_name = (0..u32::MAX)
+        .map(|index| format!("utun{index}"))
+        .find(|name| !interfaces.contains(name))
+        .unwrap_or_else(|| "utun0".into());
+    debug!("Using interface name {interface_name} for location {location}");
+    Ok(ResolvedInterfaceName {
+        interface_name,
+        collisions: Vec::new(),
+    })
 }
 
 fn is_port_free(port: u16) -> bool {
@@ -180,57 +725,363 @@ fn is_port_free(port: u16) -> bool {
     }
 }
 
+/// Measures round-trip time to given address with system `ping` command.
+/// Returns `None` if host doesn't respond, e.g. when ICMP is blocked.
+fn ping(address: IpAddr) -> Option<i64> {
+    #[cfg(target_os = "windows")]
+    let output = Command::new("ping")
+        .args(["-n", "1", "-w", "1000", &address.to_string()])
+        .output();
+    #[cfg(target_os = "macos")]
+    let output = Command::new(if address.is_ipv6() { "ping6" } else { "ping" })
+        .args(["-c", "1", "-t", "1", &address.to_string()])
+        .output();
+    #[cfg(target_os = "linux")]
+    let output = Command::new("ping")
+        .args(["-c", "1", "-W", "1", &address.to_string()])
+        .output();
+    let output = output.ok().filter(|output| output.status.success())?;
+    parse_ping_time(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Extracts round-trip time in milliseconds from `ping` output, e.g. `time=12.3 ms` or `time<1ms`.
+fn parse_ping_time(output: &str) -> Option<i64> {
+    let (_, rest) = output.split_once("time")?;
+    let rest = rest.trim_start_matches(['=', '<']);
+    let value: String = rest
+        .chars()
+        .take_while(|c| c.is_ascii_digit() || *c == '.')
+        .collect();
+    value.parse::<f64>().ok().map(|time| time.round() as i64)
+}
+
+/// Measures latency to location endpoint without blocking async runtime.
+async fn endpoint_latency(location: &Location, resolution: &EndpointResolution) -> Option<i64> {
+    let address = match location_endpoint(location, resolution).await {
+        Ok(endpoint) => endpoint.ip(),
+        Err(err) => {
+            warn!("Failed to resolve endpoint of location {location}: {err}");
+            return None;
+        }
+    };
+    let latency = tokio::task::spawn_blocking(move || ping(address))
+        .await
+        .ok()
+        .flatten();
+    if latency.is_none() {
+        debug!("Endpoint {address} of location {location} didn't respond to ping");
+    }
+    latency
+}
+
+/// Payload of `connection-stale-{interface_name}` event, emitted when handshake of connected
+/// interface goes stale and again once a fresh handshake arrives.
+#[derive(Clone, Debug, Serialize)]
+pub struct StaleHandshakePayload {
+    pub interface_name: String,
+    pub stale: bool,
+    // seconds since the latest handshake
+    pub handshake_age: u64,
+}
+
+/// Returns time since the latest handshake of given peers, `None` if there was no handshake yet.
+fn handshake_age(peers: &[Peer]) -> Option<Duration> {
+    peers
+        .iter()
+        .filter_map(|peer| peer.last_handshake)
+        .filter(|handshake| *handshake > SystemTime::UNIX_EPOCH)
+        .max()
+        .map(|handshake| handshake.elapsed().unwrap_or_default())
+}
+
+fn report_stale_handshake(
+    handle: &AppHandle,
+    interface_name: &str,
+    stale: bool,
+    age: Duration,
+    notify: bool,
+) {
+    let handshake_age = age.as_secs();
+    if stale {
+        warn!(
+            "No handshake on interface {interface_name} for {handshake_age}s, connection is stale"
+        );
+    } else {
+        info!("Fresh handshake on interface {interface_name}, connection is no longer stale");
+    }
+    let event = format!("connection-stale-{interface_name}");
+    let payload = StaleHandshakePayload {
+        interface_name: interface_name.into(),
+        stale,
+        handshake_age,
+    };
+    if let Err(err) = emit_event(&handle, &event, payload) {
+        error!("Failed to emit stale connection event {event}: {err}");
+    }
+    if stale && notify {
+        if let Err(err) = Notification::new(&handle.config().tauri.bundle.identifier)
+            .title("Connection stale")
+            .body(format!(
+                "No handshake on interface {interface_name} for {handshake_age} seconds"
+            ))
+            .show()
+        {
+            error!("Failed to show stale connection notification: {err}");
+        }
+    }
+}
+
+/// Streams stats of interface until it's removed. Traffic of `restored` interface, created
+/// before the app started, was already counted up to its first sample.
 pub async fn spawn_stats_thread(
     handle: tauri::AppHandle,
     interface_name: String,
     connection_type: ConnectionType,
+    restored: bool,
 ) {
     tokio::spawn(async move {
         let state = handle.state::<AppState>();
-        let mut client = state.client.clone();
-        let request = ReadInterfaceDataRequest {
-            interface_name: interface_name.clone(),
-        };
-        let mut stream = client
-            .read_interface_data(request)
-            .await
-            .expect("Failed to connect to interface stats stream")
-            .into_inner();
-
-        while let Some(item) = stream.next().await {
-            match item {
-                Ok(interface_data) => {
-                    debug!("Received interface data update: {interface_data:?}");
-                    let peers: Vec<Peer> =
-                        interface_data.peers.into_iter().map(Into::into).collect();
-                    for peer in peers {
-                        if connection_type.eq(&ConnectionType::Location) {
-                            let mut location_stats = peer_to_location_stats(
-                                &peer,
-                                interface_data.listen_port,
-                                &state.get_pool(),
-                            )
-                            .await
-                            .unwrap();
-                            debug!("Saving location stats: {location_stats:#?}");
-                            let _ = location_stats.save(&state.get_pool()).await;
-                            debug!("Saved location stats: {location_stats:#?}");
-                        } else {
-                            let mut tunnel_stats = peer_to_tunnel_stats(
-                                &peer,
-                                interface_data.listen_port,
-                                &state.get_pool(),
-                            )
-                            .await
-                            .unwrap();
-                            debug!("Saving tunnel stats: {tunnel_stats:#?}");
-                            let _ = tunnel_stats.save(&state.get_pool()).await;
-                            debug!("Saved location stats: {tunnel_stats:#?}");
+        let mut service_url_changes = state.service_url_changes.subscribe();
+        let mut last_latency_check: Option<Instant> = None;
+        let mut last_collected: Option<Instant> = None;
+        // transfer counters of previous sample for every location, used to compute throughput
+        let mut last_counters: HashMap<i64, (i64, i64)> = HashMap::new();
+        // highest upload and download rates of every location during this connection,
+        // together with peak generation they were reached in
+        let mut peaks: HashMap<i64, (u64, (i64, i64))> = HashMap::new();
+        // set when the latest handshake is older than configured threshold
+        let mut stale = false;
+        // the same error would otherwise be logged on every update
+        let mut error_log = RateLimitedLog::new(STATS_ERROR_LOG_WINDOW);
+        let event = format!("location-stats-{interface_name}");
+
+        'stream: loop {
+            // client is read on every reconnect, so changed `service_url` setting applies
+            let request = ReadInterfaceDataRequest {
+                interface_name: interface_name.clone(),
+            };
+            let mut stream = match state.client().read_interface_data(request).await {
+                Ok(response) => response.into_inner(),
+                Err(err) => {
+                    error!(
+                        "Failed to connect to stats stream of interface {interface_name}: {err}"
+                    );
+                    break;
+                }
+            };
+            service_url_changes.borrow_and_update();
+            loop {
+                let item = tokio::select! {
+                    item = stream.next() => item,
+                    Ok(()) = service_url_changes.changed() => {
+                        info!("defguard-service URL changed, reconnecting stats stream of interface {interface_name}");
+                        continue 'stream;
+                    }
+                };
+                let Some(item) = item else {
+                    break 'stream;
+                };
+                match item {
+                    Ok(interface_data) => {
+                        error_log.success("receive");
+                        // settings are read once on every update so changes apply immediately
+                        let settings = match Settings::get(&state.get_pool()).await {
+                            Ok(settings) => {
+                                error_log.success("settings");
+                                settings
+                            }
+                            Err(err) => {
+                                error_log.error(
+                                    "settings",
+                                    format!("Failed to read settings, using defaults: {err}"),
+                                );
+                                Settings::defaults()
+                            }
+                        };
+                        let interval = settings.stats_interval_duration();
+                        if last_collected.is_some_and(|collected| {
+                            collected.elapsed() + STATS_INTERVAL_TOLERANCE < interval
+                        }) {
+                            trace!("Skipping interface data update received within stats interval");
+                            continue;
+                        }
+                        let since_last = last_collected.map(|collected| collected.elapsed());
+                        last_collected = Some(Instant::now());
+                        debug!("Received interface data update: {interface_data:?}");
+                        let peers: Vec<Peer> =
+                            interface_data.peers.into_iter().map(Into::into).collect();
+                        if let (Some((threshold, notify)), Some(age)) =
+                            (settings.stale_handshake_threshold(), handshake_age(&peers))
+                        {
+                            if (age > threshold) != stale {
+                                stale = !stale;
+                                report_stale_handshake(
+                                    &handle,
+                                    &interface_name,
+                                    stale,
+                                    age,
+                                    notify,
+                                );
+                            }
+                        }
+                        // key profile connections share location with its default connection,
+                        // their stats would mix into the same stored samples and totals
+                        let profile_connection = state.get_connections().iter().any(|connection| {
+                            connection.interface_name == interface_name
+                                && connection.profile_id.is_some()
+                        });
+                        for peer in peers {
+                            if connection_type.eq(&ConnectionType::Location) {
+                                let mut location_stats = match peer_to_location_stats(
+                                    &peer,
+                                    interface_data.listen_port,
+                                    &state.get_pool(),
+                                )
+                                .await
+                                {
+                                    Ok(location_stats) => {
+                                        error_log.success("stats");
+                                        location_stats
+                                    }
+                                    Err(err) => {
+                                        error_log.error(
+                                        "stats",
+                                        format!("Failed to read stats of interface {interface_name}: {err}"),
+                                    );
+                                        continue;
+                                    }
+                                };
+                                if last_latency_check.map_or(true, |checked| {
+                                    checked.elapsed() >= LATENCY_CHECK_INTERVAL
+                                }) {
+                                    last_latency_check = Some(Instant::now());
+                                    if let Ok(location) = Location::find_by_public_key(
+                                        &state.get_pool(),
+                                        &peer.public_key.to_string(),
+                                    )
+                                    .await
+                                    {
+                                        location_stats.set_latency(
+                                            endpoint_latency(
+                                                &location,
+                                                &settings.endpoint_resolution,
+                                            )
+                                            .await,
+                                        );
+                                    }
+                                }
+                                let (location_id, counters) = location_stats.counters();
+                                let update = location_stats.update_since(
+                                    last_counters
+                                        .insert(location_id, counters)
+                                        .or(restored.then_some(counters)),
+                                );
+                                match emit_event(&handle, &event, &update) {
+                                    Ok(()) => error_log.success("emit"),
+                                    Err(err) => error_log.error(
+                                        "emit",
+                                        format!(
+                                            "Failed to emit location stats event {event}: {err}"
+                                        ),
+                                    ),
+                                }
+                                if profile_connection {
+                                    continue;
+                                }
+                                // deltas ignore counter resets, so totals only grow by new traffic
+                                if update.upload_delta > 0 || update.download_delta > 0 {
+                                    match LifetimeTransfer::add(
+                                    &state.get_pool(),
+                                    location_id,
+                                    update.upload_delta,
+                                    update.download_delta,
+                                )
+                                .await
+                                {
+                                    Ok(()) => error_log.success("lifetime_transfer"),
+                                    Err(err) => error_log.error(
+                                        "lifetime_transfer",
+                                        format!("Failed to record lifetime transfer of location {location_id}: {err}"),
+                                    ),
+                                }
+                                }
+                                if let Some(since_last) =
+                                    since_last.filter(|since| !since.is_zero())
+                                {
+                                    let rate = |delta: i64| {
+                                        (delta as f64 / since_last.as_secs_f64()) as i64
+                                    };
+                                    let rates =
+                                        (rate(update.upload_delta), rate(update.download_delta));
+                                    let (generation, peak) = peaks.entry(location_id).or_default();
+                                    // peak was reset since it was reached
+                                    let current_generation = state.peak_generation(location_id);
+                                    if *generation != current_generation {
+                                        *generation = current_generation;
+                                        *peak = (0, 0);
+                                    }
+                                    // stored peaks are only updated when this connection sets a new high
+                                    if rates.0 > peak.0 || rates.1 > peak.1 {
+                                        *peak = (peak.0.max(rates.0), peak.1.max(rates.1));
+                                        match PeakThroughput::record(
+                                        &state.get_pool(),
+                                        location_id,
+                                        peak.0,
+                                        peak.1,
+                                    )
+                                    .await
+                                    {
+                                        Ok(()) => error_log.success("peak_throughput"),
+                                        Err(err) => error_log.error(
+                                            "peak_throughput",
+                                            format!("Failed to record peak throughput of location {location_id}: {err}"),
+                                        ),
+                                    }
+                                    }
+                                }
+                                debug!("Buffering location stats: {location_stats:#?}");
+                                state.record_capture(
+                                    CaptureEntryKind::Stats,
+                                    &interface_name,
+                                    &location_stats,
+                                );
+                                state.stats_buffer.push_location_stats(location_stats);
+                            } else {
+                                let tunnel_stats = match peer_to_tunnel_stats(
+                                    &peer,
+                                    interface_data.listen_port,
+                                    &state.get_pool(),
+                                )
+                                .await
+                                {
+                                    Ok(tunnel_stats) => {
+                                        error_log.success("stats");
+                                        tunnel_stats
+                                    }
+                                    Err(err) => {
+                                        error_log.error(
+                                        "stats",
+                                        format!("Failed to read stats of interface {interface_name}: {err}"),
+                                    );
+                                        continue;
+                                    }
+                                };
+                                debug!("Buffering tunnel stats: {tunnel_stats:#?}");
+                                state.record_capture(
+                                    CaptureEntryKind::Stats,
+                                    &interface_name,
+                                    &tunnel_stats,
+                                );
+                                state.stats_buffer.push_tunnel_stats(tunnel_stats);
+                            }
                         }
                     }
-                }
-                Err(err) => {
-                    error!("Failed to receive interface data update: {err}");
+                    Err(err) => {
+                        error_log.error(
+                            "receive",
+                            format!("Failed to receive interface data update: {err}"),
+                        );
+                    }
                 }
             }
         }
@@ -238,6 +1089,232 @@ pub async fn spawn_stats_thread(
     });
 }
 
+/// Checks if `defguard-service` manages interface with given name by reading its data.
+/// Stream ends without data if the interface doesn't exist.
+#[cfg(not(target_os = "macos"))]
+async fn interface_exists(
+    client: &mut DesktopDaemonServiceClient<Channel>,
+    interface_name: &str,
+) -> bool {
+    let request = ReadInterfaceDataRequest {
+        interface_name: interface_name.into(),
+    };
+    let Ok(response) = client.read_interface_data(request).await else {
+        return false;
+    };
+    let mut stream = response.into_inner();
+    matches!(
+        tokio::time::timeout(INTERFACE_PROBE_TIMEOUT, stream.next()).await,
+        Ok(Some(Ok(_)))
+    )
+}
+
+/// Register connection for interface which already exists and start its stats and log threads.
+#[cfg(not(target_os = "macos"))]
+async fn register_restored_connection(
+    handle: &AppHandle,
+    location_id: i64,
+    interface_name: String,
+    connection_type: ConnectionType,
+) -> Result<(), Error> {
+    let state = handle.state::<AppState>();
+    let address = local_ip()?;
+    state
+        .active_connections
+        .lock()
+        .map_err(|_| Error::MutexError)?
+        .push(ActiveConnection::new(
+            location_id,
+            address.to_string(),
+            interface_name.clone(),
+            connection_type.clone(),
+        ));
+    state.set_connection_state(location_id, &connection_type, ConnectionState::Connected);
+    spawn_stats_thread(
+        handle.clone(),
+        interface_name.clone(),
+        connection_type.clone(),
+        true,
+    )
+    .await;
+    spawn_log_watcher_task(
+        handle.clone(),
+        location_id,
+        interface_name,
+        connection_type,
+        None,
+        LogFormat::Text,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Rebuild active connections from interfaces still managed by `defguard-service`,
+/// e.g. after the client was killed while connected. Connections whose interfaces
+/// no longer exist are removed.
+#[cfg(not(target_os = "macos"))]
+pub async fn restore_active_connections(handle: &AppHandle) -> Result<(), Error> {
+    debug!("Restoring active connections");
+    let state = handle.state::<AppState>();
+    let mut client = state.client();
+    let pool = state.get_pool();
+
+    for connection in state.get_connections() {
+        if !interface_exists(&mut client, &connection.interface_name).await {
+            info!(
+                "Interface {} no longer exists, removing connection",
+                connection.interface_name
+            );
+            let Some(connection) = state
+                .find_and_remove_connection(connection.location_id, &connection.connection_type)
+            else {
+                continue;
+            };
+            // interface was removed while the client wasn't watching it
+            let result = match connection.connection_type {
+                ConnectionType::Location => {
+                    connection
+                        .into_connection(DisconnectReason::NetworkFailure)
+                        .save(&pool)
+                        .await
+                }
+                ConnectionType::Tunnel => {
+                    connection
+                        .into_tunnel_connection(DisconnectReason::NetworkFailure)
+                        .save(&pool)
+                        .await
+                }
+            };
+            if let Err(err) = result {
+                warn!("Failed to save history of removed connection: {err}");
+            }
+        }
+    }
+
+    let mut restored = 0;
+    let active_locations = state.get_connection_id_by_type(&ConnectionType::Location);
+    for location in Location::all(&pool).await? {
+        let location_id = location.id.expect("Missing Location ID");
+        let interface_name = location_interface_name(&location);
+        if !active_locations.contains(&location_id)
+            && interface_exists(&mut client, &interface_name).await
+        {
+            info!("Restoring connection of location {location} on interface {interface_name}");
+            register_restored_connection(
+                handle,
+                location_id,
+                interface_name,
+                ConnectionType::Location,
+            )
+            .await?;
+            restored += 1;
+        }
+    }
+    let active_tunnels = state.get_connection_id_by_type(&ConnectionType::Tunnel);
+    for tunnel in Tunnel::all(&pool).await? {
+        let tunnel_id = tunnel.id.expect("Missing Tunnel ID");
+        let interface_name = get_interface_name(&tunnel.name);
+        if !active_tunnels.contains(&tunnel_id)
+            && interface_exists(&mut client, &interface_name).await
+        {
+            info!(
+                "Restoring connection of tunnel {} on interface {interface_name}",
+                tunnel.name
+            );
+            register_restored_connection(handle, tunnel_id, interface_name, ConnectionType::Tunnel)
+                .await?;
+            restored += 1;
+        }
+    }
+
+    info!("Restored {restored} active connections");
+    emit_event(
+        &handle,
+        "connection-changed",
+        Payload {
+            message: "Restored active connections".into(),
+        },
+    )?;
+    refresh_tray(handle);
+    Ok(())
+}
+
+/// Connect locations with autoconnect enabled. `defguard-service` may still be starting
+/// together with the client, so connections failing to create interface are retried with backoff.
+pub async fn autoconnect_locations(handle: AppHandle) {
+    let state = handle.state::<AppState>();
+    let locations = match Location::all(&state.get_pool()).await {
+        Ok(locations) => locations,
+        Err(err) => {
+            error!("Failed to load locations to autoconnect: {err}");
+            return;
+        }
+    };
+    // locations of archived instances are hidden and must not be connected
+    let instance_ids: Vec<i64> = match Instance::all(&state.get_pool()).await {
+        Ok(instances) => instances
+            .into_iter()
+            .filter_map(|instance| instance.id)
+            .collect(),
+        Err(err) => {
+            error!("Failed to load instances to autoconnect: {err}");
+            return;
+        }
+    };
+    let active_locations = state.get_connection_id_by_type(&ConnectionType::Location);
+    for location in locations
+        .into_iter()
+        .filter(|location| location.autoconnect && instance_ids.contains(&location.instance_id))
+    {
+        let location_id = location.id.expect("Missing Location ID");
+        if active_locations.contains(&location_id) {
+            debug!("Location {location} already connected, skipping autoconnect");
+            continue;
+        }
+        if location.mfa_enabled {
+            // preshared key can only be obtained through MFA initiated by the user
+            warn!("Location {location} requires MFA, skipping autoconnect");
+            continue;
+        }
+        let mut backoff = AUTOCONNECT_INITIAL_BACKOFF;
+        for attempt in 1..=AUTOCONNECT_ATTEMPTS {
+            info!("Autoconnecting location {location}, attempt {attempt}/{AUTOCONNECT_ATTEMPTS}");
+            match connect(
+                location_id,
+                ConnectionType::Location,
+                None,
+                None,
+                None,
+                handle.clone(),
+            )
+            .await
+            {
+                Ok(()) => {
+                    info!("Location {location} connected automatically");
+                    break;
+                }
+                // interface couldn't be created, most likely service isn't running yet
+                Err(Error::InternalError) if attempt < AUTOCONNECT_ATTEMPTS => {
+                    debug!("Autoconnecting location {location} failed, retrying in {backoff:?}");
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                }
+                Err(err) => {
+                    warn!("Failed to autoconnect location {location}: {err}");
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Interface names are generated randomly on macOS, so they can't be matched with locations.
+#[cfg(target_os = "macos")]
+pub async fn restore_active_connections(_handle: &AppHandle) -> Result<(), Error> {
+    debug!("Restoring active connections is not supported on macOS");
+    Ok(())
+}
+
 // gets targets that will be allowed by logger, this will be empty if not provided
 #[must_use]
 pub fn load_log_targets() -> Vec<String> {
@@ -270,15 +1347,18 @@ pub fn get_service_log_dir() -> PathBuf {
 pub async fn setup_interface_tunnel(
     tunnel: &Tunnel,
     interface_name: String,
+    pool: &DbPool,
     mut client: DesktopDaemonServiceClient<Channel>,
-) -> Result<(), Error> {
+) -> Result<SocketAddr, Error> {
+    validate_interface_name(&interface_name)?;
     // prepare peer config
     debug!("Decoding location public key: {}.", tunnel.server_pubkey);
     let peer_key: Key = Key::from_str(&tunnel.server_pubkey)?;
     let mut peer = Peer::new(peer_key);
 
     debug!("Parsing location endpoint: {}", tunnel.endpoint);
-    let endpoint: SocketAddr = tunnel.endpoint.to_socket_addrs()?.next().expect("Unable to resolve domain");
+    let resolution = Settings::endpoint_resolution(pool).await;
+    let endpoint = resolve_endpoint(&tunnel.endpoint, &resolution, None).await?;
     peer.endpoint = Some(endpoint);
     peer.persistent_keepalive_interval = Some(
         tunnel
@@ -299,6 +1379,7 @@ pub async fn setup_interface_tunnel(
             .map(|ips| ips.split(',').map(str::to_string).collect())
             .unwrap_or_default()
     };
+    let allowed_ips = exclude_control_channel(allowed_ips)?;
     for allowed_ip in &allowed_ips {
         match IpAddrMask::from_str(allowed_ip.trim()) {
             Ok(addr) => {
@@ -335,7 +1416,7 @@ pub async fn setup_interface_tunnel(
             Err(Error::InternalError)
         } else {
             info!("Created interface {interface_config:#?}");
-            Ok(())
+            Ok(endpoint)
         }
     } else {
         error!("Error finding free port");
@@ -391,6 +1472,10 @@ pub async fn get_tunnel_interface_details(
             allowed_ips: tunnel.allowed_ips.unwrap_or_default(),
             persistent_keepalive_interval,
             last_handshake,
+            mtu: None,
+            dns_search: None,
+            preshared_key: false,
+            extra_routes: None,
         })
     } else {
         error!("Tunnel ID {tunnel_id} not found");
@@ -399,6 +1484,7 @@ pub async fn get_tunnel_interface_details(
 }
 pub async fn get_location_interface_details(
     location_id: i64,
+    active_pubkey: Option<String>,
     pool: &DbPool,
 ) -> Result<LocationInterfaceDetails, Error> {
     debug!("Fetching location details for location ID {location_id}");
@@ -407,13 +1493,12 @@ pub async fn get_location_interface_details(
         let keys = WireguardKeys::find_by_instance_id(pool, location.instance_id)
             .await?
             .ok_or(Error::NotFound)?;
-        let peer_pubkey = keys.pubkey;
 
         // generate interface name
         #[cfg(target_os = "macos")]
         let interface_name = get_interface_name();
         #[cfg(not(target_os = "macos"))]
-        let interface_name = get_interface_name(&location.name);
+        let interface_name = location_interface_name(&location);
 
         let result = query!(
             r#"
@@ -435,6 +1520,8 @@ pub async fn get_location_interface_details(
             ),
             None => (None, None, None),
         };
+        // key pair selected when connecting differs from the current one during key migration
+        let peer_pubkey = active_pubkey.unwrap_or(keys.pubkey);
 
         Ok(LocationInterfaceDetails {
             location_id,
@@ -448,6 +1535,10 @@ pub async fn get_location_interface_details(
             allowed_ips: location.allowed_ips,
             persistent_keepalive_interval,
             last_handshake,
+            mtu: location.mtu,
+            dns_search: location.dns_search,
+            preshared_key: location.preshared_key.is_some(),
+            extra_routes: location.extra_routes,
         })
     } else {
         error!("Location ID {location_id} not found");
@@ -455,10 +1546,553 @@ pub async fn get_location_interface_details(
     }
 }
 
+#[derive(Clone, Serialize)]
+pub struct RoutingConflictPayload {
+    pub location_id: i64,
+    // connected locations which already route all traffic
+    pub conflicting_location_ids: Vec<i64>,
+}
+
+/// Returns IDs of connected locations routing all traffic through their tunnel.
+/// More than one of them compete for the default route.
+pub async fn active_full_tunnel_locations(state: &AppState) -> Result<Vec<i64>, Error> {
+    let mut location_ids = state.get_connection_id_by_type(&ConnectionType::Location);
+    // location can be connected with multiple key profiles
+    location_ids.sort_unstable();
+    location_ids.dedup();
+    let pool = state.get_pool();
+    let mut full_tunnel = Vec::new();
+    for location_id in location_ids {
+        if let Some(location) = Location::find_by_id(&pool, location_id).await? {
+            if location.route_all_traffic {
+                full_tunnel.push(location_id);
+            }
+        }
+    }
+    Ok(full_tunnel)
+}
+
+#[derive(Clone, Serialize)]
+pub struct Ipv6LeakPayload {
+    pub location_id: i64,
+    pub interface_name: String,
+}
+
+/// Checks if the host can reach public IPv6 addresses outside of any tunnel.
+#[must_use]
+pub fn has_native_ipv6() -> bool {
+    // connecting an UDP socket doesn't send any packets, it only requires a route to exist
+    UdpSocket::bind(SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0))
+        .and_then(|socket| socket.connect(IPV6_PROBE_ADDRESS))
+        .is_ok()
+}
+
+/// Checks if location only tunnels IPv4 traffic while IPv6 traffic leaks
+/// through native host connectivity.
+#[must_use]
+pub fn check_location_ipv6_leak(location: &Location) -> bool {
+    // routing all traffic only installs the IPv4 default route
+    let routes_ipv6 = !location.route_all_traffic
+        && location
+            .allowed_ips
+            .split(',')
+            .any(|allowed_ip| allowed_ip.trim() == IPV6_DEFAULT_ROUTE);
+    if routes_ipv6 {
+        debug!("Location {location} routes all IPv6 traffic through the tunnel");
+        return false;
+    }
+    has_native_ipv6()
+}
+
+/// Local interface address overlapping with location network
+#[derive(Debug, Serialize)]
+pub struct AddressConflict {
+    pub location_address: String,
+    pub interface_name: String,
+    pub local_address: IpAddr,
+    pub local_network: String,
+}
+
+impl std::fmt::Display for AddressConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} overlaps network {} of interface {}",
+            self.location_address, self.local_network, self.interface_name
+        )
+    }
+}
+
+/// Checks if network contains given address.
+fn network_contains(network: &IpAddrMask, address: &IpAddr) -> bool {
+    match (network.ip, address) {
+        (IpAddr::V4(network_ip), IpAddr::V4(address)) => {
+            let mask = u32::MAX
+                .checked_shl(32 - u32::from(network.cidr))
+                .unwrap_or(0);
+            u32::from(network_ip) & mask == u32::from(*address) & mask
+        }
+        (IpAddr::V6(network_ip), IpAddr::V6(address)) => {
+            let mask = u128::MAX
+                .checked_shl(128 - u32::from(network.cidr))
+                .unwrap_or(0);
+            u128::from(network_ip) & mask == u128::from(*address) & mask
+        }
+        _ => false,
+    }
+}
+
+/// Checks if networks share any address, i.e. one of them contains the other.
+fn networks_overlap(first: &IpAddrMask, second: &IpAddrMask) -> bool {
+    let wider = if first.cidr <= second.cidr {
+        first
+    } else {
+        second
+    };
+    network_contains(wider, &first.ip) && network_contains(wider, &second.ip)
+}
+
+/// Converts network mask into prefix length.
+fn netmask_prefix(netmask: IpAddr) -> u8 {
+    let ones = match netmask {
+        IpAddr::V4(netmask) => u32::from(netmask).leading_ones(),
+        IpAddr::V6(netmask) => u128::from(netmask).leading_ones(),
+    };
+    ones as u8
+}
+
+/// Splits network into subnets which cover it entirely except given address.
+fn exclude_address(network: &IpAddrMask, address: &IpAddr) -> Vec<IpAddrMask> {
+    let (bits, width) = match address {
+        IpAddr::V4(address) => (u128::from(u32::from(*address)), 32),
+        IpAddr::V6(address) => (u128::from(*address), 128),
+    };
+    let full_mask = u128::MAX >> (128 - width);
+    (u32::from(network.cidr) + 1..=width)
+        .map(|prefix| {
+            // sibling of the subnet containing address at given prefix length
+            let mask = (full_mask << (width - prefix)) & full_mask;
+            let subnet = (bits ^ (1 << (width - prefix))) & mask;
+            let ip = if width == 32 {
+                IpAddr::V4(Ipv4Addr::from(subnet as u32))
+            } else {
+                IpAddr::V6(Ipv6Addr::from(subnet))
+            };
+            IpAddrMask::new(ip, prefix as u8)
+        })
+        .collect()
+}
+
+/// Makes sure traffic to daemon control channel isn't routed through the tunnel.
+/// Networks containing control channel address are replaced with subnets excluding it.
+pub fn exclude_control_channel(allowed_ips: Vec<String>) -> Result<Vec<String>, Error> {
+    // unix socket control channel isn't affected by routing
+    let control_addresses: Vec<IpAddr> = service_address()
+        .socket_addrs()?
+        .iter()
+        .map(SocketAddr::ip)
+        .collect();
+    exclude_addresses(allowed_ips, &control_addresses)
+}
+
+/// Replaces allowed IPs containing any of `addresses` with subnets excluding them.
+/// Default routes are kept as they are.
+fn exclude_addresses(
+    allowed_ips: Vec<String>,
+    control_addresses: &[IpAddr],
+) -> Result<Vec<String>, Error> {
+    let mut result = Vec::new();
+    for allowed_ip in allowed_ips {
+        let Ok(network) = IpAddrMask::from_str(allowed_ip.trim()) else {
+            // invalid entries are reported and skipped while configuring peer
+            result.push(allowed_ip);
+            continue;
+        };
+        let mut networks = vec![network];
+        for address in control_addresses {
+            let mut remaining = Vec::new();
+            for network in networks {
+                // default routes have lower priority than local routes to the control channel
+                if network.cidr == 0 || !network_contains(&network, address) {
+                    remaining.push(network);
+                } else {
+                    let subnets = exclude_address(&network, address);
+                    if subnets.is_empty() {
+                        error!("Allowed IP {allowed_ip} captures daemon control channel {address}");
+                        return Err(Error::ControlChannelBlackhole(allowed_ip));
+                    }
+                    warn!(
+                        "Excluding daemon control channel {address} from allowed IP {allowed_ip}"
+                    );
+                    remaining.extend(subnets);
+                }
+            }
+            networks = remaining;
+        }
+        result.extend(networks.iter().map(ToString::to_string));
+    }
+    Ok(result)
+}
+
+/// Parses comma-separated list of allowed IPs in CIDR notation.
+/// Host bits are cleared and duplicates removed, e.g. `10.1.2.3/24, 10.1.2.0/24` becomes `10.1.2.0/24`.
+pub fn parse_allowed_ips(allowed_ips: &str) -> Result<Vec<IpAddrMask>, Error> {
+    let mut networks: Vec<IpAddrMask> = Vec::new();
+    for entry in allowed_ips.split(',').map(str::trim) {
+        let invalid = |reason: &str| Error::InvalidAllowedIps(format!("{entry:?} {reason}"));
+        let (ip, cidr) = entry
+            .split_once('/')
+            .ok_or_else(|| invalid("is missing network prefix length"))?;
+        let ip = IpAddr::from_str(ip).map_err(|_| invalid("has invalid IP address"))?;
+        let cidr = cidr
+            .parse::<u8>()
+            .map_err(|_| invalid("has invalid network prefix length"))?;
+        let network = match ip {
+            IpAddr::V4(ip) if cidr <= 32 => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(cidr)).unwrap_or(0);
+                IpAddr::V4(Ipv4Addr::from(u32::from(ip) & mask))
+            }
+            IpAddr::V6(ip) if cidr <= 128 => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(cidr)).unwrap_or(0);
+                IpAddr::V6(Ipv6Addr::from(u128::from(ip) & mask))
+            }
+            _ => return Err(invalid("has network prefix length out of range")),
+        };
+        let network = IpAddrMask::new(network, cidr);
+        if !networks.contains(&network) {
+            networks.push(network);
+        }
+    }
+    Ok(networks)
+}
+
+/// Parses networks routed through location tunnel in addition to allowed IPs, separated with
+/// commas or newlines. Default route is only accepted if location routes all traffic anyway.
+pub fn parse_extra_routes(
+    extra_routes: &str,
+    route_all_traffic: bool,
+) -> Result<Vec<IpAddrMask>, Error> {
+    let entries: Vec<&str> = extra_routes
+        .split([',', '\n'])
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .collect();
+    if entries.is_empty() {
+        return Ok(Vec::new());
+    }
+    let routes = parse_allowed_ips(&entries.join(","))?;
+    if !route_all_traffic {
+        if let Some(route) = routes.iter().find(|route| route.cidr == 0) {
+            return Err(Error::InvalidAllowedIps(format!(
+                "{route} conflicts with default route, enable routing all traffic instead"
+            )));
+        }
+    }
+    Ok(routes)
+}
+
+/// Finds host interface networks which overlap the network assigned to location,
+/// in either direction. Such overlap breaks local connectivity once the tunnel is up.
+pub fn find_address_conflicts(location: &Location) -> Result<Vec<AddressConflict>, Error> {
+    let interfaces = get_if_addrs()?;
+    let mut conflicts = Vec::new();
+    for address in location.address.split(',') {
+        let network = IpAddrMask::from_str(address.trim())?;
+        for interface in &interfaces {
+            let local_address = interface.ip();
+            // skip loopback and the address of already configured tunnel interface
+            if interface.is_loopback() || local_address == network.ip {
+                continue;
+            }
+            let netmask = match &interface.addr {
+                IfAddr::V4(addr) => IpAddr::V4(addr.netmask),
+                IfAddr::V6(addr) => IpAddr::V6(addr.netmask),
+            };
+            let local_network = IpAddrMask::new(local_address, netmask_prefix(netmask));
+            if networks_overlap(&network, &local_network) {
+                conflicts.push(AddressConflict {
+                    location_address: address.trim().into(),
+                    interface_name: interface.name.clone(),
+                    local_address,
+                    local_network: local_network.to_string(),
+                });
+            }
+        }
+    }
+    Ok(conflicts)
+}
+
 /// Setup new connection for location
 pub async fn handle_connection_for_location(
     location: &Location,
     preshared_key: Option<String>,
+    profile_id: Option<i64>,
+    handle: AppHandle,
+) -> Result<(), Error> {
+    setup_location_connection(location, preshared_key, profile_id, handle.clone()).await?;
+    debug!("Sending event connection-changed.");
+    let payload = Payload {
+        message: "Created new connection".into(),
+    };
+    emit_event(&handle, "connection-changed", payload)?;
+    refresh_tray(&handle);
+    Ok(())
+}
+
+/// Waits until location peer completes the first handshake on given interface.
+async fn wait_for_handshake(
+    mut client: DesktopDaemonServiceClient<Channel>,
+    interface_name: &str,
+) -> Result<(), Error> {
+    debug!("Waiting for first handshake on interface {interface_name}");
+    let request = ReadInterfaceDataRequest {
+        interface_name: interface_name.into(),
+    };
+    let mut stream = client
+        .read_interface_data(request)
+        .await
+        .map_err(|status| {
+            error!("Failed to read interface {interface_name} data: {status}");
+            Error::InternalError
+        })?
+        .into_inner();
+    while let Some(item) = stream.next().await {
+        let Ok(interface_data) = item else {
+            continue;
+        };
+        if interface_data
+            .peers
+            .into_iter()
+            .map(Peer::from)
+            .any(|peer| {
+                peer.last_handshake
+                    .is_some_and(|ts| ts > SystemTime::UNIX_EPOCH)
+            })
+        {
+            debug!("First handshake on interface {interface_name} completed");
+            return Ok(());
+        }
+    }
+    error!("Interface {interface_name} data stream ended before first handshake");
+    Err(Error::InternalError)
+}
+
+/// Reads endpoint address of interface peer as currently used by WireGuard.
+async fn read_peer_endpoint(
+    mut client: DesktopDaemonServiceClient<Channel>,
+    interface_name: &str,
+) -> Option<String> {
+    let request = ReadInterfaceDataRequest {
+        interface_name: interface_name.into(),
+    };
+    let mut stream = client.read_interface_data(request).await.ok()?.into_inner();
+    match tokio::time::timeout(ENDPOINT_READ_TIMEOUT, stream.next()).await {
+        Ok(Some(Ok(interface_data))) => interface_data
+            .peers
+            .into_iter()
+            .find_map(|peer| peer.endpoint),
+        _ => {
+            warn!("Failed to read peer endpoint of interface {interface_name}");
+            None
+        }
+    }
+}
+
+/// Remove interface through the service, retrying with exponential backoff on failure.
+async fn remove_interface_with_retry(
+    client: &mut DesktopDaemonServiceClient<Channel>,
+    request: RemoveInterfaceRequest,
+    state: &AppState,
+) -> Result<(), Status> {
+    let interface_name = request.interface_name.clone();
+    let mut backoff = REMOVE_INTERFACE_INITIAL_BACKOFF;
+    let mut attempt = 1;
+    loop {
+        let result = client.remove_interface(request.clone()).await;
+        state.record_capture(
+            CaptureEntryKind::Rpc,
+            "remove_interface",
+            json!({ "interface_name": interface_name, "result": format!("{result:?}") }),
+        );
+        match result {
+            Ok(_) => return Ok(()),
+            Err(error) if attempt < REMOVE_INTERFACE_ATTEMPTS => {
+                warn!(
+                    "Failed to remove interface {interface_name}, attempt \
+                    {attempt}/{REMOVE_INTERFACE_ATTEMPTS}, retrying in {backoff:?}: {error}"
+                );
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+                attempt += 1;
+            }
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+/// Remove interface left behind by unsuccessful connection attempt.
+async fn remove_failed_interface(
+    location: &Location,
+    interface_name: &str,
+    mut client: DesktopDaemonServiceClient<Channel>,
+) {
+    let request = RemoveInterfaceRequest {
+        interface_name: interface_name.into(),
+        pre_down: location_pre_down(location, interface_name),
+        post_down: None,
+    };
+    match client.remove_interface(request).await {
+        Ok(_) => info!("Removed interface {interface_name} of location {location}"),
+        Err(status) => warn!("Failed to remove interface {interface_name}: {status}"),
+    }
+}
+
+/// Commands reverting location configuration applied on top of WireGuard interface.
+fn location_pre_down(location: &Location, interface_name: &str) -> Option<String> {
+    validate_interface_name(interface_name).ok()?;
+    encode_commands([
+        encrypted_dns_commands(location, interface_name)
+            .map(|(_, pre_down)| pre_down)
+            .unwrap_or_default(),
+        kill_switch_commands(location, interface_name, None)
+            .map(|(_, pre_down)| pre_down)
+            .unwrap_or_default(),
+        tunnel_dns_commands(location, interface_name)
+            .map(|(_, pre_down)| pre_down)
+            .unwrap_or_default(),
+    ])
+}
+
+/// In sticky endpoint resolution remembers endpoint address location connected through,
+/// or forgets it after a failed connection so the address is resolved again next time.
+async fn update_sticky_endpoint(pool: &DbPool, location: &Location, endpoint: Option<SocketAddr>) {
+    if Settings::endpoint_resolution(pool).await != EndpointResolution::Sticky {
+        return;
+    }
+    let endpoint = endpoint.map(|endpoint| endpoint.to_string());
+    if endpoint == location.sticky_endpoint {
+        return;
+    }
+    let location_id = location.id.expect("Missing Location ID");
+    if let Err(err) = Location::set_sticky_endpoint(pool, location_id, endpoint.as_deref()).await {
+        warn!("Failed to store sticky endpoint of location {location}: {err}");
+    }
+}
+
+/// Checks if endpoint is served by relay, given either as endpoint host or whole endpoint.
+#[must_use]
+pub fn served_by_relay(endpoint: &str, relay: &str) -> bool {
+    endpoint.eq_ignore_ascii_case(relay)
+        || split_endpoint(endpoint).is_ok_and(|(host, _)| host.eq_ignore_ascii_case(relay))
+}
+
+/// Primary endpoint of location followed by its backup endpoints, without duplicates.
+/// Endpoints served by `preferred_relay` are moved to the front.
+#[must_use]
+pub fn location_endpoints(location: &Location, preferred_relay: Option<&str>) -> Vec<String> {
+    let mut endpoints = vec![location.endpoint.clone()];
+    for endpoint in location
+        .endpoints
+        .iter()
+        .flat_map(|endpoints| endpoints.lines())
+        .map(str::trim)
+        .filter(|endpoint| !endpoint.is_empty())
+    {
+        if !endpoints.iter().any(|existing| existing == endpoint) {
+            endpoints.push(endpoint.to_string());
+        }
+    }
+    if let Some(relay) = preferred_relay {
+        let (mut preferred, others): (Vec<String>, Vec<String>) = endpoints
+            .into_iter()
+            .partition(|endpoint| served_by_relay(endpoint, relay));
+        if preferred.is_empty() {
+            warn!("Preferred relay {relay} doesn't serve location {location}, using its default endpoints");
+        }
+        preferred.extend(others);
+        endpoints = preferred;
+    }
+    endpoints
+}
+
+/// Validates backup endpoints and joins them into newline-separated list,
+/// `None` if there are none.
+pub fn parse_endpoints(endpoints: Vec<String>) -> Result<Option<String>, Error> {
+    let mut parsed: Vec<String> = Vec::new();
+    for endpoint in endpoints {
+        let endpoint = endpoint.trim();
+        if endpoint.is_empty() {
+            continue;
+        }
+        split_endpoint(endpoint)?;
+        if !parsed.iter().any(|existing| existing == endpoint) {
+            parsed.push(endpoint.to_string());
+        }
+    }
+    Ok(Some(parsed.join("\n")).filter(|endpoints| !endpoints.is_empty()))
+}
+
+/// Sets up interface of location using its endpoint and waits for the first handshake.
+/// Interface is removed if the handshake doesn't complete within `timeout`
+/// or the attempt is cancelled. Only the handshake counts against `timeout`, setting up
+/// the interface may take longer, e.g. when the service runs pre-up commands.
+async fn connect_location_endpoint(
+    state: &AppState,
+    location: &Location,
+    interface_name: &str,
+    preshared_key: Option<String>,
+    profile_id: Option<i64>,
+    timeout: Duration,
+    cancel: &CancellationToken,
+) -> Result<InterfaceSetup, Error> {
+    let cancelled = || async {
+        info!("Connecting location {location} cancelled, removing its interface");
+        remove_failed_interface(location, interface_name, state.client()).await;
+        Err(Error::Cancelled)
+    };
+    let setup = setup_interface(
+        location,
+        interface_name.to_string(),
+        preshared_key,
+        profile_id,
+        &state.get_pool(),
+        state.client(),
+        cancel,
+    );
+    let interface = tokio::select! {
+        result = setup => result?,
+        () = cancel.cancelled() => return cancelled().await,
+    };
+    let handshake =
+        tokio::time::timeout(timeout, wait_for_handshake(state.client(), interface_name));
+    let result = tokio::select! {
+        result = handshake => result,
+        () = cancel.cancelled() => return cancelled().await,
+    };
+    match result {
+        Ok(Ok(())) => Ok(interface),
+        Ok(Err(err)) => {
+            remove_failed_interface(location, interface_name, state.client()).await;
+            Err(err)
+        }
+        Err(_) => {
+            error!(
+                "Connecting location {location} through endpoint {} timed out after {}s",
+                location.endpoint,
+                timeout.as_secs()
+            );
+            remove_failed_interface(location, interface_name, state.client()).await;
+            Err(Error::ConnectionTimeout(timeout.as_secs()))
+        }
+    }
+}
+
+/// Setup interface for location and register it as active connection without notifying
+/// the frontend, so multiple locations can be connected with a single event.
+pub async fn setup_location_connection(
+    location: &Location,
+    preshared_key: Option<String>,
+    profile_id: Option<i64>,
     handle: AppHandle,
 ) -> Result<(), Error> {
     debug!(
@@ -466,25 +2100,90 @@ pub async fn handle_connection_for_location(
         location.name
     );
     let state = handle.state::<AppState>();
-    #[cfg(target_os = "macos")]
-    let interface_name = get_interface_name();
+    let interface_name = resolve_interface_name(&state, location)
+        .await?
+        .interface_name;
     #[cfg(not(target_os = "macos"))]
-    let interface_name = get_interface_name(&location.name);
-    setup_interface(
-        location,
-        interface_name.clone(),
-        preshared_key,
-        &state.get_pool(),
-        state.client.clone(),
-    )
-    .await?;
-    let address = local_ip()?;
-    let connection = ActiveConnection::new(
+    let interface_name = match profile_id {
+        Some(profile_id) => profile_interface_name(&interface_name, profile_id),
+        None => interface_name,
+    };
+    let timeout = Settings::connection_timeout(&state.get_pool()).await;
+    let preferred_relay = Instance::find_by_id(&state.get_pool(), location.instance_id)
+        .await?
+        .and_then(|instance| instance.preferred_relay);
+    // endpoints of the pool are tried in order until one of them completes a handshake
+    let endpoints = location_endpoints(location, preferred_relay.as_deref());
+    let mut result = Err(Error::EndpointResolution(location.endpoint.clone()));
+    let mut connected_endpoint = None;
+    let location_id = location.id.expect("Missing Location ID");
+    let cancel = state.begin_connect(location_id, profile_id);
+    for (index, endpoint) in endpoints.iter().enumerate() {
+        let candidate = Location {
+            endpoint: endpoint.clone(),
+            // IP override belongs to the primary endpoint only
+            endpoint_ip_override: location
+                .endpoint_ip_override
+                .clone()
+                .filter(|_| *endpoint == location.endpoint),
+            ..location.clone()
+        };
+        result = connect_location_endpoint(
+            &state,
+            &candidate,
+            &interface_name,
+            preshared_key.clone(),
+            profile_id,
+            timeout,
+            &cancel,
+        )
+        .await;
+        match &result {
+            Ok(_) => {
+                match preferred_relay.as_deref() {
+                    Some(relay) if !served_by_relay(endpoint, relay) => warn!(
+                        "Preferred relay {relay} is unavailable, location {location} connected through {endpoint}"
+                    ),
+                    _ if index > 0 => {
+                        info!("Location {location} connected through backup endpoint {endpoint}");
+                    }
+                    _ => {}
+                }
+                connected_endpoint = Some(endpoint.clone());
+                break;
+            }
+            Err(Error::Cancelled) => {
+                info!("Connecting location {location} cancelled");
+                break;
+            }
+            Err(err) if index + 1 < endpoints.len() => {
+                warn!("Failed to connect location {location} through endpoint {endpoint}, trying next endpoint: {err}");
+            }
+            Err(_) => {}
+        }
+    }
+    state.record_capture(
+        CaptureEntryKind::Rpc,
+        "create_interface",
+        json!({ "interface_name": interface_name, "result": format!("{result:?}") }),
+    );
+    state.finish_connect(location_id, profile_id);
+    if !matches!(result, Err(Error::Cancelled)) {
+        let endpoint = result.as_ref().ok().map(|interface| interface.endpoint);
+        update_sticky_endpoint(&state.get_pool(), location, endpoint).await;
+    }
+    let InterfaceSetup { endpoint, pubkey } = result?;
+    let address = local_address_for(endpoint)?;
+    let mut connection = ActiveConnection::new(
         location.id.expect("Missing Location ID"),
         address.to_string(),
         interface_name.clone(),
         ConnectionType::Location,
     );
+    connection.profile_id = profile_id;
+    connection.endpoint = connected_endpoint;
+    connection.resolved_endpoint = Some(endpoint.to_string());
+    connection.pubkey = Some(pubkey);
     state
         .active_connections
         .lock()
@@ -497,13 +2196,20 @@ pub async fn handle_connection_for_location(
             .lock()
             .map_err(|_| Error::MutexError)?
     );
-    debug!("Sending event connection-changed.");
-    handle.emit_all(
-        "connection-changed",
-        Payload {
-            message: "Created new connection".into(),
-        },
-    )?;
+
+    if check_location_ipv6_leak(location) {
+        warn!(
+            "Location {location} tunnels only IPv4 traffic but host has native IPv6 connectivity"
+        );
+        emit_event(
+            &handle,
+            "ipv6-leak-detected",
+            Ipv6LeakPayload {
+                location_id: location.id.expect("Missing Location ID"),
+                interface_name: interface_name.clone(),
+            },
+        )?;
+    }
 
     // Spawn stats threads
     debug!("Spawning stats thread");
@@ -511,6 +2217,7 @@ pub async fn handle_connection_for_location(
         handle.clone(),
         interface_name.clone(),
         ConnectionType::Location,
+        false,
     )
     .await;
 
@@ -520,8 +2227,8 @@ pub async fn handle_connection_for_location(
         location.id.expect("Missing Location ID"),
         interface_name,
         ConnectionType::Location,
-        Level::DEBUG,
         None,
+        LogFormat::Text,
     )
     .await?;
     Ok(())
@@ -538,14 +2245,27 @@ pub async fn handle_connection_for_tunnel(tunnel: &Tunnel, handle: AppHandle) ->
     let interface_name = get_interface_name();
     #[cfg(not(target_os = "macos"))]
     let interface_name = get_interface_name(&tunnel.name);
-    setup_interface_tunnel(tunnel, interface_name.clone(), state.client.clone()).await?;
-    let address = local_ip()?;
-    let connection = ActiveConnection::new(
+    let result = setup_interface_tunnel(
+        tunnel,
+        interface_name.clone(),
+        &state.get_pool(),
+        state.client(),
+    )
+    .await;
+    state.record_capture(
+        CaptureEntryKind::Rpc,
+        "create_interface",
+        json!({ "interface_name": interface_name, "result": format!("{result:?}") }),
+    );
+    let endpoint = result?;
+    let address = local_address_for(endpoint)?;
+    let mut connection = ActiveConnection::new(
         tunnel.id.expect("Missing Tunnel ID"),
         address.to_string(),
         interface_name.clone(),
         ConnectionType::Tunnel,
     );
+    connection.resolved_endpoint = Some(endpoint.to_string());
     state
         .active_connections
         .lock()
@@ -559,12 +2279,11 @@ pub async fn handle_connection_for_tunnel(tunnel: &Tunnel, handle: AppHandle) ->
             .map_err(|_| Error::MutexError)?
     );
     debug!("Sending event connection-changed.");
-    handle.emit_all(
-        "connection-changed",
-        Payload {
-            message: "Created new connection".into(),
-        },
-    )?;
+    let payload = Payload {
+        message: "Created new connection".into(),
+    };
+    emit_event(&handle, "connection-changed", payload)?;
+    refresh_tray(&handle);
 
     // Spawn stats threads
     info!("Spawning stats thread");
@@ -572,6 +2291,7 @@ pub async fn handle_connection_for_tunnel(tunnel: &Tunnel, handle: AppHandle) ->
         handle.clone(),
         interface_name.clone(),
         ConnectionType::Tunnel,
+        false,
     )
     .await;
 
@@ -581,30 +2301,651 @@ pub async fn handle_connection_for_tunnel(tunnel: &Tunnel, handle: AppHandle) ->
         tunnel.id.expect("Missing Tunnel ID"),
         interface_name,
         ConnectionType::Tunnel,
-        Level::DEBUG,
         None,
+        LogFormat::Text,
     )
     .await?;
     Ok(())
 }
-/// Execute command passed as argument.
-pub fn execute_command(command: &str) -> Result<(), Error> {
-    let mut command_parts = command.split_whitespace();
 
-    if let Some(command) = command_parts.next() {
-        let output = Command::new(command).args(command_parts).output()?;
+/// Splits comma-separated DNS server list from location config.
+fn dns_servers(location: &Location) -> Result<Vec<IpAddr>, Error> {
+    parse_dns_servers(location.dns.as_deref())
+}
 
-        if output.status.success() {
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            let stderr = String::from_utf8_lossy(&output.stderr);
+/// Parses comma-separated list of DNS server addresses.
+pub fn parse_dns_servers(dns: Option<&str>) -> Result<Vec<IpAddr>, Error> {
+    dns.iter()
+        .flat_map(|dns| dns.split(','))
+        .map(|server| {
+            IpAddr::from_str(server.trim())
+                .map_err(|_| Error::InvalidDns(format!("invalid DNS server address: {server}")))
+        })
+        .collect()
+}
 
-            info!("Command executed successfully. Stdout:\n{}", stdout);
-            if !stderr.is_empty() {
-                error!("Stderr:\n{stderr}");
+/// Sends `fallback_dns` setting to `defguard-service`, which applies it while no tunnel is up
+/// and restores system DNS before the next one connects. Failure is only logged,
+/// system DNS is kept then.
+pub async fn push_fallback_dns(fallback_dns: Option<&str>) {
+    let servers = fallback_dns
+        .iter()
+        .flat_map(|dns| dns.split(','))
+        .map(|server| server.trim().to_string())
+        .filter(|server| !server.is_empty())
+        .collect();
+    if let Err(err) = set_fallback_dns(servers).await {
+        warn!("Failed to send fallback DNS to defguard-service: {err}");
+    }
+}
+
+/// DNS servers used when no interface overrides them, read from `/etc/resolv.conf`.
+#[cfg(unix)]
+#[must_use]
+pub fn system_dns_servers() -> Vec<IpAddr> {
+    match std::fs::read_to_string(RESOLV_CONF) {
+        Ok(resolv_conf) => parse_resolv_conf(&resolv_conf),
+        Err(err) => {
+            warn!("Failed to read {RESOLV_CONF}: {err}");
+            Vec::new()
+        }
+    }
+}
+
+/// System DNS servers can't be read on Windows, so the list is always empty.
+#[cfg(not(unix))]
+#[must_use]
+pub fn system_dns_servers() -> Vec<IpAddr> {
+    Vec::new()
+}
+
+#[cfg(unix)]
+fn parse_resolv_conf(resolv_conf: &str) -> Vec<IpAddr> {
+    resolv_conf
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        .filter_map(|server| IpAddr::from_str(server.trim()).ok())
+        .collect()
+}
+
+/// Splits DNS entries received from defguard into server addresses and search domains.
+/// Like in wg-quick configs, entries which aren't IP addresses are used as search domains.
+#[must_use]
+pub fn split_dns_entries(dns: Option<&str>) -> (Option<String>, Option<String>) {
+    let (servers, domains): (Vec<&str>, Vec<&str>) = dns
+        .iter()
+        .flat_map(|dns| dns.split(','))
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .partition(|entry| IpAddr::from_str(entry).is_ok());
+    let join = |entries: Vec<&str>| (!entries.is_empty()).then(|| entries.join(","));
+    (join(servers), join(domains))
+}
+
+/// Parses comma-separated list of DNS search domains.
+pub fn parse_dns_search(dns_search: Option<&str>) -> Result<Vec<String>, Error> {
+    dns_search
+        .iter()
+        .flat_map(|dns_search| dns_search.split(','))
+        .map(str::trim)
+        .filter(|domain| !domain.is_empty())
+        .map(|domain| {
+            if is_valid_domain(domain) {
+                Ok(domain.to_string())
+            } else {
+                Err(Error::InvalidDns(format!(
+                    "invalid DNS search domain: {domain}"
+                )))
+            }
+        })
+        .collect()
+}
+
+fn is_valid_domain(domain: &str) -> bool {
+    !domain.is_empty()
+        && domain.len() <= 253
+        && domain.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/// Validates encrypted DNS resolver settings of a location.
+pub fn validate_encrypted_dns(doh_url: Option<&str>, dot_host: Option<&str>) -> Result<(), Error> {
+    if let Some(doh_url) = doh_url {
+        let url = reqwest::Url::parse(doh_url).map_err(|err| {
+            Error::InvalidDns(format!("invalid DNS-over-HTTPS URL {doh_url}: {err}"))
+        })?;
+        if url.scheme() != "https" || url.host_str().is_none() {
+            return Err(Error::InvalidDns(format!(
+                "DNS-over-HTTPS URL must use https scheme and contain a host: {doh_url}"
+            )));
+        }
+        // IP address hosts have no domain
+        let valid_host = url.domain().map_or(true, is_valid_domain);
+        // URL is passed to system tools, so only characters of plain URL templates are allowed
+        if !valid_host
+            || !doh_url
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || DOH_URL_CHARS.contains(c))
+        {
+            return Err(Error::InvalidDns(format!(
+                "invalid DNS-over-HTTPS URL: {doh_url}"
+            )));
+        }
+    }
+    if let Some(dot_host) = dot_host {
+        if !is_valid_domain(dot_host) {
+            return Err(Error::InvalidDns(format!(
+                "invalid DNS-over-TLS host name: {dot_host}"
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Commands executed by `defguard-service`, each given as program followed by its arguments.
+pub type Commands = Vec<Vec<String>>;
+
+/// Builds argument vector of a command from values of any displayable type.
+macro_rules! argv {
+    ($($arg:expr),* $(,)?) => {
+        vec![$($arg.to_string()),*]
+    };
+}
+
+/// Returns command setting DNS search domains of location interface.
+///
+/// Domains are configured with `systemd-resolved` on Linux. Windows supports a single
+/// connection-specific suffix, so only the first domain is used there.
+pub fn dns_search_command(location: &Location, interface_name: &str) -> Result<Commands, Error> {
+    let domains = parse_dns_search(location.dns_search.as_deref())?;
+    if domains.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        let mut command = argv!["resolvectl", "domain", interface_name];
+        command.extend(domains);
+        Ok(vec![command])
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        if domains.len() > 1 {
+            warn!(
+                "Only first DNS search domain {} is used for location {location}",
+                domains[0]
+            );
+        }
+        // both values are validated, so they can't break out of the quoted strings
+        Ok(vec![argv![
+            "powershell",
+            "-NoProfile",
+            "-Command",
+            format!(
+                "Set-DnsClient -InterfaceAlias '{interface_name}' -ConnectionSpecificSuffix '{}'",
+                domains[0]
+            )
+        ]])
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        // search domains are configured per network service, which utun interfaces don't have
+        let _ = interface_name;
+        warn!("DNS search domains of location {location} are not supported on macOS");
+        Ok(Vec::new())
+    }
+}
+
+/// Prepares commands executed by `defguard-service` which enable encrypted DNS on location interface
+/// and revert it before the interface is removed. Returns `(post_up, pre_down)` commands.
+///
+/// DNS-over-TLS is configured with `systemd-resolved` on Linux, DNS-over-HTTPS is configured
+/// with `netsh` on Windows.
+pub fn encrypted_dns_commands(
+    location: &Location,
+    interface_name: &str,
+) -> Result<(Commands, Commands), Error> {
+    if location.doh_url.is_none() && location.dot_host.is_none() {
+        return Ok((Vec::new(), Vec::new()));
+    }
+    validate_encrypted_dns(location.doh_url.as_deref(), location.dot_host.as_deref())?;
+    let servers = dns_servers(location)?;
+    if servers.is_empty() {
+        return Err(Error::InvalidDns(
+            "encrypted DNS requires a DNS server address".into(),
+        ));
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        if location.doh_url.is_some() {
+            return Err(Error::Unsupported("DNS-over-HTTPS".into()));
+        }
+        let dot_host = location.dot_host.as_deref().unwrap_or_default();
+        let mut set_dns = argv!["resolvectl", "dns", interface_name];
+        set_dns.extend(servers.iter().map(|server| format!("{server}#{dot_host}")));
+        let post_up = vec![
+            set_dns,
+            argv!["resolvectl", "dnsovertls", interface_name, "yes"],
+        ];
+        let pre_down = vec![argv!["resolvectl", "revert", interface_name]];
+        Ok((post_up, pre_down))
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        if location.dot_host.is_some() {
+            return Err(Error::Unsupported("DNS-over-TLS".into()));
+        }
+        let doh_url = location.doh_url.as_deref().unwrap_or_default();
+        let post_up = servers
+            .iter()
+            .map(|server| {
+                argv![
+                    "netsh",
+                    "dns",
+                    "add",
+                    "encryption",
+                    format!("server={server}"),
+                    format!("dohtemplate={doh_url}"),
+                    "autoupgrade=yes",
+                    "udpfallback=no"
+                ]
+            })
+            .collect();
+        let pre_down = servers
+            .iter()
+            .map(|server| {
+                argv![
+                    "netsh",
+                    "dns",
+                    "delete",
+                    "encryption",
+                    format!("server={server}")
+                ]
+            })
+            .collect();
+        Ok((post_up, pre_down))
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "windows")))]
+    {
+        let _ = (servers, interface_name);
+        Err(Error::Unsupported("encrypted DNS".into()))
+    }
+}
+
+/// Prepares commands executed by `defguard-service` which block all traffic not going through
+/// location interface (kill switch) and remove the block before the interface is removed.
+/// Returns `(post_up, pre_down)` commands. Kill switch is used only when all traffic is routed
+/// through the location.
+///
+/// Rules are kept in a dedicated `iptables` and `ip6tables` chain on Linux, so they can be
+/// removed without knowing the endpoint address.
+pub fn kill_switch_commands(
+    location: &Location,
+    interface_name: &str,
+    endpoint: Option<SocketAddr>,
+) -> Result<(Commands, Commands), Error> {
+    if !(location.kill_switch && location.route_all_traffic) {
+        return Ok((Vec::new(), Vec::new()));
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        let chain = format!("{KILL_SWITCH_CHAIN_PREFIX}{interface_name}");
+        let mut post_up = Vec::new();
+        let mut pre_down = Vec::new();
+        for (iptables, is_ipv4) in [("iptables", true), ("ip6tables", false)] {
+            post_up.push(argv![iptables, "-N", chain]);
+            post_up.push(argv![
+                iptables,
+                "-A",
+                chain,
+                "-o",
+                interface_name,
+                "-j",
+                "ACCEPT"
+            ]);
+            post_up.push(argv![iptables, "-A", chain, "-o", "lo", "-j", "ACCEPT"]);
+            // WireGuard traffic to the endpoint itself goes through physical interface
+            if let Some(endpoint) = endpoint.filter(|endpoint| endpoint.is_ipv4() == is_ipv4) {
+                post_up.push(argv![
+                    iptables,
+                    "-A",
+                    chain,
+                    "-d",
+                    endpoint.ip(),
+                    "-p",
+                    "udp",
+                    "--dport",
+                    endpoint.port(),
+                    "-j",
+                    "ACCEPT"
+                ]);
+            }
+            post_up.push(argv![iptables, "-A", chain, "-j", "REJECT"]);
+            post_up.push(argv![iptables, "-I", "OUTPUT", "-j", chain]);
+            pre_down.push(argv![iptables, "-D", "OUTPUT", "-j", chain]);
+            pre_down.push(argv![iptables, "-F", chain]);
+            pre_down.push(argv![iptables, "-X", chain]);
+        }
+        Ok((post_up, pre_down))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (interface_name, endpoint);
+        Err(Error::Unsupported("kill switch".into()))
+    }
+}
+
+/// Host routes to DNS servers of location forcing DNS through the tunnel, so queries reach
+/// them only through the tunnel. Servers must be reachable inside the tunnel, otherwise
+/// name resolution stops working while location is connected.
+fn tunnel_dns_routes(location: &Location) -> Result<Vec<String>, Error> {
+    if !location.force_tunnel_dns {
+        return Ok(Vec::new());
+    }
+    let routes = dns_servers(location)?
+        .into_iter()
+        .map(|server| match server {
+            IpAddr::V4(_) => format!("{server}/32"),
+            IpAddr::V6(_) => format!("{server}/128"),
+        })
+        .collect();
+    Ok(routes)
+}
+
+/// Commands blocking DNS queries which don't go through location tunnel, returns
+/// `(post_up, pre_down)` commands. Queries to local stub resolvers are still allowed,
+/// as they're forwarded to DNS servers configured on the interface.
+///
+/// Rules are kept in a dedicated `iptables` and `ip6tables` chain on Linux, like kill switch.
+pub fn tunnel_dns_commands(
+    location: &Location,
+    interface_name: &str,
+) -> Result<(Commands, Commands), Error> {
+    if !location.force_tunnel_dns {
+        return Ok((Vec::new(), Vec::new()));
+    }
+    if dns_servers(location)?.is_empty() {
+        return Err(Error::InvalidDns(
+            "forcing DNS through tunnel requires a DNS server address".into(),
+        ));
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        let chain = format!("{TUNNEL_DNS_CHAIN_PREFIX}{interface_name}");
+        let mut post_up = Vec::new();
+        let mut pre_down = Vec::new();
+        for iptables in ["iptables", "ip6tables"] {
+            post_up.push(argv![iptables, "-N", chain]);
+            post_up.push(argv![
+                iptables,
+                "-A",
+                chain,
+                "-o",
+                interface_name,
+                "-j",
+                "RETURN"
+            ]);
+            post_up.push(argv![iptables, "-A", chain, "-o", "lo", "-j", "RETURN"]);
+            for protocol in ["udp", "tcp"] {
+                post_up.push(argv![
+                    iptables, "-A", chain, "-p", protocol, "--dport", "53", "-j", "REJECT"
+                ]);
+            }
+            post_up.push(argv![iptables, "-I", "OUTPUT", "-j", chain]);
+            pre_down.push(argv![iptables, "-D", "OUTPUT", "-j", chain]);
+            pre_down.push(argv![iptables, "-F", chain]);
+            pre_down.push(argv![iptables, "-X", chain]);
+        }
+        Ok((post_up, pre_down))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = interface_name;
+        Err(Error::Unsupported("forcing DNS through tunnel".into()))
+    }
+}
+
+/// Returns command setting MTU of location interface. Without configured MTU
+/// it's left to be auto-detected when interface is created.
+pub fn mtu_command(location: &Location, interface_name: &str) -> Commands {
+    let Some(mtu) = location.mtu else {
+        return Vec::new();
+    };
+
+    #[cfg(target_os = "linux")]
+    {
+        vec![argv![
+            "ip",
+            "link",
+            "set",
+            "dev",
+            interface_name,
+            "mtu",
+            mtu
+        ]]
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        vec![argv!["ifconfig", interface_name, "mtu", mtu]]
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        ["ipv4", "ipv6"]
+            .into_iter()
+            .map(|family| {
+                argv![
+                    "netsh",
+                    "interface",
+                    family,
+                    "set",
+                    "subinterface",
+                    interface_name,
+                    format!("mtu={mtu}"),
+                    "store=active"
+                ]
+            })
+            .collect()
+    }
+}
+
+/// Validates URL of service echoing public IP of the caller, e.g. `https://api.ipify.org`.
+pub fn validate_echo_url(url: &str) -> Result<(), Error> {
+    let parsed =
+        reqwest::Url::parse(url).map_err(|err| Error::InvalidUrl(format!("{url}: {err}")))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(Error::InvalidUrl(format!(
+            "{url} has unsupported scheme {}, use http or https",
+            parsed.scheme()
+        )));
+    }
+    if parsed.host_str().is_none() {
+        return Err(Error::InvalidUrl(format!("{url} is missing host")));
+    }
+    Ok(())
+}
+
+/// Asks echo service for public IP of requests sent from tunnel address. Only the source
+/// address is bound, so the request leaves through the tunnel only if routing table sends
+/// echo service address there, i.e. for locations routing all traffic. Configured proxy
+/// is bypassed, as it would hide the egress IP.
+pub async fn fetch_public_ip(echo_url: &str, tunnel_address: IpAddr) -> Result<IpAddr, Error> {
+    let request_error = |err: reqwest::Error| {
+        warn!("Public IP request to {echo_url} failed: {err}");
+        Error::CommandError(format!("Public IP request failed: {err}"))
+    };
+    let client = reqwest::Client::builder()
+        .local_address(tunnel_address)
+        .no_proxy()
+        .timeout(PUBLIC_IP_TIMEOUT)
+        .build()
+        .map_err(request_error)?;
+    let body = client
+        .get(echo_url)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(request_error)?
+        .text()
+        .await
+        .map_err(request_error)?;
+    IpAddr::from_str(body.trim()).map_err(|_| {
+        warn!("Public IP service {echo_url} returned unexpected response: {body}");
+        Error::CommandError(format!("{echo_url} didn't return an IP address"))
+    })
+}
+
+/// Validates URL of defguard instance and returns it without trailing slashes.
+/// Plaintext HTTP is only accepted if `allow_insecure` is set.
+pub fn normalize_instance_url(url: &str, allow_insecure: bool) -> Result<String, Error> {
+    let parsed = reqwest::Url::parse(url.trim())
+        .map_err(|err| Error::InvalidUrl(format!("{url}: {err}")))?;
+    match parsed.scheme() {
+        "https" => {}
+        "http" if allow_insecure => warn!("Using insecure instance URL {url}"),
+        "http" => {
+            return Err(Error::InvalidUrl(format!(
+                "{url} uses plaintext HTTP, enable insecure URLs in settings to allow it"
+            )))
+        }
+        scheme => {
+            return Err(Error::InvalidUrl(format!(
+                "{url} has unsupported scheme {scheme}, use https"
+            )))
+        }
+    }
+    if parsed.host_str().is_none() {
+        return Err(Error::InvalidUrl(format!("{url} is missing host")));
+    }
+    Ok(parsed.as_str().trim_end_matches('/').to_string())
+}
+
+/// Normalizes new URL of instance stored with `stored` URL. Plaintext HTTP was accepted
+/// before `allow_insecure` setting existed, so instances already using it keep working.
+pub fn normalize_stored_instance_url(
+    url: &str,
+    stored: &str,
+    allow_insecure: bool,
+) -> Result<String, Error> {
+    let grandfathered =
+        reqwest::Url::parse(stored.trim()).is_ok_and(|stored| stored.scheme() == "http");
+    normalize_instance_url(url, allow_insecure || grandfathered)
+}
+
+/// Checks if MTU can be used for WireGuard interface.
+pub fn validate_mtu(mtu: u32) -> Result<(), Error> {
+    if (MIN_MTU..=MAX_MTU).contains(&mtu) {
+        Ok(())
+    } else {
+        Err(Error::CommandError(format!(
+            "MTU must be between {MIN_MTU} and {MAX_MTU}"
+        )))
+    }
+}
+
+/// Encodes commands for `defguard-service` as JSON list of argument vectors, so none of
+/// the values is ever split or interpreted by a shell. See `execute_command`.
+fn encode_commands<const N: usize>(commands: [Commands; N]) -> Option<String> {
+    let commands: Commands = commands.into_iter().flatten().collect();
+    if commands.is_empty() {
+        None
+    } else {
+        serde_json::to_string(&commands).ok()
+    }
+}
+
+/// Checks if executable with given name can be found in `PATH`.
+#[cfg(any(target_os = "windows", target_os = "macos"))]
+fn find_in_path(name: &str) -> bool {
+    std::env::var_os("PATH")
+        .is_some_and(|paths| std::env::split_paths(&paths).any(|dir| dir.join(name).is_file()))
+}
+
+/// Verifies that WireGuard backend used on this platform is available.
+/// Returns `Error::DriverMissing` with installation guidance otherwise.
+pub fn check_wireguard_backend() -> Result<(), Error> {
+    #[cfg(target_os = "linux")]
+    {
+        // module is either loaded, built into the kernel or can be loaded on demand
+        let available = std::path::Path::new("/sys/module/wireguard").exists()
+            || Command::new("modprobe")
+                .args(["--dry-run", "wireguard"])
+                .status()
+                .is_ok_and(|status| status.success());
+        if !available {
+            return Err(Error::DriverMissing(
+                "WireGuard kernel module not found. Use Linux kernel 5.6 or newer, or install \
+                WireGuard module package for your distribution (e.g. wireguard-dkms)."
+                    .into(),
+            ));
+        }
+    }
+    #[cfg(target_os = "windows")]
+    {
+        let available = find_in_path("wireguard.exe")
+            || PathBuf::from(r"C:\Program Files\WireGuard\wireguard.exe").is_file();
+        if !available {
+            return Err(Error::DriverMissing(
+                "WireGuard for Windows not found. Install it from https://www.wireguard.com/install/"
+                    .into(),
+            ));
+        }
+    }
+    #[cfg(target_os = "macos")]
+    {
+        if !find_in_path("wireguard-go") {
+            return Err(Error::DriverMissing(
+                "Bundled wireguard-go binary not found. Reinstall defguard client.".into(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Splits commands passed to `defguard-service` into argument vectors. Commands prepared by
+/// the client are a JSON list of argument vectors, anything else is a single command
+/// configured by user (e.g. tunnel `PreUp`) with arguments separated by whitespace.
+fn decode_commands(command: &str) -> Commands {
+    serde_json::from_str(command).unwrap_or_else(|_| {
+        vec![command
+            .split_whitespace()
+            .map(ToString::to_string)
+            .collect()]
+    })
+}
+
+/// Execute command passed as argument, see `decode_commands`.
+/// Programs are executed directly, never through a shell.
+pub fn execute_command(command: &str) -> Result<(), Error> {
+    for args in decode_commands(command) {
+        if let Some((program, args)) = args.split_first() {
+            let output = Command::new(program).args(args).output()?;
+
+            if output.status.success() {
+                let stdout = String::from_utf8_lossy(&output.stdout);
+                let stderr = String::from_utf8_lossy(&output.stderr);
+
+                info!("Command executed successfully. Stdout:\n{}", stdout);
+                if !stderr.is_empty() {
+                    error!("Stderr:\n{stderr}");
+                }
+            } else {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                error!("Error executing command {program}. Stderr:\n{stderr}");
             }
-        } else {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            error!("Error executing command. Stderr:\n{stderr}");
         }
     }
     Ok(())
@@ -613,9 +2954,10 @@ pub fn execute_command(command: &str) -> Result<(), Error> {
 pub async fn disconnect_interface(
     active_connection: ActiveConnection,
     state: &AppState,
+    reason: DisconnectReason,
 ) -> Result<(), Error> {
-    debug!("Removing interface");
-    let mut client = state.client.clone();
+    debug!("Removing interface, reason: {reason:?}");
+    let mut client = state.client();
     let interface_name = active_connection.interface_name.clone();
     let (id, connection_type) = (
         active_connection.location_id,
@@ -623,16 +2965,27 @@ pub async fn disconnect_interface(
     );
     match active_connection.connection_type {
         ConnectionType::Location => {
+            let pre_down = Location::find_by_id(&state.get_pool(), id)
+                .await?
+                .and_then(|location| location_pre_down(&location, &interface_name));
+            // endpoint has to be read while the interface still exists
+            let resolved_endpoint = read_peer_endpoint(client.clone(), &interface_name).await;
             let request = RemoveInterfaceRequest {
                 interface_name: interface_name.clone(),
-                pre_down: None,
+                pre_down,
                 post_down: None,
             };
-            if let Err(error) = client.remove_interface(request).await {
+            if let Err(error) = remove_interface_with_retry(&mut client, request, state).await {
                 error!("Failed to remove interface: {error}");
-                return Err(Error::InternalError);
+                let mut connection =
+                    active_connection.into_connection(DisconnectReason::ServiceError);
+                connection.resolved_endpoint = resolved_endpoint.or(connection.resolved_endpoint);
+                connection.save(&state.get_pool()).await?;
+                return Err(Error::remove_interface(&error));
             }
-            let mut connection: Connection = active_connection.into();
+            // peer may have roamed since connecting, current endpoint is preferred
+            let mut connection = active_connection.into_connection(reason);
+            connection.resolved_endpoint = resolved_endpoint.or(connection.resolved_endpoint);
             connection.save(&state.get_pool()).await?;
             trace!("Saved connection: {connection:#?}");
             debug!("Removed interface");
@@ -648,11 +3001,14 @@ pub async fn disconnect_interface(
                     pre_down: tunnel.pre_down,
                     post_down: tunnel.post_down,
                 };
-                if let Err(error) = client.remove_interface(request).await {
+                if let Err(error) = remove_interface_with_retry(&mut client, request, state).await {
                     error!("Failed to remove interface: {error}");
-                    return Err(Error::InternalError);
+                    let mut connection =
+                        active_connection.into_tunnel_connection(DisconnectReason::ServiceError);
+                    connection.save(&state.get_pool()).await?;
+                    return Err(Error::remove_interface(&error));
                 }
-                let mut connection: TunnelConnection = active_connection.into();
+                let mut connection = active_connection.into_tunnel_connection(reason);
                 connection.save(&state.get_pool()).await?;
                 trace!("Saved connection: {connection:#?}");
             } else {
@@ -662,6 +3018,797 @@ pub async fn disconnect_interface(
         }
     }
 
+    // persist stats buffered for this connection, failed flush is retried by flush loop
+    if let Err(err) = state.stats_buffer.flush(&state.get_pool()).await {
+        error!("Failed to flush stats of disconnected location {id}: {err}");
+    }
+
     info!("Location {} {:?} disconnected", id, connection_type);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn location() -> Location {
+        Location {
+            id: Some(1),
+            instance_id: 1,
+            network_id: 1,
+            name: "Office".into(),
+            address: "10.0.0.2/24".into(),
+            pubkey: "BvUB3iZq3U0jZrY6b4KbGhz0IVZzpAdbJiRZGdci9ZU=".into(),
+            endpoint: "vpn.example.com:51820".into(),
+            allowed_ips: "10.0.0.0/24".into(),
+            dns: None,
+            route_all_traffic: false,
+            mfa_enabled: false,
+            keepalive_interval: 25,
+            doh_url: None,
+            dot_host: None,
+            metadata: None,
+            endpoint_ip_override: None,
+            kill_switch: false,
+            mtu: None,
+            autoconnect: false,
+            dns_search: None,
+            favorite: false,
+            sort_order: 0,
+            preshared_key: None,
+            display_name: None,
+            interface_name: None,
+            extra_routes: None,
+            failover_group: None,
+            failover_priority: 0,
+            schedule: None,
+            sticky_endpoint: None,
+            force_tunnel_dns: false,
+            endpoints: None,
+            imported: false,
+        }
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    #[test]
+    fn test_truncate_interface_name() {
+        assert_eq!(truncate_interface_name("Office", 15), "Office");
+        assert_eq!(
+            truncate_interface_name("HeadquartersWarsaw", 15),
+            "HeadquartersWar"
+        );
+        // multi-byte characters are never split
+        assert_eq!(truncate_interface_name("Zażółć", 5), "Zaż");
+        assert_eq!(
+            profile_interface_name("HeadquartersWar", 12),
+            "Headquarter-p12"
+        );
+    }
+
+    #[test]
+    fn test_split_endpoint() {
+        assert_eq!(
+            split_endpoint("vpn.example.com:51821").unwrap(),
+            ("vpn.example.com", 51821)
+        );
+        assert_eq!(
+            split_endpoint("10.0.0.1:51821").unwrap(),
+            ("10.0.0.1", 51821)
+        );
+        assert_eq!(split_endpoint("[::1]:51821").unwrap(), ("::1", 51821));
+        assert_eq!(
+            split_endpoint("[2001:db8::1]").unwrap(),
+            ("2001:db8::1", DEFAULT_WIREGUARD_PORT)
+        );
+        // bare IPv6 address is not mistaken for `host:port`
+        assert_eq!(
+            split_endpoint("2001:db8::1").unwrap(),
+            ("2001:db8::1", DEFAULT_WIREGUARD_PORT)
+        );
+        assert_eq!(
+            split_endpoint("vpn.example.com").unwrap(),
+            ("vpn.example.com", DEFAULT_WIREGUARD_PORT)
+        );
+        assert!(split_endpoint("[::1]51821").is_err());
+        assert!(split_endpoint("[::1]:port").is_err());
+        assert!(split_endpoint(":51821").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_ip_endpoint() {
+        assert_eq!(
+            resolve_endpoint("[::1]:51820", &EndpointResolution::Auto, None)
+                .await
+                .unwrap(),
+            SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 51820)
+        );
+        assert_eq!(
+            resolve_endpoint("2001:db8::1", &EndpointResolution::Auto, None)
+                .await
+                .unwrap(),
+            "[2001:db8::1]:51820".parse().unwrap()
+        );
+        assert_eq!(
+            resolve_endpoint("127.0.0.1:51821", &EndpointResolution::Auto, None)
+                .await
+                .unwrap(),
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 51821)
+        );
+    }
+
+    #[test]
+    fn test_select_dual_stack_endpoint() {
+        let ipv4: SocketAddr = "192.0.2.1:51820".parse().unwrap();
+        let ipv6: SocketAddr = "[2001:db8::1]:51820".parse().unwrap();
+        let addresses = [ipv4, ipv6];
+        assert_eq!(select_endpoint_address(&addresses, true), Some(ipv6));
+        assert_eq!(select_endpoint_address(&addresses, false), Some(ipv4));
+        // single family is used regardless of preference
+        assert_eq!(select_endpoint_address(&[ipv6], false), Some(ipv6));
+        assert_eq!(select_endpoint_address(&[ipv4], true), Some(ipv4));
+        assert_eq!(select_endpoint_address(&[], true), None);
+    }
+
+    #[test]
+    fn test_parse_allowed_ips() {
+        let networks = parse_allowed_ips("10.1.2.3/24, 10.1.2.0/24,fd00::1/64,0.0.0.0/0").unwrap();
+        let networks: Vec<String> = networks.iter().map(ToString::to_string).collect();
+        assert_eq!(networks, ["10.1.2.0/24", "fd00::/64", "0.0.0.0/0"]);
+        assert!(parse_allowed_ips("10.1.2.0/24,10.1.2.0").is_err());
+        assert!(parse_allowed_ips("10.1.2.0/33").is_err());
+        assert!(parse_allowed_ips("fd00::/129").is_err());
+        assert!(parse_allowed_ips("10.1.2/24").is_err());
+        assert!(parse_allowed_ips("").is_err());
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    #[test]
+    fn test_get_interface_name() {
+        assert_eq!(get_interface_name("Office VPN"), "OfficeVPN");
+        assert_eq!(get_interface_name("vpn'; reboot #"), "vpnreboot");
+        assert_eq!(get_interface_name("Zażółć"), "Za");
+        assert_eq!(get_interface_name("Біро"), "wg");
+        assert!(validate_interface_name("wg-office.2").is_ok());
+        assert!(validate_interface_name("wg0$(reboot)").is_err());
+        assert!(validate_interface_name("").is_err());
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    #[test]
+    fn test_interface_name_template() {
+        assert!(validate_interface_name_template("dg-{location}").is_ok());
+        assert!(validate_interface_name_template("{instance}-{location}").is_ok());
+        assert!(validate_interface_name_template("dg-{instance}").is_err());
+        assert!(validate_interface_name_template("dg {location}").is_err());
+        assert!(validate_interface_name_template("defguard-vpn{location}").is_err());
+        assert_eq!(
+            render_interface_name_template("dg-{location}", "Office VPN", "corp"),
+            "dg-OfficeVPN"
+        );
+        // values of placeholders are sanitized like generated names
+        let rendered = render_interface_name_template("{location}.{instance}", "a';b", "c$(d)");
+        assert_eq!(rendered, "ab.cd");
+        assert!(validate_interface_name(&rendered).is_ok());
+    }
+
+    #[test]
+    fn test_parse_extra_routes() {
+        let routes = parse_extra_routes("10.2.0.1/32\n 10.3.0.0/16,\n", false).unwrap();
+        let routes: Vec<String> = routes.iter().map(ToString::to_string).collect();
+        assert_eq!(routes, ["10.2.0.1/32", "10.3.0.0/16"]);
+        assert!(parse_extra_routes(" \n", false).unwrap().is_empty());
+        assert!(parse_extra_routes("10.2.0.1", false).is_err());
+        assert!(parse_extra_routes("0.0.0.0/0", false).is_err());
+        assert!(parse_extra_routes("::/0", true).is_ok());
+    }
+
+    #[test]
+    fn test_networks_overlap() {
+        let network = |value| IpAddrMask::from_str(value).unwrap();
+        let location = network("10.0.0.2/24");
+        assert!(networks_overlap(&location, &network("10.0.0.7/24")));
+        // local network containing location network
+        assert!(networks_overlap(&location, &network("10.1.2.3/8")));
+        // location network containing local network
+        assert!(networks_overlap(
+            &network("10.0.0.2/8"),
+            &network("10.1.2.3/24")
+        ));
+        assert!(!networks_overlap(&location, &network("192.168.1.10/24")));
+        assert!(!networks_overlap(&location, &network("fd00::1/64")));
+        assert_eq!(netmask_prefix("255.255.240.0".parse().unwrap()), 20);
+    }
+
+    #[test]
+    fn test_exclude_control_channel() {
+        let subnets = |network, address: &str| -> Vec<String> {
+            exclude_address(
+                &IpAddrMask::from_str(network).unwrap(),
+                &address.parse().unwrap(),
+            )
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+        };
+        assert_eq!(
+            subnets("10.0.0.0/30", "10.0.0.1"),
+            ["10.0.0.2/31", "10.0.0.0/32"]
+        );
+        // addresses at both edges of the range
+        assert_eq!(
+            subnets("10.0.0.0/30", "10.0.0.0"),
+            ["10.0.0.2/31", "10.0.0.1/32"]
+        );
+        assert_eq!(
+            subnets("10.0.0.0/30", "10.0.0.3"),
+            ["10.0.0.0/31", "10.0.0.2/32"]
+        );
+        assert_eq!(
+            subnets("fd00::/126", "fd00::3"),
+            ["fd00::/127", "fd00::2/128"]
+        );
+        assert!(subnets("10.0.0.1/32", "10.0.0.1").is_empty());
+
+        let exclude = |allowed_ips: &[&str], address: &str| {
+            exclude_addresses(
+                allowed_ips.iter().map(ToString::to_string).collect(),
+                &[address.parse().unwrap()],
+            )
+        };
+        assert_eq!(
+            exclude(&["10.0.0.0/30", "192.168.0.0/24"], "10.0.0.1").unwrap(),
+            ["10.0.0.2/31", "10.0.0.0/32", "192.168.0.0/24"]
+        );
+        assert_eq!(
+            exclude(&["fd00::/126"], "fd00::").unwrap(),
+            ["fd00::2/127", "fd00::1/128"]
+        );
+        // address outside of every allowed IP, also of other family, leaves them unchanged
+        assert_eq!(
+            exclude(&["192.168.0.0/24", "fd00::/64"], "10.0.0.1").unwrap(),
+            ["192.168.0.0/24", "fd00::/64"]
+        );
+        // default routes have lower priority than route to the control channel
+        assert_eq!(
+            exclude(&["0.0.0.0/0", "::/0"], "10.0.0.1").unwrap(),
+            ["0.0.0.0/0", "::/0"]
+        );
+        assert!(matches!(
+            exclude(&["10.0.0.1/32"], "10.0.0.1"),
+            Err(Error::ControlChannelBlackhole(_))
+        ));
+    }
+
+    #[test]
+    fn test_split_dns_entries() {
+        assert_eq!(
+            split_dns_entries(Some("10.0.0.2, tnt, teonite.net,fd00::1")),
+            (
+                Some("10.0.0.2,fd00::1".to_string()),
+                Some("tnt,teonite.net".to_string())
+            )
+        );
+        assert_eq!(
+            split_dns_entries(Some("10.0.0.2")),
+            (Some("10.0.0.2".into()), None)
+        );
+        assert_eq!(split_dns_entries(Some("")), (None, None));
+        assert_eq!(split_dns_entries(None), (None, None));
+        assert!(parse_dns_search(Some("example.com, -bad..domain")).is_err());
+    }
+
+    #[test]
+    fn test_normalize_instance_url() {
+        assert_eq!(
+            normalize_instance_url("https://defguard.example.com/", false).unwrap(),
+            "https://defguard.example.com"
+        );
+        assert_eq!(
+            normalize_instance_url(" https://example.com/enroll// ", false).unwrap(),
+            "https://example.com/enroll"
+        );
+        assert!(normalize_instance_url("defguard.example.com", false).is_err());
+        assert!(normalize_instance_url("ftp://example.com", true).is_err());
+        assert!(normalize_instance_url("http://10.0.0.1:8080/", false).is_err());
+        assert_eq!(
+            normalize_stored_instance_url("http://10.0.0.1:8080/", "http://10.0.0.1:8080", false)
+                .unwrap(),
+            "http://10.0.0.1:8080"
+        );
+        assert!(
+            normalize_stored_instance_url("http://10.0.0.1:8080/", "https://10.0.0.1", false)
+                .is_err()
+        );
+        assert_eq!(
+            normalize_instance_url("http://10.0.0.1:8080/", true).unwrap(),
+            "http://10.0.0.1:8080"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_parse_resolv_conf() {
+        let resolv_conf =
+            "# generated\nnameserver 10.0.0.2\nsearch example.com\nnameserver fd00::1\n";
+        assert_eq!(
+            parse_resolv_conf(resolv_conf),
+            [
+                IpAddr::from_str("10.0.0.2").unwrap(),
+                IpAddr::from_str("fd00::1").unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_decode_commands() {
+        let commands = encode_commands([
+            vec![argv!["resolvectl", "domain", "wg0", "example.com"]],
+            Vec::new(),
+            vec![argv!["ip", "link", "set", "dev", "wg0", "mtu", 1420]],
+        ])
+        .unwrap();
+        assert_eq!(
+            decode_commands(&commands),
+            [
+                argv!["resolvectl", "domain", "wg0", "example.com"],
+                argv!["ip", "link", "set", "dev", "wg0", "mtu", "1420"]
+            ]
+        );
+        assert_eq!(encode_commands([Vec::new(), Vec::new()]), None);
+        // commands configured by user are executed as a single command
+        assert_eq!(
+            decode_commands("iptables -A FORWARD -i wg0 -j ACCEPT; rm -rf /"),
+            [argv![
+                "iptables", "-A", "FORWARD", "-i", "wg0", "-j", "ACCEPT;", "rm", "-rf", "/"
+            ]]
+        );
+    }
+
+    #[test]
+    fn test_validate_encrypted_dns() {
+        assert!(
+            validate_encrypted_dns(Some("https://dns.example.com/dns-query{?dns}"), None).is_ok()
+        );
+        assert!(validate_encrypted_dns(Some("https://[2001:db8::1]/dns-query"), None).is_ok());
+        assert!(validate_encrypted_dns(None, Some("dns.example.com")).is_ok());
+        assert!(validate_encrypted_dns(Some("https://dns.example.com/q;reboot"), None).is_err());
+        assert!(validate_encrypted_dns(Some("https://dns.example.com/ q"), None).is_err());
+        assert!(validate_encrypted_dns(Some("http://dns.example.com/dns-query"), None).is_err());
+        assert!(validate_encrypted_dns(None, Some("dns.example.com; reboot")).is_err());
+    }
+
+    #[test]
+    fn test_kill_switch_commands() {
+        let endpoint: SocketAddr = "192.0.2.1:51820".parse().unwrap();
+        // kill switch is used only when all traffic is routed through location
+        let partial = Location {
+            kill_switch: true,
+            ..location()
+        };
+        assert_eq!(
+            kill_switch_commands(&partial, "wg-office", Some(endpoint)).unwrap(),
+            (Vec::new(), Vec::new())
+        );
+        let location = Location {
+            kill_switch: true,
+            route_all_traffic: true,
+            ..location()
+        };
+
+        #[cfg(target_os = "linux")]
+        {
+            let (post_up, pre_down) =
+                kill_switch_commands(&location, "wg-office", Some(endpoint)).unwrap();
+            // interface name is a separate argument, also in name of the chain
+            assert_eq!(
+                post_up[..6],
+                [
+                    argv!["iptables", "-N", "defguard-ks-wg-office"],
+                    argv![
+                        "iptables",
+                        "-A",
+                        "defguard-ks-wg-office",
+                        "-o",
+                        "wg-office",
+                        "-j",
+                        "ACCEPT"
+                    ],
+                    argv![
+                        "iptables",
+                        "-A",
+                        "defguard-ks-wg-office",
+                        "-o",
+                        "lo",
+                        "-j",
+                        "ACCEPT"
+                    ],
+                    argv![
+                        "iptables",
+                        "-A",
+                        "defguard-ks-wg-office",
+                        "-d",
+                        "192.0.2.1",
+                        "-p",
+                        "udp",
+                        "--dport",
+                        "51820",
+                        "-j",
+                        "ACCEPT"
+                    ],
+                    argv!["iptables", "-A", "defguard-ks-wg-office", "-j", "REJECT"],
+                    argv!["iptables", "-I", "OUTPUT", "-j", "defguard-ks-wg-office"],
+                ]
+            );
+            // IPv4 endpoint isn't allowed in IPv6 rules
+            assert_eq!(
+                post_up[6..],
+                [
+                    argv!["ip6tables", "-N", "defguard-ks-wg-office"],
+                    argv![
+                        "ip6tables",
+                        "-A",
+                        "defguard-ks-wg-office",
+                        "-o",
+                        "wg-office",
+                        "-j",
+                        "ACCEPT"
+                    ],
+                    argv![
+                        "ip6tables",
+                        "-A",
+                        "defguard-ks-wg-office",
+                        "-o",
+                        "lo",
+                        "-j",
+                        "ACCEPT"
+                    ],
+                    argv!["ip6tables", "-A", "defguard-ks-wg-office", "-j", "REJECT"],
+                    argv!["ip6tables", "-I", "OUTPUT", "-j", "defguard-ks-wg-office"],
+                ]
+            );
+            assert_eq!(
+                pre_down,
+                [
+                    argv!["iptables", "-D", "OUTPUT", "-j", "defguard-ks-wg-office"],
+                    argv!["iptables", "-F", "defguard-ks-wg-office"],
+                    argv!["iptables", "-X", "defguard-ks-wg-office"],
+                    argv!["ip6tables", "-D", "OUTPUT", "-j", "defguard-ks-wg-office"],
+                    argv!["ip6tables", "-F", "defguard-ks-wg-office"],
+                    argv!["ip6tables", "-X", "defguard-ks-wg-office"],
+                ]
+            );
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        assert!(matches!(
+            kill_switch_commands(&location, "wg-office", Some(endpoint)),
+            Err(Error::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn test_tunnel_dns_commands() {
+        assert_eq!(
+            tunnel_dns_commands(&location(), "wg-office").unwrap(),
+            (Vec::new(), Vec::new())
+        );
+        let single = Location {
+            force_tunnel_dns: true,
+            dns: Some("10.0.0.1".into()),
+            ..location()
+        };
+        assert_eq!(tunnel_dns_routes(&single).unwrap(), ["10.0.0.1/32"]);
+        let multiple = Location {
+            dns: Some("10.0.0.1, fd00::1".into()),
+            dns_search: Some("example.com".into()),
+            ..single.clone()
+        };
+        assert_eq!(
+            tunnel_dns_routes(&multiple).unwrap(),
+            ["10.0.0.1/32", "fd00::1/128"]
+        );
+        // search domains alone don't tell where queries should go
+        let search_only = Location {
+            dns: None,
+            ..multiple.clone()
+        };
+        assert!(matches!(
+            tunnel_dns_commands(&search_only, "wg-office"),
+            Err(Error::InvalidDns(_))
+        ));
+        let empty = Location {
+            dns: Some(String::new()),
+            ..single.clone()
+        };
+        assert!(tunnel_dns_commands(&empty, "wg-office").is_err());
+
+        #[cfg(target_os = "linux")]
+        {
+            let (post_up, pre_down) = tunnel_dns_commands(&single, "wg-office").unwrap();
+            // rules block queries by port, so they're the same for any number of servers
+            assert_eq!(
+                tunnel_dns_commands(&multiple, "wg-office").unwrap(),
+                (post_up.clone(), pre_down.clone())
+            );
+            assert_eq!(
+                post_up[..6],
+                [
+                    argv!["iptables", "-N", "defguard-dns-wg-office"],
+                    argv![
+                        "iptables",
+                        "-A",
+                        "defguard-dns-wg-office",
+                        "-o",
+                        "wg-office",
+                        "-j",
+                        "RETURN"
+                    ],
+                    argv![
+                        "iptables",
+                        "-A",
+                        "defguard-dns-wg-office",
+                        "-o",
+                        "lo",
+                        "-j",
+                        "RETURN"
+                    ],
+                    argv![
+                        "iptables",
+                        "-A",
+                        "defguard-dns-wg-office",
+                        "-p",
+                        "udp",
+                        "--dport",
+                        "53",
+                        "-j",
+                        "REJECT"
+                    ],
+                    argv![
+                        "iptables",
+                        "-A",
+                        "defguard-dns-wg-office",
+                        "-p",
+                        "tcp",
+                        "--dport",
+                        "53",
+                        "-j",
+                        "REJECT"
+                    ],
+                    argv!["iptables", "-I", "OUTPUT", "-j", "defguard-dns-wg-office"],
+                ]
+            );
+            assert_eq!(post_up.len(), 12);
+            assert_eq!(
+                pre_down[..3],
+                [
+                    argv!["iptables", "-D", "OUTPUT", "-j", "defguard-dns-wg-office"],
+                    argv!["iptables", "-F", "defguard-dns-wg-office"],
+                    argv!["iptables", "-X", "defguard-dns-wg-office"],
+                ]
+            );
+            assert_eq!(pre_down.len(), 6);
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        assert!(matches!(
+            tunnel_dns_commands(&single, "wg-office"),
+            Err(Error::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_ping_time() {
+        let linux = "PING 10.0.0.1 (10.0.0.1) 56(84) bytes of data.
+64 bytes from 10.0.0.1: icmp_seq=1 ttl=64 time=12.6 ms
+
+--- 10.0.0.1 ping statistics ---
+1 packets transmitted, 1 received, 0% packet loss, time 0ms
+rtt min/avg/max/mdev = 12.634/12.634/12.634/0.000 ms
+";
+        assert_eq!(parse_ping_time(linux), Some(13));
+        let macos = "PING 10.0.0.1 (10.0.0.1): 56 data bytes
+64 bytes from 10.0.0.1: icmp_seq=0 ttl=64 time=0.215 ms
+
+--- 10.0.0.1 ping statistics ---
+1 packets transmitted, 1 packets received, 0.0% packet loss
+round-trip min/avg/max/stddev = 0.215/0.215/0.215/0.000 ms
+";
+        assert_eq!(parse_ping_time(macos), Some(0));
+        let windows = "
+Pinging 10.0.0.1 with 32 bytes of data:
+Reply from 10.0.0.1: bytes=32 time=8ms TTL=128
+
+Ping statistics for 10.0.0.1:
+    Packets: Sent = 1, Received = 1, Lost = 0 (0% loss),
+Approximate round trip times in milli-seconds:
+    Minimum = 8ms, Maximum = 8ms, Average = 8ms
+";
+        assert_eq!(parse_ping_time(windows), Some(8));
+        assert_eq!(
+            parse_ping_time("Reply from 10.0.0.1: bytes=32 time<1ms TTL=128"),
+            Some(1)
+        );
+        // lines without round-trip time
+        assert_eq!(parse_ping_time("Request timed out."), None);
+        assert_eq!(
+            parse_ping_time("1 packets transmitted, 0 received, 100% packet loss, time 0ms"),
+            None
+        );
+        assert_eq!(parse_ping_time(""), None);
+    }
+
+    #[test]
+    fn test_location_endpoints() {
+        assert_eq!(
+            parse_endpoints(vec![
+                "vpn.example.com:51820".into(),
+                " [2001:db8::1]:51820 ".into(),
+                String::new(),
+                "10.0.0.1:51821".into(),
+                "vpn.example.com:51820".into(),
+            ])
+            .unwrap()
+            .as_deref(),
+            Some("vpn.example.com:51820\n[2001:db8::1]:51820\n10.0.0.1:51821")
+        );
+        assert_eq!(parse_endpoints(vec![" ".into()]).unwrap(), None);
+        assert_eq!(parse_endpoints(Vec::new()).unwrap(), None);
+        assert!(
+            parse_endpoints(vec!["vpn.example.com:51820".into(), "[::1]51821".into()]).is_err()
+        );
+        assert!(parse_endpoints(vec![":51821".into()]).is_err());
+
+        let backup = Location {
+            endpoints: Some(
+                "[2001:db8::1]:51820\n vpn.example.com:51820\n\nrelay.example.com:51820".into(),
+            ),
+            ..location()
+        };
+        // primary endpoint comes first, duplicates and empty lines are skipped
+        assert_eq!(
+            location_endpoints(&backup, None),
+            [
+                "vpn.example.com:51820",
+                "[2001:db8::1]:51820",
+                "relay.example.com:51820"
+            ]
+        );
+        assert_eq!(
+            location_endpoints(&backup, Some("relay.example.com")),
+            [
+                "relay.example.com:51820",
+                "vpn.example.com:51820",
+                "[2001:db8::1]:51820"
+            ]
+        );
+        assert_eq!(
+            location_endpoints(&backup, Some("2001:db8::1")),
+            [
+                "[2001:db8::1]:51820",
+                "vpn.example.com:51820",
+                "relay.example.com:51820"
+            ]
+        );
+        // relay not serving location keeps default order
+        assert_eq!(
+            location_endpoints(&backup, Some("other.example.com")),
+            location_endpoints(&backup, None)
+        );
+        assert_eq!(
+            location_endpoints(&location(), None),
+            ["vpn.example.com:51820"]
+        );
+    }
+
+    #[test]
+    fn test_served_by_relay() {
+        assert!(served_by_relay(
+            "relay.example.com:51820",
+            "relay.example.com"
+        ));
+        assert!(served_by_relay(
+            "Relay.Example.com:51820",
+            "relay.example.COM"
+        ));
+        assert!(served_by_relay(
+            "relay.example.com:51820",
+            "relay.example.com:51820"
+        ));
+        assert!(served_by_relay("[2001:db8::1]:51820", "2001:db8::1"));
+        assert!(served_by_relay("10.0.0.1", "10.0.0.1"));
+        assert!(!served_by_relay("relay.example.com:51820", "example.com"));
+        assert!(!served_by_relay(
+            "relay.example.com:51820",
+            "relay.example.com:51821"
+        ));
+        assert!(!served_by_relay(
+            "vpn.example.com:51820",
+            "relay.example.com"
+        ));
+        assert!(!served_by_relay("[::1]51820", "::1"));
+    }
+
+    #[test]
+    fn test_mtu_command() {
+        assert!(validate_mtu(MIN_MTU).is_ok());
+        assert!(validate_mtu(1420).is_ok());
+        assert!(validate_mtu(MAX_MTU).is_ok());
+        assert!(validate_mtu(MIN_MTU - 1).is_err());
+        assert!(validate_mtu(MAX_MTU + 1).is_err());
+        assert!(validate_mtu(0).is_err());
+
+        // MTU is auto-detected unless configured
+        assert!(mtu_command(&location(), "wg-office").is_empty());
+        let location = Location {
+            mtu: Some(1420),
+            ..location()
+        };
+        let commands = mtu_command(&location, "wg-office");
+        #[cfg(target_os = "linux")]
+        assert_eq!(
+            commands,
+            [argv![
+                "ip",
+                "link",
+                "set",
+                "dev",
+                "wg-office",
+                "mtu",
+                "1420"
+            ]]
+        );
+        #[cfg(target_os = "macos")]
+        assert_eq!(commands, [argv!["ifconfig", "wg-office", "mtu", "1420"]]);
+        #[cfg(target_os = "windows")]
+        assert_eq!(
+            commands,
+            [
+                argv![
+                    "netsh",
+                    "interface",
+                    "ipv4",
+                    "set",
+                    "subinterface",
+                    "wg-office",
+                    "mtu=1420",
+                    "store=active"
+                ],
+                argv![
+                    "netsh",
+                    "interface",
+                    "ipv6",
+                    "set",
+                    "subinterface",
+                    "wg-office",
+                    "mtu=1420",
+                    "store=active"
+                ]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_handshake_age() {
+        let peer = |last_handshake| {
+            let mut peer =
+                Peer::new(Key::from_str("BvUB3iZq3U0jZrY6b4KbGhz0IVZzpAdbJiRZGdci9ZU=").unwrap());
+            peer.last_handshake = last_handshake;
+            peer
+        };
+        assert_eq!(handshake_age(&[]), None);
+        // WireGuard reports missing handshake as zero timestamp
+        assert_eq!(handshake_age(&[peer(None)]), None);
+        assert_eq!(handshake_age(&[peer(Some(SystemTime::UNIX_EPOCH))]), None);
+        // clock going backwards doesn't make handshake stale
+        let future = SystemTime::now() + Duration::from_secs(60);
+        assert_eq!(handshake_age(&[peer(Some(future))]), Some(Duration::ZERO));
+        // the latest handshake of all peers counts
+        let age = handshake_age(&[
+            peer(Some(SystemTime::now() - Duration::from_secs(300))),
+            peer(Some(SystemTime::now() - Duration::from_secs(30))),
+            peer(None),
+        ])
+        .unwrap();
+        assert!(age >= Duration::from_secs(30) && age < Duration::from_secs(60));
+    }
+}
@@ -1,6 +1,10 @@
-use crate::database::Tunnel;
+use crate::{
+    database::{Location, PresharedKey, Tunnel},
+    error::Error,
+    utils::{location_allowed_ips, split_dns_entries},
+};
 use base64::{prelude::BASE64_STANDARD, DecodeError, Engine};
-use std::{array::TryFromSliceError, net::IpAddr};
+use std::{array::TryFromSliceError, fmt::Write, net::IpAddr};
 use thiserror::Error;
 use x25519_dalek::{PublicKey, StaticSecret};
 
@@ -18,6 +22,8 @@ pub enum WireguardConfigParseError {
     InvalidKey(String),
     #[error("Invalid port: {0}")]
     InvalidPort(String),
+    #[error("Invalid value of {0}: {1}")]
+    InvalidValue(String, String),
 }
 
 impl From<TryFromSliceError> for WireguardConfigParseError {
@@ -42,12 +48,7 @@ pub fn parse_wireguard_config(config: &str) -> Result<Tunnel, WireguardConfigPar
     let prvkey = interface_section
         .get("PrivateKey")
         .ok_or_else(|| WireguardConfigParseError::KeyNotFound("PrivateKey".to_string()))?;
-    let prvkey_bytes: [u8; 32] = BASE64_STANDARD
-        .decode(prvkey.as_bytes())?
-        .try_into()
-        .map_err(|_| WireguardConfigParseError::InvalidKey(prvkey.to_string()))?;
-    let pubkey =
-        BASE64_STANDARD.encode(PublicKey::from(&StaticSecret::from(prvkey_bytes)).to_bytes());
+    let pubkey = derive_pubkey(prvkey)?;
     let address = interface_section
         .get("Address")
         .ok_or_else(|| WireguardConfigParseError::KeyNotFound("Address".to_string()))?;
@@ -105,6 +106,195 @@ pub fn parse_wireguard_config(config: &str) -> Result<Tunnel, WireguardConfigPar
 
     Ok(tunnel)
 }
+
+/// Decodes base64 encoded 32 byte WireGuard key.
+fn decode_key(key: &str) -> Result<[u8; 32], WireguardConfigParseError> {
+    BASE64_STANDARD
+        .decode(key.as_bytes())?
+        .try_into()
+        .map_err(|_| WireguardConfigParseError::InvalidKey(key.to_string()))
+}
+
+/// Computes public key matching base64 encoded private key.
+pub fn derive_pubkey(prvkey: &str) -> Result<String, WireguardConfigParseError> {
+    let prvkey_bytes = decode_key(prvkey)?;
+    Ok(BASE64_STANDARD.encode(PublicKey::from(&StaticSecret::from(prvkey_bytes)).to_bytes()))
+}
+
+/// Location parsed from `wg-quick` config together with client keys from `[Interface]` section.
+#[derive(Debug)]
+pub struct LocationConfig {
+    pub location: Location,
+    pub pubkey: String,
+    pub prvkey: String,
+}
+
+/// Name of imported location derived from endpoint host. Characters other than letters, digits,
+/// `_`, `.` and `-` are replaced, as location name is used for interface name.
+fn imported_location_name(endpoint: &str) -> String {
+    let host = endpoint
+        .rsplit_once(':')
+        .map_or(endpoint, |(host, _)| host)
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    host.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-') {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+/// Parses `wg-quick` config into a location of given instance. Only the first peer is used.
+/// Location isn't managed by defguard, so it's marked as imported and has no network ID.
+pub fn parse_location_config(
+    config: &str,
+    instance_id: i64,
+) -> Result<LocationConfig, WireguardConfigParseError> {
+    let config = ini::Ini::load_from_str(config)?;
+    let section = |name: &str| {
+        config
+            .section(Some(name))
+            .ok_or_else(|| WireguardConfigParseError::SectionNotFound(name.to_string()))
+    };
+    let required = |section: &ini::Properties, key: &str| {
+        section
+            .get(key)
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(str::to_string)
+            .ok_or_else(|| WireguardConfigParseError::KeyNotFound(key.to_string()))
+    };
+    let invalid = |key: &str, value: &str| {
+        WireguardConfigParseError::InvalidValue(key.to_string(), value.to_string())
+    };
+
+    let interface_section = section("Interface")?;
+    let prvkey = required(interface_section, "PrivateKey")?;
+    let pubkey = derive_pubkey(&prvkey)?;
+    let address = required(interface_section, "Address")?;
+    let (dns, dns_search) = split_dns_entries(interface_section.get("DNS"));
+    let mtu = interface_section
+        .get("MTU")
+        .map(|mtu| mtu.trim().parse().map_err(|_| invalid("MTU", mtu)))
+        .transpose()?;
+
+    let peer_section = section("Peer")?;
+    let peer_pubkey = required(peer_section, "PublicKey")?;
+    decode_key(&peer_pubkey)?;
+    let endpoint = required(peer_section, "Endpoint")?;
+    let allowed_ips = required(peer_section, "AllowedIPs")?;
+    let preshared_key = peer_section
+        .get("PresharedKey")
+        .map(|key| {
+            // don't include the key itself in error message
+            decode_key(key.trim())
+                .map(|_| PresharedKey(key.trim().to_string()))
+                .map_err(|_| WireguardConfigParseError::InvalidKey("PresharedKey".to_string()))
+        })
+        .transpose()?;
+    let keepalive_interval = peer_section
+        .get("PersistentKeepalive")
+        .map(|keepalive| {
+            keepalive
+                .trim()
+                .parse()
+                .map_err(|_| invalid("PersistentKeepalive", keepalive))
+        })
+        .transpose()?
+        .unwrap_or(25);
+
+    let location = Location {
+        id: None,
+        instance_id,
+        network_id: 0,
+        name: imported_location_name(&endpoint),
+        address,
+        pubkey: peer_pubkey,
+        endpoint,
+        allowed_ips,
+        dns,
+        route_all_traffic: false,
+        mfa_enabled: false,
+        keepalive_interval,
+        doh_url: None,
+        dot_host: None,
+        metadata: None,
+        endpoint_ip_override: None,
+        kill_switch: false,
+        mtu,
+        autoconnect: false,
+        dns_search,
+        favorite: false,
+        sort_order: 0,
+        preshared_key,
+        display_name: None,
+        interface_name: None,
+        extra_routes: None,
+        failover_group: None,
+        failover_priority: 0,
+        schedule: None,
+        sticky_endpoint: None,
+        force_tunnel_dns: false,
+        endpoints: None,
+        imported: true,
+    };
+    Ok(LocationConfig {
+        location,
+        pubkey,
+        prvkey,
+    })
+}
+
+/// Builds `wg-quick` compatible config of a location, routing the same networks as
+/// connection made by the client.
+/// `PrivateKey` is left out if `prvkey` is not provided, so it has to be filled in manually.
+pub fn location_to_wireguard_config(
+    location: &Location,
+    prvkey: Option<&str>,
+) -> Result<String, Error> {
+    let mut config = String::from("[Interface]\n");
+    if let Some(prvkey) = prvkey {
+        let _ = writeln!(config, "PrivateKey = {prvkey}");
+    }
+    let _ = writeln!(config, "Address = {}", location.address);
+    if let Some(mtu) = location.mtu {
+        let _ = writeln!(config, "MTU = {mtu}");
+    }
+    // search domains are listed together with DNS servers like in wg-quick configs
+    let dns: Vec<&str> = [location.dns.as_deref(), location.dns_search.as_deref()]
+        .into_iter()
+        .flatten()
+        .filter(|entries| !entries.is_empty())
+        .collect();
+    if !dns.is_empty() {
+        let _ = writeln!(config, "DNS = {}", dns.join(","));
+    }
+    let _ = writeln!(config, "\n[Peer]");
+    let _ = writeln!(config, "PublicKey = {}", location.pubkey);
+    let _ = writeln!(config, "Endpoint = {}", location.endpoint);
+    let _ = writeln!(
+        config,
+        "AllowedIPs = {}",
+        location_allowed_ips(location)?.join(", ")
+    );
+    // preshared key is a secret, so it's only exported together with private key
+    if let (Some(_), Some(preshared_key)) = (prvkey, &location.preshared_key) {
+        let _ = writeln!(config, "PresharedKey = {}", preshared_key.0);
+    }
+    if location.keepalive_interval > 0 {
+        let _ = writeln!(
+            config,
+            "PersistentKeepalive = {}",
+            location.keepalive_interval
+        );
+    }
+    Ok(config)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -151,4 +341,116 @@ mod test {
         assert_eq!(tunnel.pre_down, None);
         assert_eq!(tunnel.post_down, None);
     }
+    #[test]
+    fn test_export_location_config() {
+        let location = Location {
+            id: Some(1),
+            instance_id: 1,
+            network_id: 1,
+            name: "location".into(),
+            address: "10.0.0.2/24".into(),
+            pubkey: "BvUB3iZq3U0jZrY6b4KbGhz0IVZzpAdbJiRZGdci9ZU=".into(),
+            endpoint: "vpn.example.com:51820".into(),
+            allowed_ips: "10.0.0.0/24, 10.1.0.0/16".into(),
+            dns: Some("10.0.0.1".into()),
+            route_all_traffic: false,
+            mfa_enabled: false,
+            keepalive_interval: 25,
+            doh_url: None,
+            dot_host: None,
+            metadata: None,
+            endpoint_ip_override: None,
+            kill_switch: false,
+            mtu: None,
+            autoconnect: false,
+            dns_search: None,
+            favorite: false,
+            sort_order: 0,
+            preshared_key: None,
+            display_name: None,
+            interface_name: None,
+            extra_routes: None,
+            failover_group: None,
+            failover_priority: 0,
+            schedule: None,
+            sticky_endpoint: None,
+            force_tunnel_dns: false,
+            endpoints: None,
+            imported: false,
+        };
+        let config = location_to_wireguard_config(
+            &location,
+            Some("GAA2X3DW0WakGVx+DsGjhDpTgg50s1MlmrLf24Psrlg="),
+        )
+        .unwrap();
+        let tunnel = parse_wireguard_config(&config).unwrap();
+        assert_eq!(
+            tunnel.prvkey,
+            "GAA2X3DW0WakGVx+DsGjhDpTgg50s1MlmrLf24Psrlg="
+        );
+        assert_eq!(tunnel.address, location.address);
+        assert_eq!(tunnel.dns, location.dns);
+        assert_eq!(tunnel.server_pubkey, location.pubkey);
+        assert_eq!(tunnel.endpoint, location.endpoint);
+        assert_eq!(tunnel.allowed_ips, Some(location.allowed_ips.clone()));
+        assert_eq!(tunnel.persistent_keep_alive, 25);
+
+        // private key is left out unless requested
+        let config = location_to_wireguard_config(&location, None).unwrap();
+        assert!(!config.contains("PrivateKey"));
+
+        // routing all traffic replaces allowed IPs with default route
+        let location = Location {
+            route_all_traffic: true,
+            keepalive_interval: 0,
+            ..location
+        };
+        let config = location_to_wireguard_config(&location, None).unwrap();
+        assert!(config.contains("AllowedIPs = 0.0.0.0/0\n"));
+        assert!(!config.contains("PersistentKeepalive"));
+    }
+
+    #[test]
+    fn test_parse_location_config() {
+        let config = "
+            [Interface]
+            PrivateKey = GAA2X3DW0WakGVx+DsGjhDpTgg50s1MlmrLf24Psrlg=
+            Address = 10.0.0.2/24
+            DNS = 10.0.0.1, example.com
+            MTU = 1380
+
+            [Peer]
+            PublicKey = BvUB3iZq3U0jZrY6b4KbGhz0IVZzpAdbJiRZGdci9ZU=
+            PresharedKey = BvUB3iZq3U0jZrY6b4KbGhz0IVZzpAdbJiRZGdci9ZU=
+            AllowedIPs = 10.0.0.0/24
+            Endpoint = vpn.example.com:51820
+        ";
+        let LocationConfig {
+            location,
+            pubkey,
+            prvkey,
+        } = parse_location_config(config, 1).unwrap();
+        assert_eq!(prvkey, "GAA2X3DW0WakGVx+DsGjhDpTgg50s1MlmrLf24Psrlg=");
+        assert_eq!(pubkey, derive_pubkey(&prvkey).unwrap());
+        assert_eq!(location.instance_id, 1);
+        assert!(location.imported);
+        assert_eq!(location.name, "vpn.example.com");
+        assert_eq!(location.address, "10.0.0.2/24");
+        assert_eq!(location.dns, Some("10.0.0.1".to_string()));
+        assert_eq!(location.dns_search, Some("example.com".to_string()));
+        assert_eq!(location.mtu, Some(1380));
+        assert_eq!(location.keepalive_interval, 25);
+        assert!(location.preshared_key.is_some());
+
+        // peer section is required
+        let config = "
+            [Interface]
+            PrivateKey = GAA2X3DW0WakGVx+DsGjhDpTgg50s1MlmrLf24Psrlg=
+            Address = 10.0.0.2/24
+        ";
+        assert!(matches!(
+            parse_location_config(config, 1),
+            Err(WireguardConfigParseError::SectionNotFound(_))
+        ));
+    }
 }
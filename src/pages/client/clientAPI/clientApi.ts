@@ -7,6 +7,7 @@ import { NewApplicationVersionInfo } from '../../../shared/hooks/api/types';
 import {
   CommonWireguardFields,
   Connection,
+  ConnectionsPage,
   DefguardInstance,
   LocationStats,
   Tunnel,
@@ -14,11 +15,13 @@ import {
 import {
   ConnectionRequest,
   GetLocationsRequest,
+  InstanceUpdatePreview,
   LocationDetails,
   LocationDetailsRequest,
   RoutingRequest,
   SaveConfigRequest,
   SaveDeviceConfigResponse,
+  ServiceStatus,
   Settings,
   StatsRequest,
   TauriCommandKey,
@@ -68,7 +71,7 @@ const getLocationStats = async (data: StatsRequest): Promise<LocationStats[]> =>
 const getLastConnection = async (data: ConnectionRequest): Promise<Connection> =>
   invokeWrapper('last_connection', data);
 
-const getConnectionHistory = async (data: ConnectionRequest): Promise<Connection[]> =>
+const getConnectionHistory = async (data: ConnectionRequest): Promise<ConnectionsPage> =>
   invokeWrapper('all_connections', data);
 
 const getActiveConnection = async (data: ConnectionRequest): Promise<Connection> =>
@@ -82,11 +85,14 @@ const getSettings = async (): Promise<Settings> => invokeWrapper('get_settings')
 const updateSettings = async (data: Partial<Settings>): Promise<Settings> =>
   invokeWrapper('update_settings', { data });
 
+const resetSettings = async (): Promise<Settings> => invokeWrapper('reset_settings');
+
 const deleteInstance = async (id: number): Promise<void> =>
   invokeWrapper('delete_instance', { instanceId: id });
 
-const updateInstance = async (data: UpdateInstnaceRequest): Promise<void> =>
-  invokeWrapper('update_instance', data);
+const updateInstance = async (
+  data: UpdateInstnaceRequest,
+): Promise<InstanceUpdatePreview | null> => invokeWrapper('update_instance', data);
 
 const parseTunnelConfig = async (config: string) =>
   invokeWrapper('parse_tunnel_config', { config: config });
@@ -114,6 +120,9 @@ const deleteTunnel = async (id: number): Promise<void> =>
 const getLatestAppVersion = async (): Promise<NewApplicationVersionInfo> =>
   invokeWrapper('get_latest_app_version');
 
+const getServiceStatus = async (): Promise<ServiceStatus> =>
+  invokeWrapper('service_status');
+
 export const clientApi = {
   getInstances,
   getTunnels,
@@ -128,6 +137,7 @@ export const clientApi = {
   updateLocationRouting,
   getSettings,
   updateSettings,
+  resetSettings,
   deleteInstance,
   deleteTunnel,
   getLocationDetails,
@@ -137,4 +147,5 @@ export const clientApi = {
   openLink,
   getTunnelDetails,
   getLatestAppVersion,
+  getServiceStatus,
 };
@@ -10,6 +10,8 @@ export type ConnectionRequest = {
   locationId: number;
   connectionType: WireguardInstanceType;
   presharedKey?: string;
+  // connect location outside of its schedule
+  force?: boolean;
 };
 
 export type RoutingRequest = {
@@ -32,11 +34,14 @@ export type SaveConfigRequest = {
 export type UpdateInstnaceRequest = {
   instanceId: number;
   response: CreateDeviceResponse;
+  // only preview changes without saving them
+  dryRun?: boolean;
 };
 
 export type SaveDeviceConfigResponse = {
   instance: DefguardInstance;
   locations: DefguardLocation[];
+  created: boolean;
 };
 export type SaveTunnelRequest = {
   privateKey: string;
@@ -45,6 +50,8 @@ export type SaveTunnelRequest = {
 
 export type TrayIconTheme = 'color' | 'white' | 'black' | 'gray';
 
+export type EndpointResolution = 'auto' | 'ipv4_first' | 'ipv6_first' | 'sticky';
+
 export type LogLevel = 'error' | 'info' | 'debug' | 'trace';
 
 export type LogItemField = {
@@ -60,8 +67,23 @@ export type LogItem = {
   fields: LogItemField;
 };
 
+export type LogFormat = 'Text' | 'Json';
+
+// log line emitted in Json format
+export type LogEntry = {
+  // datetime UTC
+  timestamp: string;
+  level: string;
+  interface?: string;
+  message: string;
+  fields: Record<string, unknown>;
+};
+
 export type InterfaceLogsRequest = {
   locationId: DefguardLocation['id'];
+  connectionType: ConnectionType;
+  from?: string;
+  format?: LogFormat;
 };
 
 export type Settings = {
@@ -69,6 +91,81 @@ export type Settings = {
   log_level: LogLevel;
   tray_icon_theme: TrayIconTheme;
   check_for_updates: boolean;
+  stats_interval_seconds: number;
+  retention_days: number;
+  proxy_url?: string;
+  connection_timeout_seconds: number;
+  stale_handshake_seconds: number;
+  stale_handshake_notification: boolean;
+  fallback_dns?: string;
+  allow_insecure: boolean;
+  disconnect_on_exit: boolean;
+  interface_name_template?: string;
+  service_url?: string;
+  endpoint_resolution: EndpointResolution;
+  public_ip_echo_url?: string;
+  launch_on_startup: boolean;
+};
+
+export type DnsSource = 'location' | 'tunnel' | 'fallback' | 'system';
+
+export type EffectiveDns = {
+  servers: string[];
+  source: DnsSource;
+  interface_name?: string;
+};
+
+export type PeakThroughput = {
+  location_id: number;
+  upload_rate: number;
+  download_rate: number;
+  updated_at: string;
+};
+
+export type LifetimeTransfer = {
+  location_id: number;
+  upload: number;
+  download: number;
+  updated_at: string;
+};
+
+export type TunnelPublicIp = {
+  public_ip?: string;
+  local_ip?: string;
+  differs_from_local: boolean;
+  error?: string;
+};
+
+export type KeysValidation = {
+  valid: boolean;
+  pubkey_matches: boolean;
+};
+
+export type InstanceUpdatePreview = {
+  instance_changes: FieldChange[];
+  locations: LocationDrift[];
+};
+
+export type ConnectionHealth = 'healthy' | 'degraded' | 'stale' | 'disconnected';
+
+export type LocationHealth = {
+  health: ConnectionHealth;
+  last_handshake?: number;
+  latency_ms?: number;
+};
+
+export type FieldChange = {
+  field: string;
+  current: unknown;
+  updated: unknown;
+};
+
+export type LocationDrift = {
+  network_id: number;
+  location_id?: number;
+  name: string;
+  status: 'added' | 'removed' | 'changed';
+  changes: FieldChange[];
 };
 
 export type LocationDetails = {
@@ -83,6 +180,10 @@ export type LocationDetails = {
   allowed_ips: string;
   persistent_keepalive_interval?: number;
   last_handshake?: number;
+  mtu?: number;
+  dns_search?: string;
+  preshared_key: boolean;
+  extra_routes?: string;
 };
 
 export type TunnelRequest = {
@@ -106,6 +207,18 @@ export type LocationDetailsRequest = {
   connectionType: WireguardInstanceType;
 };
 
+export type ServiceStatus = {
+  running: boolean;
+  version: string;
+  proto_version: number;
+};
+
+// Error returned by failed command
+export type ClientError = {
+  code: string;
+  message: string;
+};
+
 export type TauriCommandKey =
   | 'all_instances'
   | 'all_locations'
@@ -119,6 +232,7 @@ export type TauriCommandKey =
   | 'update_location_routing'
   | 'get_settings'
   | 'update_settings'
+  | 'reset_settings'
   | 'delete_instance'
   | 'update_instance'
   | 'parse_tunnel_config'
@@ -128,4 +242,5 @@ export type TauriCommandKey =
   | 'delete_tunnel'
   | 'location_interface_details'
   | 'open_link'
-  | 'get_latest_app_version';
+  | 'get_latest_app_version'
+  | 'service_status';
@@ -25,6 +25,13 @@ const defaultValues: StoreValues = {
     theme: 'light',
     tray_icon_theme: 'color',
     check_for_updates: true,
+    stats_interval_seconds: 1,
+    retention_days: 0,
+    connection_timeout_seconds: 30,
+    stale_handshake_seconds: 180,
+    stale_handshake_notification: false,
+    allow_insecure: false,
+    disconnect_on_exit: true,
   },
 };
 
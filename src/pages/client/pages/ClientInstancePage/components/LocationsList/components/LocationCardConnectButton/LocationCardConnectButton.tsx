@@ -1,5 +1,6 @@
 import './style.scss';
 
+import { useQuery } from '@tanstack/react-query';
 import classNames from 'classnames';
 import { useState } from 'react';
 import { error } from 'tauri-plugin-log-api';
@@ -14,10 +15,11 @@ import {
 import SvgIconX from '../../../../../../../../shared/defguard-ui/components/svg/IconX';
 import { useToaster } from '../../../../../../../../shared/defguard-ui/hooks/toasts/useToaster';
 import { clientApi } from '../../../../../../clientAPI/clientApi';
+import { clientQueryKeys } from '../../../../../../query';
 import { CommonWireguardFields } from '../../../../../../types';
 import { useMFAModal } from '../../modals/MFAModal/useMFAModal';
 
-const { connect, disconnect } = clientApi;
+const { connect, disconnect, getServiceStatus } = clientApi;
 
 type Props = {
   location?: CommonWireguardFields;
@@ -28,6 +30,13 @@ export const LocationCardConnectButton = ({ location }: Props) => {
   const [isLoading, setIsLoading] = useState(false);
   const { LL } = useI18nContext();
   const openMFAModal = useMFAModal((state) => state.open);
+  const { data: serviceStatus } = useQuery({
+    queryFn: getServiceStatus,
+    queryKey: [clientQueryKeys.getServiceStatus],
+    refetchInterval: 10000,
+  });
+  // connecting requires running service, disconnecting is still allowed
+  const serviceUnavailable = serviceStatus?.running === false && !location?.active;
 
   const cn = classNames('location-card-connect-button', {
     connected: location?.active,
@@ -57,7 +66,7 @@ export const LocationCardConnectButton = ({ location }: Props) => {
     } catch (e) {
       setIsLoading(false);
       toaster.error(LL.common.messages.error());
-      error(`Error handling interface: ${e}`);
+      error(`Error handling interface: ${JSON.stringify(e)}`);
       console.error(e);
     }
   };
@@ -70,6 +79,7 @@ export const LocationCardConnectButton = ({ location }: Props) => {
       size={ButtonSize.SMALL}
       styleVariant={ButtonStyleVariant.STANDARD}
       loading={isLoading}
+      disabled={serviceUnavailable}
       text={
         location?.active
           ? LL.pages.client.pages.instancePage.controls.disconnect()
@@ -25,7 +25,7 @@ export const LocationCardRoute = ({ location }: Props) => {
         });
       }
     } catch (e) {
-      error(`Error handling routing: ${e}`);
+      error(`Error handling routing: ${JSON.stringify(e)}`);
       console.error(e);
     }
   };
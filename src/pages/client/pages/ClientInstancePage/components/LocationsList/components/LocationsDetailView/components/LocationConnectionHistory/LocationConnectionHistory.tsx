@@ -42,13 +42,13 @@ export const LocationConnectionHistory = ({
       <header>
         <h2>{localLL.title()}</h2>
       </header>
-      {connectionHistory.length === 0 && !connected && (
+      {connectionHistory.connections.length === 0 && !connected && (
         <div className="location-never-connected">
           <LocationCardNeverConnected />
         </div>
       )}
-      {connectionHistory.length > 0 && (
-        <LocationHistoryTable connections={connectionHistory} />
+      {connectionHistory.connections.length > 0 && (
+        <LocationHistoryTable connections={connectionHistory.connections} />
       )}
     </Card>
   );
@@ -26,7 +26,7 @@ import { useMFAModal } from './useMFAModal';
 const { connect } = clientApi;
 
 const CODE_LENGTH = 6;
-const CLIENT_MFA_ENDPOINT = 'api/v1/client-mfa';
+const CLIENT_MFA_ENDPOINT = '/api/v1/client-mfa';
 
 type FormFields = {
   code: string;
@@ -79,8 +79,10 @@ export const MFAModal = () => {
       return toaster.error(localLL.errors.instanceNotFound());
     }
 
-    setProxyUrl(instance.proxy_url + CLIENT_MFA_ENDPOINT);
-    const mfaStartUrl = instance.proxy_url + CLIENT_MFA_ENDPOINT + '/start';
+    // stored URLs are normalized without trailing slash, older ones may still have it
+    const proxyBaseUrl = instance.proxy_url.replace(/\/+$/, '');
+    setProxyUrl(proxyBaseUrl + CLIENT_MFA_ENDPOINT);
+    const mfaStartUrl = proxyBaseUrl + CLIENT_MFA_ENDPOINT + '/start';
 
     // selectedMethod: 0 = authenticator app, 1 = email
     const data = {
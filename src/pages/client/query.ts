@@ -7,4 +7,5 @@ export const clientQueryKeys = {
   getActiveConnection: 'GET_ACTIVE_CONNECTION',
   getLocationDetails: 'GET_LOCATION_DETAILS',
   getTunnels: 'GET_TUNNELS',
+  getServiceStatus: 'GET_SERVICE_STATUS',
 };
@@ -7,6 +7,8 @@ export type DefguardInstance = {
   // connected
   active: boolean;
   pubkey: string;
+  archived_at?: string;
+  preferred_relay?: string;
 };
 
 export type DefguardLocation = {
@@ -17,8 +19,19 @@ export type LocationStats = {
   collected_at: number;
   download: number;
   upload: number;
+  latency_ms?: number;
+  upload_rate: number;
+  download_rate: number;
 };
 
+export type DisconnectReason =
+  | 'user_initiated'
+  | 'service_error'
+  | 'app_shutdown'
+  | 'network_failure'
+  | 'paused'
+  | 'schedule';
+
 export type Connection = {
   id: number;
   location_id: number;
@@ -27,6 +40,44 @@ export type Connection = {
   end: string;
   upload?: number;
   download?: number;
+  disconnect_reason?: DisconnectReason;
+  resolved_endpoint?: string;
+  // only present for active connection
+  endpoint?: string;
+  connected_since?: string;
+  duration_seconds?: number;
+  profile_id?: number;
+  profile_name?: string;
+  // connected regardless of location schedule
+  forced?: boolean;
+};
+
+export type RecentConnection = {
+  id: number;
+  location_id: number;
+  location_name: string;
+  instance_id: number;
+  instance_name: string;
+  connected_from: string;
+  start: string;
+  end: string;
+  upload?: number;
+  download?: number;
+  disconnect_reason?: DisconnectReason;
+  resolved_endpoint?: string;
+};
+
+export type KeyProfile = {
+  id: number;
+  instance_id: number;
+  name: string;
+  pubkey: string;
+  address?: string;
+};
+
+export type ConnectionsPage = {
+  connections: Connection[];
+  total: number;
 };
 
 export type Tunnel = {
@@ -60,6 +111,18 @@ export type CommonWireguardFields = {
   pubkey: string;
   instance_id: number;
   network_id: number;
+  favorite?: boolean;
+  sort_order?: number;
+  failover_group?: string;
+  failover_priority?: number;
+  schedule?: string;
+  force_tunnel_dns?: boolean;
+  // newline-separated backup endpoints
+  endpoints?: string;
+  // imported from WireGuard config file, not synced with defguard
+  imported?: boolean;
+  last_error?: string;
+  last_error_at?: string;
 };
 
 export enum ClientView {